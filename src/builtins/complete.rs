@@ -0,0 +1,182 @@
+use structopt::StructOpt;
+
+use crate::builtins::utils::print_stderr_with_capture;
+use crate::builtins::utils::print_stdout_with_capture;
+use crate::parsers;
+use crate::shell::Shell;
+use crate::types::{Command, CommandLine, CommandResult, CompletionRule, CompletionSource};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "complete", about = "Define completions for commands")]
+struct OptMain {
+    #[structopt(short = "W", value_name = "WORDS", help = "Complete with these words")]
+    words: Option<String>,
+
+    #[structopt(
+        short = "F",
+        value_name = "FUNC",
+        help = "Complete with output lines of the function"
+    )]
+    function: Option<String>,
+
+    #[structopt(
+        short = "C",
+        value_name = "CMD",
+        help = "Complete with output lines of the command"
+    )]
+    command: Option<String>,
+
+    #[structopt(
+        short = "G",
+        value_name = "GLOB",
+        help = "Complete with files matching the pattern"
+    )]
+    glob: Option<String>,
+
+    #[structopt(
+        short = "s",
+        long = "subcommand",
+        value_name = "SUBCMD",
+        help = "Only for arguments after the subcommand"
+    )]
+    subcmd: Option<String>,
+
+    #[structopt(
+        short = "o",
+        long = "option",
+        value_name = "OPTION",
+        allow_hyphen_values = true,
+        help = "Only for the value of the option"
+    )]
+    option: Option<String>,
+
+    #[structopt(short = "p", help = "Print the rules")]
+    print: bool,
+
+    #[structopt(short = "r", help = "Remove the rules")]
+    remove: bool,
+
+    #[structopt(name = "COMMAND", help = "The commands to complete")]
+    cmds: Vec<String>,
+}
+
+fn quote(text: &str) -> String {
+    let is_plain = !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+%@".contains(c));
+    if is_plain {
+        text.to_string()
+    } else {
+        format!("'{}'", text.replace('\'', "'\\''"))
+    }
+}
+
+/// The rule in the syntax of the `complete` builtin.
+fn format_rule(rule: &CompletionRule) -> String {
+    let mut result = String::from("complete");
+    if let Some(x) = &rule.subcmd {
+        result.push_str(&format!(" -s {}", quote(x)));
+    }
+    if let Some(x) = &rule.option {
+        result.push_str(&format!(" -o {}", quote(x)));
+    }
+    let (flag, value) = match &rule.source {
+        CompletionSource::Words(x) => ("-W", x.join(" ")),
+        CompletionSource::Function(x) => ("-F", x.clone()),
+        CompletionSource::Command(x) => ("-C", x.clone()),
+        CompletionSource::Glob(x) => ("-G", x.clone()),
+    };
+    result.push_str(&format!(" {} {} {}", flag, quote(&value), quote(&rule.cmd)));
+    result
+}
+
+pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let tokens = &cmd.tokens;
+    let args = parsers::parser_line::tokens_to_args(tokens);
+    let show_usage = args.len() > 1 && (args[1] == "-h" || args[1] == "--help");
+
+    let opt = match OptMain::from_iter_safe(args) {
+        Ok(x) => x,
+        Err(e) => {
+            let info = format!("{}", e);
+            if show_usage {
+                print_stdout_with_capture(&info, &mut cr, cl, cmd, capture);
+                cr.status = 0;
+            } else {
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                cr.status = 1;
+            }
+            return cr;
+        }
+    };
+
+    let mut sources = Vec::new();
+    if let Some(x) = &opt.words {
+        let words = x.split_whitespace().map(|x| x.to_string()).collect();
+        sources.push(CompletionSource::Words(words));
+    }
+    if let Some(x) = &opt.function {
+        sources.push(CompletionSource::Function(x.clone()));
+    }
+    if let Some(x) = &opt.command {
+        sources.push(CompletionSource::Command(x.clone()));
+    }
+    if let Some(x) = &opt.glob {
+        sources.push(CompletionSource::Glob(x.clone()));
+    }
+
+    if opt.remove {
+        if opt.cmds.is_empty() {
+            let info = "cicada: complete: no command given";
+            print_stderr_with_capture(info, &mut cr, cl, cmd, capture);
+            return cr;
+        }
+        sh.completion_rules.retain(|x| {
+            !opt.cmds.contains(&x.cmd)
+                || opt
+                    .subcmd
+                    .as_ref()
+                    .is_some_and(|y| x.subcmd.as_ref() != Some(y))
+                || opt
+                    .option
+                    .as_ref()
+                    .is_some_and(|y| x.option.as_ref() != Some(y))
+        });
+        return cr;
+    }
+
+    if opt.print || sources.is_empty() {
+        let lines: Vec<String> = sh
+            .completion_rules
+            .iter()
+            .filter(|x| opt.cmds.is_empty() || opt.cmds.contains(&x.cmd))
+            .map(format_rule)
+            .collect();
+        if !lines.is_empty() {
+            print_stdout_with_capture(&lines.join("\n"), &mut cr, cl, cmd, capture);
+        }
+        return cr;
+    }
+
+    if opt.cmds.is_empty() {
+        let info = "cicada: complete: no command given";
+        print_stderr_with_capture(info, &mut cr, cl, cmd, capture);
+        return cr;
+    }
+    for name in opt.cmds.iter() {
+        for source in sources.iter() {
+            let rule = CompletionRule {
+                cmd: name.clone(),
+                subcmd: opt.subcmd.clone(),
+                option: opt.option.clone(),
+                source: source.clone(),
+            };
+            if !sh.completion_rules.contains(&rule) {
+                sh.completion_rules.push(rule);
+            }
+        }
+    }
+    cr
+}
@@ -0,0 +1,121 @@
+use regex::Regex;
+
+use crate::builtins;
+use crate::builtins::utils::print_stderr_with_capture;
+use crate::builtins::utils::print_stdout_with_capture;
+use crate::shell::{self, Shell};
+use crate::types::{Array, Command, CommandLine, CommandResult};
+
+pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let tokens = cmd.tokens.clone();
+
+    let mut opts = String::new();
+    let mut idx_names = 1;
+    for (_, text) in tokens.iter().skip(1) {
+        match text.strip_prefix('-') {
+            Some(x) if !x.is_empty() => opts.push_str(x),
+            _ => break,
+        }
+        idx_names += 1;
+    }
+    if let Some(c) = opts.chars().find(|c| !"aAfFp".contains(*c)) {
+        let info = format!("cicada: declare: -{}: invalid option", c);
+        print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+        cr.status = 2;
+        return cr;
+    }
+    if opts.contains('f') || opts.contains('F') {
+        return builtins::functions::run(sh, cl, cmd, capture);
+    }
+
+    let names: Vec<String> = tokens.iter().skip(idx_names).map(|x| x.1.clone()).collect();
+    if names.is_empty() || opts.contains('p') {
+        return show_vars(sh, cl, cmd, capture, &names);
+    }
+
+    let re_name_ptn = Regex::new(r"^([a-zA-Z_][a-zA-Z0-9_]*)(\+?=(.*))?$").unwrap();
+    for text in names.iter() {
+        let cap = match re_name_ptn.captures(text) {
+            Some(x) => x,
+            None => {
+                let info = format!("cicada: declare: `{}': not a valid identifier", text);
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                return cr;
+            }
+        };
+
+        let name = &cap[1];
+        let is_assoc = sh.get_array(name).map(|x| x.is_assoc());
+        if opts.contains('A') && is_assoc != Some(true) {
+            if is_assoc.is_some() {
+                let info = format!(
+                    "cicada: declare: {}: cannot convert indexed to associative array",
+                    name
+                );
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                return cr;
+            }
+            sh.set_array(name, Array::new_assoc());
+        } else if opts.contains('a') && is_assoc.is_none() {
+            let mut array = Array::new_indexed();
+            if let Some(x) = sh.get_env(name) {
+                array.set("0", &x);
+            }
+            sh.set_array(name, array);
+        }
+
+        if cap.get(2).is_some() {
+            let (var, value) = match text.split_once('=') {
+                Some(x) => x,
+                None => continue,
+            };
+            if let Err(e) = shell::assign_variable(sh, var, value) {
+                let info = format!("cicada: declare: {}", e);
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                return cr;
+            }
+        }
+    }
+    cr
+}
+
+/// Show variables in the form of `declare -a arr=([0]="a" [1]="b")`,
+/// all arrays and *Shell Variables* if no names are given.
+fn show_vars(
+    sh: &Shell,
+    cl: &CommandLine,
+    cmd: &Command,
+    capture: bool,
+    names: &[String],
+) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let mut names = names.to_vec();
+    if names.is_empty() {
+        names = sh.envs.keys().chain(sh.arrays.keys()).cloned().collect();
+        names.sort();
+    }
+
+    let mut lines = Vec::new();
+    for name in names.iter() {
+        if let Some(array) = sh.get_array(name) {
+            let flag = if array.is_assoc() { "-A" } else { "-a" };
+            let items: Vec<String> = array
+                .keys()
+                .iter()
+                .zip(array.values().iter())
+                .map(|(k, v)| format!("[{}]={:?}", k, v))
+                .collect();
+            lines.push(format!("declare {} {}=({})", flag, name, items.join(" ")));
+        } else if let Some(value) = sh.get_env(name) {
+            lines.push(format!("declare -- {}={:?}", name, value));
+        } else {
+            let info = format!("cicada: declare: {}: not found", name);
+            print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+            return cr;
+        }
+    }
+    let info = lines.join("\n");
+    print_stdout_with_capture(&info, &mut cr, cl, cmd, capture);
+    cr
+}
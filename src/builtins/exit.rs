@@ -2,10 +2,11 @@
 use std::process;
 
 use crate::builtins::utils::print_stderr_with_capture;
+use crate::execute;
 use crate::shell::Shell;
 use crate::types::{Command, CommandLine, CommandResult};
 
-pub fn run(sh: &Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
     let mut cr = CommandResult::new();
     let tokens = cmd.tokens.clone();
     if tokens.len() > 2 {
@@ -18,6 +19,7 @@ pub fn run(sh: &Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> Comman
         let _code = &tokens[1].1;
         match _code.parse::<i32>() {
             Ok(x) => {
+                execute::run_exit_trap(sh);
                 process::exit(x);
             }
             Err(_) => {
@@ -38,6 +40,7 @@ pub fn run(sh: &Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> Comman
         }
     }
 
+    execute::run_exit_trap(sh);
     process::exit(0);
     cr
 }
@@ -0,0 +1,76 @@
+use crate::builtins::utils::print_stderr_with_capture;
+use crate::builtins::utils::print_stdout_with_capture;
+use crate::shell::Shell;
+use crate::types::{Command, CommandLine, CommandResult};
+
+/// Handles both `functions [name ...]` and `declare -f|-F [name ...]`.
+pub fn run(sh: &Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let tokens = cmd.tokens.clone();
+    let cname = tokens[0].1.clone();
+
+    let mut names_only = false;
+    let mut idx_names = 1;
+    if cname == "declare" {
+        // options are checked in the `declare` builtin
+        for (_, text) in tokens.iter().skip(1) {
+            if !text.starts_with('-') {
+                break;
+            }
+            names_only = names_only || text.contains('F');
+            idx_names += 1;
+        }
+    }
+    let names: Vec<String> = tokens.iter().skip(idx_names).map(|x| x.1.clone()).collect();
+
+    let mut funcs = Vec::new();
+    if names.is_empty() {
+        funcs = sh.get_func_list();
+    } else {
+        for name in names.iter() {
+            match sh.get_func(name) {
+                Some(body) => funcs.push((name.clone(), body)),
+                None => {
+                    let info = format!("cicada: {}: {}: not found", cname, name);
+                    print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                    return cr;
+                }
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    for (name, body) in funcs.iter() {
+        if names_only {
+            lines.push(name.clone());
+        } else {
+            lines.push(format_func(name, body));
+        }
+    }
+    let info = lines.join("\n");
+    print_stdout_with_capture(&info, &mut cr, cl, cmd, capture);
+    cr
+}
+
+/// Show function in the form of `name() { ... }`, with its body
+/// re-indented with 4 spaces.
+fn format_func(name: &str, body: &str) -> String {
+    let indent = body
+        .lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| x.chars().take_while(|c| c.is_whitespace()).count())
+        .min()
+        .unwrap_or(0);
+
+    let mut result = format!("{}() {{\n", name);
+    for line in body.lines() {
+        if line.trim().is_empty() {
+            result.push('\n');
+        } else {
+            let line: String = line.chars().skip(indent).collect();
+            result.push_str(&format!("    {}\n", line));
+        }
+    }
+    result.push('}');
+    result
+}
@@ -0,0 +1,74 @@
+use structopt::StructOpt;
+
+use crate::builtins::utils::print_stderr_with_capture;
+use crate::builtins::utils::print_stdout_with_capture;
+use crate::cmdcache;
+use crate::parsers;
+use crate::shell::Shell;
+use crate::types::{Command, CommandLine, CommandResult};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "hash", about = "Commands cached from PATH")]
+struct OptMain {
+    #[structopt(short, help = "Forget all commands and scan PATH again")]
+    rehash: bool,
+
+    #[structopt(short, help = "List all commands with their paths")]
+    list: bool,
+
+    #[structopt(name = "NAME", help = "Show the paths of these commands")]
+    names: Vec<String>,
+}
+
+pub fn run(_sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let tokens = &cmd.tokens;
+    let args = parsers::parser_line::tokens_to_args(tokens);
+    let show_usage = args.len() > 1 && (args[1] == "-h" || args[1] == "--help");
+
+    let opt = match OptMain::from_iter_safe(args) {
+        Ok(x) => x,
+        Err(e) => {
+            let info = format!("{}", e);
+            if show_usage {
+                print_stdout_with_capture(&info, &mut cr, cl, cmd, capture);
+                cr.status = 0;
+            } else {
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                cr.status = 1;
+            }
+            return cr;
+        }
+    };
+
+    if opt.rehash {
+        cmdcache::rehash();
+    }
+
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    if opt.list {
+        for (name, path) in cmdcache::list() {
+            lines.push(format!("{}\t{}", name, path));
+        }
+    }
+    for name in opt.names.iter() {
+        match cmdcache::find(name) {
+            Some(path) => lines.push(path),
+            None => errors.push(format!("cicada: hash: {}: not found", name)),
+        }
+    }
+    if !opt.rehash && !opt.list && opt.names.is_empty() {
+        for (dir, count) in cmdcache::dirs() {
+            lines.push(format!("{}\t{}", count, dir));
+        }
+    }
+
+    if !lines.is_empty() {
+        print_stdout_with_capture(&lines.join("\n"), &mut cr, cl, cmd, capture);
+    }
+    if !errors.is_empty() {
+        print_stderr_with_capture(&errors.join("\n"), &mut cr, cl, cmd, capture);
+    }
+    cr
+}
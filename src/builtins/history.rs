@@ -1,6 +1,9 @@
+use std::collections::HashMap;
+use std::fs;
 use std::path::Path;
 
-use rusqlite::Connection as Conn;
+use rusqlite::types::Value;
+use rusqlite::{params_from_iter, Connection as Conn};
 use structopt::StructOpt;
 
 use crate::builtins::utils::print_stderr_with_capture;
@@ -35,6 +38,24 @@ struct OptMain {
     #[structopt(short, long, default_value = "20")]
     limit: i32,
 
+    #[structopt(short, long, help = "Only show items with non-zero exit status")]
+    failed: bool,
+
+    #[structopt(long, help = "Only show items with this exit status")]
+    status: Option<i32>,
+
+    #[structopt(long, help = "Only show items taking at least these seconds")]
+    min_duration: Option<f64>,
+
+    #[structopt(
+        long,
+        help = "Only show items since this time, e.g. 2h, 7d, 2024-01-31"
+    )]
+    since: Option<String>,
+
+    #[structopt(long, help = "Only show items until this time")]
+    until: Option<String>,
+
     #[structopt(
         name = "PATTERN",
         default_value = "",
@@ -61,10 +82,73 @@ enum SubCommand {
         #[structopt(name = "ROWID", help = "Row IDs of item to delete")]
         rowid: Vec<usize>,
     },
+    #[structopt(about = "Export history items as JSON Lines or plain text")]
+    Export {
+        #[structopt(short, long, default_value = "jsonl", help = "jsonl or text")]
+        format: String,
+
+        #[structopt(short, long, help = "Write into this file instead of stdout")]
+        output: Option<String>,
+    },
+    #[structopt(about = "Import history items from cicada, bash, zsh or fish")]
+    Import {
+        #[structopt(
+            short,
+            long,
+            default_value = "auto",
+            help = "auto, jsonl, bash, zsh or fish"
+        )]
+        format: String,
+
+        #[structopt(name = "FILE", help = "History file to import")]
+        file: String,
+    },
+    #[structopt(about = "Show details of a history item")]
+    Show {
+        #[structopt(name = "ROWID", help = "Row ID of the item")]
+        rowid: i64,
+
+        #[structopt(short, long, help = "Show the captured output only")]
+        output: bool,
+    },
+    #[structopt(about = "Show most used, failed and slowest commands")]
+    Stats {
+        #[structopt(short, long, default_value = "10")]
+        limit: usize,
+    },
 }
 
 pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
     let mut cr = CommandResult::new();
+    let tokens = cmd.tokens.clone();
+    let args = parsers::parser_line::tokens_to_args(&tokens);
+
+    let show_usage = args.len() > 1 && (args[1] == "-h" || args[1] == "--help");
+    let opt = OptMain::from_iter_safe(args);
+
+    // importing could create the history file
+    if let Ok(OptMain {
+        cmd: Some(SubCommand::Import { format, file }),
+        ..
+    }) = &opt
+    {
+        match import_history(format, file) {
+            Ok((added, total)) => {
+                let info = format!(
+                    "imported {} items ({} duplicated skipped)",
+                    added,
+                    total - added
+                );
+                print_stdout_with_capture(&info, &mut cr, cl, cmd, capture);
+            }
+            Err(e) => {
+                let info = format!("history: import: {}", e);
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+            }
+        }
+        return cr;
+    }
+
     let hfile = history::get_history_file();
     let path = Path::new(hfile.as_str());
     if !path.exists() {
@@ -72,33 +156,30 @@ pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> Co
         print_stderr_with_capture(info, &mut cr, cl, cmd, capture);
         return cr;
     }
-    let conn = match Conn::open(&hfile) {
-        Ok(x) => x,
-        Err(e) => {
-            let info = format!("history: sqlite error: {:?}", e);
-            print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
-            return cr;
-        }
-    };
-
-    let tokens = cmd.tokens.clone();
-    let args = parsers::parser_line::tokens_to_args(&tokens);
 
-    let show_usage = args.len() > 1 && (args[1] == "-h" || args[1] == "--help");
-    let opt = OptMain::from_iter_safe(args);
     match opt {
         Ok(opt) => match opt.cmd {
             Some(SubCommand::Delete { rowid: rowids }) => {
-                let mut _count = 0;
-                for rowid in rowids {
-                    let _deleted = delete_history_item(&conn, rowid);
-                    if _deleted {
-                        _count += 1;
+                let result = history::with_conn(|conn, htable| {
+                    let mut _count = 0;
+                    for rowid in rowids {
+                        let _deleted = delete_history_item(conn, htable, rowid);
+                        if _deleted {
+                            _count += 1;
+                        }
+                    }
+                    Ok(_count)
+                });
+                match result {
+                    Ok(0) => {}
+                    Ok(_count) => {
+                        let info = format!("deleted {} items", _count);
+                        print_stdout_with_capture(&info, &mut cr, cl, cmd, capture);
+                    }
+                    Err(e) => {
+                        let info = format!("history: {}", e);
+                        print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
                     }
-                }
-                if _count > 0 {
-                    let info = format!("deleted {} items", _count);
-                    print_stdout_with_capture(&info, &mut cr, cl, cmd, capture);
                 }
                 cr
             }
@@ -110,8 +191,59 @@ pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> Co
                 add_history(sh, ts, &input);
                 cr
             }
+            Some(SubCommand::Export {
+                ref format,
+                ref output,
+            }) => {
+                let text = match export_history(sh, &opt, format) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        print_stderr_with_capture(&e, &mut cr, cl, cmd, capture);
+                        return cr;
+                    }
+                };
+                match output {
+                    Some(file) => {
+                        if let Err(e) = fs::write(file, text) {
+                            let info = format!("history: {}: {}", file, e);
+                            print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                        }
+                    }
+                    None => {
+                        if !text.is_empty() {
+                            print_stdout_with_capture(text.trim_end(), &mut cr, cl, cmd, capture);
+                        }
+                    }
+                }
+                cr
+            }
+            Some(SubCommand::Import { .. }) => cr,
+            Some(SubCommand::Show { rowid, output }) => {
+                match history::with_conn(|conn, htable| show_item(conn, htable, rowid, output)) {
+                    Ok(info) => {
+                        if !info.is_empty() {
+                            print_stdout_with_capture(&info, &mut cr, cl, cmd, capture);
+                        }
+                    }
+                    Err(e) => {
+                        let info = format!("history: show: {}", e);
+                        print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                    }
+                }
+                cr
+            }
+            Some(SubCommand::Stats { limit }) => {
+                let (str_out, str_err) = show_stats(sh, &opt, limit);
+                if !str_out.is_empty() {
+                    print_stdout_with_capture(&str_out, &mut cr, cl, cmd, capture);
+                }
+                if !str_err.is_empty() {
+                    print_stderr_with_capture(&str_err, &mut cr, cl, cmd, capture);
+                }
+                cr
+            }
             None => {
-                let (str_out, str_err) = list_current_history(sh, &conn, &opt);
+                let (str_out, str_err) = list_current_history(sh, &opt);
                 if !str_out.is_empty() {
                     print_stdout_with_capture(&str_out, &mut cr, cl, cmd, capture);
                 }
@@ -137,36 +269,81 @@ pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> Co
 
 fn add_history(sh: &Shell, ts: f64, input: &str) {
     let (tsb, tse) = (ts, ts + 1.0);
-    history::add_raw(sh, input, 0, tsb, tse);
+    history::add_raw(sh, input, 0, tsb, tse, "");
 }
 
-fn list_current_history(sh: &Shell, conn: &Conn, opt: &OptMain) -> (String, String) {
-    let mut result_stderr = String::new();
-    let result_stdout = String::new();
-
-    let history_table = history::get_history_table();
-    let mut sql = format!(
-        "SELECT ROWID, inp, tsb FROM {} WHERE ROWID > 0",
-        history_table
-    );
+/// Get the SQL conditions from the filter options, e.g. `AND rtn != ?`,
+/// along with the parameters of them.
+fn get_conditions(sh: &Shell, opt: &OptMain) -> Result<(String, Vec<Value>), String> {
+    let mut sql = String::new();
+    let mut params = Vec::new();
     if !opt.pattern.is_empty() {
-        sql = format!("{} AND inp LIKE '%{}%'", sql, opt.pattern)
+        sql.push_str(" AND inp LIKE ?");
+        params.push(Value::Text(format!("%{}%", opt.pattern)));
     }
     if opt.session {
-        sql = format!("{} AND sessionid = '{}'", sql, sh.session_id)
+        sql.push_str(" AND sessionid = ?");
+        params.push(Value::Text(sh.session_id.clone()));
     }
     if opt.pwd {
-        sql = format!("{} AND info like '%dir:{}|%'", sql, sh.current_dir)
+        sql.push_str(" AND cwd = ?");
+        params.push(Value::Text(sh.current_dir.clone()));
+    }
+    if opt.failed {
+        sql.push_str(" AND rtn != 0");
+    }
+    if let Some(status) = opt.status {
+        sql.push_str(" AND rtn = ?");
+        params.push(Value::Integer(status as i64));
     }
+    if let Some(seconds) = opt.min_duration {
+        sql.push_str(" AND duration >= ?");
+        params.push(Value::Real(seconds));
+    }
+    for (text, op) in [(&opt.since, ">="), (&opt.until, "<=")] {
+        if let Some(text) = text {
+            match ctime::parse_timestamp(text) {
+                Some(ts) => {
+                    sql.push_str(&format!(" AND tsb {} ?", op));
+                    params.push(Value::Real(ts));
+                }
+                None => return Err(format!("history: invalid time: {}", text)),
+            }
+        }
+    }
+    Ok((sql, params))
+}
 
-    if opt.asc {
-        sql = format!("{} ORDER BY tsb", sql);
-    } else {
-        sql = format!("{} order by tsb desc", sql);
+fn list_current_history(sh: &Shell, opt: &OptMain) -> (String, String) {
+    let (conditions, mut params) = match get_conditions(sh, opt) {
+        Ok(x) => x,
+        Err(e) => return (String::new(), e),
     };
-    sql = format!("{} limit {} ", sql, opt.limit);
+    let result = history::with_conn(|conn, htable| {
+        let mut sql = format!(
+            "SELECT ROWID, inp, tsb FROM {} WHERE ROWID > 0{}",
+            htable, conditions
+        );
+        if opt.asc {
+            sql = format!("{} ORDER BY tsb", sql);
+        } else {
+            sql = format!("{} order by tsb desc", sql);
+        };
+        sql = format!("{} limit ?", sql);
+        params.push(Value::Integer(opt.limit as i64));
+        Ok(list_history_rows(conn, &sql, &params, opt))
+    });
+    match result {
+        Ok(x) => x,
+        Err(e) => (String::new(), format!("history: {}", e)),
+    }
+}
 
-    let mut stmt = match conn.prepare(&sql) {
+fn list_history_rows(conn: &Conn, sql: &str, params: &[Value], opt: &OptMain) -> (String, String) {
+    let mut result_stderr = String::new();
+    let result_stdout = String::new();
+
+    let mut stmt = match conn.prepare(sql) {
         Ok(x) => x,
         Err(e) => {
             let info = format!("history: prepare select error: {:?}", e);
@@ -175,7 +352,7 @@ fn list_current_history(sh: &Shell, conn: &Conn, opt: &OptMain) -> (String, Stri
         }
     };
 
-    let mut rows = match stmt.query([]) {
+    let mut rows = match stmt.query(params_from_iter(params)) {
         Ok(x) => x,
         Err(e) => {
             let info = format!("history: query error: {:?}", e);
@@ -245,10 +422,240 @@ fn list_current_history(sh: &Shell, conn: &Conn, opt: &OptMain) -> (String, Stri
     (buffer, result_stderr)
 }
 
-fn delete_history_item(conn: &Conn, rowid: usize) -> bool {
-    let history_table = history::get_history_table();
-    let sql = format!("DELETE from {} where rowid = {}", history_table, rowid);
-    match conn.execute(&sql, []) {
+fn export_history(sh: &Shell, opt: &OptMain, format: &str) -> Result<String, String> {
+    if format != "jsonl" && format != "text" {
+        return Err(format!("history: export: unknown format: {}", format));
+    }
+    let (conditions, params) = get_conditions(sh, opt)?;
+    history::with_conn(|conn, htable| {
+        let sql = format!(
+            "SELECT inp, rtn, tsb, tse, sessionid, cwd, hostname, duration, tty, out
+             FROM {} WHERE ROWID > 0{} ORDER BY tsb",
+            htable, conditions
+        );
+        export_rows(conn, &sql, &params, format)
+    })
+    .map_err(|e| format!("history: {}", e))
+}
+
+fn export_rows(conn: &Conn, sql: &str, params: &[Value], format: &str) -> Result<String, String> {
+    let mut stmt = conn
+        .prepare(sql)
+        .map_err(|e| format!("prepare select error: {:?}", e))?;
+    let rows = stmt
+        .query_map(params_from_iter(params), |row| {
+            Ok(history::Item {
+                inp: row.get(0)?,
+                rtn: row.get::<_, Option<i32>>(1)?.unwrap_or(0),
+                tsb: row.get::<_, Option<f64>>(2)?.unwrap_or(0.0),
+                tse: row.get::<_, Option<f64>>(3)?.unwrap_or(0.0),
+                sessionid: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                cwd: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+                hostname: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                duration: row.get::<_, Option<f64>>(7)?.unwrap_or(0.0),
+                tty: row.get::<_, Option<String>>(8)?.unwrap_or_default(),
+                out: row.get::<_, Option<String>>(9)?.unwrap_or_default(),
+            })
+        })
+        .map_err(|e| format!("query error: {:?}", e))?;
+
+    let mut result = String::new();
+    for item in rows.flatten() {
+        if format == "text" {
+            result.push_str(&item.inp);
+        } else {
+            result.push_str(&item.to_json());
+        }
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// Returns the number of items added, and the number of items found.
+fn import_history(format: &str, file: &str) -> Result<(usize, usize), String> {
+    let bytes = fs::read(file).map_err(|e| format!("{}: {}", file, e))?;
+    let text = String::from_utf8_lossy(&bytes);
+    let format = match format {
+        "auto" => history::detect_format(&text),
+        x => x,
+    };
+    let items = match format {
+        "jsonl" => history::parse_jsonl_history(&text)?,
+        "bash" => {
+            let now = ctime::DateTime::now().unix_timestamp();
+            history::parse_bash_history(&text, now)
+        }
+        "zsh" => {
+            let bytes = history::unmetafy_zsh(&bytes);
+            history::parse_zsh_history(&String::from_utf8_lossy(&bytes))
+        }
+        "fish" => history::parse_fish_history(&text),
+        x => return Err(format!("unknown format: {}", x)),
+    };
+    let added = history::add_items(&items)?;
+    Ok((added, items.len()))
+}
+
+/// Show the most used commands, their failure rates, and the slowest
+/// commands in each directory.
+fn show_stats(sh: &Shell, opt: &OptMain, limit: usize) -> (String, String) {
+    let (conditions, params) = match get_conditions(sh, opt) {
+        Ok(x) => x,
+        Err(e) => return (String::new(), e),
+    };
+    let result = history::with_conn(|conn, htable| {
+        let sql = format!(
+            "SELECT inp, rtn, duration, cwd FROM {} WHERE ROWID > 0{} ORDER BY duration DESC",
+            htable, conditions
+        );
+        Ok(stats_rows(conn, &sql, &params, limit))
+    });
+    match result {
+        Ok(x) => x,
+        Err(e) => (String::new(), format!("history: {}", e)),
+    }
+}
+
+fn stats_rows(conn: &Conn, sql: &str, params: &[Value], limit: usize) -> (String, String) {
+    let mut stmt = match conn.prepare(sql) {
+        Ok(x) => x,
+        Err(e) => {
+            return (
+                String::new(),
+                format!("history: prepare select error: {:?}", e),
+            )
+        }
+    };
+    let rows = match stmt.query_map(params_from_iter(params), |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, Option<i32>>(1)?,
+            row.get::<_, Option<f64>>(2)?,
+            row.get::<_, Option<String>>(3)?,
+        ))
+    }) {
+        Ok(x) => x,
+        Err(e) => return (String::new(), format!("history: query error: {:?}", e)),
+    };
+
+    // command name -> (count, failed count)
+    let mut counts: HashMap<String, (usize, usize)> = HashMap::new();
+    // rows are ordered by duration, so the slowest come first
+    let mut slowest: Vec<(String, Vec<(f64, String)>)> = Vec::new();
+    for (inp, rtn, duration, cwd) in rows.flatten() {
+        let name = match inp.split_whitespace().next() {
+            Some(x) => x.to_string(),
+            None => continue,
+        };
+        let item = counts.entry(name).or_insert((0, 0));
+        item.0 += 1;
+        if rtn.unwrap_or(0) != 0 {
+            item.1 += 1;
+        }
+
+        let (duration, cwd) = match (duration, cwd) {
+            (Some(x), Some(y)) => (x, y),
+            _ => continue,
+        };
+        match slowest.iter_mut().find(|x| x.0 == cwd) {
+            Some(x) => {
+                if x.1.len() < 3 {
+                    x.1.push((duration, inp));
+                }
+            }
+            None => {
+                if slowest.len() < limit {
+                    slowest.push((cwd, vec![(duration, inp)]));
+                }
+            }
+        }
+    }
+
+    let mut counts: Vec<(String, (usize, usize))> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then(a.0.cmp(&b.0)));
+    counts.truncate(limit);
+    let width = counts.iter().map(|x| x.0.len()).max().unwrap_or(0);
+
+    let mut lines = vec!["Most used commands:".to_string()];
+    for (name, (count, _)) in counts.iter() {
+        lines.push(format!("  {:<width$}  {}", name, count, width = width));
+    }
+    lines.push(String::new());
+    lines.push("Failure rates:".to_string());
+    for (name, (count, failed)) in counts.iter() {
+        let rate = *failed as f64 * 100.0 / *count as f64;
+        lines.push(format!(
+            "  {:<width$}  {}/{} ({:.1}%)",
+            name,
+            failed,
+            count,
+            rate,
+            width = width
+        ));
+    }
+    lines.push(String::new());
+    lines.push("Slowest commands per directory:".to_string());
+    for (cwd, items) in slowest.iter() {
+        lines.push(format!("  {}", cwd));
+        for (duration, inp) in items.iter() {
+            lines.push(format!("    {:>8.3}s  {}", duration, inp));
+        }
+    }
+    (lines.join("\n"), String::new())
+}
+
+/// Returns the details of the item, or its captured output with `output`.
+fn show_item(conn: &Conn, htable: &str, rowid: i64, output: bool) -> Result<String, String> {
+    let sql = format!(
+        "SELECT inp, rtn, tsb, sessionid, cwd, hostname, duration, tty, out
+         FROM {} WHERE rowid = ?",
+        htable
+    );
+    let item = conn
+        .query_row(&sql, [rowid], |row| {
+            Ok(history::Item {
+                inp: row.get(0)?,
+                rtn: row.get::<_, Option<i32>>(1)?.unwrap_or(0),
+                tsb: row.get::<_, Option<f64>>(2)?.unwrap_or(0.0),
+                sessionid: row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                cwd: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                hostname: row.get::<_, Option<String>>(5)?.unwrap_or_default(),
+                duration: row.get::<_, Option<f64>>(6)?.unwrap_or(0.0),
+                tty: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+                out: row.get::<_, Option<String>>(8)?.unwrap_or_default(),
+                ..Default::default()
+            })
+        })
+        .map_err(|e| match e {
+            rusqlite::Error::QueryReturnedNoRows => format!("no such item: {}", rowid),
+            e => format!("query error: {:?}", e),
+        })?;
+
+    if output {
+        if item.out.is_empty() {
+            return Err(format!("no output captured for item {}", rowid));
+        }
+        return Ok(item.out.trim_end_matches('\n').to_string());
+    }
+
+    let dt = ctime::DateTime::from_timestamp(item.tsb);
+    let lines = vec![
+        format!("input:    {}", item.inp),
+        format!("time:     {}", dt),
+        format!("status:   {}", item.rtn),
+        format!("duration: {:.3}s", item.duration),
+        format!("cwd:      {}", item.cwd),
+        format!("hostname: {}", item.hostname),
+        format!("tty:      {}", item.tty),
+        format!("session:  {}", item.sessionid),
+        format!("output:   {} bytes", item.out.len()),
+    ];
+    Ok(lines.join("\n"))
+}
+
+fn delete_history_item(conn: &Conn, htable: &str, rowid: usize) -> bool {
+    let sql = format!("DELETE from {} where rowid = ?", htable);
+    match conn.execute(&sql, [rowid as i64]) {
         Ok(_) => true,
         Err(e) => {
             log!("history: error when delete: {:?}", e);
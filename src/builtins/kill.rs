@@ -0,0 +1,102 @@
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+
+use crate::builtins::utils::print_stderr_with_capture;
+use crate::builtins::utils::print_stdout_with_capture;
+use crate::jobc;
+use crate::shell::Shell;
+use crate::signals;
+use crate::types::{Command, CommandLine, CommandResult};
+
+fn list_signals() -> String {
+    let names: Vec<String> = Signal::iterator()
+        .map(|x| format!("{:>2}) {}", x as i32, x.as_str()))
+        .collect();
+    names.join("\n")
+}
+
+pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let args: Vec<String> = cmd.tokens.iter().skip(1).map(|x| x.1.clone()).collect();
+    if args.is_empty() {
+        let info = "cicada: kill: usage: kill [-s sigspec | -sigspec] pid | %job ...";
+        print_stderr_with_capture(info, &mut cr, cl, cmd, capture);
+        cr.status = 2;
+        return cr;
+    }
+
+    if args[0] == "-l" || args[0] == "-L" {
+        // e.g. `kill -l 15` gives `TERM`
+        if let Some(sig) = args.get(1).and_then(|x| signals::signal_from_name(x)) {
+            let info = sig.as_str().trim_start_matches("SIG");
+            print_stdout_with_capture(info, &mut cr, cl, cmd, capture);
+        } else {
+            print_stdout_with_capture(&list_signals(), &mut cr, cl, cmd, capture);
+        }
+        return cr;
+    }
+
+    let mut sig = Signal::SIGTERM;
+    let mut idx_targets = 0;
+    let sig_text = if args[0] == "-s" || args[0] == "-n" {
+        idx_targets = 2;
+        args.get(1).map(|x| x.as_str())
+    } else if args[0] != "--" && args[0].starts_with('-') {
+        idx_targets = 1;
+        Some(&args[0][1..])
+    } else {
+        if args[0] == "--" {
+            idx_targets = 1;
+        }
+        None
+    };
+    if let Some(text) = sig_text {
+        match signals::signal_from_name(text) {
+            Some(x) => sig = x,
+            None => {
+                let info = format!("cicada: kill: {}: invalid signal specification", text);
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                return cr;
+            }
+        }
+    }
+
+    for target in args.iter().skip(idx_targets) {
+        let result = if target.starts_with('%') {
+            match jobc::get_job_by_spec(sh, target) {
+                Some(job) => {
+                    let gid = Pid::from_raw(job.gid);
+                    let result = signal::killpg(gid, sig);
+                    // stopped jobs need to continue to handle the signal
+                    if job.status == "Stopped" && sig != Signal::SIGKILL && sig != Signal::SIGCONT
+                    {
+                        let _ = signal::killpg(gid, Signal::SIGCONT);
+                    }
+                    result
+                }
+                None => {
+                    let info = format!("cicada: kill: {}: no such job", target);
+                    print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                    continue;
+                }
+            }
+        } else {
+            match target.parse::<i32>() {
+                Ok(pid) => signal::kill(Pid::from_raw(pid), sig),
+                Err(_) => {
+                    let info = format!(
+                        "cicada: kill: {}: arguments must be process or job IDs",
+                        target
+                    );
+                    print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                    continue;
+                }
+            }
+        };
+        if let Err(e) = result {
+            let info = format!("cicada: kill: ({}) - {}", target, e.desc());
+            print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+        }
+    }
+    cr
+}
@@ -0,0 +1,43 @@
+use regex::Regex;
+
+use crate::parsers;
+
+use crate::builtins::utils::print_stderr_with_capture;
+use crate::shell::{self, Shell};
+use crate::types::{Command, CommandLine, CommandResult};
+
+pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let tokens = cmd.tokens.clone();
+
+    if sh.func_frames.is_empty() {
+        let info = "cicada: local: can only be used in a function";
+        print_stderr_with_capture(info, &mut cr, cl, cmd, capture);
+        return cr;
+    }
+
+    let re_name_ptn = Regex::new(r"^([a-zA-Z_][a-zA-Z0-9_]*)(?:=(.*))?$").unwrap();
+    for (_, text) in tokens.iter().skip(1) {
+        let cap = match re_name_ptn.captures(text) {
+            Some(x) => x,
+            None => {
+                let info = format!("cicada: local: `{}': not a valid identifier", text);
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                return cr;
+            }
+        };
+
+        let name = cap[1].to_string();
+        if shell::is_array_assignment(text) {
+            let info = format!("cicada: local: {}: arrays cannot be local", name);
+            print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+            return cr;
+        }
+        let value = match cap.get(2) {
+            Some(x) => parsers::parser_line::unquote(x.as_str()),
+            None => String::new(),
+        };
+        sh.set_local(&name, &value);
+    }
+    cr
+}
@@ -3,19 +3,28 @@ pub mod bg;
 pub mod cd;
 pub mod check;
 pub mod cinfo;
+pub mod complete;
+pub mod declare;
 pub mod exec;
 pub mod exit;
 pub mod export;
 pub mod fg;
+pub mod functions;
+pub mod hash;
 pub mod history;
 pub mod jobs;
+pub mod kill;
+pub mod local;
 pub mod minfd;
 pub mod read;
+pub mod r#return;
 pub mod set;
 pub mod source;
+pub mod trap;
 pub mod ulimit;
 pub mod unalias;
 pub mod unpath;
 pub mod unset;
 pub mod utils;
 pub mod vox;
+pub mod wait;
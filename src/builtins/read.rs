@@ -38,6 +38,12 @@ pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> Co
             buffer.push_str(&redirect_from.1);
             buffer.push('\n');
         }
+    } else if cmd.has_here_doc() {
+        if let Some(redirect_from) = &cmd.redirect_from {
+            let line = redirect_from.1.lines().next().unwrap_or("");
+            buffer.push_str(line);
+            buffer.push('\n');
+        }
     } else {
         match io::stdin().read_line(&mut buffer) {
             Ok(_) => {}
@@ -0,0 +1,41 @@
+use crate::builtins::utils::print_stderr_with_capture;
+use crate::shell::Shell;
+use crate::types::{Command, CommandLine, CommandResult};
+
+pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let tokens = cmd.tokens.clone();
+
+    if sh.func_frames.is_empty() {
+        let info = "cicada: return: can only `return' from a function";
+        print_stderr_with_capture(info, &mut cr, cl, cmd, capture);
+        return cr;
+    }
+
+    if tokens.len() > 2 {
+        let info = "cicada: return: too many arguments";
+        print_stderr_with_capture(info, &mut cr, cl, cmd, capture);
+        return cr;
+    }
+
+    let mut status = sh.previous_status;
+    if tokens.len() == 2 {
+        let _code = &tokens[1].1;
+        match _code.parse::<i32>() {
+            Ok(x) => {
+                status = x & 255;
+            }
+            Err(_) => {
+                let info = format!("cicada: return: {}: numeric argument required", _code);
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                status = 2;
+            }
+        }
+    }
+
+    if let Some(frame) = sh.func_frames.last_mut() {
+        frame.return_status = Some(status);
+    }
+    cr.status = status;
+    cr
+}
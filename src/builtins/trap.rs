@@ -0,0 +1,112 @@
+use nix::sys::signal::Signal;
+
+use crate::builtins::utils::print_stderr_with_capture;
+use crate::builtins::utils::print_stdout_with_capture;
+use crate::shell::Shell;
+use crate::signals;
+use crate::types::{Command, CommandLine, CommandResult};
+
+/// Get the name of signals given like `INT`, `SIGINT`, `int` or `2`,
+/// along with the signal. `EXIT` and `ERR` have no signals.
+fn parse_signal(text: &str) -> Option<(String, Option<Signal>)> {
+    let upper = text.to_uppercase();
+    let name = upper.trim_start_matches("SIG");
+    if name == "EXIT" || name == "0" {
+        return Some(("EXIT".to_string(), None));
+    }
+    if name == "ERR" {
+        return Some(("ERR".to_string(), None));
+    }
+
+    let sig = signals::signal_from_name(name)?;
+    // SIGCHLD is used for job control of the shell itself
+    if sig == Signal::SIGKILL || sig == Signal::SIGSTOP || sig == Signal::SIGCHLD {
+        return None;
+    }
+    let name = sig.as_str().trim_start_matches("SIG").to_string();
+    Some((name, Some(sig)))
+}
+
+/// The order of `trap -p` output, which is the same as Bash.
+fn signal_order(name: &str) -> i32 {
+    match parse_signal(name) {
+        Some((_, Some(sig))) => sig as i32,
+        _ if name == "EXIT" => 0,
+        _ => i32::MAX,
+    }
+}
+
+fn format_trap(name: &str, cmd: &str) -> String {
+    format!("trap -- '{}' {}", cmd.replace('\'', "'\\''"), name)
+}
+
+pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let args: Vec<String> = cmd.tokens.iter().skip(1).map(|x| x.1.clone()).collect();
+
+    if args.is_empty() || args[0] == "-p" {
+        let mut names: Vec<String> = if args.len() > 1 {
+            let mut names = Vec::new();
+            for text in args.iter().skip(1) {
+                match parse_signal(text) {
+                    Some((name, _)) => names.push(name),
+                    None => {
+                        let info = format!("cicada: trap: {}: invalid signal specification", text);
+                        print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                        return cr;
+                    }
+                }
+            }
+            names
+        } else {
+            let mut names: Vec<String> = sh.traps.keys().cloned().collect();
+            names.sort_by_key(|x| signal_order(x));
+            names
+        };
+        names.dedup();
+
+        let lines: Vec<String> = names
+            .iter()
+            .filter_map(|x| sh.traps.get(x).map(|cmd| format_trap(x, cmd)))
+            .collect();
+        if !lines.is_empty() {
+            print_stdout_with_capture(&lines.join("\n"), &mut cr, cl, cmd, capture);
+        }
+        return cr;
+    }
+
+    if args.len() < 2 {
+        let info = "cicada: trap: usage: trap [-p] ['command' | -] signal ...";
+        print_stderr_with_capture(info, &mut cr, cl, cmd, capture);
+        return cr;
+    }
+
+    // `trap - SIG` resets the handler
+    let handler = if args[0] == "-" {
+        None
+    } else {
+        Some(args[0].as_str())
+    };
+    for text in args.iter().skip(1) {
+        let (name, sig) = match parse_signal(text) {
+            Some(x) => x,
+            None => {
+                let info = format!("cicada: trap: {}: invalid signal specification", text);
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                return cr;
+            }
+        };
+        if let Some(sig) = sig {
+            signals::setup_trap_handler(sig, handler);
+        }
+        match handler {
+            Some(x) => {
+                sh.traps.insert(name, x.to_string());
+            }
+            None => {
+                sh.traps.remove(&name);
+            }
+        }
+    }
+    cr
+}
@@ -1,5 +1,5 @@
 use crate::builtins::utils::print_stderr_with_capture;
-use crate::shell::Shell;
+use crate::shell::{self, Shell};
 use crate::types::{Command, CommandLine, CommandResult};
 
 pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
@@ -13,6 +13,13 @@ pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> Co
     }
 
     let input = &tokens[1].1;
+    if let Some((name, subscript)) = shell::split_array_subscript(input) {
+        if let Err(e) = shell::remove_array_item(sh, name, subscript) {
+            let info = format!("cicada: unset: {}", e);
+            print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+        }
+        return cr;
+    }
     if !sh.remove_env(input) {
         let info = format!("cicada: unset: invalid varname: {:?}", input);
         print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
@@ -0,0 +1,74 @@
+use crate::builtins::utils::print_stderr_with_capture;
+use crate::jobc;
+use crate::shell::Shell;
+use crate::types::{Command, CommandLine, CommandResult};
+
+/// Wait all members of the job, returns the status of the last one.
+fn wait_job(sh: &mut Shell, gid: i32, pids: &[i32]) -> i32 {
+    let mut status = 0;
+    for pid in pids.iter() {
+        status = jobc::wait_bg_pid(sh, gid, *pid);
+        if status > 128
+            && sh
+                .get_job_by_gid(gid)
+                .is_some_and(|x| x.status == "Stopped")
+        {
+            break;
+        }
+    }
+    status
+}
+
+pub fn run(sh: &mut Shell, cl: &CommandLine, cmd: &Command, capture: bool) -> CommandResult {
+    let mut cr = CommandResult::new();
+    let tokens = cmd.tokens.clone();
+
+    if tokens.len() == 1 {
+        let mut ids: Vec<i32> = sh.jobs.keys().cloned().collect();
+        ids.sort();
+        for id in ids {
+            if let Some(job) = sh.get_job_by_id(id).cloned() {
+                wait_job(sh, job.gid, &job.pids);
+            }
+        }
+        return cr;
+    }
+
+    for (_, text) in tokens.iter().skip(1) {
+        if text.starts_with('%') {
+            match jobc::get_job_by_spec(sh, text) {
+                Some(job) => {
+                    cr.status = wait_job(sh, job.gid, &job.pids);
+                }
+                None => {
+                    let info = format!("cicada: wait: {}: no such job", text);
+                    print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                    cr.status = 127;
+                }
+            }
+            continue;
+        }
+
+        let pid = match text.parse::<i32>() {
+            Ok(x) if x > 0 => x,
+            _ => {
+                let info = format!("cicada: wait: `{}': not a pid or valid job spec", text);
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                cr.status = 2;
+                return cr;
+            }
+        };
+        let job = sh.jobs.values().find(|x| x.pids.contains(&pid)).cloned();
+        match job {
+            Some(job) => {
+                cr.status = jobc::wait_bg_pid(sh, job.gid, pid);
+            }
+            None => {
+                let info = format!("cicada: wait: pid {} is not a child of this shell", pid);
+                print_stderr_with_capture(&info, &mut cr, cl, cmd, capture);
+                cr.status = 127;
+            }
+        }
+    }
+    cr
+}
@@ -0,0 +1,352 @@
+//! Arithmetic expansion, i.e. `$((...))`, which works on integers and
+//! shell variables, with most operators of C (the same as Bash).
+use pest::iterators::{Pair, Pairs};
+use pest::pratt_parser::{Assoc, Op, PrattParser};
+use pest::Parser;
+
+use crate::shell::Shell;
+
+#[derive(Parser)]
+#[grammar = "calculator/arith.pest"]
+struct Arith;
+
+lazy_static! {
+    static ref PRATT_PARSER: PrattParser<Rule> = {
+        use Assoc::*;
+        use Rule::*;
+
+        PrattParser::new()
+            .op(Op::infix(lor, Left))
+            .op(Op::infix(land, Left))
+            .op(Op::infix(bor, Left))
+            .op(Op::infix(bxor, Left))
+            .op(Op::infix(band, Left))
+            .op(Op::infix(eq, Left) | Op::infix(ne, Left))
+            .op(Op::infix(le, Left)
+                | Op::infix(ge, Left)
+                | Op::infix(lt, Left)
+                | Op::infix(gt, Left))
+            .op(Op::infix(shl, Left) | Op::infix(shr, Left))
+            .op(Op::infix(add, Left) | Op::infix(sub, Left))
+            .op(Op::infix(mul, Left) | Op::infix(div, Left) | Op::infix(rem, Left))
+            .op(Op::infix(pow, Right))
+            .op(Op::prefix(neg) | Op::prefix(pos) | Op::prefix(not) | Op::prefix(bnot))
+    };
+}
+
+// variables could hold expressions too, e.g. `a=b+1`
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug)]
+enum Node {
+    Num(i64),
+    Var(String),
+    // `x = 1`, `x += 1` etc, with the operator part without `=`
+    Assign(String, String, Box<Node>),
+    // `++x`, `x--` etc, with the delta and whether it's the prefix form
+    IncDec(String, i64, bool),
+    Prefix(String, Box<Node>),
+    Infix(Box<Node>, String, Box<Node>),
+    Ternary(Box<Node>, Box<Node>, Box<Node>),
+    Seq(Vec<Node>),
+}
+
+/// Evaluate the arithmetic expression, variables used in it would be
+/// read from, and assigned into the shell.
+pub fn eval(sh: &mut Shell, expr: &str) -> Result<i64, String> {
+    eval_with_depth(sh, expr, 0)
+}
+
+fn eval_with_depth(sh: &mut Shell, expr: &str, depth: usize) -> Result<i64, String> {
+    if depth > MAX_DEPTH {
+        return Err("expression recursion level exceeded".to_string());
+    }
+    if expr.trim().is_empty() {
+        return Ok(0);
+    }
+
+    let pairs = match Arith::parse(Rule::arithmetic, expr) {
+        Ok(x) => x,
+        Err(_) => return Err(format!("{}: syntax error in expression", expr.trim())),
+    };
+    let mut nodes = Vec::new();
+    for pair in pairs {
+        if pair.as_rule() == Rule::exprs {
+            nodes.push(build_exprs(pair)?);
+        }
+    }
+    match nodes.pop() {
+        Some(node) => eval_node(sh, &node, depth),
+        None => Ok(0),
+    }
+}
+
+/// Parse number literals, `0x` prefixed ones are hexadecimal,
+/// and other `0` prefixed ones are octal.
+fn parse_num(text: &str) -> Result<i64, String> {
+    let result = if text.starts_with("0x") || text.starts_with("0X") {
+        i64::from_str_radix(&text[2..], 16)
+    } else if text.len() > 1 && text.starts_with('0') {
+        i64::from_str_radix(&text[1..], 8)
+    } else {
+        text.parse::<i64>()
+    };
+    result.map_err(|_| format!("{}: value too great for base", text))
+}
+
+fn build_exprs(pair: Pair<Rule>) -> Result<Node, String> {
+    let mut nodes = Vec::new();
+    for p in pair.into_inner() {
+        nodes.push(build_expr(p)?);
+    }
+    if nodes.len() == 1 {
+        return Ok(nodes.remove(0));
+    }
+    Ok(Node::Seq(nodes))
+}
+
+fn build_expr(pair: Pair<Rule>) -> Result<Node, String> {
+    let mut inner = pair.into_inner();
+    let binary = match inner.next() {
+        Some(x) => build_binary(x.into_inner())?,
+        None => return Err("syntax error: operand expected".to_string()),
+    };
+    match (inner.next(), inner.next()) {
+        (Some(yes), Some(no)) => Ok(Node::Ternary(
+            Box::new(binary),
+            Box::new(build_expr(yes)?),
+            Box::new(build_expr(no)?),
+        )),
+        _ => Ok(binary),
+    }
+}
+
+fn build_binary(pairs: Pairs<Rule>) -> Result<Node, String> {
+    PRATT_PARSER
+        .map_primary(build_primary)
+        .map_prefix(|op, rhs| Ok(Node::Prefix(op.as_str().to_string(), Box::new(rhs?))))
+        .map_infix(|lhs, op, rhs| {
+            Ok(Node::Infix(
+                Box::new(lhs?),
+                op.as_str().to_string(),
+                Box::new(rhs?),
+            ))
+        })
+        .parse(pairs)
+}
+
+fn build_primary(pair: Pair<Rule>) -> Result<Node, String> {
+    match pair.as_rule() {
+        Rule::num => Ok(Node::Num(parse_num(pair.as_str())?)),
+        Rule::var => Ok(Node::Var(get_name(pair))),
+        Rule::exprs => build_exprs(pair),
+        Rule::assign => {
+            let mut inner = pair.into_inner();
+            let (name, op, expr) = match (inner.next(), inner.next(), inner.next()) {
+                (Some(a), Some(b), Some(c)) => (a, b, c),
+                _ => return Err("syntax error in assignment".to_string()),
+            };
+            let op = op.as_str().trim_end_matches('=').to_string();
+            Ok(Node::Assign(
+                name.as_str().to_string(),
+                op,
+                Box::new(build_expr(expr)?),
+            ))
+        }
+        Rule::pre_incdec | Rule::post_incdec => {
+            let is_prefix = pair.as_rule() == Rule::pre_incdec;
+            let mut name = String::new();
+            let mut delta = 1;
+            for p in pair.into_inner() {
+                if p.as_rule() == Rule::name {
+                    name = p.as_str().to_string();
+                } else if p.as_str() == "--" {
+                    delta = -1;
+                }
+            }
+            Ok(Node::IncDec(name, delta, is_prefix))
+        }
+        _ => Err(format!("{}: syntax error in expression", pair.as_str())),
+    }
+}
+
+fn get_name(pair: Pair<Rule>) -> String {
+    for p in pair.into_inner() {
+        if p.as_rule() == Rule::name {
+            return p.as_str().to_string();
+        }
+    }
+    String::new()
+}
+
+fn get_var(sh: &mut Shell, name: &str, depth: usize) -> Result<i64, String> {
+    let value = sh.get_env(name).unwrap_or_default();
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(0);
+    }
+    if let Ok(x) = parse_num(value) {
+        return Ok(x);
+    }
+    eval_with_depth(sh, value, depth + 1)
+}
+
+fn eval_node(sh: &mut Shell, node: &Node, depth: usize) -> Result<i64, String> {
+    match node {
+        Node::Num(x) => Ok(*x),
+        Node::Var(name) => get_var(sh, name, depth),
+        Node::Assign(name, op, expr) => {
+            let rhs = eval_node(sh, expr, depth)?;
+            let value = if op.is_empty() {
+                rhs
+            } else {
+                let lhs = get_var(sh, name, depth)?;
+                calculate(lhs, op, rhs)?
+            };
+            sh.set_env(name, &value.to_string());
+            Ok(value)
+        }
+        Node::IncDec(name, delta, is_prefix) => {
+            let old = get_var(sh, name, depth)?;
+            let new = old.wrapping_add(*delta);
+            sh.set_env(name, &new.to_string());
+            Ok(if *is_prefix { new } else { old })
+        }
+        Node::Prefix(op, rhs) => {
+            let rhs = eval_node(sh, rhs, depth)?;
+            match op.as_str() {
+                "-" => Ok(rhs.wrapping_neg()),
+                "!" => Ok((rhs == 0) as i64),
+                "~" => Ok(!rhs),
+                _ => Ok(rhs),
+            }
+        }
+        Node::Infix(lhs, op, rhs) => {
+            let lhs = eval_node(sh, lhs, depth)?;
+            // do not evaluate the right side when we already know the
+            // result, it may have side effects, e.g. `x && y++`
+            if op == "&&" && lhs == 0 {
+                return Ok(0);
+            }
+            if op == "||" && lhs != 0 {
+                return Ok(1);
+            }
+            let rhs = eval_node(sh, rhs, depth)?;
+            calculate(lhs, op, rhs)
+        }
+        Node::Ternary(cond, yes, no) => {
+            if eval_node(sh, cond, depth)? != 0 {
+                eval_node(sh, yes, depth)
+            } else {
+                eval_node(sh, no, depth)
+            }
+        }
+        Node::Seq(nodes) => {
+            let mut result = 0;
+            for x in nodes {
+                result = eval_node(sh, x, depth)?;
+            }
+            Ok(result)
+        }
+    }
+}
+
+fn calculate(lhs: i64, op: &str, rhs: i64) -> Result<i64, String> {
+    let result = match op {
+        "+" => lhs.wrapping_add(rhs),
+        "-" => lhs.wrapping_sub(rhs),
+        "*" => lhs.wrapping_mul(rhs),
+        "/" | "%" => {
+            if rhs == 0 {
+                return Err("division by 0".to_string());
+            }
+            if op == "/" {
+                lhs.wrapping_div(rhs)
+            } else {
+                lhs.wrapping_rem(rhs)
+            }
+        }
+        "**" => {
+            if rhs < 0 {
+                return Err("exponent less than 0".to_string());
+            }
+            lhs.wrapping_pow(rhs.min(u32::MAX as i64) as u32)
+        }
+        "<<" => lhs.wrapping_shl(rhs as u32),
+        ">>" => lhs.wrapping_shr(rhs as u32),
+        "<=" => (lhs <= rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "&&" => (lhs != 0 && rhs != 0) as i64,
+        "||" => (lhs != 0 || rhs != 0) as i64,
+        "&" => lhs & rhs,
+        "^" => lhs ^ rhs,
+        "|" => lhs | rhs,
+        _ => return Err(format!("{}: unknown operator", op)),
+    };
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::eval;
+    use crate::shell::Shell;
+
+    fn _eval(sh: &mut Shell, expr: &str) -> i64 {
+        match eval(sh, expr) {
+            Ok(x) => x,
+            Err(e) => panic!("failed to eval {:?}: {}", expr, e),
+        }
+    }
+
+    #[test]
+    fn test_eval_literals() {
+        let mut sh = Shell::new();
+        assert_eq!(_eval(&mut sh, "1 + 2 * 3"), 7);
+        assert_eq!(_eval(&mut sh, "(1 + 2) * 3"), 9);
+        assert_eq!(_eval(&mut sh, "7 / 2"), 3);
+        assert_eq!(_eval(&mut sh, "7 % 3"), 1);
+        assert_eq!(_eval(&mut sh, "2 ** 10"), 1024);
+        assert_eq!(_eval(&mut sh, "-2 ** 2"), 4);
+        assert_eq!(_eval(&mut sh, "0x1f + 010"), 39);
+        assert_eq!(_eval(&mut sh, "1 << 4 | 1"), 17);
+        assert_eq!(_eval(&mut sh, "6 & 3 ^ 1"), 3);
+        assert_eq!(_eval(&mut sh, "~0"), -1);
+        assert_eq!(_eval(&mut sh, "!0 && 3 > 2"), 1);
+        assert_eq!(_eval(&mut sh, "2 <= 1 || 1 != 1"), 0);
+        assert_eq!(_eval(&mut sh, "1 ? 10 : 20"), 10);
+        assert_eq!(_eval(&mut sh, "0 ? 10 : 1 ? 20 : 30"), 20);
+        assert_eq!(_eval(&mut sh, ""), 0);
+
+        assert!(eval(&mut sh, "1 / 0").is_err());
+        assert!(eval(&mut sh, "08").is_err());
+        assert!(eval(&mut sh, "1 +").is_err());
+        assert!(eval(&mut sh, "1 = 2").is_err());
+    }
+
+    #[test]
+    fn test_eval_variables() {
+        let mut sh = Shell::new();
+        sh.set_env("arith_a", "5");
+        assert_eq!(_eval(&mut sh, "arith_a + 1"), 6);
+        assert_eq!(_eval(&mut sh, "$arith_a * ${arith_a}"), 25);
+        assert_eq!(_eval(&mut sh, "arith_none + 1"), 1);
+
+        assert_eq!(_eval(&mut sh, "arith_a++"), 5);
+        assert_eq!(sh.get_env("arith_a"), Some("6".to_string()));
+        assert_eq!(_eval(&mut sh, "--arith_a"), 5);
+        assert_eq!(_eval(&mut sh, "arith_a += 10"), 15);
+        assert_eq!(_eval(&mut sh, "arith_a <<= 1"), 30);
+        assert_eq!(_eval(&mut sh, "arith_b = arith_a = 2, arith_b + 1"), 3);
+        assert_eq!(sh.get_env("arith_a"), Some("2".to_string()));
+
+        assert_eq!(_eval(&mut sh, "0 && arith_a++"), 0);
+        assert_eq!(sh.get_env("arith_a"), Some("2".to_string()));
+
+        sh.set_env("arith_c", "arith_a * 3");
+        assert_eq!(_eval(&mut sh, "arith_c + 1"), 7);
+    }
+}
@@ -5,6 +5,8 @@ use pest::iterators::{Pair, Pairs};
 use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest::Parser;
 
+pub mod arith;
+
 #[derive(Parser)]
 #[grammar = "calculator/grammar.pest"]
 struct Calculator;
@@ -0,0 +1,218 @@
+//! The cache of commands found in `$PATH`, which is shared by syntax
+//! highlighting and completion. A directory is scanned again when its
+//! mtime changes, and the cache follows the changes of `$PATH`.
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[derive(Debug, Default)]
+struct DirCache {
+    // `None` for directories not scanned yet, or not existing
+    mtime: Option<SystemTime>,
+    names: HashSet<String>,
+}
+
+#[derive(Debug, Default)]
+struct Cache {
+    path_var: String,
+    dirs: Vec<(PathBuf, DirCache)>,
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache::default());
+}
+
+fn scan_dir(dir: &PathBuf) -> HashSet<String> {
+    let mut names = HashSet::new();
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let metadata = match entry.metadata() {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            if metadata.is_dir() || metadata.permissions().mode() & 0o111 == 0 {
+                continue;
+            }
+            if let Ok(name) = entry.file_name().into_string() {
+                names.insert(name);
+            }
+        }
+    }
+    names
+}
+
+impl Cache {
+    /// Follow the value of `$PATH`, and scan the directories changed.
+    fn update(&mut self, path_var: &str) {
+        if path_var != self.path_var {
+            let mut old: HashMap<PathBuf, DirCache> = self.dirs.drain(..).collect();
+            let mut seen = HashSet::new();
+            for dir in env::split_paths(path_var) {
+                if dir.as_os_str().is_empty() || !seen.insert(dir.clone()) {
+                    continue;
+                }
+                let cache = old.remove(&dir).unwrap_or_default();
+                self.dirs.push((dir, cache));
+            }
+            self.path_var = path_var.to_string();
+        }
+
+        for (dir, cache) in self.dirs.iter_mut() {
+            let mtime = fs::metadata(&dir).and_then(|x| x.modified()).ok();
+            if mtime != cache.mtime {
+                cache.names = scan_dir(dir);
+                cache.mtime = mtime;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        for (_, cache) in self.dirs.iter_mut() {
+            *cache = DirCache::default();
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<String> {
+        self.dirs
+            .iter()
+            .find(|x| x.1.names.contains(name))
+            .map(|x| x.0.join(name).to_string_lossy().to_string())
+    }
+
+    /// Commands with the prefix in the order of `$PATH`, without duplicates.
+    fn commands_with_prefix(&self, prefix: &str) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut result = Vec::new();
+        for (_, cache) in self.dirs.iter() {
+            let mut names: Vec<&String> = cache
+                .names
+                .iter()
+                .filter(|x| x.starts_with(prefix) && !seen.contains(*x))
+                .collect();
+            names.sort();
+            for name in names {
+                seen.insert(name.clone());
+                result.push(name.clone());
+            }
+        }
+        result
+    }
+}
+
+fn with_cache<T, F: FnOnce(&mut Cache) -> T>(f: F) -> T {
+    let mut cache = match CACHE.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+    cache.update(&env::var("PATH").unwrap_or_default());
+    f(&mut cache)
+}
+
+/// Scan the directories in `$PATH` which are changed.
+pub fn refresh() {
+    with_cache(|_| ());
+}
+
+/// Forget all the commands and scan `$PATH` again, e.g. `hash -r`.
+pub fn rehash() {
+    with_cache(|cache| {
+        cache.clear();
+        cache.update(&env::var("PATH").unwrap_or_default());
+    });
+}
+
+pub fn contains(name: &str) -> bool {
+    with_cache(|cache| cache.dirs.iter().any(|x| x.1.names.contains(name)))
+}
+
+/// Get the full path of the command, which is the first one in `$PATH`.
+pub fn find(name: &str) -> Option<String> {
+    with_cache(|cache| cache.find(name))
+}
+
+pub fn commands_with_prefix(prefix: &str) -> Vec<String> {
+    with_cache(|cache| cache.commands_with_prefix(prefix))
+}
+
+/// All the commands with their full paths, sorted by names.
+pub fn list() -> Vec<(String, String)> {
+    with_cache(|cache| {
+        let mut result: Vec<(String, String)> = cache
+            .commands_with_prefix("")
+            .into_iter()
+            .filter_map(|x| cache.find(&x).map(|y| (x, y)))
+            .collect();
+        result.sort();
+        result
+    })
+}
+
+/// The directories in `$PATH` with number of commands in them.
+pub fn dirs() -> Vec<(String, usize)> {
+    with_cache(|cache| {
+        cache
+            .dirs
+            .iter()
+            .map(|x| (x.0.to_string_lossy().to_string(), x.1.names.len()))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn touch_exec(path: &PathBuf) {
+        fs::write(path, "").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_cache_update() {
+        let base = env::temp_dir().join(format!("cicada-cmdcache-{}", std::process::id()));
+        let (dir1, dir2) = (base.join("a"), base.join("b"));
+        fs::create_dir_all(&dir1).unwrap();
+        fs::create_dir_all(&dir2).unwrap();
+        touch_exec(&dir1.join("foo"));
+        touch_exec(&dir2.join("foo"));
+        touch_exec(&dir2.join("fob"));
+        fs::write(dir1.join("not-exec"), "").unwrap();
+
+        let mut cache = Cache::default();
+        let path_var = format!("{}:{}", dir1.display(), dir2.display());
+        cache.update(&path_var);
+        assert_eq!(cache.commands_with_prefix("fo"), vec!["foo", "fob"]);
+        assert_eq!(
+            cache.find("foo"),
+            Some(dir1.join("foo").display().to_string())
+        );
+        assert_eq!(cache.find("not-exec"), None);
+
+        // new commands are found after the mtime of the dir changed
+        touch_exec(&dir1.join("bar"));
+        let mtime = SystemTime::now() + Duration::from_secs(1);
+        fs::File::open(&dir1).unwrap().set_modified(mtime).unwrap();
+        cache.update(&path_var);
+        assert!(cache.find("bar").is_some());
+
+        // follow changes of PATH
+        cache.update(&dir2.display().to_string());
+        assert_eq!(cache.find("bar"), None);
+        assert_eq!(
+            cache.find("foo"),
+            Some(dir2.join("foo").display().to_string())
+        );
+
+        cache.clear();
+        assert_eq!(cache.find("foo"), None);
+        cache.update(&dir2.display().to_string());
+        assert!(cache.find("foo").is_some());
+
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
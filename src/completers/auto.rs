@@ -0,0 +1,524 @@
+//! Completions generated from man pages, or `--help` outputs of compiled
+//! binaries without man pages, for commands without any completion rules. Flags and subcommands are
+//! extracted on first use, and cached as YAML files in the dots format
+//! under `<completer-dir>/auto/`, which are generated again when the
+//! command is updated.
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use lineread::complete::{Completer, Completion};
+use lineread::prompter::Prompter;
+use lineread::terminal::Terminal;
+use regex::Regex;
+
+use crate::cmdcache;
+use crate::completers::dots;
+use crate::libs::prefix;
+use crate::shell;
+use crate::signals;
+use crate::tools;
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_OUTPUT: u64 = 256 * 1024;
+
+/// Performs completion with flags and subcommands found in the
+/// help messages of commands
+pub struct AutoCompleter {
+    pub sh: Arc<shell::Shell>,
+}
+
+impl<Term: Terminal> Completer<Term> for AutoCompleter {
+    fn complete(
+        &self,
+        word: &str,
+        reader: &Prompter<Term>,
+        _start: usize,
+        _end: usize,
+    ) -> Option<Vec<Completion>> {
+        let line = reader.buffer();
+        let cmd = prefix::get_effective_command(line)?;
+        let file = get_cache_file(&cmd)?;
+        let mut res = dots::complete_file(&self.sh, line, word, &file);
+        // do not list all the flags for `ls <Tab>`
+        if !word.starts_with('-') {
+            res.retain(|x| !x.completion.starts_with('-'));
+        }
+        Some(res)
+    }
+}
+
+/// Generated completions can be turned off with `CICADA_AUTO_COMPLETION=0`.
+pub fn is_enabled() -> bool {
+    env::var("CICADA_AUTO_COMPLETION").map_or(true, |x| x != "0")
+}
+
+fn get_cache_dir() -> String {
+    format!("{}/auto", tools::get_user_completer_dir())
+}
+
+/// The cached completion file of the command, which is generated if
+/// missing or older than the command itself.
+fn get_cache_file(cmd: &str) -> Option<String> {
+    if cmd.contains('/') || tools::is_builtin(cmd) {
+        return None;
+    }
+    let path = cmdcache::find(cmd)?;
+    let dir = get_cache_dir();
+    let file = format!("{}/{}.yaml", dir, cmd);
+    let modified = |x: &str| fs::metadata(x).and_then(|m| m.modified()).ok();
+    if let (Some(cached), Some(updated)) = (modified(&file), modified(&path)) {
+        if cached >= updated {
+            return Some(file);
+        }
+    }
+
+    let text = generate(cmd, &path);
+    if let Err(e) = fs::create_dir_all(&dir).and_then(|_| fs::write(&file, text)) {
+        log!("auto completion: write {} error: {:?}", file, e);
+        return None;
+    }
+    Some(file)
+}
+
+/// Whether the file is a compiled executable (ELF or Mach-O), rather than
+/// a script, whose `--help` could be anything.
+fn is_compiled_binary(path: &str) -> bool {
+    let mut magic = [0u8; 4];
+    let ok = fs::File::open(path).and_then(|mut f| f.read_exact(&mut magic));
+    if ok.is_err() {
+        return false;
+    }
+    matches!(
+        magic,
+        [0x7f, b'E', b'L', b'F']
+            | [0xfe, 0xed, 0xfa, 0xce]
+            | [0xfe, 0xed, 0xfa, 0xcf]
+            | [0xce, 0xfa, 0xed, 0xfe]
+            | [0xcf, 0xfa, 0xed, 0xfe]
+            | [0xca, 0xfe, 0xba, 0xbe]
+    )
+}
+
+/// Extract completions of the command into a YAML file in dots format.
+/// The man page is used if there is one, or else the output of
+/// `<cmd> --help` for compiled binaries.
+fn generate(cmd: &str, path: &str) -> String {
+    let (text, source) = match find_man_page(cmd) {
+        Some(x) => (roff_to_text(&read_man_page(&x)), "the man page".to_string()),
+        None if is_compiled_binary(path) => (
+            run_sandboxed(&[path, "--help"]),
+            format!("`{} --help`", cmd),
+        ),
+        // scripts are not run
+        None => (String::new(), "nothing, there is no man page".to_string()),
+    };
+    let (flags, subcmds) = parse_help(&text);
+
+    let quote = |x: &str| format!("'{}'", x.replace('\'', "''"));
+    let mut result = format!("# generated by cicada from {}\n", source);
+    for x in subcmds.iter().filter(|x| x.as_str() != cmd) {
+        result.push_str(&format!("- {}:\n", quote(x)));
+    }
+    for x in flags.iter() {
+        result.push_str(&format!("- {}\n", quote(x)));
+    }
+    result
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(x) = pipe {
+            let _ = x.take(MAX_OUTPUT).read_to_end(&mut buf);
+        }
+        buf
+    })
+}
+
+/// Run the command in a new session, without stdin, and with a file
+/// size limit of zero. It's killed after a short timeout.
+/// Returns its stdout and stderr. It's not a sandbox: the command can
+/// still create, truncate or remove files, talk to the network etc.
+fn run_sandboxed(args: &[&str]) -> String {
+    let mut command = Command::new(args[0]);
+    command
+        .args(&args[1..])
+        .current_dir(env::temp_dir())
+        .env("PAGER", "cat")
+        .env("MANPAGER", "cat")
+        .env("GIT_PAGER", "cat")
+        .env("TERM", "dumb")
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            let zero = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            let cpu = libc::rlimit {
+                rlim_cur: 2,
+                rlim_max: 2,
+            };
+            libc::setrlimit(libc::RLIMIT_FSIZE, &zero);
+            libc::setrlimit(libc::RLIMIT_CORE, &zero);
+            libc::setrlimit(libc::RLIMIT_CPU, &cpu);
+            Ok(())
+        });
+    }
+
+    // do not let the SIGCHLD handler reap it
+    signals::block_signals();
+    let mut child = match command.spawn() {
+        Ok(x) => x,
+        Err(e) => {
+            signals::unblock_signals();
+            log!("auto completion: run {:?} error: {:?}", args, e);
+            return String::new();
+        }
+    };
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    let deadline = Instant::now() + TIMEOUT;
+    loop {
+        match child.try_wait() {
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Ok(Some(_)) | Err(_) => break,
+            Ok(None) => {
+                log!("auto completion: {:?} timed out", args);
+                let _ = child.kill();
+                let _ = child.wait();
+                break;
+            }
+        }
+    }
+    // also the processes it left behind
+    unsafe {
+        libc::kill(-(child.id() as i32), libc::SIGKILL);
+    }
+    signals::unblock_signals();
+
+    let mut output = stdout.join().unwrap_or_default();
+    output.extend(stderr.join().unwrap_or_default());
+    String::from_utf8_lossy(&output).to_string()
+}
+
+fn find_man_page(cmd: &str) -> Option<String> {
+    let dirs = match env::var("MANPATH") {
+        Ok(x) if !x.is_empty() => x.split(':').map(|x| x.to_string()).collect(),
+        _ => vec![
+            "/usr/local/share/man".to_string(),
+            "/usr/share/man".to_string(),
+            "/usr/local/man".to_string(),
+            "/opt/homebrew/share/man".to_string(),
+        ],
+    };
+    for dir in dirs.iter().filter(|x| !x.is_empty()) {
+        for section in ["1", "8", "6"] {
+            for ext in ["", ".gz"] {
+                let file = format!("{}/man{}/{}.{}{}", dir, section, cmd, section, ext);
+                if Path::new(&file).exists() {
+                    return Some(file);
+                }
+            }
+        }
+    }
+    None
+}
+
+fn read_man_page(file: &str) -> String {
+    let text = if file.ends_with(".gz") {
+        run_sandboxed(&["gzip", "-dc", file])
+    } else {
+        fs::read_to_string(file).unwrap_or_default()
+    };
+
+    // follow pages like `.so man1/other.1`
+    if let Some(x) = text.trim().strip_prefix(".so ") {
+        let root = Path::new(file).parent().and_then(|x| x.parent());
+        if let Some(root) = root {
+            let target = root.join(x.trim()).to_string_lossy().to_string();
+            for ext in ["", ".gz"] {
+                let path = format!("{}{}", target, ext);
+                if Path::new(&path).exists() {
+                    return if ext.is_empty() {
+                        fs::read_to_string(&path).unwrap_or_default()
+                    } else {
+                        run_sandboxed(&["gzip", "-dc", &path])
+                    };
+                }
+            }
+        }
+    }
+    text
+}
+
+fn unescape_roff(text: &str) -> String {
+    lazy_static! {
+        static ref RE_FONT: Regex = Regex::new(r"\\f(\[[^\]]*\]|\(..|.)").unwrap();
+        static ref RE_SPECIAL: Regex = Regex::new(r"\\(\*?\(..|\*.|\[[^\]]*\]|&|c)").unwrap();
+    }
+    let text = RE_FONT.replace_all(text, "");
+    let text = RE_SPECIAL.replace_all(&text, "");
+    text.replace("\\-", "-")
+        .replace("\\ ", " ")
+        .replace("\\e", "\\")
+}
+
+/// Split the arguments of a roff macro, which could be quoted.
+fn split_roff_args(text: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' | '\t' if !quoted => {
+                if !current.is_empty() {
+                    result.push(current.clone());
+                    current.clear();
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+/// Text of mdoc macros like `.It Fl a Ar file`, which is `-a file`.
+fn mdoc_text(args: &[String]) -> String {
+    let mut result = Vec::new();
+    let mut is_flag = false;
+    for arg in args {
+        if is_flag {
+            result.push(format!("-{}", arg));
+            is_flag = false;
+        } else if arg == "Fl" {
+            is_flag = true;
+        } else if arg.len() != 2 || !arg.starts_with(|c: char| c.is_ascii_uppercase()) {
+            result.push(arg.clone());
+        }
+    }
+    result.join(" ")
+}
+
+/// Render the roff source of a man page roughly like `man` does:
+/// section headers without indent, tags of paragraphs with indent 7,
+/// and other text with indent 14.
+fn roff_to_text(text: &str) -> String {
+    let mut result = String::new();
+    let mut is_tag = false;
+    for line in text.lines() {
+        let (indent, content) = match line.strip_prefix('.') {
+            Some(rest) => {
+                let rest = unescape_roff(rest.trim_start());
+                let mut args = split_roff_args(&rest);
+                if args.is_empty() {
+                    continue;
+                }
+                let name = args.remove(0);
+                match name.as_str() {
+                    "SH" | "SS" | "Sh" | "Ss" => {
+                        is_tag = false;
+                        (0, args.join(" "))
+                    }
+                    "TP" => {
+                        is_tag = true;
+                        continue;
+                    }
+                    "IP" => match args.first() {
+                        Some(x) => (7, x.clone()),
+                        None => continue,
+                    },
+                    "It" => (7, mdoc_text(&args)),
+                    "B" | "I" | "SM" | "SB" => (if is_tag { 7 } else { 14 }, args.join(" ")),
+                    "BR" | "BI" | "IB" | "RB" | "RI" | "IR" => {
+                        (if is_tag { 7 } else { 14 }, args.concat())
+                    }
+                    "Fl" | "Nm" | "Cm" => {
+                        args.insert(0, name);
+                        (if is_tag { 7 } else { 14 }, mdoc_text(&args))
+                    }
+                    _ => continue,
+                }
+            }
+            None => (if is_tag { 7 } else { 14 }, unescape_roff(line)),
+        };
+        if content.trim().is_empty() {
+            continue;
+        }
+        if indent != 0 {
+            is_tag = false;
+        }
+        result.push_str(&" ".repeat(indent));
+        result.push_str(content.trim());
+        result.push('\n');
+    }
+    result
+}
+
+fn is_header(line: &str) -> bool {
+    let line = line.trim_end();
+    line.ends_with(':')
+        || (line.chars().any(|c| c.is_alphabetic()) && !line.chars().any(|c| c.is_lowercase()))
+}
+
+/// Extract flags and subcommands from help messages. Flags are the
+/// leading words of indented lines starting with `-`, and subcommands
+/// are the leading words of indented lines in "commands" sections.
+fn parse_help(text: &str) -> (Vec<String>, Vec<String>) {
+    lazy_static! {
+        static ref RE_FLAG: Regex =
+            Regex::new(r"(?:^|[\s,/|\[])(--?[A-Za-z0-9][A-Za-z0-9_-]*)").unwrap();
+        static ref RE_SUBCMD: Regex =
+            Regex::new(r"^([a-z][a-z0-9_-]*[a-z0-9])(\s{2,}|\s*$|\s*,|\s+[\[<-])").unwrap();
+    }
+
+    let mut flags: Vec<String> = Vec::new();
+    let mut subcmds: Vec<(usize, String)> = Vec::new();
+    let mut in_commands = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let indent = line.len() - trimmed.len();
+        if indent == 0 {
+            if is_header(line) {
+                in_commands = line.to_lowercase().contains("command");
+            }
+            continue;
+        }
+
+        if trimmed.starts_with('-') {
+            // the flags are followed by the description
+            let head = trimmed.split("  ").next().unwrap_or("");
+            let head = head.split('\t').next().unwrap_or("");
+            for caps in RE_FLAG.captures_iter(head) {
+                let flag = caps[1].to_string();
+                if !flags.contains(&flag) {
+                    flags.push(flag);
+                }
+            }
+        } else if in_commands {
+            if let Some(caps) = RE_SUBCMD.captures(trimmed) {
+                subcmds.push((indent, caps[1].to_string()));
+            }
+        }
+    }
+
+    // descriptions are indented deeper than subcommands
+    let min_indent = subcmds.iter().map(|x| x.0).min().unwrap_or(0);
+    let mut names: Vec<String> = Vec::new();
+    for (indent, name) in subcmds {
+        if indent == min_indent && !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    (flags, names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_help() {
+        let text = "\
+Usage: foo [OPTIONS] <COMMAND>
+
+Commands:
+  build, b    Compile the current package
+  run         Run a binary
+  help        Print this message or the help
+              of the given subcommand(s)
+
+Options:
+  -v, --verbose...      Use verbose output
+  -C <DIR>              Change to DIR before doing anything
+      --color <WHEN>    Coloring: auto, always, never
+  -h, --help            Print help
+";
+        let (flags, subcmds) = parse_help(text);
+        assert_eq!(subcmds, vec!["build", "run", "help"]);
+        assert_eq!(
+            flags,
+            vec!["-v", "--verbose", "-C", "--color", "-h", "--help"]
+        );
+
+        let text = "\
+These are common Git commands used in various situations:
+
+start a working area (see also: git help tutorial)
+   clone     Clone a repository into a new directory
+   init      Create an empty Git repository
+
+work on the current change (see also: git help everyday)
+   add       Add file contents to the index
+";
+        let (flags, subcmds) = parse_help(text);
+        assert!(flags.is_empty());
+        assert_eq!(subcmds, vec!["clone", "init", "add"]);
+    }
+
+    #[test]
+    fn test_is_compiled_binary() {
+        let exe = env::current_exe().unwrap();
+        assert!(is_compiled_binary(&exe.to_string_lossy()));
+        let script = format!("{}/tests/test_scripts.sh", env!("CARGO_MANIFEST_DIR"));
+        assert!(!is_compiled_binary(&script));
+        assert!(!is_compiled_binary("/no/such/file"));
+    }
+
+    #[test]
+    fn test_roff_to_text() {
+        let text = r#".TH LS 1
+.SH DESCRIPTION
+List information about the FILEs.
+.TP
+\fB\-a\fR, \fB\-\-all\fR
+do not ignore entries starting with .
+.TP
+.BR \-B ", " \-\-ignore\-backups
+do not list implied entries ending with ~
+.IP "\-\-color[=\fIWHEN\fR]" 4
+colorize the output
+.Sh OPTIONS
+.It Fl d Ar dir
+use the directory
+"#;
+        let expected = "\
+DESCRIPTION
+              List information about the FILEs.
+       -a, --all
+              do not ignore entries starting with .
+       -B, --ignore-backups
+              do not list implied entries ending with ~
+       --color[=WHEN]
+              colorize the output
+OPTIONS
+       -d dir
+              use the directory
+";
+        assert_eq!(roff_to_text(text), expected);
+        let (flags, subcmds) = parse_help(expected);
+        assert_eq!(
+            flags,
+            vec!["-a", "--all", "-B", "--ignore-backups", "--color", "-d"]
+        );
+        assert!(subcmds.is_empty());
+    }
+}
@@ -2,6 +2,7 @@ use std::borrow::Cow;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::Path;
+use std::sync::Arc;
 
 use lineread::complete::escape;
 use lineread::complete::escaped_word_start;
@@ -10,16 +11,20 @@ use lineread::complete::Suffix;
 use lineread::complete::{Completer, Completion};
 use lineread::prompter::Prompter;
 use lineread::terminal::Terminal;
-use yaml_rust::yaml::Hash;
 use yaml_rust::{Yaml, YamlLoader};
 
+use crate::completers::path;
 use crate::execute;
 use crate::libs::prefix;
 use crate::parsers;
+use crate::shell;
 use crate::tools;
+use crate::types::{CompletionRule, CompletionSource};
 
-/// Performs completion by searching dotfiles
-pub struct DotsCompleter;
+/// Performs completion by the rules of the `complete` builtin and dotfiles
+pub struct DotsCompleter {
+    pub sh: Arc<shell::Shell>,
+}
 
 impl<Term: Terminal> Completer<Term> for DotsCompleter {
     fn complete(
@@ -30,7 +35,7 @@ impl<Term: Terminal> Completer<Term> for DotsCompleter {
         _end: usize,
     ) -> Option<Vec<Completion>> {
         let line = reader.buffer();
-        Some(complete_dots(line, word))
+        Some(complete_dots(&self.sh, line, word))
     }
 
     fn word_start(&self, line: &str, end: usize, _reader: &Prompter<Term>) -> usize {
@@ -46,48 +51,215 @@ impl<Term: Terminal> Completer<Term> for DotsCompleter {
     }
 }
 
-fn get_dot_file(line: &str) -> (String, String) {
-    let cmd = match prefix::get_effective_command(line) {
-        Some(c) => c,
-        None => return (String::new(), String::new()),
-    };
+/// All the completion rules of a command.
+#[derive(Debug, Default)]
+struct Spec {
+    rules: Vec<CompletionRule>,
+    // completing args after a subcommand needs rules for the subcommand
+    has_subcmds: bool,
+}
 
+fn get_dot_file(cmd: &str) -> String {
     let dir = tools::get_user_completer_dir();
-    let dot_file = format!("{}/{}.yaml", dir, cmd);
-    if !Path::new(&dot_file).exists() {
-        return (String::new(), String::new());
+    format!("{}/{}.yaml", dir, cmd)
+}
+
+/// Whether there are any completion rules for the command.
+pub fn has_rules(sh: &shell::Shell, cmd: &str) -> bool {
+    sh.completion_rules.iter().any(|x| x.cmd == cmd) || Path::new(&get_dot_file(cmd)).exists()
+}
+
+fn new_rule(
+    cmd: &str,
+    subcmd: Option<&str>,
+    option: Option<&str>,
+    source: CompletionSource,
+) -> CompletionRule {
+    CompletionRule {
+        cmd: cmd.to_string(),
+        subcmd: subcmd.map(|x| x.to_string()),
+        option: option.map(|x| x.to_string()),
+        source,
     }
+}
 
-    // Get args after the effective command for subcommand detection
-    let segment = prefix::get_current_segment(line);
-    let args = parsers::parser_line::line_to_plain_tokens(segment);
-
-    // Find the position of the effective command in the args
-    let mut cmd_pos = None;
-    for (i, arg) in args.iter().enumerate() {
-        if arg == &cmd {
-            cmd_pos = Some(i);
-            break;
-        }
+/// Source given by items like `- glob: "*.tar.gz"`.
+fn get_source(key: &str, value: &str) -> Option<CompletionSource> {
+    match key {
+        "words" => Some(CompletionSource::Words(
+            value.split_whitespace().map(|x| x.to_string()).collect(),
+        )),
+        "function" => Some(CompletionSource::Function(value.to_string())),
+        "command" => Some(CompletionSource::Command(value.to_string())),
+        "glob" => Some(CompletionSource::Glob(value.to_string())),
+        _ => None,
     }
+}
+
+fn parse_items(
+    spec: &mut Spec,
+    cmd: &str,
+    subcmd: Option<&str>,
+    option: Option<&str>,
+    items: &[Yaml],
+) -> Result<(), String> {
+    let top_level = subcmd.is_none() && option.is_none();
+    for item in items {
+        let h = match item {
+            Yaml::String(name) => {
+                let words = CompletionSource::Words(vec![name.clone()]);
+                spec.rules.push(new_rule(cmd, subcmd, option, words));
+                if top_level {
+                    spec.has_subcmds = true;
+                }
+                continue;
+            }
+            Yaml::Hash(h) => h,
+            _ => return Err("bad item".to_string()),
+        };
 
-    let sub_cmd = match cmd_pos {
-        Some(pos) => {
-            let remaining_args: Vec<_> = args.iter().skip(pos + 1).collect();
-            if (remaining_args.len() >= 2 && !remaining_args[0].starts_with('-'))
-                || (!remaining_args.is_empty()
-                    && !remaining_args[0].starts_with('-')
-                    && line.ends_with(' '))
-            {
-                remaining_args[0].as_str()
-            } else {
-                ""
+        for (k, v) in h.iter() {
+            let key = match k {
+                Yaml::String(x) => x.as_str(),
+                _ => return Err("bad key".to_string()),
+            };
+            if let Yaml::String(value) = v {
+                match get_source(key, value) {
+                    Some(source) => spec.rules.push(new_rule(cmd, subcmd, option, source)),
+                    None => return Err(format!("unknown source: {}", key)),
+                }
+                continue;
+            }
+
+            let sub_items = match v {
+                Yaml::Array(x) => x.as_slice(),
+                Yaml::Null => &[],
+                _ => return Err(format!("bad value of {}", key)),
+            };
+            let words = CompletionSource::Words(vec![key.to_string()]);
+            spec.rules.push(new_rule(cmd, subcmd, option, words));
+            if key.starts_with('-') && option.is_none() {
+                parse_items(spec, cmd, subcmd, Some(key), sub_items)?;
+            } else if top_level {
+                spec.has_subcmds = true;
+                parse_items(spec, cmd, Some(key), None, sub_items)?;
             }
         }
-        None => "",
-    };
+    }
+    Ok(())
+}
 
-    (dot_file, sub_cmd.to_string())
+/// Convert the YAML completion file into completion rules.
+fn parse_yaml(cmd: &str, text: &str) -> Result<Spec, String> {
+    let docs = YamlLoader::load_from_str(text).map_err(|e| format!("{:?}", e))?;
+    let mut spec = Spec::default();
+    for doc in docs.iter() {
+        match doc {
+            Yaml::Array(items) => parse_items(&mut spec, cmd, None, None, items)?,
+            _ => return Err("not a list".to_string()),
+        }
+    }
+    Ok(spec)
+}
+
+fn load_dot_file(cmd: &str, dot_file: &str) -> Spec {
+    if !Path::new(dot_file).exists() {
+        return Spec::default();
+    }
+
+    let mut f;
+    match File::open(dot_file) {
+        Ok(x) => f = x,
+        Err(e) => {
+            println_stderr!("\ncicada: open dot_file error: {:?}", e);
+            return Spec::default();
+        }
+    }
+
+    let mut s = String::new();
+    match f.read_to_string(&mut s) {
+        Ok(_) => {}
+        Err(e) => {
+            println_stderr!("\ncicada: read_to_string error: {:?}", e);
+            return Spec::default();
+        }
+    }
+
+    match parse_yaml(cmd, &s) {
+        Ok(x) => x,
+        Err(e) => {
+            println_stderr!("\ncicada: Bad Yaml file: {}: {}", dot_file, e);
+            Spec::default()
+        }
+    }
+}
+
+fn get_spec(sh: &shell::Shell, cmd: &str) -> Spec {
+    let mut spec = load_dot_file(cmd, &get_dot_file(cmd));
+    for rule in sh.completion_rules.iter().filter(|x| x.cmd == cmd) {
+        if rule.subcmd.is_some() {
+            spec.has_subcmds = true;
+        }
+        spec.rules.push(rule.clone());
+    }
+    spec
+}
+
+/// The args after the effective command and before the current word.
+fn get_args(line: &str, cmd: &str) -> Vec<String> {
+    let segment = prefix::get_current_segment(line);
+    let mut args = parsers::parser_line::line_to_plain_tokens(segment);
+    if !line.ends_with(' ') {
+        args.pop();
+    }
+    match args.iter().position(|x| x == cmd) {
+        Some(pos) => args.split_off(pos + 1),
+        None => Vec::new(),
+    }
+}
+
+/// Find the subcommand in args, skipping options and their values.
+fn get_subcmd(spec: &Spec, args: &[String]) -> Option<String> {
+    if !spec.has_subcmds {
+        return None;
+    }
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if !arg.starts_with('-') {
+            return Some(arg.clone());
+        }
+        let takes_value = spec
+            .rules
+            .iter()
+            .any(|x| x.subcmd.is_none() && x.option.as_ref() == Some(arg));
+        if takes_value {
+            iter.next();
+        }
+    }
+    None
+}
+
+/// The rules for the value of the previous option, or for args of
+/// the subcommand. Rules of options without subcommand apply to all
+/// subcommands.
+fn select_rules<'a>(
+    spec: &'a Spec,
+    subcmd: Option<&String>,
+    prev: Option<&String>,
+) -> Vec<&'a CompletionRule> {
+    let for_option: Vec<&CompletionRule> = spec
+        .rules
+        .iter()
+        .filter(|x| x.option.is_some() && x.option.as_ref() == prev)
+        .filter(|x| x.subcmd.is_none() || x.subcmd.as_ref() == subcmd)
+        .collect();
+    if !for_option.is_empty() {
+        return for_option;
+    }
+    spec.rules
+        .iter()
+        .filter(|x| x.option.is_none() && x.subcmd.as_ref() == subcmd)
+        .collect()
 }
 
 fn handle_lv1_string(res: &mut Vec<Completion>, value: &str, word: &str) {
@@ -119,93 +291,209 @@ fn handle_lv1_string(res: &mut Vec<Completion>, value: &str, word: &str) {
     });
 }
 
-fn handle_lv1_hash(res: &mut Vec<Completion>, h: &Hash, word: &str) {
-    for v in h.values() {
-        if let Yaml::Array(ref arr) = v {
-            for s in arr {
-                if let Yaml::String(value) = s {
-                    if !value.starts_with(word) && !value.starts_with('`') {
-                        continue;
-                    }
-                    handle_lv1_string(res, value, word);
-                }
-            }
-        }
-    }
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
 }
 
-fn complete_dots(line: &str, word: &str) -> Vec<Completion> {
-    let mut res = Vec::new();
-    if line.trim().is_empty() {
-        return res;
-    }
-    let (dot_file, sub_cmd) = get_dot_file(line);
-    if dot_file.is_empty() {
-        return res;
-    }
-
-    let mut f;
-    match File::open(&dot_file) {
-        Ok(x) => f = x,
-        Err(e) => {
-            println_stderr!("\ncicada: open dot_file error: {:?}", e);
-            return res;
-        }
-    }
-
-    let mut s = String::new();
-    match f.read_to_string(&mut s) {
-        Ok(_) => {}
-        Err(e) => {
-            println_stderr!("\ncicada: read_to_string error: {:?}", e);
-            return res;
+/// Run the command line in a copy of the shell, and use its output
+/// as candidates, one per line, or one per word for functions.
+fn handle_output(
+    res: &mut Vec<Completion>,
+    sh: &shell::Shell,
+    line: &str,
+    word: &str,
+    by_words: bool,
+) {
+    log!("run completion cmd: {:?}", line);
+    let mut sh = sh.clone();
+    let cr = execute::run_with_shell(&mut sh, line);
+    let items: Vec<&str> = if by_words {
+        cr.stdout.split_whitespace().collect()
+    } else {
+        cr.stdout.lines().map(|x| x.trim()).collect()
+    };
+    for s in items {
+        if s.is_empty() || !s.starts_with(word) {
+            continue;
         }
+        res.push(Completion {
+            completion: s.to_string(),
+            display: None,
+            suffix: Suffix::Default,
+        });
     }
+}
 
-    let docs = match YamlLoader::load_from_str(&s) {
+/// Files whose names match the pattern, and all directories.
+fn handle_glob(res: &mut Vec<Completion>, pattern: &str, word: &str) {
+    let pattern = match glob::Pattern::new(pattern) {
         Ok(x) => x,
         Err(e) => {
-            println_stderr!("\ncicada: Bad Yaml file: {}: {:?}", dot_file, e);
-            return res;
+            println_stderr!("\ncicada: bad glob pattern: {}: {}", pattern, e);
+            return;
         }
     };
+    for item in path::complete_path(word, false) {
+        if let Suffix::Some(_) = item.suffix {
+            res.push(item);
+            continue;
+        }
+        let name = match &item.display {
+            Some(x) => x.clone(),
+            None => tools::unquote(&item.completion),
+        };
+        if pattern.matches(&name) {
+            res.push(item);
+        }
+    }
+}
 
-    for doc in docs.iter() {
-        match *doc {
-            Yaml::Array(ref v) => {
-                for x in v {
-                    match *x {
-                        Yaml::String(ref name) => {
-                            if !sub_cmd.is_empty() {
-                                continue;
-                            }
-                            handle_lv1_string(&mut res, name, word);
-                        }
-                        Yaml::Hash(ref h) => {
-                            if sub_cmd.is_empty() {
-                                for k in h.keys() {
-                                    if let Yaml::String(value) = k {
-                                        handle_lv1_string(&mut res, value, word);
-                                    }
-                                }
-                            } else {
-                                let key = Yaml::from_str(&sub_cmd);
-                                if !h.contains_key(&key) {
-                                    continue;
-                                }
-                                handle_lv1_hash(&mut res, h, word);
-                            }
-                        }
-                        _ => {
-                            println_stderr!("\nThis yaml file is in bad format: {}", dot_file);
-                        }
-                    }
+fn complete_spec(
+    sh: &shell::Shell,
+    spec: &Spec,
+    cmd: &str,
+    line: &str,
+    word: &str,
+) -> Vec<Completion> {
+    let mut res = Vec::new();
+    let args = get_args(line, cmd);
+    let subcmd = get_subcmd(spec, &args);
+    let prev = args.last();
+    for rule in select_rules(spec, subcmd.as_ref(), prev) {
+        match &rule.source {
+            CompletionSource::Words(words) => {
+                for x in words {
+                    handle_lv1_string(&mut res, x, word);
                 }
             }
-            _ => {
-                println_stderr!("\nThis yaml file is in bad format: {}", dot_file);
+            CompletionSource::Function(name) => {
+                let prev = prev.map_or("", |x| x.as_str());
+                let line = format!("{} {} {} {}", name, quote(cmd), quote(word), quote(prev));
+                handle_output(&mut res, sh, &line, word, true);
+            }
+            CompletionSource::Command(line) => {
+                handle_output(&mut res, sh, line, word, false);
+            }
+            CompletionSource::Glob(pattern) => {
+                handle_glob(&mut res, pattern, word);
             }
         }
     }
     res
 }
+
+fn complete_dots(sh: &shell::Shell, line: &str, word: &str) -> Vec<Completion> {
+    if line.trim().is_empty() {
+        return Vec::new();
+    }
+    match prefix::get_effective_command(line) {
+        Some(cmd) => complete_spec(sh, &get_spec(sh, &cmd), &cmd, line, word),
+        None => Vec::new(),
+    }
+}
+
+/// Complete with the rules in a YAML file of the dots format.
+pub fn complete_file(sh: &shell::Shell, line: &str, word: &str, file: &str) -> Vec<Completion> {
+    match prefix::get_effective_command(line) {
+        Some(cmd) => complete_spec(sh, &load_dot_file(&cmd, file), &cmd, line, word),
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(x: &str) -> CompletionSource {
+        CompletionSource::Words(vec![x.to_string()])
+    }
+
+    #[test]
+    fn test_parse_yaml() {
+        let text = "
+- download
+- install:
+    - -U
+    - -r:
+        - glob: '*.txt'
+- search:
+- function: _pip_pkgs
+";
+        let spec = parse_yaml("pip", text).unwrap();
+        assert!(spec.has_subcmds);
+        let install = Some("install");
+        let expected = vec![
+            new_rule("pip", None, None, words("download")),
+            new_rule("pip", None, None, words("install")),
+            new_rule("pip", install, None, words("-U")),
+            new_rule("pip", install, None, words("-r")),
+            new_rule(
+                "pip",
+                install,
+                Some("-r"),
+                CompletionSource::Glob("*.txt".to_string()),
+            ),
+            new_rule("pip", None, None, words("search")),
+            new_rule(
+                "pip",
+                None,
+                None,
+                CompletionSource::Function("_pip_pkgs".to_string()),
+            ),
+        ];
+        assert_eq!(spec.rules, expected);
+
+        assert!(parse_yaml("pip", "foo: bar").is_err());
+        assert!(parse_yaml("pip", "- foo: bar").is_err());
+    }
+
+    #[test]
+    fn test_select_rules() {
+        let text = "
+- -C:
+    - glob: '*'
+- checkout:
+    - main
+- remote:
+    - -v
+    - --push:
+        - words: origin upstream
+";
+        let spec = parse_yaml("git", text).unwrap();
+        let args =
+            |x: &str| -> Vec<String> { x.split_whitespace().map(|x| x.to_string()).collect() };
+        let select = |line: &str| -> Vec<CompletionRule> {
+            let args = args(line);
+            let subcmd = get_subcmd(&spec, &args);
+            select_rules(&spec, subcmd.as_ref(), args.last())
+                .into_iter()
+                .cloned()
+                .collect()
+        };
+
+        assert_eq!(
+            get_subcmd(&spec, &args("-C /tmp checkout")),
+            Some("checkout".to_string())
+        );
+        assert_eq!(get_subcmd(&spec, &args("-C /tmp")), None);
+        assert_eq!(select("").len(), 3);
+        assert_eq!(select("-C").len(), 1);
+        assert_eq!(select("-C /tmp").len(), 3);
+        assert_eq!(
+            select("checkout"),
+            vec![new_rule("git", Some("checkout"), None, words("main"))]
+        );
+        assert_eq!(select("remote").len(), 2);
+        assert_eq!(select("remote --push").len(), 1);
+        assert_eq!(select("remote -C").len(), 1);
+        assert!(select("commit").is_empty());
+    }
+
+    #[test]
+    fn test_get_args() {
+        assert_eq!(get_args("git ", "git"), Vec::<String>::new());
+        assert_eq!(get_args("git co", "git"), Vec::<String>::new());
+        assert_eq!(get_args("sudo git co ", "git"), vec!["co"]);
+        assert_eq!(get_args("ls | git -C /tmp ch", "git"), vec!["-C", "/tmp"]);
+    }
+}
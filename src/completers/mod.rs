@@ -1,10 +1,10 @@
-use std::path::Path;
 use std::sync::Arc;
 
 use lineread::complete::{Completer, Completion};
 use lineread::prompter::Prompter;
 use lineread::terminal::Terminal;
 
+pub mod auto;
 pub mod dots;
 pub mod env;
 pub mod make;
@@ -16,7 +16,6 @@ use crate::libs;
 use crate::libs::prefix;
 use crate::parsers;
 use crate::shell;
-use crate::tools;
 
 pub struct CicadaCompleter {
     pub sh: Arc<shell::Shell>,
@@ -105,13 +104,14 @@ fn for_bin(line: &str) -> bool {
     true
 }
 
-fn for_dots(line: &str) -> bool {
+/// Commands in `$PATH` without any completion rules.
+fn for_auto(sh: &shell::Shell, line: &str) -> bool {
+    auto::is_enabled() && !for_bin(line) && !for_dots(sh, line)
+}
+
+fn for_dots(sh: &shell::Shell, line: &str) -> bool {
     match prefix::get_effective_command(line) {
-        Some(cmd) => {
-            let dir = tools::get_user_completer_dir();
-            let dot_file = format!("{}/{}.yaml", dir, cmd);
-            Path::new(dot_file.as_str()).exists()
-        }
+        Some(cmd) => dots::has_rules(sh, &cmd),
         None => false,
     }
 }
@@ -127,8 +127,10 @@ impl<Term: Terminal> Completer<Term> for CicadaCompleter {
         let line = reader.buffer();
 
         let completions: Option<Vec<Completion>>;
-        if for_dots(line) {
-            let cpl = Arc::new(dots::DotsCompleter);
+        if for_dots(&self.sh, line) {
+            let cpl = Arc::new(dots::DotsCompleter {
+                sh: self.sh.clone(),
+            });
             completions = cpl.complete(word, reader, start, _end);
         } else if for_ssh(line) {
             let cpl = Arc::new(ssh::SshCompleter);
@@ -162,6 +164,17 @@ impl<Term: Terminal> Completer<Term> for CicadaCompleter {
             }
         }
 
+        if for_auto(&self.sh, line) {
+            let cpl = Arc::new(auto::AutoCompleter {
+                sh: self.sh.clone(),
+            });
+            if let Some(x) = cpl.complete(word, reader, start, _end) {
+                if !x.is_empty() {
+                    return Some(x);
+                }
+            }
+        }
+
         // empty completions should fail back to path-completion,
         // so that `$ make generate /path/to/fi<Tab>` still works.
         let cpl = Arc::new(path::PathCompleter);
@@ -1,16 +1,13 @@
 use std::collections::HashSet;
-use std::env;
 use std::fs::read_dir;
-use std::io::Write;
-use std::iter::FromIterator;
-use std::os::unix::fs::PermissionsExt;
-use std::path::{PathBuf, MAIN_SEPARATOR};
+use std::path::MAIN_SEPARATOR;
 use std::sync::Arc;
 
 use lineread::complete::{Completer, Completion, Suffix};
 use lineread::terminal::Terminal;
 use lineread::Prompter;
 
+use crate::cmdcache;
 use crate::completers::utils;
 use crate::libs;
 use crate::parsers;
@@ -181,14 +178,6 @@ fn split_pathname(path: &str, prefix: &str) -> (String, String, String) {
 fn complete_bin(sh: &shell::Shell, path: &str) -> Vec<Completion> {
     let mut res = Vec::new();
     let (prefix, _, fname) = split_pathname(path, "");
-    let env_path = match env::var("PATH") {
-        Ok(x) => x,
-        Err(e) => {
-            println_stderr!("cicada: env error when complete_bin: {:?}", e);
-            return res;
-        }
-    };
-
     let mut checker: HashSet<String> = HashSet::new();
 
     // handle alias, builtins, and functions
@@ -221,9 +210,11 @@ fn complete_bin(sh: &shell::Shell, path: &str) -> Vec<Completion> {
         });
     }
 
+    #[rustfmt::skip]
     let builtins = vec![
         "alias", "bg", "cd", "check", "cinfo", "exec", "exit", "export", "fg", "history", "jobs",
         "read", "source", "ulimit", "unalias", "vox", "minfd", "set", "unset", "unpath",
+        "complete", "declare", "functions", "hash", "kill", "local", "return", "trap", "wait",
     ];
     for item in &builtins {
         if !item.starts_with(&fname) {
@@ -240,45 +231,19 @@ fn complete_bin(sh: &shell::Shell, path: &str) -> Vec<Completion> {
         });
     }
 
-    let vec_path = env::split_paths(&env_path);
-    let path_list: HashSet<PathBuf> = HashSet::from_iter(vec_path);
-
-    for p in path_list {
-        if let Ok(list) = read_dir(p) {
-            for entry in list.flatten() {
-                if let Ok(name) = entry.file_name().into_string() {
-                    if name.starts_with(&fname) {
-                        let _mode = match entry.metadata() {
-                            Ok(x) => x,
-                            Err(e) => {
-                                println_stderr!("cicada: metadata error: {:?}", e);
-                                continue;
-                            }
-                        };
-                        let mode = _mode.permissions().mode();
-                        if mode & 0o111 == 0 {
-                            // not binary
-                            continue;
-                        }
-                        if checker.contains(&name) {
-                            continue;
-                        }
-
-                        let display = None;
-                        let suffix = Suffix::Default;
-                        checker.insert(name.clone());
-                        // TODO: need to handle quoted: `$ "foo#bar"`
-                        let name_e = tools::escape_path(&name);
-                        let name_e = format!("{}{}", prefix, name_e);
-                        res.push(Completion {
-                            completion: name_e,
-                            display,
-                            suffix,
-                        });
-                    }
-                }
-            }
+    for name in cmdcache::commands_with_prefix(&fname) {
+        if checker.contains(&name) {
+            continue;
         }
+        checker.insert(name.clone());
+        // TODO: need to handle quoted: `$ "foo#bar"`
+        let name_e = tools::escape_path(&name);
+        let name_e = format!("{}{}", prefix, name_e);
+        res.push(Completion {
+            completion: name_e,
+            display: None,
+            suffix: Suffix::Default,
+        });
     }
     res
 }
@@ -11,13 +11,18 @@ use nix::unistd::{execve, ForkResult};
 
 use crate::builtins;
 use crate::calculator;
+use crate::execute;
+use crate::history;
 use crate::jobc;
 use crate::libs;
 use crate::parsers;
 use crate::scripting;
 use crate::shell::{self, Shell};
+use crate::signals;
 use crate::tools;
-use crate::types::{CommandLine, CommandOptions, CommandResult};
+use crate::types::{CommandLine, CommandOptions, CommandResult, FuncFrame, OutputTee};
+
+const MAX_FUNC_NESTING: usize = 256;
 
 fn try_run_builtin_in_subprocess(
     sh: &mut Shell,
@@ -63,6 +68,9 @@ fn try_run_builtin(
     } else if cname == "cinfo" {
         let cr = builtins::cinfo::run(sh, cl, cmd, capture);
         return Some(cr);
+    } else if cname == "complete" {
+        let cr = builtins::complete::run(sh, cl, cmd, capture);
+        return Some(cr);
     } else if cname == "exec" {
         let cr = builtins::exec::run(sh, cl, cmd, capture);
         return Some(cr);
@@ -75,24 +83,45 @@ fn try_run_builtin(
     } else if cname == "fg" {
         let cr = builtins::fg::run(sh, cl, cmd, capture);
         return Some(cr);
+    } else if cname == "declare" {
+        let cr = builtins::declare::run(sh, cl, cmd, capture);
+        return Some(cr);
+    } else if cname == "functions" {
+        let cr = builtins::functions::run(sh, cl, cmd, capture);
+        return Some(cr);
+    } else if cname == "hash" {
+        let cr = builtins::hash::run(sh, cl, cmd, capture);
+        return Some(cr);
     } else if cname == "history" {
         let cr = builtins::history::run(sh, cl, cmd, capture);
         return Some(cr);
     } else if cname == "jobs" {
         let cr = builtins::jobs::run(sh, cl, cmd, capture);
         return Some(cr);
+    } else if cname == "kill" {
+        let cr = builtins::kill::run(sh, cl, cmd, capture);
+        return Some(cr);
+    } else if cname == "local" {
+        let cr = builtins::local::run(sh, cl, cmd, capture);
+        return Some(cr);
     } else if cname == "minfd" {
         let cr = builtins::minfd::run(sh, cl, cmd, capture);
         return Some(cr);
     } else if cname == "read" {
         let cr = builtins::read::run(sh, cl, cmd, capture);
         return Some(cr);
+    } else if cname == "return" {
+        let cr = builtins::r#return::run(sh, cl, cmd, capture);
+        return Some(cr);
     } else if cname == "set" {
         let cr = builtins::set::run(sh, cl, cmd, capture);
         return Some(cr);
     } else if cname == "source" {
         let cr = builtins::source::run(sh, cl, cmd, capture);
         return Some(cr);
+    } else if cname == "trap" {
+        let cr = builtins::trap::run(sh, cl, cmd, capture);
+        return Some(cr);
     } else if cname == "ulimit" {
         let cr = builtins::ulimit::run(sh, cl, cmd, capture);
         return Some(cr);
@@ -108,10 +137,242 @@ fn try_run_builtin(
     } else if cname == "vox" {
         let cr = builtins::vox::run(sh, cl, cmd, capture);
         return Some(cr);
+    } else if cname == "wait" {
+        let cr = builtins::wait::run(sh, cl, cmd, capture);
+        return Some(cr);
     }
     None
 }
 
+/// Run the command of process substitution (`<(cmd)` or `>(cmd)`) in a
+/// forked subshell, which is connected with a pipe. Returns the fd of the
+/// other end of the pipe, which is kept open until the main command exits.
+/// The subshell leads a process group with its commands, so that they can
+/// be killed together.
+pub fn run_proc_substitution(
+    sh: &mut Shell,
+    line: &str,
+    is_output: bool,
+) -> Result<RawFd, String> {
+    let fds = match pipe() {
+        Ok(x) => x,
+        Err(e) => return Err(format!("process substitution: {}", e)),
+    };
+
+    match libs::fork::fork() {
+        Ok(ForkResult::Child) => {
+            unsafe {
+                // child processes need to handle ctrl-Z
+                libc::signal(libc::SIGTSTP, libc::SIG_DFL);
+                libc::signal(libc::SIGQUIT, libc::SIG_DFL);
+                libc::setpgid(0, 0);
+            }
+            sh.in_proc_sub = true;
+            // do not hold the pipes of other substitutions, otherwise
+            // they could never get EOF or SIGPIPE.
+            for (fd, _) in sh.proc_subs.drain(..) {
+                libs::close(fd);
+            }
+
+            if is_output {
+                libs::dup2(fds.0, 0);
+            } else {
+                libs::dup2(fds.1, 1);
+            }
+            libs::close(fds.0);
+            libs::close(fds.1);
+
+            let cr_list = execute::run_command_line(sh, line, false, false);
+            let status = cr_list.last().map_or(0, |x| x.status);
+            process::exit(status);
+        }
+        Ok(ForkResult::Parent { child, .. }) => {
+            let (fd, fd_child) = if is_output {
+                (fds.1, fds.0)
+            } else {
+                (fds.0, fds.1)
+            };
+            libs::close(fd_child);
+            let pid: i32 = child.into();
+            // also set in parent, in case it's killed before the child runs
+            unsafe {
+                libc::setpgid(pid, pid);
+            }
+            sh.proc_subs.push((fd, pid));
+            Ok(fd)
+        }
+        Err(e) => {
+            libs::close(fds.0);
+            libs::close(fds.1);
+            Err(format!("process substitution: fork: {}", e))
+        }
+    }
+}
+
+/// Close the pipes of process substitutions after the main command exits,
+/// and reap their subshells. They are killed if the main command was
+/// killed by a signal, e.g. with `Ctrl-C`, instead of being waited for.
+pub fn wait_proc_substitutions(sh: &mut Shell, status: i32) {
+    let proc_subs: Vec<(RawFd, i32)> = sh.proc_subs.drain(..).collect();
+    for (fd, _) in proc_subs.iter() {
+        libs::close(*fd);
+    }
+    for (_, pid) in proc_subs.iter() {
+        if status > 128 {
+            unsafe {
+                libc::kill(-pid, libc::SIGKILL);
+            }
+        }
+        // it could be reaped when waiting the main command
+        if signals::pop_reap_map(*pid).is_some() || signals::killed_map_pop(*pid).is_some() {
+            continue;
+        }
+        loop {
+            let ws = jobc::waitpidx(*pid, true);
+            if !ws.is_stopped() && !ws.is_continued() {
+                break;
+            }
+        }
+    }
+}
+
+/// Fork a process to copy output of the pipeline to the terminal, and
+/// keep the last part of it to be saved into history.
+fn start_output_tee(sh: &mut Shell) -> Option<OutputTee> {
+    let mut fds_list = Vec::new();
+    for _ in 0..3 {
+        match pipe() {
+            Ok(fds) => fds_list.push(fds),
+            Err(e) => {
+                println_stderr!("cicada: output capture: {}", e);
+                for fds in fds_list {
+                    libs::close(fds.0);
+                    libs::close(fds.1);
+                }
+                return None;
+            }
+        }
+    }
+    let (fds_out, fds_err, fds_result) = (fds_list[0], fds_list[1], fds_list[2]);
+
+    match libs::fork::fork() {
+        Ok(ForkResult::Child) => {
+            libs::close(fds_out.1);
+            libs::close(fds_err.1);
+            libs::close(fds_result.0);
+            for (fd, _) in sh.proc_subs.drain(..) {
+                libs::close(fd);
+            }
+            let output = tee_output(fds_out.0, fds_err.0, history::get_capture_size());
+            let mut f = unsafe { File::from_raw_fd(fds_result.1) };
+            let _ = f.write_all(&output);
+            process::exit(0);
+        }
+        Ok(ForkResult::Parent { child, .. }) => {
+            libs::close(fds_out.0);
+            libs::close(fds_err.0);
+            libs::close(fds_result.1);
+            Some(OutputTee {
+                pid: child.into(),
+                fd_out: fds_out.1,
+                fd_err: fds_err.1,
+                fd_result: fds_result.0,
+            })
+        }
+        Err(e) => {
+            println_stderr!("cicada: output capture: fork: {}", e);
+            for fds in fds_list {
+                libs::close(fds.0);
+                libs::close(fds.1);
+            }
+            None
+        }
+    }
+}
+
+/// Copy data from the two fds to stdout and stderr until both of them
+/// are closed. Returns the last `size` bytes of the data.
+fn tee_output(fd_out: RawFd, fd_err: RawFd, size: usize) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut buf = [0u8; 4096];
+    let mut fds = [
+        libc::pollfd {
+            fd: fd_out,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: fd_err,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    while fds.iter().any(|x| x.fd >= 0) {
+        let rc = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) };
+        if rc < 0 {
+            if nix::errno::Errno::last() == nix::errno::Errno::EINTR {
+                continue;
+            }
+            break;
+        }
+        for (i, item) in fds.iter_mut().enumerate() {
+            if item.fd < 0 || item.revents == 0 {
+                continue;
+            }
+            let n =
+                unsafe { libc::read(item.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if n <= 0 {
+                libs::close(item.fd);
+                item.fd = -1;
+                continue;
+            }
+            let data = &buf[..n as usize];
+            // flush at once, data like prompts may not end with newlines
+            let _ = if i == 0 {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(data).and_then(|_| stdout.flush())
+            } else {
+                std::io::stderr().write_all(data)
+            };
+            output.extend_from_slice(data);
+            if output.len() > size * 2 {
+                history::trim_output(&mut output, size);
+            }
+        }
+    }
+    history::trim_output(&mut output, size);
+    output
+}
+
+/// Collect the output kept by the tee process into `sh.captured_output`.
+fn finish_output_tee(sh: &mut Shell, tee: OutputTee, stopped: bool) {
+    // the stopped job still holds the pipes, we would not wait for it
+    if !stopped {
+        let mut fds = libc::pollfd {
+            fd: tee.fd_result,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // background processes of the pipeline may still hold the pipes
+        if unsafe { libc::poll(&mut fds, 1, 500) } > 0 {
+            let mut output = Vec::new();
+            let mut f = unsafe { File::from_raw_fd(tee.fd_result) };
+            let _ = f.read_to_end(&mut output);
+            sh.captured_output
+                .push_str(&String::from_utf8_lossy(&output));
+            let mut bytes = sh.captured_output.as_bytes().to_vec();
+            history::trim_output(&mut bytes, history::get_capture_size());
+            sh.captured_output = String::from_utf8_lossy(&bytes).to_string();
+            // the fd is closed when `f` is dropped
+            if signals::pop_reap_map(tee.pid).is_none() {
+                jobc::waitpidx(tee.pid, true);
+            }
+            return;
+        }
+    }
+    libs::close(tee.fd_result);
+}
+
 /// Run a pipeline (e.g. `echo hi | wc -l`)
 /// returns: (is-terminal-given, command-result)
 pub fn run_pipeline(
@@ -146,6 +407,24 @@ pub fn run_pipeline(
         return (false, CommandResult::error());
     }
 
+    let isatty = if tty {
+        unsafe { libc::isatty(1) == 1 }
+    } else {
+        false
+    };
+
+    // start the tee before creating pipes, so it would not hold them
+    let mut output_tee = None;
+    if isatty
+        && sh.has_terminal
+        && !capture
+        && !cl.background
+        && !cl.is_single_and_builtin()
+        && history::should_capture_output(&cl.line)
+    {
+        output_tee = start_output_tee(sh);
+    }
+
     let mut pipes = Vec::new();
     let mut errored_pipes = false;
     for _ in 0..length - 1 {
@@ -165,6 +444,11 @@ pub fn run_pipeline(
             libs::close(fds.0);
             libs::close(fds.1);
         }
+        if let Some(tee) = output_tee {
+            libs::close(tee.fd_out);
+            libs::close(tee.fd_err);
+            finish_output_tee(sh, tee, false);
+        }
         return (false, CommandResult::error());
     }
 
@@ -176,16 +460,12 @@ pub fn run_pipeline(
     let mut pgid: i32 = 0;
     let mut fg_pids: Vec<i32> = Vec::new();
 
-    let isatty = if tty {
-        unsafe { libc::isatty(1) == 1 }
-    } else {
-        false
-    };
     let options = CommandOptions {
         isatty,
         capture_output: capture,
         background: cl.background,
         envs: cl.envs.clone(),
+        output_tee,
     };
 
     let mut fds_capture_stdout = None;
@@ -229,12 +509,21 @@ pub fn run_pipeline(
         if child_id > 0 && !cl.background {
             fg_pids.push(child_id);
         }
+        if child_id > 0 && cl.background {
+            sh.last_bg_pid = Some(child_id);
+        }
     }
 
     if cl.is_single_and_builtin() {
         return (false, cmd_result);
     }
 
+    // only the children hold the write ends now
+    if let Some(tee) = output_tee {
+        libs::close(tee.fd_out);
+        libs::close(tee.fd_err);
+    }
+
     if cl.background {
         if let Some(job) = sh.get_job_by_gid(pgid) {
             println_stderr!("[{}] {}", job.id, job.gid);
@@ -250,6 +539,14 @@ pub fn run_pipeline(
             cmd_result = _cr;
         }
     }
+
+    if let Some(tee) = output_tee {
+        let stopped = match sh.get_job_by_gid(pgid) {
+            Some(job) => job.status == "Stopped",
+            None => false,
+        };
+        finish_output_tee(sh, tee, stopped);
+    }
     (term_given, cmd_result)
 }
 
@@ -285,7 +582,7 @@ fn run_single_program(
     let mut fds_stdin = None;
     let cmd = cl.commands.get(idx_cmd).unwrap();
 
-    if cmd.has_here_string() {
+    if cmd.has_here_string() || cmd.has_here_doc() {
         match pipe() {
             Ok(fds) => fds_stdin = Some(fds),
             Err(e) => {
@@ -303,14 +600,8 @@ fn run_single_program(
                 libc::signal(libc::SIGQUIT, libc::SIG_DFL);
             }
 
-            // close pipes unrelated to current child (left side)
-            if idx_cmd > 0 {
-                for i in 0..idx_cmd - 1 {
-                    let fds = pipes[i];
-                    libs::close(fds.0);
-                    libs::close(fds.1);
-                }
-            }
+            // pipes on the left side had been closed by the parent before
+            // this fork, their fd numbers may have been reused already.
             // close pipes unrelated to current child (right side)
             for i in idx_cmd + 1..pipes_count {
                 let fds = pipes[i];
@@ -330,7 +621,9 @@ fn run_single_program(
                 }
             }
 
-            if idx_cmd == 0 {
+            if sh.in_proc_sub {
+                // stay in the process group of the substitution
+            } else if idx_cmd == 0 {
                 unsafe {
                     let pid = libc::getpid();
                     libc::setpgid(0, pid);
@@ -346,7 +639,6 @@ fn run_single_program(
                 let fds_prev = pipes[idx_cmd - 1];
                 libs::dup2(fds_prev.0, 0);
                 libs::close(fds_prev.0);
-                libs::close(fds_prev.1);
             }
             if idx_cmd < pipes_count {
                 let fds = pipes[idx_cmd];
@@ -355,6 +647,18 @@ fn run_single_program(
                 libs::close(fds.0);
             }
 
+            // output goes to the tee process before the redirections,
+            // so that `2>&1` and `> file` still work as expected.
+            if let Some(tee) = options.output_tee {
+                if idx_cmd == pipes_count {
+                    libs::dup2(tee.fd_out, 1);
+                }
+                libs::dup2(tee.fd_err, 2);
+                libs::close(tee.fd_out);
+                libs::close(tee.fd_err);
+                libs::close(tee.fd_result);
+            }
+
             if cmd.has_redirect_from() {
                 if let Some(redirect_from) = &cmd.redirect_from {
                     let fd = tools::get_fd_from_file(&redirect_from.clone().1);
@@ -367,7 +671,7 @@ fn run_single_program(
                 }
             }
 
-            if cmd.has_here_string() {
+            if cmd.has_here_string() || cmd.has_here_doc() {
                 if let Some(fds) = fds_stdin {
                     libs::close(fds.1);
                     libs::dup2(fds.0, 0);
@@ -520,7 +824,7 @@ fn run_single_program(
                     //    immediate after start on linux (ubuntu).
                     // 2. but on mac, we need this loop, otherwise commands
                     //    like `vim` will go to `T` status after start.
-                    if cfg!(target_os = "macos") {
+                    if cfg!(target_os = "macos") && !sh.in_proc_sub {
                         loop {
                             let _pgid = libc::getpgid(pid);
                             if _pgid == pid {
@@ -535,28 +839,35 @@ fn run_single_program(
                 }
             }
 
-            if options.isatty && !options.capture_output {
+            // background jobs are tracked for `wait` even without tty
+            if (options.isatty || cl.background) && !options.capture_output {
                 let _cmd = parsers::parser_line::tokens_to_line(&cmd.tokens);
                 sh.insert_job(*pgid, pid, &_cmd, "Running", cl.background);
             }
 
             if let Some(redirect_from) = &cmd.redirect_from {
-                if redirect_from.0 == "<<<" {
-                    if let Some(fds) = fds_stdin {
-                        unsafe {
-                            libs::close(fds.0);
-
-                            let mut f = File::from_raw_fd(fds.1);
-                            match f.write_all(redirect_from.1.clone().as_bytes()) {
-                                Ok(_) => {}
-                                Err(e) => println_stderr!("cicada: write_all: {}", e),
-                            }
-                            match f.write_all(b"\n") {
-                                Ok(_) => {}
-                                Err(e) => println_stderr!("cicada: write_all: {}", e),
-                            }
-                        }
+                if let Some(fds) = fds_stdin {
+                    libs::close(fds.0);
+                    let mut input = redirect_from.1.clone();
+                    if redirect_from.0 == "<<<" {
+                        input.push('\n');
+                    }
+
+                    // the pipe ends the writer must not keep open
+                    let mut fds_unused = Vec::new();
+                    if idx_cmd > 0 {
+                        fds_unused.push(pipes[idx_cmd - 1].0);
+                    }
+                    for fds in pipes[idx_cmd..].iter() {
+                        fds_unused.extend([fds.0, fds.1]);
+                    }
+                    for fds in [*fds_capture_stdout, *fds_capture_stderr].iter().flatten() {
+                        fds_unused.extend([fds.0, fds.1]);
+                    }
+                    if let Some(tee) = &options.output_tee {
+                        fds_unused.extend([tee.fd_out, tee.fd_err]);
                     }
+                    write_to_stdin_pipe(fds.1, &input, &fds_unused);
                 }
             }
 
@@ -614,6 +925,46 @@ fn run_single_program(
     }
 }
 
+/// Feed here-string/here-document input to the child via the pipe `fd`.
+/// It's written by a grandchild: the shell would block on inputs bigger
+/// than the pipe buffer, while the child could be waiting for the rest of
+/// the pipeline to be forked, e.g. `cat <<EOF | wc`. The intermediate
+/// child exits at once, so the writer is not a job member to wait for. It
+/// exits when the input is written, or by SIGPIPE when the child exits
+/// without reading all of it.
+fn write_to_stdin_pipe(fd: RawFd, input: &str, fds_unused: &[RawFd]) {
+    match libs::fork::fork() {
+        Ok(ForkResult::Child) => {
+            for fd in fds_unused {
+                libs::close(*fd);
+            }
+            match libs::fork::fork() {
+                Ok(ForkResult::Child) => {
+                    let mut f = unsafe { File::from_raw_fd(fd) };
+                    if let Err(e) = f.write_all(input.as_bytes()) {
+                        println_stderr!("cicada: write_all: {}", e);
+                    }
+                }
+                Ok(ForkResult::Parent { .. }) => {}
+                Err(e) => println_stderr!("cicada: here-document: fork: {}", e),
+            }
+            process::exit(0);
+        }
+        Ok(ForkResult::Parent { child, .. }) => {
+            libs::close(fd);
+            let pid: i32 = child.into();
+            // it could be reaped by the SIGCHLD handler already
+            if signals::pop_reap_map(pid).is_none() {
+                jobc::waitpidx(pid, true);
+            }
+        }
+        Err(e) => {
+            libs::close(fd);
+            println_stderr!("cicada: here-document: fork: {}", e);
+        }
+    }
+}
+
 fn try_run_func(
     sh: &mut Shell,
     cl: &CommandLine,
@@ -633,7 +984,23 @@ fn try_run_func(
         if log_cmd {
             log!("run func: {:?}", &args);
         }
+        if sh.func_frames.len() >= MAX_FUNC_NESTING {
+            println_stderr!(
+                "cicada: {}: maximum function nesting level exceeded ({})",
+                &command.tokens[0].1,
+                MAX_FUNC_NESTING
+            );
+            return Some(CommandResult::error());
+        }
+
+        sh.func_frames.push(FuncFrame::default());
         let cr_list = scripting::run_lines(sh, &func_body, &args, capture);
+        let frame = sh.func_frames.pop().unwrap_or_default();
+        let status = match frame.return_status {
+            Some(x) => x,
+            None => cr_list.last().map_or(0, |x| x.status),
+        };
+
         let mut stdout = String::new();
         let mut stderr = String::new();
         for cr in cr_list {
@@ -643,6 +1010,7 @@ fn try_run_func(
             stderr.push(' ');
         }
         let mut cr = CommandResult::new();
+        cr.status = status;
         cr.stdout = stdout;
         cr.stderr = stderr;
         return Some(cr);
@@ -1,5 +1,5 @@
 use std::fmt;
-use time::OffsetDateTime;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct DateTime {
@@ -32,6 +32,54 @@ impl DateTime {
     }
 }
 
+/// Parse time given like `1700000000`, `2024-01-31`, `2024-01-31 08:30[:00]`
+/// in local time, or relative to now like `30m`, `2h`, `7d`, `1w`.
+pub fn parse_timestamp(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if let Ok(x) = text.parse::<f64>() {
+        return Some(x);
+    }
+
+    let units = [
+        ('s', 1.0),
+        ('m', 60.0),
+        ('h', 3600.0),
+        ('d', 86400.0),
+        ('w', 604800.0),
+    ];
+    for (unit, seconds) in units {
+        if let Some(Ok(x)) = text.strip_suffix(unit).map(|x| x.parse::<f64>()) {
+            return Some(DateTime::now().unix_timestamp() - x * seconds);
+        }
+    }
+
+    let (str_date, str_time) = match text.split_once([' ', 'T']) {
+        Some((x, y)) => (x, y.trim()),
+        None => (text, "00:00:00"),
+    };
+    let nums: Vec<&str> = str_date.split('-').collect();
+    if nums.len() != 3 {
+        return None;
+    }
+    let month = Month::try_from(nums[1].parse::<u8>().ok()?).ok()?;
+    let date =
+        Date::from_calendar_date(nums[0].parse().ok()?, month, nums[2].parse().ok()?).ok()?;
+
+    let nums: Vec<&str> = str_time.split(':').collect();
+    if nums.len() < 2 || nums.len() > 3 {
+        return None;
+    }
+    let second = match nums.get(2) {
+        Some(x) => x.parse().ok()?,
+        None => 0,
+    };
+    let time = Time::from_hms(nums[0].parse().ok()?, nums[1].parse().ok()?, second).ok()?;
+
+    let offset = DateTime::now().odt.offset();
+    let odt = PrimitiveDateTime::new(date, time).assume_offset(offset);
+    Some(odt.unix_timestamp() as f64)
+}
+
 impl fmt::Display for DateTime {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -47,3 +95,27 @@ impl fmt::Display for DateTime {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_timestamp;
+    use super::DateTime;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("1700000000"), Some(1700000000.0));
+        assert_eq!(parse_timestamp("2024-02-30"), None);
+        assert_eq!(parse_timestamp("2024-01-31 25:00"), None);
+        assert_eq!(parse_timestamp("foo"), None);
+
+        let ts = parse_timestamp("2024-01-31 08:30").unwrap();
+        let ts2 = parse_timestamp("2024-01-31T08:30:15").unwrap();
+        assert_eq!(ts2 - ts, 15.0);
+        let ts3 = parse_timestamp("2024-01-31").unwrap();
+        assert_eq!(ts - ts3, 30600.0);
+
+        let now = DateTime::now().unix_timestamp();
+        let ts = parse_timestamp("2h").unwrap();
+        assert!((now - 7200.0 - ts).abs() < 5.0);
+    }
+}
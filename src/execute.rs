@@ -1,10 +1,14 @@
+use nix::sys::signal::Signal;
 use std::collections::HashMap;
 use std::io::{self, Read, Write};
+use std::process;
 
 use crate::core;
 use crate::parsers;
+use crate::scripting;
 use crate::shell::{self, Shell};
-use crate::types::{drain_env_tokens, CommandLine, CommandResult, Tokens};
+use crate::signals;
+use crate::types::{CommandLine, CommandResult, Tokens};
 
 /// Entry point for non-ttys (e.g. Cmd-N on MacVim)
 pub fn run_procs_for_non_tty(sh: &mut Shell) {
@@ -28,10 +32,18 @@ pub fn run_command_line(
     tty: bool,
     capture: bool,
 ) -> Vec<CommandResult> {
+    if parsers::locust::is_func_def(line) || parsers::locust::is_case_stmt(line) {
+        // function definitions and case statements typed in prompt or given
+        // by `-c`, where positional parameters are kept like in other lines
+        let args = vec!["cicada".to_string()];
+        return scripting::run_lines(sh, line, &args, capture);
+    }
+
     let mut cr_list = Vec::new();
     let mut status = 0;
     let mut sep = String::new();
-    for token in parsers::parser_line::line_to_cmds(line) {
+    let cmds = parsers::parser_line::line_to_cmds(line);
+    for (i, token) in cmds.iter().enumerate() {
         if token == ";" || token == "&&" || token == "||" {
             sep = token.clone();
             continue;
@@ -47,21 +59,77 @@ pub fn run_command_line(
         status = cr.status;
         sh.previous_status = status;
         cr_list.push(cr);
+
+        // only the last command of `&&` and `||` lists could trigger ERR
+        let is_list_end = cmds.get(i + 1).is_none_or(|x| x == ";");
+        if status != 0 && is_list_end && !sh.in_condition {
+            run_trap(sh, "ERR");
+        }
+        run_pending_traps(sh);
+        if sh.is_func_returning() {
+            break;
+        }
     }
     cr_list
 }
 
-fn line_to_tokens(sh: &mut Shell, line: &str) -> (Tokens, HashMap<String, String>) {
+/// Run the handler defined by the `trap` builtin, keeping the value of `$?`.
+fn run_trap(sh: &mut Shell, name: &str) {
+    if sh.in_trap {
+        return;
+    }
+    let cmd = match sh.traps.get(name) {
+        Some(x) if !x.is_empty() => x.clone(),
+        _ => return,
+    };
+    let status = sh.previous_status;
+    sh.in_trap = true;
+    run_command_line(sh, &cmd, true, false);
+    sh.in_trap = false;
+    sh.previous_status = status;
+}
+
+/// Run the `trap` handlers of the signals received.
+pub fn run_pending_traps(sh: &mut Shell) {
+    for sig in signals::pop_pending_traps() {
+        if let Ok(x) = Signal::try_from(sig) {
+            let name = x.as_str().trim_start_matches("SIG");
+            run_trap(sh, name);
+        }
+    }
+}
+
+/// Run the EXIT handler of `trap` when the shell is exiting.
+pub fn run_exit_trap(sh: &mut Shell) {
+    run_pending_traps(sh);
+    run_trap(sh, "EXIT");
+    sh.traps.remove("EXIT");
+}
+
+/// Expansion errors like `${foo:?not set}` abort the command with status
+/// 1, and exit the shell if it's not interactive.
+pub fn abort_on_expansion_error(sh: &mut Shell, e: &str) -> CommandResult {
+    println_stderr!("cicada: {}", e);
+    sh.previous_status = 1;
+    if !sh.is_interactive {
+        run_exit_trap(sh);
+        process::exit(1);
+    }
+    CommandResult::from_status(0, 1)
+}
+
+fn line_to_tokens(sh: &mut Shell, line: &str) -> Result<Tokens, String> {
     let linfo = parsers::parser_line::parse_line(line);
     let mut tokens = linfo.tokens;
-    shell::do_expansion(sh, &mut tokens);
-    let envs = drain_env_tokens(&mut tokens);
-    (tokens, envs)
+    shell::do_expansion(sh, &mut tokens)?;
+    Ok(tokens)
 }
 
 fn set_shell_vars(sh: &mut Shell, envs: &HashMap<String, String>) {
     for (name, value) in envs.iter() {
-        sh.set_env(name, value);
+        if let Err(e) = shell::assign_variable(sh, name, value) {
+            println_stderr!("cicada: {}", e);
+        }
     }
 }
 
@@ -69,8 +137,18 @@ fn set_shell_vars(sh: &mut Shell, envs: &HashMap<String, String>) {
 /// example 1: `ls`
 /// example 2: `ls | wc`
 fn run_proc(sh: &mut Shell, line: &str, tty: bool, capture: bool) -> CommandResult {
+    let cr = run_proc_line(sh, line, tty, capture);
+    core::wait_proc_substitutions(sh, cr.status);
+    cr
+}
+
+fn run_proc_line(sh: &mut Shell, line: &str, tty: bool, capture: bool) -> CommandResult {
     let log_cmd = !sh.cmd.starts_with(' ');
-    match CommandLine::from_line(line, sh) {
+    let tokens = match line_to_tokens(sh, line) {
+        Ok(x) => x,
+        Err(e) => return abort_on_expansion_error(sh, &e),
+    };
+    match CommandLine::from_tokens(line, tokens) {
         Ok(cl) => {
             if cl.is_empty() {
                 // for commands with only envs, e.g.
@@ -99,14 +177,19 @@ fn run_proc(sh: &mut Shell, line: &str, tty: bool, capture: bool) -> CommandResu
     }
 }
 
-fn run_with_shell(sh: &mut Shell, line: &str) -> CommandResult {
-    let (tokens, envs) = line_to_tokens(sh, line);
-    if tokens.is_empty() {
-        set_shell_vars(sh, &envs);
-        return CommandResult::new();
-    }
+/// Run the command line with the shell, and capture its output.
+pub fn run_with_shell(sh: &mut Shell, line: &str) -> CommandResult {
+    let tokens = match line_to_tokens(sh, line) {
+        Ok(x) => x,
+        Err(e) => return abort_on_expansion_error(sh, &e),
+    };
 
-    match CommandLine::from_line(line, sh) {
+    // the tokens are expanded already, do not parse the line again
+    let cr = match CommandLine::from_tokens(line, tokens) {
+        Ok(c) if c.is_empty() => {
+            set_shell_vars(sh, &c.envs);
+            CommandResult::new()
+        }
         Ok(c) => {
             let (term_given, cr) = core::run_pipeline(sh, &c, false, true, false);
             if term_given {
@@ -122,7 +205,9 @@ fn run_with_shell(sh: &mut Shell, line: &str) -> CommandResult {
             println_stderr!("cicada: {}", e);
             CommandResult::from_status(0, 1)
         }
-    }
+    };
+    core::wait_proc_substitutions(sh, cr.status);
+    cr
 }
 
 pub fn run(line: &str) -> CommandResult {
@@ -150,6 +235,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_run_with_shell_expand_once() {
+        let mut sh = shell::Shell::new();
+        sh.set_env("test_expand_once", "0");
+        let cr = run_with_shell(&mut sh, "echo $((test_expand_once++)) ${test_once_new:=v}");
+        assert_eq!(cr.stdout.trim(), "0 v");
+        assert_eq!(sh.get_env("test_expand_once"), Some("1".to_string()));
+
+        run_with_shell(&mut sh, "test_expand_once=5");
+        assert_eq!(sh.get_env("test_expand_once"), Some("5".to_string()));
+    }
+
     #[test]
     fn test_run_itself() {
         use std::fs::File;
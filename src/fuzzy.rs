@@ -0,0 +1,510 @@
+//! Full-screen fuzzy finder of history items, bound to `Ctrl-R`.
+use std::env;
+use std::io::{self, Write};
+
+use lineread::{Function, Prompter, Terminal};
+
+use crate::ctime;
+use crate::history;
+use crate::libs;
+
+const KEY_CTRL_C: u8 = 0x03;
+const KEY_CTRL_G: u8 = 0x07;
+const KEY_BACKSPACE: u8 = 0x08;
+const KEY_CTRL_J: u8 = 0x0a;
+const KEY_CTRL_K: u8 = 0x0b;
+const KEY_ENTER: u8 = 0x0d;
+const KEY_CTRL_N: u8 = 0x0e;
+const KEY_CTRL_P: u8 = 0x10;
+const KEY_CTRL_R: u8 = 0x12;
+const KEY_CTRL_U: u8 = 0x15;
+const KEY_CTRL_W: u8 = 0x17;
+const KEY_ESC: u8 = 0x1b;
+const KEY_DEL: u8 = 0x7f;
+
+// items are loaded from the most recent ones
+const MAX_ITEMS: usize = 20000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    Global,
+    Session,
+    Directory,
+}
+
+impl Scope {
+    fn next(self) -> Self {
+        match self {
+            Scope::Global => Scope::Session,
+            Scope::Session => Scope::Directory,
+            Scope::Directory => Scope::Global,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Scope::Global => "global",
+            Scope::Session => "session",
+            Scope::Directory => "directory",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Item {
+    pub inp: String,
+    pub count: usize,
+    pub last_used: f64,
+    /// number of times used in the current directory
+    pub count_cwd: usize,
+    /// number of times exited with status 0
+    pub count_ok: usize,
+}
+
+/// Returns the score when all chars in `query` appear in `text` in order,
+/// along with the char indices of the matches. Query terms separated by
+/// spaces can match in any order. Matching is case insensitive, unless
+/// `query` contains upper case letters.
+pub fn fuzzy_match(query: &str, text: &str) -> Option<(i64, Vec<usize>)> {
+    let ignore_case = !query.chars().any(|c| c.is_uppercase());
+    let chars: Vec<char> = if ignore_case {
+        text.chars().map(|c| c.to_ascii_lowercase()).collect()
+    } else {
+        text.chars().collect()
+    };
+
+    let mut score = 0;
+    let mut positions = Vec::new();
+    for term in query.split_whitespace() {
+        let term: Vec<char> = if ignore_case {
+            term.chars().map(|c| c.to_ascii_lowercase()).collect()
+        } else {
+            term.chars().collect()
+        };
+        let (x, pos) = match_term(&term, &chars)?;
+        score += x;
+        positions.extend(pos);
+    }
+    positions.sort();
+    positions.dedup();
+    Some((score, positions))
+}
+
+/// Try every start of the first char and keep the best one.
+fn match_term(term: &[char], chars: &[char]) -> Option<(i64, Vec<usize>)> {
+    let mut best: Option<(i64, Vec<usize>)> = None;
+    for start in 0..chars.len() {
+        if chars[start] != term[0] {
+            continue;
+        }
+
+        let mut positions = vec![start];
+        let mut idx = start + 1;
+        for c in term.iter().skip(1) {
+            match chars[idx..].iter().position(|x| x == c) {
+                Some(n) => {
+                    positions.push(idx + n);
+                    idx += n + 1;
+                }
+                None => break,
+            }
+        }
+        if positions.len() < term.len() {
+            // later starts cannot match either
+            break;
+        }
+
+        let score = score_positions(&positions, chars);
+        if best.as_ref().is_none_or(|x| score > x.0) {
+            best = Some((score, positions));
+        }
+    }
+    best
+}
+
+fn score_positions(positions: &[usize], chars: &[char]) -> i64 {
+    let mut score = 0;
+    for (i, pos) in positions.iter().enumerate() {
+        score += 16;
+        if *pos == 0 || " /-_.=".contains(chars[pos - 1]) {
+            score += 10;
+        }
+        if i > 0 {
+            let gap = (pos - positions[i - 1] - 1) as i64;
+            if gap == 0 {
+                score += 8;
+            } else {
+                score -= gap.min(10);
+            }
+        }
+    }
+    if positions.first() == Some(&0) {
+        score += 5;
+    }
+    score
+}
+
+/// Rank items by how often and how recently they are used, whether they
+/// were used in current directory, and whether they were succeeded.
+pub fn frecency(item: &Item, now: f64) -> i64 {
+    let age = now - item.last_used;
+    let weight = if age < 3600.0 {
+        8
+    } else if age < 86400.0 {
+        4
+    } else if age < 604800.0 {
+        2
+    } else {
+        1
+    };
+
+    let mut score = item.count.min(50) as i64 * weight;
+    if item.count_cwd > 0 {
+        score += 20;
+    }
+    if item.count_ok == 0 {
+        score -= 20;
+    }
+    score
+}
+
+fn load_items(scope: Scope, session_id: &str) -> Vec<Item> {
+    let cwd = match env::current_dir() {
+        Ok(x) => x.to_string_lossy().to_string(),
+        Err(_) => String::new(),
+    };
+    let (condition, value) = match scope {
+        Scope::Global => ("", ""),
+        Scope::Session => ("AND sessionid = ?2", session_id),
+        Scope::Directory => ("AND cwd = ?1", cwd.as_str()),
+    };
+    let result = history::with_conn(|conn, htable| {
+        let sql = format!(
+            "SELECT inp, COUNT(*), MAX(tsb),
+                    SUM(CASE WHEN cwd = ?1 THEN 1 ELSE 0 END),
+                    SUM(CASE WHEN rtn = 0 THEN 1 ELSE 0 END)
+             FROM {} WHERE ROWID > 0 {}
+             GROUP BY inp ORDER BY MAX(tsb) DESC LIMIT ?3",
+            htable, condition,
+        );
+        let mut stmt = conn
+            .prepare(&sql)
+            .map_err(|e| format!("prepare select error: {}", e))?;
+        let params = rusqlite::params![cwd, value, MAX_ITEMS as i64];
+        let rows = stmt
+            .query_map(params, |row| {
+                Ok(Item {
+                    inp: row.get(0)?,
+                    count: row.get::<_, i64>(1)? as usize,
+                    last_used: row.get::<_, Option<f64>>(2)?.unwrap_or(0.0),
+                    count_cwd: row.get::<_, i64>(3)? as usize,
+                    count_ok: row.get::<_, i64>(4)? as usize,
+                })
+            })
+            .map_err(|e| format!("query select error: {}", e))?;
+        Ok(rows.flatten().collect())
+    });
+    match result {
+        Ok(x) => x,
+        Err(e) => {
+            log!("fuzzy: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+struct Finder {
+    session_id: String,
+    scope: Scope,
+    items: Vec<Item>,
+    query: String,
+    /// `(index of items, positions of matched chars)`
+    matches: Vec<(usize, Vec<usize>)>,
+    selected: usize,
+    offset: usize,
+}
+
+impl Finder {
+    fn new(session_id: &str, query: &str) -> Self {
+        let mut finder = Finder {
+            session_id: session_id.to_string(),
+            scope: Scope::Global,
+            items: Vec::new(),
+            query: query.to_string(),
+            matches: Vec::new(),
+            selected: 0,
+            offset: 0,
+        };
+        finder.reload();
+        finder
+    }
+
+    fn reload(&mut self) {
+        self.items = load_items(self.scope, &self.session_id);
+        self.update_matches();
+    }
+
+    fn update_matches(&mut self) {
+        let now = ctime::DateTime::now().unix_timestamp();
+        let mut matches: Vec<(i64, usize, Vec<usize>)> = Vec::new();
+        for (i, item) in self.items.iter().enumerate() {
+            if let Some((score, positions)) = fuzzy_match(&self.query, &item.inp) {
+                matches.push((score * 4 + frecency(item, now), i, positions));
+            }
+        }
+        // items are ordered by recency already, and the sort is stable
+        matches.sort_by_key(|x| std::cmp::Reverse(x.0));
+        self.matches = matches.into_iter().map(|x| (x.1, x.2)).collect();
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    fn move_selection(&mut self, up: bool) {
+        if up && self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        } else if !up && self.selected > 0 {
+            self.selected -= 1;
+        }
+    }
+
+    /// Draw the items from bottom to top, with the query at the bottom.
+    fn draw(&mut self) -> io::Result<()> {
+        let (columns, lines) = libs::term_size::dimensions().unwrap_or((80, 24));
+        let height = lines.saturating_sub(2).max(1);
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + height {
+            self.offset = self.selected + 1 - height;
+        }
+
+        let mut buf = String::from("\x1b[H\x1b[2J");
+        for row in 0..height {
+            let idx = self.offset + height - 1 - row;
+            if let Some((i, positions)) = self.matches.get(idx) {
+                let text = &self.items[*i].inp;
+                let is_selected = idx == self.selected;
+                buf.push_str(&format_item(text, positions, columns, is_selected));
+            }
+            buf.push_str("\r\n");
+        }
+        let info = format!(
+            "  {}/{} [{}] (Ctrl-R: switch scope)",
+            self.matches.len(),
+            self.items.len(),
+            self.scope.name(),
+        );
+        buf.push_str(&format!("\x1b[2m{}\x1b[0m\r\n", truncate(&info, columns)));
+        buf.push_str(&format!("> {}", self.query));
+
+        let mut stdout = io::stdout();
+        stdout.write_all(buf.as_bytes())?;
+        stdout.flush()
+    }
+
+    /// Returns the selected input, or `None` if aborted.
+    fn run(&mut self) -> io::Result<Option<String>> {
+        loop {
+            self.draw()?;
+            let key = match read_byte()? {
+                Some(x) => x,
+                None => return Ok(None),
+            };
+            match key {
+                KEY_ENTER | KEY_CTRL_J => {
+                    let result = self.matches.get(self.selected);
+                    return Ok(result.map(|x| self.items[x.0].inp.clone()));
+                }
+                KEY_CTRL_C | KEY_CTRL_G => return Ok(None),
+                KEY_ESC => match read_escape()? {
+                    Some(b'A') => self.move_selection(true),
+                    Some(b'B') => self.move_selection(false),
+                    None => return Ok(None),
+                    _ => {}
+                },
+                KEY_CTRL_P | KEY_CTRL_K => self.move_selection(true),
+                KEY_CTRL_N => self.move_selection(false),
+                KEY_CTRL_R => {
+                    self.scope = self.scope.next();
+                    self.reload();
+                }
+                KEY_CTRL_U => {
+                    self.query.clear();
+                    self.update_matches();
+                }
+                KEY_CTRL_W => {
+                    let text = self.query.trim_end();
+                    let n = text.rfind(' ').map(|x| x + 1).unwrap_or(0);
+                    self.query.truncate(n);
+                    self.update_matches();
+                }
+                KEY_BACKSPACE | KEY_DEL => {
+                    self.query.pop();
+                    self.update_matches();
+                }
+                x if x >= 0x20 => {
+                    if let Some(c) = read_utf8(x)? {
+                        self.query.push(c);
+                        self.update_matches();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn truncate(text: &str, columns: usize) -> String {
+    text.chars().take(columns).collect()
+}
+
+fn format_item(text: &str, positions: &[usize], columns: usize, is_selected: bool) -> String {
+    let mut result = String::new();
+    if is_selected {
+        result.push_str("\x1b[7m> ");
+    } else {
+        result.push_str("  ");
+    }
+    for (i, c) in text.chars().take(columns.saturating_sub(2)).enumerate() {
+        let c = if c == '\n' || c == '\t' { ' ' } else { c };
+        if positions.contains(&i) {
+            result.push_str("\x1b[1;32m");
+            result.push(c);
+            result.push_str("\x1b[22;39m");
+        } else {
+            result.push(c);
+        }
+    }
+    if is_selected {
+        result.push_str("\x1b[0m");
+    }
+    result
+}
+
+fn wait_readable(timeout_ms: i32) -> bool {
+    let mut fds = libc::pollfd {
+        fd: 0,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, timeout_ms) > 0 }
+}
+
+/// Read a byte from stdin, which is in raw mode during reading lines.
+/// The terminal may be set with `VMIN=0`, so wait it readable first.
+fn read_byte() -> io::Result<Option<u8>> {
+    let mut buf = [0u8; 1];
+    loop {
+        if !wait_readable(-1) {
+            continue;
+        }
+        let n = unsafe { libc::read(0, buf.as_mut_ptr() as *mut libc::c_void, 1) };
+        if n == 1 {
+            return Ok(Some(buf[0]));
+        }
+        if n == 0 {
+            return Ok(None);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Returns the final byte of sequences like `ESC [ A`, or `None` when
+/// the `ESC` key itself is pressed.
+fn read_escape() -> io::Result<Option<u8>> {
+    if !wait_readable(50) {
+        return Ok(None);
+    }
+    let mut last = read_byte()?;
+    while let Some(x) = last {
+        if x.is_ascii_alphabetic() || x == b'~' || !wait_readable(50) {
+            break;
+        }
+        last = read_byte()?;
+    }
+    Ok(Some(last.unwrap_or(0)))
+}
+
+fn read_utf8(first: u8) -> io::Result<Option<char>> {
+    let len = match first {
+        x if x < 0x80 => 1,
+        x if x >> 5 == 0b110 => 2,
+        x if x >> 4 == 0b1110 => 3,
+        _ => 4,
+    };
+    let mut bytes = vec![first];
+    for _ in 1..len {
+        match read_byte()? {
+            Some(x) => bytes.push(x),
+            None => break,
+        }
+    }
+    Ok(std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|x| x.chars().next()))
+}
+
+pub struct FuzzySearchFunction {
+    pub session_id: String,
+}
+
+impl<T: Terminal> Function<T> for FuzzySearchFunction {
+    fn execute(&self, prompter: &mut Prompter<T>, _count: i32, _ch: char) -> io::Result<()> {
+        let mut finder = Finder::new(&self.session_id, prompter.buffer());
+
+        // the alternate screen keeps the prompt untouched
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h")?;
+        let result = finder.run();
+        stdout.write_all(b"\x1b[?1049l")?;
+        stdout.flush()?;
+
+        match result? {
+            Some(inp) => prompter.set_buffer(&inp),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{frecency, fuzzy_match, Item};
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert_eq!(fuzzy_match("", "ls -lh"), Some((0, vec![])));
+        assert_eq!(fuzzy_match("xyz", "ls -lh"), None);
+        assert_eq!(fuzzy_match("lh", "ls -lh").unwrap().1, vec![4, 5]);
+        assert_eq!(fuzzy_match("gco", "git checkout").unwrap().1, vec![0, 4, 9]);
+        assert_eq!(fuzzy_match("co git", "git checkout").unwrap().1.len(), 5);
+        assert_eq!(fuzzy_match("GIT", "git status"), None);
+        assert!(fuzzy_match("GIT", "GIT status").is_some());
+
+        let (s1, _) = fuzzy_match("cb", "cargo build").unwrap();
+        let (s2, _) = fuzzy_match("cb", "echo abc").unwrap();
+        assert!(s1 > s2);
+        let (s1, _) = fuzzy_match("make", "make test").unwrap();
+        let (s2, _) = fuzzy_match("make", "cmake .").unwrap();
+        assert!(s1 > s2);
+    }
+
+    #[test]
+    fn test_frecency() {
+        let now = 1700000000.0;
+        let item = |count, age, count_cwd, count_ok| Item {
+            inp: String::new(),
+            count,
+            last_used: now - age,
+            count_cwd,
+            count_ok,
+        };
+        assert!(
+            frecency(&item(1, 60.0, 0, 1), now) > frecency(&item(1, 86400.0 * 30.0, 0, 1), now)
+        );
+        assert!(frecency(&item(5, 7200.0, 0, 5), now) > frecency(&item(1, 7200.0, 0, 1), now));
+        assert!(frecency(&item(1, 7200.0, 1, 1), now) > frecency(&item(1, 7200.0, 0, 1), now));
+        assert!(frecency(&item(1, 7200.0, 0, 1), now) > frecency(&item(1, 7200.0, 0, 0), now));
+    }
+}
@@ -0,0 +1,147 @@
+//! Reading values from git config files.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::tools;
+
+/// Values with keys like `branch.main.remote`, in which the section and
+/// the name are lowercased.
+#[derive(Debug, Default)]
+pub struct Config {
+    values: Vec<(String, String)>,
+}
+
+fn parse_section(text: &str) -> String {
+    let text = text.trim();
+    match text.find('"') {
+        Some(i) => {
+            let mut subsection = String::new();
+            let mut escaped = false;
+            for c in text[i + 1..].chars() {
+                if escaped {
+                    subsection.push(c);
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == '"' {
+                    break;
+                } else {
+                    subsection.push(c);
+                }
+            }
+            format!("{}.{}", text[..i].trim().to_lowercase(), subsection)
+        }
+        None => text.to_lowercase(),
+    }
+}
+
+fn parse_value(text: &str) -> String {
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        if escaped {
+            value.push(match c {
+                'n' => '\n',
+                't' => '\t',
+                _ => c,
+            });
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if !quoted && (c == '#' || c == ';') {
+            break;
+        } else {
+            value.push(c);
+        }
+    }
+    value.trim().to_string()
+}
+
+impl Config {
+    pub fn parse(&mut self, text: &str) {
+        let mut section = String::new();
+        for line in text.lines() {
+            let mut line = line.trim();
+            if line.starts_with('[') {
+                let end = match line.find(']') {
+                    Some(x) => x,
+                    None => continue,
+                };
+                section = parse_section(&line[1..end]);
+                line = line[end + 1..].trim();
+            }
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let (name, value) = match line.find('=') {
+                Some(i) => (line[..i].trim(), parse_value(&line[i + 1..])),
+                None => (line, "true".to_string()),
+            };
+            let key = format!("{}.{}", section, name.to_lowercase());
+            self.values.push((key, value));
+        }
+    }
+
+    /// The global config files and the one of the repository.
+    pub fn load(common_dir: &Path) -> Self {
+        let home = tools::get_user_home();
+        let xdg = match env::var("XDG_CONFIG_HOME") {
+            Ok(x) if !x.is_empty() => x,
+            _ => format!("{}/.config", home),
+        };
+        let files = [
+            PathBuf::from(format!("{}/git/config", xdg)),
+            PathBuf::from(format!("{}/.gitconfig", home)),
+            common_dir.join("config"),
+        ];
+        let mut config = Config::default();
+        for file in files.iter() {
+            if let Ok(text) = fs::read_to_string(file) {
+                config.parse(&text);
+            }
+        }
+        config
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let mut config = Config::default();
+        config.parse(
+            r#"
+[core]
+    excludesFile = ~/.gitignore  # comment
+    bare
+[branch "feature/Foo"]
+    remote = origin
+    merge = refs/heads/feature/Foo
+[Alias]
+    s = "status -s ; x"
+"#,
+        );
+        assert_eq!(config.get("core.excludesfile"), Some("~/.gitignore"));
+        assert_eq!(config.get("core.bare"), Some("true"));
+        assert_eq!(config.get("branch.feature/Foo.remote"), Some("origin"));
+        assert_eq!(config.get("branch.feature/foo.remote"), None);
+        assert_eq!(config.get("alias.s"), Some("status -s ; x"));
+
+        config.parse("[branch \"feature/Foo\"]\nremote = upstream\n");
+        assert_eq!(config.get("branch.feature/Foo.remote"), Some("upstream"));
+    }
+}
@@ -0,0 +1,175 @@
+//! Matching paths against gitignore patterns.
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use glob::{MatchOptions, Pattern};
+
+use super::config::Config;
+use super::Repo;
+use crate::tools;
+
+const OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    // patterns without slashes match names in any level
+    basename: bool,
+}
+
+#[derive(Debug)]
+struct List {
+    // the dir of the .gitignore file, like "" or "src/"
+    base: String,
+    rules: Vec<Rule>,
+}
+
+fn parse_rules(text: &str) -> Vec<Rule> {
+    let mut rules = Vec::new();
+    for line in text.lines() {
+        let mut line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let negated = line.starts_with('!');
+        if negated {
+            line = &line[1..];
+        }
+        if line.starts_with("\\!") || line.starts_with("\\#") {
+            line = &line[1..];
+        }
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let basename = !line.contains('/');
+        let line = line.trim_start_matches('/');
+        if line.is_empty() {
+            continue;
+        }
+        match Pattern::new(line) {
+            Ok(pattern) => rules.push(Rule {
+                pattern,
+                negated,
+                dir_only,
+                basename,
+            }),
+            Err(e) => {
+                log!("git: bad ignore pattern {:?}: {:?}", line, e);
+            }
+        }
+    }
+    rules
+}
+
+/// Patterns from `core.excludesFile`, `info/exclude` and the .gitignore
+/// files of the dirs being walked, the later ones take precedence.
+#[derive(Debug, Default)]
+pub struct Ignore {
+    lists: Vec<List>,
+}
+
+impl Ignore {
+    pub fn new(repo: &Repo, config: &Config) -> Self {
+        let home = tools::get_user_home();
+        let excludes_file = match config.get("core.excludesfile") {
+            Some(x) => match x.strip_prefix("~/") {
+                Some(rest) => format!("{}/{}", home, rest),
+                None => x.to_string(),
+            },
+            None => match env::var("XDG_CONFIG_HOME") {
+                Ok(x) if !x.is_empty() => format!("{}/git/ignore", x),
+                _ => format!("{}/.config/git/ignore", home),
+            },
+        };
+
+        let mut ignore = Ignore::default();
+        for file in [
+            Path::new(&excludes_file),
+            &repo.common_dir.join("info/exclude"),
+        ] {
+            if let Ok(text) = fs::read_to_string(file) {
+                ignore.push(String::new(), &text);
+            }
+        }
+        ignore
+    }
+
+    pub fn push(&mut self, base: String, text: &str) {
+        self.lists.push(List {
+            base,
+            rules: parse_rules(text),
+        });
+    }
+
+    /// Load the .gitignore in the dir of the worktree, `rel_dir` is like
+    /// "" or "src/". It must be popped after the dir is walked.
+    pub fn push_dir(&mut self, workdir: &Path, rel_dir: &str) {
+        let text =
+            fs::read_to_string(workdir.join(rel_dir).join(".gitignore")).unwrap_or_default();
+        self.push(rel_dir.to_string(), &text);
+    }
+
+    pub fn pop(&mut self) {
+        self.lists.pop();
+    }
+
+    pub fn is_ignored(&self, rel_path: &str, is_dir: bool) -> bool {
+        let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+        for list in self.lists.iter().rev() {
+            let path = match rel_path.strip_prefix(&list.base) {
+                Some(x) => x,
+                None => continue,
+            };
+            for rule in list.rules.iter().rev() {
+                if rule.dir_only && !is_dir {
+                    continue;
+                }
+                let target = if rule.basename { name } else { path };
+                if rule.pattern.matches_with(target, OPTIONS) {
+                    return !rule.negated;
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_ignored() {
+        let mut ignore = Ignore::default();
+        ignore.push(
+            String::new(),
+            "*.o\n# comment\n/build/\ndocs/*.html\n!keep.o\nlogs/**\n",
+        );
+        assert!(ignore.is_ignored("a.o", false));
+        assert!(ignore.is_ignored("src/a.o", false));
+        assert!(!ignore.is_ignored("keep.o", false));
+        assert!(ignore.is_ignored("build", true));
+        assert!(!ignore.is_ignored("build", false));
+        assert!(!ignore.is_ignored("src/build", true));
+        assert!(ignore.is_ignored("docs/index.html", false));
+        assert!(!ignore.is_ignored("docs/api/index.html", false));
+        assert!(ignore.is_ignored("logs/a/b.txt", false));
+        assert!(!ignore.is_ignored("a.c", false));
+
+        ignore.push("src/".to_string(), "!a.o\n/gen\n**/tmp\n");
+        assert!(!ignore.is_ignored("src/a.o", false));
+        assert!(ignore.is_ignored("b.o", false));
+        assert!(ignore.is_ignored("src/gen", false));
+        assert!(!ignore.is_ignored("src/x/gen", false));
+        assert!(ignore.is_ignored("src/tmp", true));
+        assert!(ignore.is_ignored("src/x/tmp", true));
+        ignore.pop();
+        assert!(ignore.is_ignored("src/a.o", false));
+    }
+}
@@ -0,0 +1,222 @@
+//! Parsing the index file, i.e. the staging area.
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use super::Oid;
+
+#[derive(Debug, Default)]
+pub struct Entry {
+    pub path: String,
+    pub mode: u32,
+    pub oid: Oid,
+    pub size: u32,
+    pub mtime: (u32, u32),
+    pub ctime: (u32, u32),
+    pub ino: u32,
+    pub stage: u8,
+    pub assume_valid: bool,
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Index {
+    pub entries: Vec<Entry>,
+    /// The tree of all entries from the `TREE` extension, if it's valid.
+    pub root_tree: Option<Oid>,
+    /// Entries changed in the same second as the index was written could
+    /// be modified again without changing their stat info.
+    pub mtime: (u32, u32),
+}
+
+fn be32(data: &[u8], i: usize) -> Option<u32> {
+    let bytes = data.get(i..i + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn be16(data: &[u8], i: usize) -> Option<u16> {
+    let bytes = data.get(i..i + 2)?;
+    Some(u16::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// The root tree in the `TREE` extension, whose entries are
+/// `path NUL entry_count SP subtree_count LF sha`, the root first.
+fn parse_tree_extension(data: &[u8]) -> Option<Oid> {
+    let nul = data.iter().position(|x| *x == 0)?;
+    if nul != 0 {
+        return None;
+    }
+    let lf = data.iter().position(|x| *x == b'\n')?;
+    let counts = String::from_utf8_lossy(&data[1..lf]);
+    let entry_count: i64 = counts.split(' ').next()?.parse().ok()?;
+    if entry_count < 0 {
+        return None;
+    }
+    data.get(lf + 1..lf + 21)?.try_into().ok()
+}
+
+pub fn parse(data: &[u8]) -> Option<Index> {
+    if data.get(..4)? != b"DIRC" {
+        return None;
+    }
+    let version = be32(data, 4)?;
+    if !(2..=4).contains(&version) {
+        return None;
+    }
+    let count = be32(data, 8)? as usize;
+
+    let mut index = Index::default();
+    let mut i = 12;
+    let mut last_path: Vec<u8> = Vec::new();
+    for _ in 0..count {
+        let start = i;
+        let mut entry = Entry {
+            ctime: (be32(data, i)?, be32(data, i + 4)?),
+            mtime: (be32(data, i + 8)?, be32(data, i + 12)?),
+            ino: be32(data, i + 20)?,
+            mode: be32(data, i + 24)?,
+            size: be32(data, i + 36)?,
+            oid: data.get(i + 40..i + 60)?.try_into().ok()?,
+            ..Default::default()
+        };
+        let flags = be16(data, i + 60)?;
+        entry.assume_valid = flags & 0x8000 != 0;
+        entry.stage = ((flags >> 12) & 3) as u8;
+        i += 62;
+        if flags & 0x4000 != 0 && version >= 3 {
+            let extended = be16(data, i)?;
+            entry.skip_worktree = extended & 0x4000 != 0;
+            entry.intent_to_add = extended & 0x2000 != 0;
+            i += 2;
+        }
+
+        let path = if version == 4 {
+            // the number of bytes to strip from the previous path
+            let mut c = *data.get(i)?;
+            i += 1;
+            let mut strip = (c & 0x7f) as usize;
+            while c & 0x80 != 0 {
+                c = *data.get(i)?;
+                i += 1;
+                strip = ((strip + 1) << 7) | (c & 0x7f) as usize;
+            }
+            let len = data[i..].iter().position(|x| *x == 0)?;
+            let keep = last_path.len().checked_sub(strip)?;
+            let mut path = last_path[..keep].to_vec();
+            path.extend_from_slice(&data[i..i + len]);
+            i += len + 1;
+            path
+        } else {
+            let len = data[i..].iter().position(|x| *x == 0)?;
+            let path = data[i..i + len].to_vec();
+            // padded with 1-8 NULs to a multiple of eight bytes
+            i = start + (i + len - start + 8) / 8 * 8;
+            path
+        };
+        entry.path = String::from_utf8_lossy(&path).to_string();
+        last_path = path;
+        index.entries.push(entry);
+    }
+
+    // extensions, with the checksum of the file at the end
+    while i + 8 <= data.len().saturating_sub(20) {
+        let signature = data.get(i..i + 4)?;
+        let size = be32(data, i + 4)? as usize;
+        let body = data.get(i + 8..i + 8 + size)?;
+        match signature {
+            b"TREE" => index.root_tree = parse_tree_extension(body),
+            // entries in a shared index are not supported
+            b"link" => return None,
+            _ => {}
+        }
+        i += 8 + size;
+    }
+    Some(index)
+}
+
+pub fn read(path: &Path) -> Option<Index> {
+    let data = fs::read(path).ok()?;
+    let mut index = parse(&data)?;
+    if let Ok(d) = fs::metadata(path)
+        .and_then(|x| x.modified())
+        .map(|x| x.duration_since(UNIX_EPOCH).unwrap_or_default())
+    {
+        index.mtime = (d.as_secs() as u32, d.subsec_nanos());
+    }
+    Some(index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, flags: u16, extended: Option<u16>, version: u32, last: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        for x in [1u32, 2, 3, 4, 0, 5, 0o100644, 0, 0, 6] {
+            data.extend_from_slice(&x.to_be_bytes());
+        }
+        data.extend_from_slice(&[0xab; 20]);
+        data.extend_from_slice(&(flags | path.len() as u16).to_be_bytes());
+        if let Some(x) = extended {
+            data.extend_from_slice(&x.to_be_bytes());
+        }
+        if version == 4 {
+            data.push(last.len() as u8);
+            data.extend_from_slice(path.as_bytes());
+            data.push(0);
+        } else {
+            data.extend_from_slice(path.as_bytes());
+            data.push(0);
+            while data.len() % 8 != 0 {
+                data.push(0);
+            }
+        }
+        data
+    }
+
+    fn header(version: u32, count: u32) -> Vec<u8> {
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&version.to_be_bytes());
+        data.extend_from_slice(&count.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut data = header(3, 3);
+        data.extend(entry("a.txt", 0, None, 3, ""));
+        data.extend(entry("dir/b", 0x4000 | 0x2000, Some(0x4000), 3, ""));
+        data.extend(entry("dir/c", 0x8000, None, 3, ""));
+        let mut tree = b"\x003 1\n".to_vec();
+        tree.extend_from_slice(&[0xcd; 20]);
+        data.extend_from_slice(b"TREE");
+        data.extend_from_slice(&(tree.len() as u32).to_be_bytes());
+        data.extend(tree);
+        data.extend_from_slice(&[0; 20]);
+
+        let index = parse(&data).unwrap();
+        assert_eq!(index.entries.len(), 3);
+        assert_eq!(index.entries[0].path, "a.txt");
+        assert_eq!(index.entries[0].mode, 0o100644);
+        assert_eq!(index.entries[0].size, 6);
+        assert_eq!(index.entries[0].mtime, (3, 4));
+        assert_eq!(index.entries[1].path, "dir/b");
+        assert_eq!(index.entries[1].stage, 2);
+        assert!(index.entries[1].skip_worktree);
+        assert_eq!(index.entries[2].path, "dir/c");
+        assert!(index.entries[2].assume_valid);
+        assert_eq!(index.root_tree, Some([0xcd; 20]));
+
+        // prefix compressed paths
+        let mut data = header(4, 2);
+        data.extend(entry("dir/abc", 0, None, 4, ""));
+        data.extend(entry("xy", 0, None, 4, "abc"));
+        data.extend_from_slice(&[0; 20]);
+        let index = parse(&data).unwrap();
+        assert_eq!(index.entries[1].path, "dir/xy");
+        assert_eq!(index.root_tree, None);
+
+        assert!(parse(b"DIRC\0\0\0\x05").is_none());
+    }
+}
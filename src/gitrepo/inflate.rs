@@ -0,0 +1,295 @@
+//! A small DEFLATE (RFC 1951) decoder for zlib streams in git objects.
+//! It's a port of the canonical Huffman decoding in zlib's `puff.c`.
+
+const MAX_BITS: usize = 15;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u32,
+    count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> Result<u32, String> {
+        let mut value = self.buf;
+        while self.count < n {
+            if self.pos >= self.data.len() {
+                return Err("unexpected end of data".to_string());
+            }
+            value |= (self.data[self.pos] as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        self.buf = if n < 32 { value >> n } else { 0 };
+        self.count -= n;
+        Ok(value & ((1u64 << n) - 1) as u32)
+    }
+
+    fn align(&mut self) {
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &x in lengths {
+            counts[x as usize] += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, br: &mut BitReader) -> Result<u16, String> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= br.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err("bad huffman code".to_string())
+    }
+}
+
+fn too_large() -> String {
+    "output too large".to_string()
+}
+
+fn inflate_codes(
+    br: &mut BitReader,
+    out: &mut Vec<u8>,
+    limit: usize,
+    lencode: &Huffman,
+    distcode: &Huffman,
+) -> Result<(), String> {
+    loop {
+        let symbol = lencode.decode(br)? as usize;
+        if symbol < 256 {
+            if out.len() >= limit {
+                return Err(too_large());
+            }
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LEN_BASE.len() {
+            return Err("bad length symbol".to_string());
+        }
+        let len = LEN_BASE[symbol] as usize + br.bits(LEN_EXTRA[symbol] as u32)? as usize;
+        let symbol = distcode.decode(br)? as usize;
+        if symbol >= DIST_BASE.len() {
+            return Err("bad distance symbol".to_string());
+        }
+        let dist = DIST_BASE[symbol] as usize + br.bits(DIST_EXTRA[symbol] as u32)? as usize;
+        if dist > out.len() {
+            return Err("distance too far back".to_string());
+        }
+        if out.len() + len > limit {
+            return Err(too_large());
+        }
+        let start = out.len() - dist;
+        for i in 0..len {
+            out.push(out[start + i]);
+        }
+    }
+}
+
+fn inflate_stored(br: &mut BitReader, out: &mut Vec<u8>, limit: usize) -> Result<(), String> {
+    br.align();
+    let data = br.data;
+    if br.pos + 4 > data.len() {
+        return Err("unexpected end of data".to_string());
+    }
+    let len = u16::from_le_bytes([data[br.pos], data[br.pos + 1]]) as usize;
+    let nlen = u16::from_le_bytes([data[br.pos + 2], data[br.pos + 3]]) as usize;
+    if len != !nlen & 0xffff {
+        return Err("bad stored block".to_string());
+    }
+    br.pos += 4;
+    if br.pos + len > data.len() {
+        return Err("unexpected end of data".to_string());
+    }
+    if out.len() + len > limit {
+        return Err(too_large());
+    }
+    out.extend_from_slice(&data[br.pos..br.pos + len]);
+    br.pos += len;
+    Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (i, x) in lengths.iter_mut().enumerate() {
+        *x = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+
+fn dynamic_codes(br: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    let nlen = br.bits(5)? as usize + 257;
+    let ndist = br.bits(5)? as usize + 1;
+    let ncode = br.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err("bad counts".to_string());
+    }
+
+    let mut lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(ncode) {
+        lengths[i] = br.bits(3)? as u8;
+    }
+    let lencode = Huffman::new(&lengths);
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut index = 0;
+    while index < nlen + ndist {
+        let symbol = lencode.decode(br)?;
+        if symbol < 16 {
+            lengths[index] = symbol as u8;
+            index += 1;
+            continue;
+        }
+        let (value, repeat) = match symbol {
+            16 => {
+                if index == 0 {
+                    return Err("repeat with no first length".to_string());
+                }
+                (lengths[index - 1], 3 + br.bits(2)? as usize)
+            }
+            17 => (0, 3 + br.bits(3)? as usize),
+            _ => (0, 11 + br.bits(7)? as usize),
+        };
+        if index + repeat > nlen + ndist {
+            return Err("too many lengths".to_string());
+        }
+        for _ in 0..repeat {
+            lengths[index] = value;
+            index += 1;
+        }
+    }
+    Ok((
+        Huffman::new(&lengths[..nlen]),
+        Huffman::new(&lengths[nlen..]),
+    ))
+}
+
+/// Decompress raw DEFLATE data, which must not be larger than `limit`
+/// bytes, since the data may come from untrusted repositories.
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    let mut br = BitReader::new(data);
+    let mut out = Vec::new();
+    loop {
+        let last = br.bits(1)?;
+        match br.bits(2)? {
+            0 => inflate_stored(&mut br, &mut out, limit)?,
+            1 => {
+                let (lencode, distcode) = fixed_codes();
+                inflate_codes(&mut br, &mut out, limit, &lencode, &distcode)?;
+            }
+            2 => {
+                let (lencode, distcode) = dynamic_codes(&mut br)?;
+                inflate_codes(&mut br, &mut out, limit, &lencode, &distcode)?;
+            }
+            _ => return Err("bad block type".to_string()),
+        }
+        if last == 1 {
+            return Ok(out);
+        }
+    }
+}
+
+/// Decompress zlib data up to `limit` bytes, without checking its checksum.
+pub fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+    if data.len() < 2
+        || data[0] & 0x0f != 8
+        || !u16::from_be_bytes([data[0], data[1]]).is_multiple_of(31)
+    {
+        return Err("bad zlib header".to_string());
+    }
+    inflate(&data[2..], limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zlib_decompress() {
+        // python3 -c 'import zlib; print(list(zlib.compress(b"hello hello hello")))'
+        let data = [
+            120, 156, 203, 72, 205, 201, 201, 87, 200, 64, 144, 0, 58, 46, 6, 125,
+        ];
+        assert_eq!(zlib_decompress(&data, 17).unwrap(), b"hello hello hello");
+        assert!(zlib_decompress(&data, 16).is_err());
+
+        // stored block, python3 -c 'import zlib; print(list(zlib.compress(b"abc", 0)))'
+        let data = [120, 1, 1, 3, 0, 252, 255, 97, 98, 99, 2, 77, 1, 39];
+        assert_eq!(zlib_decompress(&data, 3).unwrap(), b"abc");
+        assert!(zlib_decompress(&data, 2).is_err());
+
+        assert!(zlib_decompress(&data[..6], 3).is_err());
+        assert!(zlib_decompress(b"not zlib", 3).is_err());
+    }
+}
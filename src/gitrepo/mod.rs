@@ -0,0 +1,264 @@
+//! Read-only access to git repositories for prompt items, by reading the
+//! files under `.git` directly instead of running `git`.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub mod config;
+pub mod ignore;
+pub mod index;
+pub mod inflate;
+pub mod objects;
+pub mod sha1;
+pub mod status;
+
+pub type Oid = [u8; 20];
+
+pub fn to_hex(oid: &[u8]) -> String {
+    oid.iter().map(|x| format!("{:02x}", x)).collect()
+}
+
+pub fn parse_hex(text: &str) -> Option<Oid> {
+    let text = text.trim();
+    if text.len() != 40 {
+        return None;
+    }
+    let mut oid = [0u8; 20];
+    for (i, x) in oid.iter_mut().enumerate() {
+        *x = u8::from_str_radix(text.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(oid)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Head {
+    /// The branch name, which may not have commits yet.
+    Branch(String),
+    Detached(Oid),
+}
+
+#[derive(Debug)]
+pub struct Repo {
+    pub workdir: PathBuf,
+    /// `.git` or `.git/worktrees/<name>` of linked worktrees, where HEAD,
+    /// the index and the files of the operation in progress are.
+    pub git_dir: PathBuf,
+    /// Where refs, objects and config are shared by all worktrees.
+    pub common_dir: PathBuf,
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|x| x.trim().to_string())
+}
+
+/// Find the repository containing the directory. `.git` can be a file
+/// pointing to the real git dir, for worktrees and submodules.
+pub fn discover(dir: &Path) -> Option<Repo> {
+    let mut dir = dir.to_path_buf();
+    loop {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() && dot_git.join("HEAD").is_file() {
+            return Some(Repo::open(&dir, dot_git));
+        }
+        if dot_git.is_file() {
+            let text = read_trimmed(&dot_git)?;
+            let path = text.strip_prefix("gitdir:")?.trim();
+            return Some(Repo::open(&dir, dir.join(path)));
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+impl Repo {
+    fn open(workdir: &Path, git_dir: PathBuf) -> Self {
+        let common_dir = match read_trimmed(&git_dir.join("commondir")) {
+            Some(x) => git_dir.join(x),
+            None => git_dir.clone(),
+        };
+        Repo {
+            workdir: workdir.to_path_buf(),
+            git_dir,
+            common_dir,
+        }
+    }
+
+    pub fn head(&self) -> Option<Head> {
+        let text = read_trimmed(&self.git_dir.join("HEAD"))?;
+        match text.strip_prefix("ref:") {
+            Some(name) => {
+                let name = name.trim();
+                let branch = name.strip_prefix("refs/heads/").unwrap_or(name);
+                Some(Head::Branch(branch.to_string()))
+            }
+            None => parse_hex(&text).map(Head::Detached),
+        }
+    }
+
+    /// Refs like `HEAD` and `refs/bisect/*` belong to the worktree.
+    fn ref_path(&self, name: &str) -> PathBuf {
+        let per_worktree = !name.starts_with("refs/")
+            || name.starts_with("refs/bisect/")
+            || name.starts_with("refs/worktree/")
+            || name.starts_with("refs/rewritten/");
+        if per_worktree {
+            self.git_dir.join(name)
+        } else {
+            self.common_dir.join(name)
+        }
+    }
+
+    fn packed_ref(&self, name: &str) -> Option<String> {
+        let text = fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+        for line in text.lines() {
+            if line.starts_with('#') || line.starts_with('^') {
+                continue;
+            }
+            let mut tokens = line.splitn(2, ' ');
+            let oid = tokens.next()?;
+            if tokens.next() == Some(name) {
+                return Some(oid.to_string());
+            }
+        }
+        None
+    }
+
+    /// Resolve the ref, e.g. `HEAD` or `refs/heads/main`, following
+    /// symbolic refs.
+    pub fn resolve_ref(&self, name: &str) -> Option<Oid> {
+        let mut name = name.to_string();
+        for _ in 0..5 {
+            let text = match read_trimmed(&self.ref_path(&name)) {
+                Some(x) => x,
+                None => self.packed_ref(&name)?,
+            };
+            match text.strip_prefix("ref:") {
+                Some(x) => name = x.trim().to_string(),
+                None => return parse_hex(&text),
+            }
+        }
+        None
+    }
+
+    /// The operation in progress, like `REBASE-i 2/5` or `MERGING`.
+    pub fn state(&self) -> String {
+        let dir = &self.git_dir;
+        let progress =
+            |a: &str, b: &str| match (read_trimmed(&dir.join(a)), read_trimmed(&dir.join(b))) {
+                (Some(step), Some(total)) => format!(" {}/{}", step, total),
+                _ => String::new(),
+            };
+
+        if dir.join("rebase-merge").is_dir() {
+            let name = if dir.join("rebase-merge/interactive").exists() {
+                "REBASE-i"
+            } else {
+                "REBASE-m"
+            };
+            return format!(
+                "{}{}",
+                name,
+                progress("rebase-merge/msgnum", "rebase-merge/end")
+            );
+        }
+        if dir.join("rebase-apply").is_dir() {
+            let name = if dir.join("rebase-apply/rebasing").exists() {
+                "REBASE"
+            } else if dir.join("rebase-apply/applying").exists() {
+                "AM"
+            } else {
+                "AM/REBASE"
+            };
+            return format!(
+                "{}{}",
+                name,
+                progress("rebase-apply/next", "rebase-apply/last")
+            );
+        }
+        let states = [
+            ("MERGE_HEAD", "MERGING"),
+            ("CHERRY_PICK_HEAD", "CHERRY-PICKING"),
+            ("REVERT_HEAD", "REVERTING"),
+            ("BISECT_LOG", "BISECTING"),
+        ];
+        for (file, name) in states.iter() {
+            if dir.join(file).exists() {
+                return name.to_string();
+            }
+        }
+        String::new()
+    }
+
+    pub fn stash_count(&self) -> usize {
+        match fs::read_to_string(self.common_dir.join("logs/refs/stash")) {
+            Ok(x) => x.lines().filter(|x| !x.is_empty()).count(),
+            Err(_) => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn create(dir: &Path, files: &[(&str, &str)]) {
+        for (path, text) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, text).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_repo() {
+        let dir = env::temp_dir().join(format!("cicada-test-gitrepo-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let oid = "ce013625030ba8dba906f756967f9e9ca394464a";
+        create(
+            &dir,
+            &[
+                ("main/.git/HEAD", "ref: refs/heads/main\n"),
+                ("main/.git/refs/heads/main", &format!("{}\n", oid)),
+                (
+                    "main/.git/packed-refs",
+                    &format!("# pack-refs\n{} refs/heads/old\n^{}\n", oid, oid),
+                ),
+                ("main/.git/logs/refs/stash", "a\nb\n"),
+                ("main/.git/worktrees/wt/HEAD", &format!("{}\n", oid)),
+                ("main/.git/worktrees/wt/commondir", "../..\n"),
+                ("main/.git/worktrees/wt/MERGE_HEAD", ""),
+                ("main/sub/file", ""),
+                ("wt/.git", "gitdir: ../main/.git/worktrees/wt\n"),
+            ],
+        );
+
+        let repo = discover(&dir.join("main/sub")).unwrap();
+        assert_eq!(repo.workdir, dir.join("main"));
+        assert_eq!(repo.head(), Some(Head::Branch("main".to_string())));
+        assert_eq!(repo.resolve_ref("HEAD"), parse_hex(oid));
+        assert_eq!(repo.resolve_ref("refs/heads/old"), parse_hex(oid));
+        assert_eq!(repo.resolve_ref("refs/heads/none"), None);
+        assert_eq!(repo.stash_count(), 2);
+        assert_eq!(repo.state(), "");
+
+        let repo = discover(&dir.join("wt")).unwrap();
+        assert_eq!(repo.head(), Some(Head::Detached(parse_hex(oid).unwrap())));
+        assert_eq!(repo.resolve_ref("refs/heads/main"), parse_hex(oid));
+        assert_eq!(repo.stash_count(), 2);
+        assert_eq!(repo.state(), "MERGING");
+
+        create(
+            &dir,
+            &[
+                ("main/.git/rebase-merge/interactive", ""),
+                ("main/.git/rebase-merge/msgnum", "2\n"),
+                ("main/.git/rebase-merge/end", "5\n"),
+            ],
+        );
+        let repo = discover(&dir.join("main")).unwrap();
+        assert_eq!(repo.state(), "REBASE-i 2/5");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,392 @@
+//! Reading commits and trees from loose objects and pack files.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use super::inflate;
+use super::{parse_hex, to_hex, Oid};
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+const MAX_DELTA_DEPTH: usize = 64;
+// larger objects are not read, they are not trees or commits anyway
+const MAX_OBJECT_SIZE: usize = 64 << 20;
+// delta bases kept around, which are often shared by objects nearby
+const MAX_CACHED: usize = 256;
+
+// the type and data of an object
+type Object = (u8, Vec<u8>);
+
+#[derive(Debug, PartialEq)]
+pub struct Commit {
+    pub tree: Oid,
+    pub parents: Vec<Oid>,
+    pub time: i64,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct TreeEntry {
+    pub mode: u32,
+    pub name: String,
+    pub oid: Oid,
+}
+
+fn be32(data: &[u8], i: usize) -> u32 {
+    u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]])
+}
+
+struct Pack {
+    file: File,
+    len: u64,
+    idx: Vec<u8>,
+    count: usize,
+}
+
+impl Pack {
+    /// Only version 2 of the pack index is supported, which is the
+    /// default since git 1.5.2.
+    fn open(idx_path: &Path) -> Option<Self> {
+        let idx = fs::read(idx_path).ok()?;
+        if idx.len() < 8 + 1024 || idx[..4] != [0xff, b't', b'O', b'c'] || be32(&idx, 4) != 2 {
+            return None;
+        }
+        let count = be32(&idx, 8 + 255 * 4) as usize;
+        if idx.len() < 8 + 1024 + count * 28 {
+            return None;
+        }
+        let file = File::open(idx_path.with_extension("pack")).ok()?;
+        let len = file.metadata().ok()?.len();
+        Some(Pack {
+            file,
+            len,
+            idx,
+            count,
+        })
+    }
+
+    fn find(&self, oid: &Oid) -> Option<u64> {
+        let first = oid[0] as usize;
+        let mut lo = if first == 0 {
+            0
+        } else {
+            be32(&self.idx, 8 + (first - 1) * 4) as usize
+        };
+        let mut hi = be32(&self.idx, 8 + first * 4) as usize;
+        let shas = 8 + 1024;
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let sha = &self.idx[shas + mid * 20..shas + mid * 20 + 20];
+            match sha.cmp(&oid[..]) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => {
+                    let offset = be32(&self.idx, shas + self.count * 24 + mid * 4);
+                    if offset & 0x8000_0000 == 0 {
+                        return Some(offset as u64);
+                    }
+                    let i = shas + self.count * 28 + (offset & 0x7fff_ffff) as usize * 8;
+                    let bytes = self.idx.get(i..i + 8)?;
+                    return Some(u64::from_be_bytes(bytes.try_into().ok()?));
+                }
+            }
+        }
+        None
+    }
+
+    fn read_at(&self, offset: u64, len: u64) -> Option<Vec<u8>> {
+        if offset >= self.len {
+            return None;
+        }
+        let mut buf = vec![0u8; len.min(self.len - offset) as usize];
+        self.file.read_exact_at(&mut buf, offset).ok()?;
+        Some(buf)
+    }
+
+    /// Inflate the data at the offset, whose size is known but its
+    /// compressed size is not.
+    fn inflate_at(&self, offset: u64, size: usize) -> Option<Vec<u8>> {
+        if size > MAX_OBJECT_SIZE {
+            return None;
+        }
+        let mut len = size as u64 + size as u64 / 8 + 64;
+        loop {
+            let chunk = self.read_at(offset, len)?;
+            match inflate::zlib_decompress(&chunk, size) {
+                Ok(x) => return Some(x),
+                Err(_) if offset + len < self.len => len *= 4,
+                Err(e) => {
+                    log!("git: inflate pack data error: {}", e);
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+/// Build the object from its base and the delta data.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Option<Vec<u8>> {
+    let mut i = 0;
+    let read_size = |i: &mut usize| -> Option<usize> {
+        let mut size = 0;
+        let mut shift = 0;
+        loop {
+            let c = *delta.get(*i)?;
+            *i += 1;
+            size |= ((c & 0x7f) as usize) << shift;
+            shift += 7;
+            if c & 0x80 == 0 {
+                return Some(size);
+            }
+        }
+    };
+    if read_size(&mut i)? != base.len() {
+        return None;
+    }
+    let result_size = read_size(&mut i)?;
+    if result_size > MAX_OBJECT_SIZE {
+        return None;
+    }
+
+    let mut result = Vec::with_capacity(result_size);
+    while i < delta.len() {
+        if result.len() > result_size {
+            return None;
+        }
+        let op = delta[i];
+        i += 1;
+        if op & 0x80 != 0 {
+            let mut offset = 0;
+            let mut size = 0;
+            for bit in 0..7 {
+                if op & (1 << bit) != 0 {
+                    let c = *delta.get(i)? as usize;
+                    i += 1;
+                    if bit < 4 {
+                        offset |= c << (8 * bit);
+                    } else {
+                        size |= c << (8 * (bit - 4));
+                    }
+                }
+            }
+            if size == 0 {
+                size = 0x10000;
+            }
+            result.extend_from_slice(base.get(offset..offset + size)?);
+        } else if op != 0 {
+            let size = op as usize;
+            result.extend_from_slice(delta.get(i..i + size)?);
+            i += size;
+        } else {
+            return None;
+        }
+    }
+    if result.len() != result_size {
+        return None;
+    }
+    Some(result)
+}
+
+pub struct ObjectDb {
+    dirs: Vec<PathBuf>,
+    packs: Vec<Pack>,
+    cache: RefCell<HashMap<(usize, u64), Object>>,
+}
+
+impl ObjectDb {
+    /// Open the objects directory and its alternates.
+    pub fn open(objects_dir: &Path) -> Self {
+        let mut dirs = vec![objects_dir.to_path_buf()];
+        if let Ok(text) = fs::read_to_string(objects_dir.join("info/alternates")) {
+            for line in text.lines() {
+                let line = line.trim();
+                if !line.is_empty() && !line.starts_with('#') {
+                    dirs.push(objects_dir.join(line));
+                }
+            }
+        }
+
+        let mut packs = Vec::new();
+        for dir in dirs.iter() {
+            let entries = match fs::read_dir(dir.join("pack")) {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|x| x == "idx") {
+                    if let Some(pack) = Pack::open(&path) {
+                        packs.push(pack);
+                    }
+                }
+            }
+        }
+        ObjectDb {
+            dirs,
+            packs,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn read_loose(&self, oid: &Oid) -> Option<Object> {
+        let hex = to_hex(oid);
+        for dir in self.dirs.iter() {
+            let data = match fs::read(dir.join(&hex[..2]).join(&hex[2..])) {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            // the size is in the compressed header, plus room for it
+            let data = inflate::zlib_decompress(&data, MAX_OBJECT_SIZE + 32).ok()?;
+            let nul = data.iter().position(|x| *x == 0)?;
+            let header = String::from_utf8_lossy(&data[..nul]);
+            let size = header.split(' ').nth(1)?.parse::<usize>().ok()?;
+            if size != data.len() - nul - 1 {
+                return None;
+            }
+            let kind = match header.split(' ').next()? {
+                "commit" => OBJ_COMMIT,
+                "tree" => OBJ_TREE,
+                "blob" => OBJ_BLOB,
+                "tag" => OBJ_TAG,
+                _ => return None,
+            };
+            return Some((kind, data[nul + 1..].to_vec()));
+        }
+        None
+    }
+
+    fn read_packed(&self, pack_index: usize, offset: u64, depth: usize) -> Option<Object> {
+        if depth > MAX_DELTA_DEPTH {
+            return None;
+        }
+        if let Some(x) = self.cache.borrow().get(&(pack_index, offset)) {
+            return Some(x.clone());
+        }
+
+        let pack = &self.packs[pack_index];
+        let header = pack.read_at(offset, 32)?;
+        let mut i = 0;
+        let mut c = *header.first()?;
+        let kind = (c >> 4) & 7;
+        let mut size = (c & 15) as usize;
+        let mut shift = 4;
+        while c & 0x80 != 0 {
+            i += 1;
+            c = *header.get(i)?;
+            size |= ((c & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+        i += 1;
+
+        let object = match kind {
+            OBJ_OFS_DELTA => {
+                let mut c = *header.get(i)?;
+                i += 1;
+                let mut distance = (c & 0x7f) as u64;
+                while c & 0x80 != 0 {
+                    c = *header.get(i)?;
+                    i += 1;
+                    distance = ((distance + 1) << 7) | (c & 0x7f) as u64;
+                }
+                let delta = pack.inflate_at(offset + i as u64, size)?;
+                let base_offset = offset.checked_sub(distance)?;
+                let (kind, base) = self.read_packed(pack_index, base_offset, depth + 1)?;
+                (kind, apply_delta(&base, &delta)?)
+            }
+            OBJ_REF_DELTA => {
+                let base_oid: Oid = header.get(i..i + 20)?.try_into().ok()?;
+                let delta = pack.inflate_at(offset + i as u64 + 20, size)?;
+                let (kind, base) = self.read_depth(&base_oid, depth + 1)?;
+                (kind, apply_delta(&base, &delta)?)
+            }
+            OBJ_COMMIT | OBJ_TREE | OBJ_BLOB | OBJ_TAG => {
+                (kind, pack.inflate_at(offset + i as u64, size)?)
+            }
+            _ => return None,
+        };
+
+        let mut cache = self.cache.borrow_mut();
+        if cache.len() >= MAX_CACHED {
+            cache.clear();
+        }
+        cache.insert((pack_index, offset), object.clone());
+        Some(object)
+    }
+
+    fn read_depth(&self, oid: &Oid, depth: usize) -> Option<Object> {
+        for (i, pack) in self.packs.iter().enumerate() {
+            if let Some(offset) = pack.find(oid) {
+                return self.read_packed(i, offset, depth);
+            }
+        }
+        self.read_loose(oid)
+    }
+
+    pub fn read_commit(&self, oid: &Oid) -> Option<Commit> {
+        let (kind, data) = self.read_depth(oid, 0)?;
+        if kind != OBJ_COMMIT {
+            return None;
+        }
+        let text = String::from_utf8_lossy(&data);
+        let mut commit = Commit {
+            tree: [0; 20],
+            parents: Vec::new(),
+            time: 0,
+        };
+        for line in text.lines() {
+            if line.is_empty() {
+                break;
+            }
+            if let Some(x) = line.strip_prefix("tree ") {
+                commit.tree = parse_hex(x)?;
+            } else if let Some(x) = line.strip_prefix("parent ") {
+                commit.parents.push(parse_hex(x)?);
+            } else if let Some(x) = line.strip_prefix("committer ") {
+                let mut tokens = x.rsplitn(3, ' ');
+                tokens.next();
+                commit.time = tokens.next()?.parse().ok()?;
+            }
+        }
+        Some(commit)
+    }
+
+    pub fn read_tree(&self, oid: &Oid) -> Option<Vec<TreeEntry>> {
+        let (kind, data) = self.read_depth(oid, 0)?;
+        if kind != OBJ_TREE {
+            return None;
+        }
+        let mut entries = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            let space = i + data[i..].iter().position(|x| *x == b' ')?;
+            let nul = space + data[space..].iter().position(|x| *x == 0)?;
+            let mode = u32::from_str_radix(&String::from_utf8_lossy(&data[i..space]), 8).ok()?;
+            let name = String::from_utf8_lossy(&data[space + 1..nul]).to_string();
+            let oid = data.get(nul + 1..nul + 21)?.try_into().ok()?;
+            entries.push(TreeEntry { mode, name, oid });
+            i = nul + 21;
+        }
+        Some(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_delta() {
+        let base = b"hello world";
+        // sizes 11 and 15, copy 6 bytes from 0, insert "git ", copy 5 from 6
+        let delta = [11, 15, 0x90, 6, 4, b'g', b'i', b't', b' ', 0x91, 6, 5];
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"hello git world");
+        assert_eq!(apply_delta(b"hello", &delta), None);
+        assert_eq!(apply_delta(base, &delta[..8]), None);
+    }
+}
@@ -0,0 +1,360 @@
+//! The working tree status, like a short version of `git status`.
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::time::Instant;
+
+use super::config::Config;
+use super::ignore::Ignore;
+use super::index::{self, Entry, Index};
+use super::objects::ObjectDb;
+use super::sha1::sha1;
+use super::{Head, Oid, Repo};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFGITLINK: u32 = 0o160000;
+const S_IFDIR: u32 = 0o040000;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Status {
+    pub conflicted: bool,
+    pub staged: bool,
+    pub modified: bool,
+    pub untracked: bool,
+    pub ahead: usize,
+    pub behind: usize,
+    pub stash: usize,
+    /// The operation in progress, see `Repo::state()`.
+    pub state: String,
+}
+
+/// The remote-tracking ref of the branch, from `branch.<name>.remote`
+/// and `branch.<name>.merge`.
+fn get_upstream(config: &Config, branch: &str) -> Option<String> {
+    let remote = config.get(&format!("branch.{}.remote", branch))?;
+    let merge = config.get(&format!("branch.{}.merge", branch))?;
+    if remote == "." {
+        return Some(merge.to_string());
+    }
+    let name = merge.strip_prefix("refs/heads/")?;
+    Some(format!("refs/remotes/{}/{}", remote, name))
+}
+
+const LEFT: u8 = 1;
+const RIGHT: u8 = 2;
+const BOTH: u8 = LEFT | RIGHT;
+const DONE: u8 = 4;
+
+struct Walk<'a> {
+    db: &'a ObjectDb,
+    flags: HashMap<Oid, u8>,
+    parents: HashMap<Oid, Vec<Oid>>,
+    queue: BinaryHeap<(i64, Oid)>,
+    // commits in the queue not reachable from both sides
+    pending: usize,
+}
+
+impl<'a> Walk<'a> {
+    fn mark(&mut self, oid: Oid, bits: u8) {
+        let old = self.flags.get(&oid).cloned();
+        let new = old.unwrap_or(0) | bits;
+        if old == Some(new) {
+            return;
+        }
+        self.flags.insert(oid, new);
+        match old {
+            None => {
+                let (time, parents) = match self.db.read_commit(&oid) {
+                    Some(x) => (x.time, x.parents),
+                    // e.g. beyond a shallow clone
+                    None => (0, Vec::new()),
+                };
+                self.parents.insert(oid, parents);
+                self.queue.push((time, oid));
+                if new & BOTH != BOTH {
+                    self.pending += 1;
+                }
+            }
+            Some(old) => {
+                if old & DONE == 0 && old & BOTH != BOTH && new & BOTH == BOTH {
+                    self.pending -= 1;
+                }
+            }
+        }
+    }
+}
+
+/// Count commits only reachable from `left` and only from `right`, by
+/// walking both histories newest first until the rest are common.
+fn ahead_behind(
+    db: &ObjectDb,
+    left: Oid,
+    right: Oid,
+    deadline: Instant,
+) -> Option<(usize, usize)> {
+    if left == right {
+        return Some((0, 0));
+    }
+    let mut walk = Walk {
+        db,
+        flags: HashMap::new(),
+        parents: HashMap::new(),
+        queue: BinaryHeap::new(),
+        pending: 0,
+    };
+    walk.mark(left, LEFT);
+    walk.mark(right, RIGHT);
+
+    let (mut ahead, mut behind) = (0, 0);
+    while walk.pending > 0 {
+        if Instant::now() > deadline {
+            return None;
+        }
+        let (_, oid) = walk.queue.pop()?;
+        let bits = walk.flags[&oid];
+        walk.flags.insert(oid, bits | DONE);
+        match bits & BOTH {
+            LEFT => ahead += 1,
+            RIGHT => behind += 1,
+            _ => {}
+        }
+        if bits & BOTH != BOTH {
+            walk.pending -= 1;
+        }
+        for parent in walk.parents.remove(&oid).unwrap_or_default() {
+            walk.mark(parent, bits & BOTH);
+        }
+    }
+    Some((ahead, behind))
+}
+
+/// Whether the file differs from its entry, by checking the stat info
+/// first and the content if the info is not reliable.
+fn is_modified(workdir: &Path, entry: &Entry, index_mtime: (u32, u32)) -> bool {
+    if entry.assume_valid || entry.skip_worktree || entry.mode & S_IFMT == S_IFGITLINK {
+        return false;
+    }
+    if entry.intent_to_add {
+        return true;
+    }
+    let path = workdir.join(&entry.path);
+    let meta = match fs::symlink_metadata(&path) {
+        Ok(x) => x,
+        Err(_) => return true,
+    };
+    let is_link = entry.mode & S_IFMT == S_IFLNK;
+    if meta.file_type().is_symlink() != is_link || meta.is_dir() {
+        return true;
+    }
+    if meta.size() as u32 != entry.size {
+        return true;
+    }
+    if !is_link && (meta.mode() & 0o100 != 0) != (entry.mode & 0o100 != 0) {
+        return true;
+    }
+
+    let same_time = |secs: i64, nsecs: i64, x: (u32, u32)| {
+        secs as u32 == x.0 && (x.1 == 0 || nsecs as u32 == x.1)
+    };
+    let racy = entry.mtime >= index_mtime;
+    if !racy
+        && same_time(meta.mtime(), meta.mtime_nsec(), entry.mtime)
+        && same_time(meta.ctime(), meta.ctime_nsec(), entry.ctime)
+        && (entry.ino == 0 || meta.ino() as u32 == entry.ino)
+    {
+        return false;
+    }
+
+    let content = if is_link {
+        match fs::read_link(&path) {
+            Ok(x) => x.to_string_lossy().as_bytes().to_vec(),
+            Err(_) => return true,
+        }
+    } else {
+        match fs::read(&path) {
+            Ok(x) => x,
+            Err(_) => return true,
+        }
+    };
+    let mut blob = format!("blob {}\0", content.len()).into_bytes();
+    blob.extend(content);
+    sha1(&blob) != entry.oid
+}
+
+fn flatten_tree(
+    db: &ObjectDb,
+    oid: &Oid,
+    prefix: &str,
+    result: &mut HashMap<String, (u32, Oid)>,
+    deadline: Instant,
+) -> Option<()> {
+    if Instant::now() > deadline {
+        return None;
+    }
+    for entry in db.read_tree(oid)? {
+        let path = format!("{}{}", prefix, entry.name);
+        if entry.mode & S_IFMT == S_IFDIR {
+            flatten_tree(db, &entry.oid, &format!("{}/", path), result, deadline)?;
+        } else {
+            result.insert(path, (entry.mode, entry.oid));
+        }
+    }
+    Some(())
+}
+
+/// Whether the index differs from the tree of HEAD.
+fn is_staged(db: &ObjectDb, index: &Index, head: Option<Oid>, deadline: Instant) -> Option<bool> {
+    let entries: Vec<&Entry> = index.entries.iter().filter(|x| !x.intent_to_add).collect();
+    let commit = match head {
+        Some(x) => db.read_commit(&x)?,
+        None => return Some(!entries.is_empty()),
+    };
+    if index.root_tree == Some(commit.tree) {
+        return Some(false);
+    }
+
+    let mut tree = HashMap::new();
+    flatten_tree(db, &commit.tree, "", &mut tree, deadline)?;
+    if tree.len() != entries.len() {
+        return Some(true);
+    }
+    for entry in entries {
+        if tree.get(&entry.path) != Some(&(entry.mode, entry.oid)) {
+            return Some(true);
+        }
+    }
+    Some(false)
+}
+
+struct Walker<'a> {
+    workdir: &'a Path,
+    tracked: HashSet<&'a str>,
+    ignore: Ignore,
+    deadline: Instant,
+}
+
+impl<'a> Walker<'a> {
+    /// Whether there is any untracked file which is not ignored in the
+    /// dir, `rel_dir` is like "" or "src/". Empty dirs don't count.
+    fn has_untracked(&mut self, rel_dir: &str) -> Option<bool> {
+        if Instant::now() > self.deadline {
+            return None;
+        }
+        let entries = match fs::read_dir(self.workdir.join(rel_dir)) {
+            Ok(x) => x,
+            Err(_) => return Some(false),
+        };
+        self.ignore.push_dir(self.workdir, rel_dir);
+        let mut found = Some(false);
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == ".git" {
+                if rel_dir.is_empty() {
+                    continue;
+                }
+                // a nested repository
+                found = Some(true);
+                break;
+            }
+            let path = format!("{}{}", rel_dir, name);
+            if self.tracked.contains(path.as_str()) {
+                continue;
+            }
+            let is_dir = entry.file_type().map(|x| x.is_dir()).unwrap_or(false);
+            if self.ignore.is_ignored(&path, is_dir) {
+                continue;
+            }
+            if !is_dir {
+                found = Some(true);
+                break;
+            }
+            match self.has_untracked(&format!("{}/", path)) {
+                Some(false) => {}
+                x => {
+                    found = x;
+                    break;
+                }
+            }
+        }
+        self.ignore.pop();
+        found
+    }
+}
+
+fn has_untracked(repo: &Repo, config: &Config, index: &Index, deadline: Instant) -> Option<bool> {
+    if config.get("status.showuntrackedfiles") == Some("no") {
+        return Some(false);
+    }
+    let mut walker = Walker {
+        workdir: &repo.workdir,
+        tracked: HashSet::new(),
+        ignore: Ignore::new(repo, config),
+        deadline,
+    };
+    for entry in index.entries.iter() {
+        walker.tracked.insert(&entry.path);
+    }
+    walker.has_untracked("")
+}
+
+/// Get the status of the repository, or `None` if it can't be done
+/// before the deadline.
+pub fn get_status(repo: &Repo, deadline: Instant) -> Option<Status> {
+    let config = Config::load(&repo.common_dir);
+    let db = ObjectDb::open(&repo.common_dir.join("objects"));
+    let mut status = Status {
+        state: repo.state(),
+        stash: repo.stash_count(),
+        ..Default::default()
+    };
+
+    let head = repo.resolve_ref("HEAD");
+    if let (Some(Head::Branch(branch)), Some(local)) = (repo.head(), head) {
+        if let Some(upstream) = get_upstream(&config, &branch).and_then(|x| repo.resolve_ref(&x)) {
+            let (ahead, behind) = ahead_behind(&db, local, upstream, deadline)?;
+            status.ahead = ahead;
+            status.behind = behind;
+        }
+    }
+
+    let index = index::read(&repo.git_dir.join("index")).unwrap_or_default();
+    status.conflicted = index.entries.iter().any(|x| x.stage != 0);
+    // it's checked against the deadline in the loop below
+    status.staged = is_staged(&db, &index, head, deadline).unwrap_or(false);
+    for (i, entry) in index.entries.iter().enumerate() {
+        if i % 256 == 0 && Instant::now() > deadline {
+            return None;
+        }
+        if entry.stage == 0 && is_modified(&repo.workdir, entry, index.mtime) {
+            status.modified = true;
+            break;
+        }
+    }
+    status.untracked = has_untracked(repo, &config, &index, deadline)?;
+    Some(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_upstream() {
+        let mut config = Config::default();
+        config.parse(
+            "[branch \"main\"]\nremote = origin\nmerge = refs/heads/master\n\
+             [branch \"dev\"]\nremote = .\nmerge = refs/heads/main\n",
+        );
+        assert_eq!(
+            get_upstream(&config, "main"),
+            Some("refs/remotes/origin/master".to_string())
+        );
+        assert_eq!(
+            get_upstream(&config, "dev"),
+            Some("refs/heads/main".to_string())
+        );
+        assert_eq!(get_upstream(&config, "none"), None);
+    }
+}
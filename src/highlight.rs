@@ -3,11 +3,13 @@ use std::env;
 use std::fs;
 use std::ops::Range;
 use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-use lineread::highlighting::{Highlighter, Style};
+use lineread::highlighting::{Highlighter, Style, RESET_STYLE};
 
+use crate::cmdcache;
 use crate::libs::prefix;
 use crate::parsers::parser_line;
 use crate::shell;
@@ -16,22 +18,148 @@ use crate::tools;
 #[derive(Clone)]
 pub struct CicadaHighlighter;
 
-const GREEN: &str = "\x1b[0;32m";
-
 lazy_static! {
-    static ref AVAILABLE_COMMANDS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
     static ref ALIASES: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    static ref FUNCTIONS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    static ref PROMPT_SUFFIX: Mutex<String> = Mutex::new(String::new());
+}
+
+/// Words after which another command is expected, e.g. `if ls; then pwd`.
+const KEYWORDS_BEFORE_CMD: &[&str] = &["!", "do", "elif", "else", "if", "then", "until", "while"];
+const KEYWORDS: &[&str] = &["case", "done", "esac", "fi", "for", "in"];
+
+/// Kinds of the highlighted parts of a line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    Command,
+    Keyword,
+    Error,
+    String,
+    Variable,
+    Operator,
+    Redirect,
+    Comment,
+    Path,
+    Suggestion,
+}
+
+/// The SGR parameters of each kind, e.g. `32` for green and `1;31` for
+/// bold red, which can be changed with `CICADA_HIGHLIGHT_COLORS`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Palette {
+    command: String,
+    keyword: String,
+    error: String,
+    string: String,
+    variable: String,
+    operator: String,
+    redirect: String,
+    comment: String,
+    path: String,
+    suggestion: String,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            command: "32".to_string(),
+            keyword: "35".to_string(),
+            error: "31".to_string(),
+            string: "33".to_string(),
+            variable: "36".to_string(),
+            operator: "35".to_string(),
+            redirect: "35".to_string(),
+            comment: "90".to_string(),
+            path: "4".to_string(),
+            suggestion: "90".to_string(),
+        }
+    }
+}
+
+impl Palette {
+    /// Parse settings like `command=32:error=1;31:comment=90` upon the
+    /// default palette. Unknown names and invalid values are ignored.
+    fn parse(text: &str) -> Self {
+        let mut palette = Palette::default();
+        for item in text.split(':') {
+            let (name, value) = match item.split_once('=') {
+                Some((x, y)) => (x.trim(), y.trim()),
+                None => continue,
+            };
+            if !value.chars().all(|c| c.is_ascii_digit() || c == ';') {
+                continue;
+            }
+            let value = value.to_string();
+            match name {
+                "command" => palette.command = value,
+                "keyword" => palette.keyword = value,
+                "error" => palette.error = value,
+                "string" => palette.string = value,
+                "variable" => palette.variable = value,
+                "operator" => palette.operator = value,
+                "redirect" => palette.redirect = value,
+                "comment" => palette.comment = value,
+                "path" => palette.path = value,
+                "suggestion" => palette.suggestion = value,
+                _ => {}
+            }
+        }
+        palette
+    }
+
+    fn from_env() -> Self {
+        match env::var("CICADA_HIGHLIGHT_COLORS") {
+            Ok(x) => Palette::parse(&x),
+            Err(_) => Palette::default(),
+        }
+    }
+
+    fn get(&self, kind: Kind) -> &str {
+        match kind {
+            Kind::Command => &self.command,
+            Kind::Keyword => &self.keyword,
+            Kind::Error => &self.error,
+            Kind::String => &self.string,
+            Kind::Variable => &self.variable,
+            Kind::Operator => &self.operator,
+            Kind::Redirect => &self.redirect,
+            Kind::Comment => &self.comment,
+            Kind::Path => &self.path,
+            Kind::Suggestion => &self.suggestion,
+        }
+    }
+
+    fn style(&self, kinds: &[Kind]) -> Style {
+        let params: Vec<&str> = kinds
+            .iter()
+            .map(|x| self.get(*x))
+            .filter(|x| !x.is_empty())
+            .collect();
+        if params.is_empty() {
+            Style::Default
+        } else {
+            Style::AnsiColor(format!("\x1b[0;{}m", params.join(";")))
+        }
+    }
 }
 
-/// Initialize the available commands cache by scanning PATH directories
-pub fn init_command_cache() {
-    let commands = scan_available_commands();
-    if let Ok(mut cache) = AVAILABLE_COMMANDS.lock() {
-        *cache = commands;
+/// Remember the last line of the prompt, which lineread passes through
+/// the highlighter too, so that it's not highlighted as a command.
+pub fn set_prompt_suffix(prompt: &str) {
+    let suffix = match prompt.rfind('\n') {
+        Some(pos) => &prompt[pos + 1..],
+        None => prompt,
+    };
+    if let Ok(mut x) = PROMPT_SUFFIX.lock() {
+        *x = suffix.to_string();
     }
 }
 
-/// Update aliases in the highlighter's cache
+fn is_prompt_suffix(line: &str) -> bool {
+    PROMPT_SUFFIX.lock().is_ok_and(|x| *x == line)
+}
+
+/// Update aliases and functions in the highlighter's cache
 pub fn update_aliases(sh: &shell::Shell) {
     if let Ok(mut aliases) = ALIASES.lock() {
         aliases.clear();
@@ -39,37 +167,16 @@ pub fn update_aliases(sh: &shell::Shell) {
             aliases.insert(alias_name.clone());
         }
     }
+    if let Ok(mut funcs) = FUNCTIONS.lock() {
+        *funcs = sh.funcs.keys().cloned().collect();
+    }
 }
 
-fn scan_available_commands() -> HashSet<String> {
-    let mut commands = HashSet::new();
-
-    if let Ok(path_var) = env::var("PATH") {
-        for dir_path in env::split_paths(&path_var) {
-            if !dir_path.is_dir() {
-                continue;
-            }
-
-            if let Ok(entries) = fs::read_dir(dir_path) {
-                for entry in entries.filter_map(Result::ok) {
-                    if let Ok(file_type) = entry.file_type() {
-                        if file_type.is_file() || file_type.is_symlink() {
-                            if let Ok(metadata) = entry.metadata() {
-                                // Check if file is executable
-                                if metadata.permissions().mode() & 0o111 != 0 {
-                                    if let Some(name) = entry.file_name().to_str() {
-                                        commands.insert(name.to_string());
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
+fn is_executable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(x) => x.is_file() && x.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
     }
-
-    commands
 }
 
 fn is_command(word: &str) -> bool {
@@ -81,160 +188,548 @@ fn is_command(word: &str) -> bool {
             return true;
         }
     }
-    if let Ok(commands) = AVAILABLE_COMMANDS.lock() {
-        if commands.contains(word) {
+    if let Ok(funcs) = FUNCTIONS.lock() {
+        if funcs.contains(word) {
             return true;
         }
     }
-    false
+    if word.contains('/') {
+        return is_executable(Path::new(&expand_tilde(word)));
+    }
+    cmdcache::contains(word)
 }
 
-fn find_token_range_heuristic(
-    line: &str,
-    start_byte: usize,
-    token: &(String, String),
-) -> Option<Range<usize>> {
-    let (sep, word) = token;
+fn expand_tilde(word: &str) -> String {
+    if word == "~" {
+        tools::get_user_home()
+    } else if let Some(rest) = word.strip_prefix("~/") {
+        format!("{}/{}", tools::get_user_home(), rest)
+    } else {
+        word.to_string()
+    }
+}
 
-    // Find the start of the token, skipping leading whitespace from the search start position
-    let mut search_area = &line[start_byte..];
-    let token_start_byte =
-        if let Some(non_ws_offset) = search_area.find(|c: char| !c.is_whitespace()) {
-            // Calculate the actual byte index of the first non-whitespace character
-            start_byte
-                + search_area
-                    .char_indices()
-                    .nth(non_ws_offset)
-                    .map_or(0, |(idx, _)| idx)
-        } else {
-            return None; // Only whitespace left
-        };
+fn is_existing_path(word: &str) -> bool {
+    if word.is_empty() || word.starts_with('-') || word.contains('$') {
+        return false;
+    }
+    Path::new(&expand_tilde(word)).exists()
+}
 
-    search_area = &line[token_start_byte..];
+/// The parts of a line found by `lex_line()`.
+#[derive(Debug, PartialEq, Eq)]
+enum Part {
+    // a word with the strings and variables in it
+    Word(Range<usize>, Vec<(Range<usize>, Kind)>),
+    Operator(Range<usize>),
+    Redirect(Range<usize>),
+    Comment(Range<usize>),
+}
 
-    // Estimate the end byte based on the token structure
-    let mut estimated_len = 0;
-    let mut current_search_offset = 0;
+struct Lexer<'a> {
+    line: &'a str,
+    chars: Vec<(usize, char)>,
+}
 
-    // Match separator prefix if needed (e.g., `"` or `'`)
-    if !sep.is_empty() && search_area.starts_with(sep) {
-        estimated_len += sep.len();
-        current_search_offset += sep.len();
+impl Lexer<'_> {
+    fn byte(&self, i: usize) -> usize {
+        match self.chars.get(i) {
+            Some(x) => x.0,
+            None => self.line.len(),
+        }
     }
 
-    // Match the word content
-    // Use starts_with for a basic check, assuming the word appears next
-    if search_area[current_search_offset..].starts_with(word) {
-        estimated_len += word.len();
-        current_search_offset += word.len();
+    fn char_at(&self, i: usize) -> Option<char> {
+        self.chars.get(i).map(|x| x.1)
+    }
 
-        // Match separator suffix if needed
-        if !sep.is_empty() && search_area[current_search_offset..].starts_with(sep) {
-            estimated_len += sep.len();
-        }
+    fn starts_with(&self, i: usize, text: &str) -> bool {
+        self.line[self.byte(i)..].starts_with(text)
+    }
 
-        Some(token_start_byte..(token_start_byte + estimated_len))
-    } else if word.is_empty()
-        && !sep.is_empty()
-        && search_area.starts_with(sep)
-        && search_area[sep.len()..].starts_with(sep)
-    {
-        // Handle empty quoted string like "" or ''
-        estimated_len += sep.len() * 2;
-        Some(token_start_byte..(token_start_byte + estimated_len))
-    } else {
-        // Fallback: Maybe it's just the word without quotes, or a separator like `|`
-        if search_area.starts_with(word) {
-            Some(token_start_byte..(token_start_byte + word.len()))
-        } else {
-            // Could not reliably map the token back to the original string segment
-            // This might happen with complex escapes or parser ambiguities
-            // As a basic fallback, consume up to the next space or end of line? Unsafe.
-            // Return None to signal failure for this token.
-            None
+    /// Returns the index after the matching `)` of the `(` at `i`.
+    fn skip_parens(&self, i: usize) -> usize {
+        let mut depth = 0;
+        let mut i = i;
+        while let Some(c) = self.char_at(i) {
+            match c {
+                '\\' => i += 1,
+                '\'' | '"' | '`' => {
+                    i += 1;
+                    while let Some(x) = self.char_at(i) {
+                        if x == c {
+                            break;
+                        }
+                        if x == '\\' && c != '\'' {
+                            i += 1;
+                        }
+                        i += 1;
+                    }
+                }
+                '(' => depth += 1,
+                ')' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return i + 1;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
         }
+        self.chars.len()
     }
-}
 
-impl Highlighter for CicadaHighlighter {
-    fn highlight(&self, line: &str) -> Vec<(Range<usize>, Style)> {
-        let mut styles = Vec::new();
-        if line.is_empty() {
-            return styles;
+    /// Returns the index after the variable or command substitution
+    /// starting with the `$` at `i`, e.g. `$HOME`, `${a:-b}`, `$(ls)`.
+    fn skip_dollar(&self, i: usize) -> Option<usize> {
+        match self.char_at(i + 1)? {
+            '(' => Some(self.skip_parens(i + 1)),
+            '{' => {
+                let mut j = i + 2;
+                while let Some(c) = self.char_at(j) {
+                    if c == '}' {
+                        return Some(j + 1);
+                    }
+                    j += 1;
+                }
+                Some(j)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut j = i + 2;
+                while let Some(c) = self.char_at(j) {
+                    if !c.is_ascii_alphanumeric() && c != '_' {
+                        break;
+                    }
+                    j += 1;
+                }
+                Some(j)
+            }
+            c if c.is_ascii_digit() || "?!$#@*-".contains(c) => Some(i + 2),
+            _ => None,
         }
+    }
 
-        let line_info = parser_line::parse_line(line);
-        if line_info.tokens.is_empty() {
-            // If parser returns no tokens, style whole line as default
-            styles.push((0..line.len(), Style::Default));
-            return styles;
+    /// Returns the index after the quoted string at `i`, along with the
+    /// spans of variables in double quoted strings.
+    fn skip_quoted(&self, i: usize, spans: &mut Vec<(Range<usize>, Kind)>) -> usize {
+        let quote = self.chars[i].1;
+        let mut start = i;
+        let mut j = i + 1;
+        while let Some(c) = self.char_at(j) {
+            if c == quote {
+                j += 1;
+                break;
+            }
+            if quote == '\'' {
+                j += 1;
+                continue;
+            }
+            if c == '\\' {
+                j += 2;
+                continue;
+            }
+            if c == '$' {
+                if let Some(end) = self.skip_dollar(j) {
+                    if start < j {
+                        spans.push((self.byte(start)..self.byte(j), Kind::String));
+                    }
+                    spans.push((self.byte(j)..self.byte(end), Kind::Variable));
+                    start = end;
+                    j = end;
+                    continue;
+                }
+            }
+            j += 1;
+        }
+        let j = j.min(self.chars.len());
+        if start < j {
+            spans.push((self.byte(start)..self.byte(j), Kind::String));
         }
+        j
+    }
 
-        let mut current_byte_idx = 0;
-        let mut expect_command = true;
-        let mut after_wrapper = false;
+    /// Returns the length of the redirection at `i`, e.g. `>`, `2>>`,
+    /// `2>&1`, `&>`, `<<<`.
+    fn redirect_len(&self, i: usize) -> usize {
+        let mut j = i;
+        while self.char_at(j).is_some_and(|c| c.is_ascii_digit()) {
+            j += 1;
+        }
+        if j == i && self.starts_with(j, "&>") {
+            j += 1;
+        }
+        match self.char_at(j) {
+            Some('>') | Some('<') => {}
+            _ => return 0,
+        }
+        // process substitutions like `<(ls)` are words
+        if self.char_at(j + 1) == Some('(') {
+            return 0;
+        }
+        for op in ["<<<", ">>", "<<-", "<<", ">|", ">&", "<&", ">", "<"] {
+            if self.starts_with(j, op) {
+                j += op.len();
+                break;
+            }
+        }
+        if self.line[self.byte(j - 1)..].starts_with('&') {
+            while self
+                .char_at(j)
+                .is_some_and(|c| c.is_ascii_digit() || c == '-')
+            {
+                j += 1;
+            }
+        }
+        j - i
+    }
 
-        for token in &line_info.tokens {
-            // Find the range in the original line for this token
-            match find_token_range_heuristic(line, current_byte_idx, token) {
-                Some(token_range) => {
-                    // Style potential whitespace before the token
-                    if token_range.start > current_byte_idx {
-                        styles.push((current_byte_idx..token_range.start, Style::Default));
+    fn lex_word(&self, i: usize) -> Part {
+        let mut spans = Vec::new();
+        let mut j = i;
+        while let Some(c) = self.char_at(j) {
+            match c {
+                ' ' | '\t' | '\n' | ';' | '&' | '|' | '(' | ')' => break,
+                '<' | '>' => {
+                    if self.char_at(j + 1) != Some('(') {
+                        break;
                     }
-
-                    let (_sep, word) = token;
-                    let mut current_token_style = Style::Default;
-
-                    if expect_command && !word.is_empty() {
-                        if prefix::is_env_assignment(word) {
-                            // Environment variable assignment like FOO=bar
-                            // Keep expecting command, don't change state
-                        } else if is_command(word) {
-                            current_token_style = Style::AnsiColor(GREEN.to_string());
-                            if prefix::is_wrapper_command(word) {
-                                after_wrapper = true;
-                            } else {
-                                expect_command = false;
-                                after_wrapper = false;
-                            }
-                        } else if !after_wrapper {
-                            // First token wasn't a command or assignment — stop expecting
-                            expect_command = false;
-                        }
-                        // else: after wrapper, not a command yet → keep looking
+                    let end = self.skip_parens(j + 1);
+                    spans.push((self.byte(j)..self.byte(end), Kind::Variable));
+                    j = end;
+                }
+                '\\' => j += 2,
+                '\'' | '"' => j = self.skip_quoted(j, &mut spans),
+                '`' => {
+                    let mut end = j + 1;
+                    while self.char_at(end).is_some_and(|x| x != '`') {
+                        end += 1;
                     }
+                    let end = (end + 1).min(self.chars.len());
+                    spans.push((self.byte(j)..self.byte(end), Kind::Variable));
+                    j = end;
+                }
+                '$' => match self.skip_dollar(j) {
+                    Some(end) => {
+                        spans.push((self.byte(j)..self.byte(end), Kind::Variable));
+                        j = end;
+                    }
+                    None => j += 1,
+                },
+                _ => j += 1,
+            }
+        }
+        let j = j.min(self.chars.len());
+        Part::Word(self.byte(i)..self.byte(j), spans)
+    }
+}
 
-                    styles.push((token_range.clone(), current_token_style));
+/// Split the line into words, operators, redirections and comments.
+fn lex_line(line: &str) -> Vec<Part> {
+    let lexer = Lexer {
+        line,
+        chars: line.char_indices().collect(),
+    };
+    let mut parts = Vec::new();
+    let mut i = 0;
+    while let Some(c) = lexer.char_at(i) {
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c == '#' {
+            parts.push(Part::Comment(lexer.byte(i)..line.len()));
+            break;
+        }
 
-                    // Check if this token marks the end of a command segment
-                    if ["|", "&&", "||", ";"].contains(&word.as_str()) {
-                        expect_command = true;
-                        after_wrapper = false;
-                    }
+        let len = lexer.redirect_len(i);
+        if len > 0 {
+            parts.push(Part::Redirect(lexer.byte(i)..lexer.byte(i + len)));
+            i += len;
+            continue;
+        }
+
+        let op = ["&&", "||", ";;", "|&", "|", "&", ";", "(", ")"]
+            .iter()
+            .find(|x| lexer.starts_with(i, x));
+        if let Some(op) = op {
+            parts.push(Part::Operator(lexer.byte(i)..lexer.byte(i + op.len())));
+            i += op.len();
+            continue;
+        }
+
+        let part = lexer.lex_word(i);
+        if let Part::Word(range, _) = &part {
+            i = lexer.chars.partition_point(|x| x.0 < range.end);
+        }
+        parts.push(part);
+    }
+    parts
+}
+
+/// Get the value of the word with quotes removed, e.g. `"foo bar"`.
+fn unquote_word(word: &str) -> String {
+    let tokens = parser_line::parse_line(word).tokens;
+    if tokens.len() == 1 {
+        tokens[0].1.clone()
+    } else {
+        word.to_string()
+    }
+}
+
+fn highlight_line(line: &str, palette: &Palette) -> Vec<(Range<usize>, Style)> {
+    let mut styles = Vec::new();
+    let mut expect_command = true;
+    let mut after_wrapper = false;
+    let mut after_redirect = false;
+
+    for part in lex_line(line) {
+        let (range, spans) = match part {
+            Part::Operator(range) => {
+                styles.push((range, palette.style(&[Kind::Operator])));
+                expect_command = true;
+                after_wrapper = false;
+                after_redirect = false;
+                continue;
+            }
+            Part::Redirect(range) => {
+                // no file names after ones like `2>&1` and `>&-`
+                let text = &line[range.clone()];
+                after_redirect = !text.contains(">&") && !text.contains("<&");
+                styles.push((range, palette.style(&[Kind::Redirect])));
+                continue;
+            }
+            Part::Comment(range) => {
+                styles.push((range, palette.style(&[Kind::Comment])));
+                continue;
+            }
+            Part::Word(range, spans) => (range, spans),
+        };
 
-                    current_byte_idx = token_range.end;
+        let word = unquote_word(&line[range.clone()]);
+        let mut kind = None;
+        if after_redirect {
+            after_redirect = false;
+            if is_existing_path(&word) {
+                kind = Some(Kind::Path);
+            }
+        } else if expect_command {
+            if prefix::is_env_assignment(&word) {
+                // Environment variable assignment like FOO=bar
+                // Keep expecting command, don't change state
+            } else if KEYWORDS_BEFORE_CMD.contains(&word.as_str()) {
+                kind = Some(Kind::Keyword);
+            } else if KEYWORDS.contains(&word.as_str()) {
+                kind = Some(Kind::Keyword);
+                expect_command = false;
+            } else if is_command(&word) {
+                kind = Some(Kind::Command);
+                if prefix::is_wrapper_command(&word) {
+                    after_wrapper = true;
+                } else {
+                    expect_command = false;
+                    after_wrapper = false;
                 }
-                None => {
-                    // If we can't map a token, style the rest of the line as default and stop.
-                    if current_byte_idx < line.len() {
-                        styles.push((current_byte_idx..line.len(), Style::Default));
+            } else if !after_wrapper {
+                // the first word is not a command nor an assignment
+                kind = Some(Kind::Error);
+                expect_command = false;
+            }
+            // else: after wrapper, not a command yet → keep looking
+        } else if is_existing_path(&word) {
+            kind = Some(Kind::Path);
+        }
+
+        match kind {
+            Some(Kind::Command) | Some(Kind::Keyword) | Some(Kind::Error) => {
+                styles.push((range, palette.style(&[kind.unwrap()])));
+            }
+            _ => {
+                // strings and variables in the word, underlined if it's a path
+                let extra: Vec<Kind> = kind.into_iter().collect();
+                let mut start = range.start;
+                for (span, span_kind) in spans {
+                    if span.start > start {
+                        styles.push((start..span.start, palette.style(&extra)));
                     }
-                    current_byte_idx = line.len(); // Mark as done
-                    break; // Stop processing further tokens
+                    let mut kinds = vec![span_kind];
+                    kinds.extend(extra.iter());
+                    start = span.end;
+                    styles.push((span, palette.style(&kinds)));
+                }
+                if start < range.end {
+                    styles.push((start..range.end, palette.style(&extra)));
                 }
             }
         }
+    }
+    styles
+}
 
-        // Style any remaining characters after the last processed token
-        if current_byte_idx < line.len() {
-            styles.push((current_byte_idx..line.len(), Style::Default));
+impl Highlighter for CicadaHighlighter {
+    fn highlight(&self, line: &str) -> Vec<(Range<usize>, Style)> {
+        if line.is_empty() || is_prompt_suffix(line) {
+            return Vec::new();
         }
-
-        styles
+        highlight_line(line, &Palette::from_env())
     }
 }
 
 pub fn create_highlighter() -> Arc<CicadaHighlighter> {
     Arc::new(CicadaHighlighter)
 }
+
+/// The line with escape sequences of its highlighting, e.g. to draw it
+/// again after it's accepted.
+pub fn render_line(line: &str) -> String {
+    let mut result = String::new();
+    let mut pos = 0;
+    for (range, style) in CicadaHighlighter.highlight(line) {
+        if range.start < pos || range.end > line.len() {
+            continue;
+        }
+        result.push_str(&line[pos..range.start]);
+        match style {
+            Style::AnsiColor(x) => {
+                result.push_str(&x);
+                result.push_str(&line[range.clone()]);
+                result.push_str(RESET_STYLE);
+            }
+            Style::Default => result.push_str(&line[range.clone()]),
+        }
+        pos = range.end;
+    }
+    result.push_str(&line[pos..]);
+    result
+}
+
+/// The escape sequence to draw autosuggestions with.
+pub fn suggestion_color() -> String {
+    match Palette::from_env().style(&[Kind::Suggestion]) {
+        Style::AnsiColor(x) => x,
+        Style::Default => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(line: &str) -> Vec<String> {
+        lex_line(line)
+            .iter()
+            .map(|x| match x {
+                Part::Word(r, _) => format!("w:{}", &line[r.clone()]),
+                Part::Operator(r) => format!("o:{}", &line[r.clone()]),
+                Part::Redirect(r) => format!("r:{}", &line[r.clone()]),
+                Part::Comment(r) => format!("c:{}", &line[r.clone()]),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lex_line() {
+        assert_eq!(
+            lex("ls -l | wc&&echo 'a b'; pwd # done"),
+            vec![
+                "w:ls", "w:-l", "o:|", "w:wc", "o:&&", "w:echo", "w:'a b'", "o:;", "w:pwd",
+                "c:# done",
+            ]
+        );
+        assert_eq!(
+            lex("cmd >out 2>&1 2>>err <in &>all <<<text"),
+            vec![
+                "w:cmd", "r:>", "w:out", "r:2>&1", "r:2>>", "w:err", "r:<", "w:in", "r:&>",
+                "w:all", "r:<<<", "w:text",
+            ]
+        );
+        assert_eq!(
+            lex("echo \"$(echo a | wc)\" a#b ${x:-a b} diff <(ls) x\\ y"),
+            vec![
+                "w:echo",
+                "w:\"$(echo a | wc)\"",
+                "w:a#b",
+                "w:${x:-a b}",
+                "w:diff",
+                "w:<(ls)",
+                "w:x\\ y",
+            ]
+        );
+        assert_eq!(lex("echo 'unclosed | x"), vec!["w:echo", "w:'unclosed | x"]);
+        assert_eq!(lex("echo 你好|wc"), vec!["w:echo", "w:你好", "o:|", "w:wc"]);
+    }
+
+    #[test]
+    fn test_word_spans() {
+        let line = "x\"a $HOME b\"'$c'$1";
+        let parts = lex_line(line);
+        let spans = match &parts[0] {
+            Part::Word(_, spans) => spans
+                .iter()
+                .map(|(r, k)| (&line[r.clone()], *k))
+                .collect::<Vec<_>>(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            spans,
+            vec![
+                ("\"a ", Kind::String),
+                ("$HOME", Kind::Variable),
+                (" b\"", Kind::String),
+                ("'$c'", Kind::String),
+                ("$1", Kind::Variable),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_palette() {
+        let palette = Palette::parse("command=1;32:error=41:foo=1:string=x:path=");
+        assert_eq!(palette.command, "1;32");
+        assert_eq!(palette.error, "41");
+        assert_eq!(palette.string, "33");
+        assert_eq!(palette.suggestion, "90");
+        assert_eq!(palette.style(&[Kind::Path]), Style::Default);
+        assert_eq!(
+            palette.style(&[Kind::Variable, Kind::Command]),
+            Style::AnsiColor("\x1b[0;36;1;32m".to_string())
+        );
+    }
+
+    #[test]
+    fn test_highlight_line() {
+        let palette = Palette::default();
+        let green = palette.style(&[Kind::Command]);
+        let red = palette.style(&[Kind::Error]);
+        let line = "echo \"hi\" | no-such-cmd-x > /tmp";
+        let styles = highlight_line(line, &palette);
+        let get = |text: &str| {
+            let start = line.find(text).unwrap();
+            styles
+                .iter()
+                .find(|x| x.0.start == start)
+                .map(|x| x.1.clone())
+                .unwrap()
+        };
+        assert_eq!(get("echo"), green);
+        assert_eq!(get("\"hi\""), palette.style(&[Kind::String]));
+        assert_eq!(get("|"), palette.style(&[Kind::Operator]));
+        assert_eq!(get("no-such"), red);
+        assert_eq!(get(">"), palette.style(&[Kind::Redirect]));
+        assert_eq!(get("/tmp"), palette.style(&[Kind::Path]));
+
+        let styles = highlight_line("FOO=1 nice -n 5 ls", &palette);
+        assert_eq!(styles[0].1, Style::Default);
+        assert_eq!(styles[1].1, green);
+        assert_eq!(styles[2].1, Style::Default);
+        assert_eq!(styles[4].1, green);
+    }
+
+    #[test]
+    fn test_prompt_suffix() {
+        set_prompt_suffix("user@host\nA|B ");
+        assert!(CicadaHighlighter.highlight("A|B ").is_empty());
+        assert!(!CicadaHighlighter.highlight("A|B").is_empty());
+        set_prompt_suffix("");
+    }
+}
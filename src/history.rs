@@ -1,58 +1,104 @@
-use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::io::Write;
 use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use lineread::terminal::DefaultTerminal;
 use lineread::Interface;
+use regex::Regex;
 use rusqlite::Connection as Conn;
-use rusqlite::Error::SqliteFailure;
 
+use crate::libs::{self, json};
 use crate::shell;
 use crate::tools;
 
-fn init_db(hfile: &str, htable: &str) {
+struct HistoryDb {
+    pid: i32,
+    hfile: String,
+    table: String,
+    conn: Conn,
+}
+
+lazy_static! {
+    static ref HISTORY_DB: Mutex<Option<HistoryDb>> = Mutex::new(None);
+}
+
+/// Table names cannot be bound as parameters, so only allow identifiers.
+pub fn is_valid_table_name(name: &str) -> bool {
+    libs::re::re_contains(name, r"^[a-zA-Z_][a-zA-Z0-9_]*$")
+}
+
+fn open_db(hfile: &str, htable: &str) -> Result<Conn, String> {
+    if !is_valid_table_name(htable) {
+        return Err(format!("invalid HISTORY_TABLE: {:?}", htable));
+    }
+
     let path = Path::new(hfile);
     if !path.exists() {
-        let _parent = match path.parent() {
-            Some(x) => x,
-            None => {
-                println_stderr!("cicada: history init - no parent found");
-                return;
-            }
-        };
-        let parent = match _parent.to_str() {
-            Some(x) => x,
-            None => {
-                println_stderr!("cicada: parent to_str is None");
-                return;
-            }
-        };
-        match fs::create_dir_all(parent) {
-            Ok(_) => {}
-            Err(e) => {
-                println_stderr!("cicada: histdir create error: {}", e);
-                return;
-            }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("histdir create error: {}", e))?;
         }
         match fs::File::create(hfile) {
             Ok(_) => {
                 println!("cicada: created history file: {}", hfile);
             }
-            Err(e) => {
-                println_stderr!("cicada: history: file create failed: {}", e);
-            }
+            Err(e) => return Err(format!("file create failed: {}", e)),
         }
     }
 
-    let conn = match Conn::open(hfile) {
+    let conn = Conn::open(hfile).map_err(|e| format!("open db error: {}", e))?;
+    // other cicada sessions may be writing at the same time
+    let _ = conn.busy_timeout(Duration::from_secs(2));
+    create_table(&conn, htable)?;
+    Ok(conn)
+}
+
+/// Run `f` with the connection of history database and its table name.
+/// The connection is opened once and kept for later calls. A new one is
+/// opened in forked children, or when `HISTORY_FILE` or `HISTORY_TABLE`
+/// are changed.
+pub fn with_conn<T, F>(f: F) -> Result<T, String>
+where
+    F: FnOnce(&mut Conn, &str) -> Result<T, String>,
+{
+    let hfile = get_history_file();
+    let htable = get_history_table();
+    let pid = unsafe { libc::getpid() };
+
+    let mut db = match HISTORY_DB.lock() {
         Ok(x) => x,
-        Err(e) => {
-            println_stderr!("cicada: history: open db error: {}", e);
-            return;
-        }
+        Err(e) => e.into_inner(),
     };
+    let reusable = db
+        .as_ref()
+        .is_some_and(|x| x.pid == pid && x.hfile == hfile && x.table == htable);
+    if !reusable {
+        // connections must not be used across fork(), forget the one from
+        // parent instead of closing it.
+        if let Some(old) = db.take() {
+            if old.pid != pid {
+                std::mem::forget(old);
+            }
+        }
+        let conn = open_db(&hfile, &htable)?;
+        *db = Some(HistoryDb {
+            pid,
+            hfile,
+            table: htable,
+            conn,
+        });
+    }
+
+    match db.as_mut() {
+        Some(x) => f(&mut x.conn, &x.table),
+        None => Err("no history db".to_string()),
+    }
+}
+
+/// Create the history table if not exists, and upgrade it if needed.
+pub fn create_table(conn: &Conn, htable: &str) -> Result<(), String> {
     let sql = format!(
         "
         CREATE TABLE IF NOT EXISTS {}
@@ -67,9 +113,60 @@ fn init_db(hfile: &str, htable: &str) {
     ",
         htable
     );
-    match conn.execute(&sql, []) {
-        Ok(_) => {}
-        Err(e) => println_stderr!("cicada: history: query error: {}", e),
+    conn.execute(&sql, [])
+        .map_err(|e| format!("query error: {}", e))?;
+    migrate_db(conn, htable)?;
+
+    // for the prefix queries of autosuggestions
+    let sql = format!(
+        "CREATE INDEX IF NOT EXISTS idx_{table}_inp ON {table} (inp)",
+        table = htable
+    );
+    conn.execute(&sql, [])
+        .map_err(|e| format!("create index error: {}", e))?;
+    Ok(())
+}
+
+/// Upgrade history tables created by older cicada, whose cwd is only
+/// stored in the `info` column as `dir:...|`.
+fn migrate_db(conn: &Conn, htable: &str) -> Result<(), String> {
+    let sql = "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = 'cwd'";
+    match conn.query_row(sql, [htable], |row| row.get::<_, i32>(0)) {
+        Ok(0) => {}
+        Ok(_) => return Ok(()),
+        Err(e) => return Err(format!("query error: {}", e)),
+    }
+
+    let sql = format!(
+        "
+        BEGIN;
+        ALTER TABLE {table} ADD COLUMN cwd TEXT;
+        ALTER TABLE {table} ADD COLUMN hostname TEXT;
+        ALTER TABLE {table} ADD COLUMN duration REAL;
+        ALTER TABLE {table} ADD COLUMN tty TEXT;
+        UPDATE {table} SET duration = tse - tsb WHERE tse >= tsb;
+        UPDATE {table} SET cwd = substr(info, 5, instr(info, '|') - 5)
+            WHERE info LIKE 'dir:%|%';
+        CREATE INDEX IF NOT EXISTS idx_{table}_tsb ON {table} (tsb);
+        CREATE INDEX IF NOT EXISTS idx_{table}_cwd ON {table} (cwd);
+        COMMIT;
+    ",
+        table = htable
+    );
+    if let Err(e) = conn.execute_batch(&sql) {
+        let _ = conn.execute_batch("ROLLBACK;");
+        return Err(format!("migrate error: {}", e));
+    }
+    Ok(())
+}
+
+fn get_tty_name() -> String {
+    unsafe {
+        let ptr = libc::ttyname(0);
+        if ptr.is_null() {
+            return String::new();
+        }
+        std::ffi::CStr::from_ptr(ptr).to_string_lossy().to_string()
     }
 }
 
@@ -82,56 +179,51 @@ pub fn init(rl: &mut Interface<DefaultTerminal>) {
     }
     rl.set_history_size(hist_size);
 
-    let history_table = get_history_table();
-    let hfile = get_history_file();
-
-    if !Path::new(&hfile).exists() {
-        init_db(&hfile, &history_table);
-    }
-
     let mut delete_dups = true;
     if let Ok(x) = env::var("HISTORY_DELETE_DUPS") {
         if x == "0" {
             delete_dups = false;
         }
     }
-    if delete_dups {
-        delete_duplicated_histories();
-    }
 
-    let conn = match Conn::open(&hfile) {
-        Ok(x) => x,
-        Err(e) => {
-            println_stderr!("cicada: history: conn error: {}", e);
-            return;
-        }
-    };
-    let sql = format!("SELECT inp FROM {} ORDER BY tsb;", history_table);
-    let mut stmt = match conn.prepare(&sql) {
-        Ok(x) => x,
-        Err(e) => {
-            println_stderr!("cicada: prepare select error: {}", e);
-            return;
+    let result = with_conn(|conn, htable| {
+        for inp in select_histories(conn, htable, hist_size, delete_dups)? {
+            rl.add_history(inp.trim().to_string());
         }
-    };
+        Ok(())
+    });
+    if let Err(e) = result {
+        println_stderr!("cicada: history: {}", e);
+    }
+}
 
-    let rows = match stmt.query_map([], |row| row.get(0)) {
-        Ok(x) => x,
-        Err(e) => {
-            println_stderr!("cicada: query select error: {}", e);
-            return;
-        }
+/// The last `size` items of history, oldest first. Only the last one of
+/// the duplicated items is taken if `dedup` is true, the rows in the
+/// database are never deleted, so `history stats` etc still see all of them.
+fn select_histories(
+    conn: &Conn,
+    htable: &str,
+    size: usize,
+    dedup: bool,
+) -> Result<Vec<String>, String> {
+    let sql = if dedup {
+        format!(
+            "SELECT inp FROM {} GROUP BY inp ORDER BY MAX(tsb) DESC LIMIT ?1",
+            htable
+        )
+    } else {
+        format!("SELECT inp FROM {} ORDER BY tsb DESC LIMIT ?1", htable)
     };
+    let mut stmt = conn
+        .prepare(&sql)
+        .map_err(|e| format!("prepare select error: {}", e))?;
+    let rows = stmt
+        .query_map([size as i64], |row| row.get::<_, String>(0))
+        .map_err(|e| format!("query select error: {}", e))?;
 
-    let mut dict_helper: HashMap<String, bool> = HashMap::new();
-    for x in rows.flatten() {
-        let inp: String = x;
-        if dict_helper.contains_key(&inp) {
-            continue;
-        }
-        dict_helper.insert(inp.clone(), true);
-        rl.add_history(inp.trim().to_string());
-    }
+    let mut items: Vec<String> = rows.flatten().collect();
+    items.reverse();
+    Ok(items)
 }
 
 pub fn get_history_file() -> String {
@@ -153,70 +245,362 @@ pub fn get_history_table() -> String {
     }
 }
 
-fn delete_duplicated_histories() {
-    let hfile = get_history_file();
-    let history_table = get_history_table();
-    let conn = match Conn::open(&hfile) {
-        Ok(x) => x,
-        Err(e) => {
-            println_stderr!("cicada: history: conn error: {}", e);
-            return;
+/// Whether the output of the command line should be saved into history,
+/// which is enabled by `HISTORY_CAPTURE_OUTPUT=1`, or for the lines
+/// starting with one of the prefixes in `HISTORY_CAPTURE_PREFIXES`.
+pub fn should_capture_output(line: &str) -> bool {
+    if env::var("HISTORY_CAPTURE_OUTPUT").unwrap_or_default() == "1" {
+        return true;
+    }
+    let prefixes = env::var("HISTORY_CAPTURE_PREFIXES").unwrap_or_default();
+    match_capture_prefixes(&prefixes, line)
+}
+
+fn match_capture_prefixes(prefixes: &str, line: &str) -> bool {
+    let line = line.trim_start();
+    prefixes
+        .split(':')
+        .map(|x| x.trim())
+        .any(|x| !x.is_empty() && line.starts_with(x))
+}
+
+/// Max bytes of output to keep for each command, `HISTORY_CAPTURE_SIZE`
+/// is in KB and defaults to 16.
+pub fn get_capture_size() -> usize {
+    let mut size: usize = 16;
+    if let Ok(x) = env::var("HISTORY_CAPTURE_SIZE") {
+        if let Ok(y) = x.parse::<usize>() {
+            size = y;
         }
-    };
-    let sql = format!(
-        "DELETE FROM {} WHERE rowid NOT IN (
-        SELECT MAX(rowid) FROM {} GROUP BY inp)",
-        history_table, history_table
-    );
-    match conn.execute(&sql, []) {
-        Ok(_) => {}
-        Err(e) => match e {
-            SqliteFailure(ee, msg) => {
-                if ee.extended_code == 5 {
-                    log!(
-                        "failed to delete dup histories: {}",
-                        msg.unwrap_or("db is locked?".to_owned()),
-                    );
-                    return;
-                }
-                println_stderr!("cicada: history: delete dups error: {}: {:?}", &ee, &msg);
+    }
+    size * 1024
+}
+
+/// Keep the last `size` bytes of `buf`, without starting in the middle
+/// of a UTF-8 char.
+pub fn trim_output(buf: &mut Vec<u8>, size: usize) {
+    if buf.len() <= size {
+        return;
+    }
+    let mut start = buf.len() - size;
+    while start < buf.len() && (buf[start] & 0xc0) == 0x80 {
+        start += 1;
+    }
+    buf.drain(..start);
+}
+
+/// An item of history, used in exporting and importing.
+#[derive(Debug, Default, PartialEq)]
+pub struct Item {
+    pub inp: String,
+    pub rtn: i32,
+    pub tsb: f64,
+    pub tse: f64,
+    pub sessionid: String,
+    pub cwd: String,
+    pub hostname: String,
+    pub duration: f64,
+    pub tty: String,
+    pub out: String,
+}
+
+impl Item {
+    fn new(inp: &str, tsb: f64, duration: f64, sessionid: &str) -> Self {
+        Item {
+            inp: inp.to_string(),
+            tsb,
+            tse: tsb + duration,
+            duration,
+            sessionid: sessionid.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"inp\": {}, \"rtn\": {}, \"tsb\": {}, \"tse\": {}, \"sessionid\": {}, \
+             \"cwd\": {}, \"hostname\": {}, \"duration\": {}, \"tty\": {}, \"out\": {}}}",
+            json::quote(&self.inp),
+            self.rtn,
+            self.tsb,
+            self.tse,
+            json::quote(&self.sessionid),
+            json::quote(&self.cwd),
+            json::quote(&self.hostname),
+            self.duration,
+            json::quote(&self.tty),
+            json::quote(&self.out),
+        )
+    }
+
+    pub fn from_json(line: &str) -> Result<Self, String> {
+        let obj = json::parse_object(line)?;
+        let get_str = |k: &str| obj.get(k).and_then(|x| x.as_str()).unwrap_or("");
+        let get_f64 = |k: &str| obj.get(k).and_then(|x| x.as_f64()).unwrap_or(0.0);
+        let inp = match obj.get("inp").and_then(|x| x.as_str()) {
+            Some(x) => x.to_string(),
+            None => return Err("no inp found".to_string()),
+        };
+        Ok(Item {
+            inp,
+            rtn: get_f64("rtn") as i32,
+            tsb: get_f64("tsb"),
+            tse: get_f64("tse"),
+            sessionid: get_str("sessionid").to_string(),
+            cwd: get_str("cwd").to_string(),
+            hostname: get_str("hostname").to_string(),
+            duration: get_f64("duration"),
+            tty: get_str("tty").to_string(),
+            out: get_str("out").to_string(),
+        })
+    }
+}
+
+/// Guess the format of history file from its first non-empty line.
+pub fn detect_format(text: &str) -> &'static str {
+    let line = text.lines().find(|x| !x.trim().is_empty()).unwrap_or("");
+    if line.starts_with('{') {
+        "jsonl"
+    } else if line.starts_with("- cmd: ") {
+        "fish"
+    } else if libs::re::re_contains(line, r"^: *[0-9]+:[0-9]+;") {
+        "zsh"
+    } else {
+        "bash"
+    }
+}
+
+pub fn parse_jsonl_history(text: &str) -> Result<Vec<Item>, String> {
+    let mut items = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match Item::from_json(line) {
+            Ok(x) => items.push(x),
+            Err(e) => return Err(format!("line {}: {}", i + 1, e)),
+        }
+    }
+    Ok(items)
+}
+
+/// Parse `~/.bash_history`, in which timestamps are saved as lines
+/// like `#1700000000` when `HISTTIMEFORMAT` is set. The items before any
+/// timestamp get increasing ones by their line number, just before `now`.
+pub fn parse_bash_history(text: &str, now: f64) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut tsb = None;
+    let count = text.lines().count();
+    for (i, line) in text.lines().enumerate() {
+        if let Some(ts) = line.strip_prefix('#').and_then(|x| x.parse::<f64>().ok()) {
+            tsb = Some(ts);
+            continue;
+        }
+        if !line.trim().is_empty() {
+            let tsb = tsb.unwrap_or(now - (count - i) as f64);
+            items.push(Item::new(line, tsb, 0.0, "import-bash"));
+        }
+    }
+    items
+}
+
+/// Parse zsh extended history like `: 1700000000:5;git status`, whose
+/// multi-line commands end with backslashes.
+pub fn parse_zsh_history(text: &str) -> Vec<Item> {
+    let re = Regex::new(r"^: *([0-9]+):([0-9]+);(.*)$").unwrap();
+    let mut items: Vec<Item> = Vec::new();
+    let mut continued = false;
+    for line in text.lines() {
+        if continued {
+            if let Some(item) = items.last_mut() {
+                item.inp.push('\n');
+                item.inp.push_str(line);
             }
-            _ => {
-                println_stderr!("cicada: history: delete dup error: {}", e);
+        } else if let Some(cap) = re.captures(line) {
+            let tsb = cap[1].parse::<f64>().unwrap_or(0.0);
+            let duration = cap[2].parse::<f64>().unwrap_or(0.0);
+            items.push(Item::new(&cap[3], tsb, duration, "import-zsh"));
+        } else if !line.trim().is_empty() {
+            items.push(Item::new(line, 0.0, 0.0, "import-zsh"));
+        } else {
+            continue;
+        }
+
+        let count = line.chars().rev().take_while(|c| *c == '\\').count();
+        continued = count % 2 == 1;
+        if continued {
+            if let Some(item) = items.last_mut() {
+                item.inp.pop();
             }
-        },
+        }
     }
+    items
 }
 
-pub fn add_raw(sh: &shell::Shell, line: &str, status: i32, tsb: f64, tse: f64) {
-    let hfile = get_history_file();
-    let history_table = get_history_table();
-    if !Path::new(&hfile).exists() {
-        init_db(&hfile, &history_table);
+/// zsh saves non-ASCII bytes as `0x83` followed by the byte xor 32.
+pub fn unmetafy_zsh(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut iter = bytes.iter();
+    while let Some(b) = iter.next() {
+        if *b == 0x83 {
+            if let Some(x) = iter.next() {
+                result.push(x ^ 32);
+            }
+        } else {
+            result.push(*b);
+        }
     }
+    result
+}
 
-    let conn = match Conn::open(&hfile) {
-        Ok(x) => x,
-        Err(e) => {
-            println_stderr!("cicada: history: conn error: {}", e);
-            return;
+/// Parse fish history, which is like:
+/// ```text
+/// - cmd: git status
+///   when: 1700000000
+/// ```
+pub fn parse_fish_history(text: &str) -> Vec<Item> {
+    let mut items: Vec<Item> = Vec::new();
+    for line in text.lines() {
+        if let Some(cmd) = line.strip_prefix("- cmd: ") {
+            let mut inp = String::new();
+            let mut chars = cmd.chars();
+            while let Some(c) = chars.next() {
+                match (c, chars.clone().next()) {
+                    ('\\', Some('n')) => {
+                        inp.push('\n');
+                        chars.next();
+                    }
+                    ('\\', Some('\\')) => {
+                        inp.push('\\');
+                        chars.next();
+                    }
+                    _ => inp.push(c),
+                }
+            }
+            items.push(Item::new(&inp, 0.0, 0.0, "import-fish"));
+        } else if let Some(ts) = line.trim_start().strip_prefix("when: ") {
+            if let (Some(item), Ok(ts)) = (items.last_mut(), ts.trim().parse::<f64>()) {
+                item.tsb = ts;
+                item.tse = ts;
+            }
         }
+    }
+    items
+}
+
+const INSERT_COLUMNS: &str =
+    "inp, rtn, tsb, tse, sessionid, info, cwd, hostname, duration, tty, out";
+
+/// Insert the item with a statement prepared with `INSERT_COLUMNS`.
+fn execute_insert(stmt: &mut rusqlite::Statement, item: &Item) -> rusqlite::Result<usize> {
+    // `info` is kept for older versions of cicada
+    let info = format!("dir:{}|", item.cwd);
+    let out = if item.out.is_empty() {
+        None
+    } else {
+        Some(&item.out)
     };
+    stmt.execute(rusqlite::params![
+        item.inp.trim(),
+        item.rtn,
+        item.tsb,
+        item.tse,
+        item.sessionid,
+        info,
+        item.cwd,
+        item.hostname,
+        item.duration,
+        item.tty,
+        out,
+    ])
+}
+
+pub fn insert_item(conn: &Conn, htable: &str, item: &Item) -> Result<(), String> {
     let sql = format!(
-        "INSERT INTO \
-         {} (inp, rtn, tsb, tse, sessionid, info) \
-         VALUES('{}', {}, {}, {}, '{}', 'dir:{}|');",
-        history_table,
-        str::replace(line.trim(), "'", "''"),
-        status,
+        "INSERT INTO {} ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+        htable, INSERT_COLUMNS
+    );
+    let mut stmt = conn
+        .prepare_cached(&sql)
+        .map_err(|e| format!("prepare insert error: {}", e))?;
+    execute_insert(&mut stmt, item).map_err(|e| format!("save error: {}", e))?;
+    Ok(())
+}
+
+/// Insert items skipping the ones with same input, start time and session.
+/// Returns the number of items inserted.
+pub fn insert_items(conn: &mut Conn, htable: &str, items: &[Item]) -> Result<usize, String> {
+    let tx = conn
+        .transaction()
+        .map_err(|e| format!("transaction error: {}", e))?;
+    let sql = format!(
+        "INSERT INTO {table} ({columns})
+         SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11
+         WHERE NOT EXISTS (
+             SELECT 1 FROM {table} WHERE inp = ?1 AND tsb = ?3 AND sessionid = ?5)",
+        table = htable,
+        columns = INSERT_COLUMNS,
+    );
+    let mut count = 0;
+    {
+        let mut stmt = tx
+            .prepare(&sql)
+            .map_err(|e| format!("prepare insert error: {}", e))?;
+        for item in items.iter() {
+            if item.inp.trim().is_empty() {
+                continue;
+            }
+            count +=
+                execute_insert(&mut stmt, item).map_err(|e| format!("insert error: {}", e))?;
+        }
+    }
+    tx.commit().map_err(|e| format!("commit error: {}", e))?;
+    Ok(count)
+}
+
+/// Add items into history, skipping the ones already there.
+/// Returns the number of items added.
+pub fn add_items(items: &[Item]) -> Result<usize, String> {
+    with_conn(|conn, htable| insert_items(conn, htable, items))
+}
+
+/// The most likely history input starting with `prefix`, preferring the
+/// ones succeeded, then the ones run in `cwd`, then the recent ones.
+fn query_suggestion(conn: &Conn, htable: &str, prefix: &str, cwd: &str) -> Option<String> {
+    // the range of `inp` can use the index, unlike `LIKE` or `substr()`
+    let sql = format!(
+        "SELECT inp FROM {} WHERE inp > ?1 AND inp < ?2 AND instr(inp, char(10)) = 0
+         ORDER BY rtn = 0 DESC, cwd = ?3 DESC, tsb DESC LIMIT 1",
+        htable
+    );
+    let upper = format!("{}\u{10ffff}", prefix);
+    conn.query_row(&sql, rusqlite::params![prefix, upper, cwd], |row| {
+        row.get::<_, String>(0)
+    })
+    .ok()
+}
+
+pub fn find_suggestion(prefix: &str, cwd: &str) -> Option<String> {
+    with_conn(|conn, htable| Ok(query_suggestion(conn, htable, prefix, cwd)))
+        .ok()
+        .flatten()
+}
+
+pub fn add_raw(sh: &shell::Shell, line: &str, status: i32, tsb: f64, tse: f64, out: &str) {
+    let item = Item {
+        inp: line.trim().to_string(),
+        rtn: status,
         tsb,
         tse,
-        sh.session_id,
-        sh.current_dir,
-    );
-    match conn.execute(&sql, []) {
-        Ok(_) => {}
-        Err(e) => println_stderr!("cicada: history: save error: {}", e),
+        sessionid: sh.session_id.clone(),
+        cwd: sh.current_dir.clone(),
+        hostname: tools::get_hostname(),
+        duration: tse - tsb,
+        tty: get_tty_name(),
+        out: out.to_string(),
+    };
+    if let Err(e) = with_conn(|conn, htable| insert_item(conn, htable, &item)) {
+        println_stderr!("cicada: history: {}", e);
     }
 }
 
@@ -228,6 +612,242 @@ pub fn add(
     tsb: f64,
     tse: f64,
 ) {
-    add_raw(sh, line, status, tsb, tse);
+    add_raw(sh, line, status, tsb, tse, &sh.captured_output);
     rl.add_history(line.to_string());
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_histories() {
+        let conn = Conn::open_in_memory().unwrap();
+        let htable = "test_history";
+        create_table(&conn, htable).unwrap();
+        for (i, inp) in ["ls", "pwd", "ls", "date", "pwd"].iter().enumerate() {
+            let item = Item::new(inp, i as f64, 0.0, "");
+            insert_item(&conn, htable, &item).unwrap();
+        }
+        assert_eq!(
+            select_histories(&conn, htable, 9, true).unwrap(),
+            vec!["ls", "date", "pwd"]
+        );
+        assert_eq!(
+            select_histories(&conn, htable, 2, true).unwrap(),
+            vec!["date", "pwd"]
+        );
+        assert_eq!(
+            select_histories(&conn, htable, 9, false).unwrap(),
+            vec!["ls", "pwd", "ls", "date", "pwd"]
+        );
+        assert_eq!(
+            select_histories(&conn, htable, 3, false).unwrap(),
+            vec!["ls", "date", "pwd"]
+        );
+    }
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect_format("\n{\"inp\": \"ls\"}\n"), "jsonl");
+        assert_eq!(detect_format("- cmd: ls\n  when: 1700000000\n"), "fish");
+        assert_eq!(detect_format(": 1700000000:0;ls\n"), "zsh");
+        assert_eq!(detect_format("#1700000000\nls\n"), "bash");
+        assert_eq!(detect_format("ls\n"), "bash");
+    }
+
+    #[test]
+    fn test_parse_history() {
+        let text = "ls\npwd\n#1700000000\ngit status\n\n#1700000009\nmake\n";
+        let items = parse_bash_history(text, 1800000000.0);
+        let inputs: Vec<(&str, f64)> = items.iter().map(|x| (x.inp.as_str(), x.tsb)).collect();
+        assert_eq!(
+            inputs,
+            vec![
+                ("ls", 1799999993.0),
+                ("pwd", 1799999994.0),
+                ("git status", 1700000000.0),
+                ("make", 1700000009.0)
+            ]
+        );
+
+        let text = ": 1700000000:5;cargo build\n: 1700000010:0;for i in 1 2; do\\\necho $i\\\ndone\n: 1700000020:0;echo a\\\\\n";
+        let items = parse_zsh_history(text);
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[0].inp, "cargo build");
+        assert_eq!(items[0].tse - items[0].tsb, 5.0);
+        assert_eq!(items[1].inp, "for i in 1 2; do\necho $i\ndone");
+        assert_eq!(items[2].inp, "echo a\\\\");
+        assert_eq!(
+            unmetafy_zsh(&[b'a', 0x83, 0xa3 ^ 32, b'b']),
+            vec![b'a', 0xa3, b'b']
+        );
+
+        let text = "- cmd: echo 'a\\nb' \\\\\n  when: 1700000000\n  paths:\n    - a\n- cmd: ls\n  when: 1700000001\n";
+        let items = parse_fish_history(text);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].inp, "echo 'a\nb' \\");
+        assert_eq!(items[0].tsb, 1700000000.0);
+        assert_eq!(items[1].sessionid, "import-fish");
+    }
+
+    fn select_inputs(conn: &Conn, htable: &str) -> Vec<String> {
+        let sql = format!("SELECT inp FROM {} ORDER BY ROWID", htable);
+        let mut stmt = conn.prepare(&sql).unwrap();
+        let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+        rows.flatten().collect()
+    }
+
+    #[test]
+    fn test_table_name() {
+        assert!(is_valid_table_name("cicada_history"));
+        assert!(is_valid_table_name("_h2"));
+        assert!(!is_valid_table_name(""));
+        assert!(!is_valid_table_name("2h"));
+        assert!(!is_valid_table_name("h; DROP TABLE x"));
+        assert!(!is_valid_table_name("h\"x"));
+        assert!(open_db("/tmp/cicada-never-created.sqlite", "a b").is_err());
+        assert!(!Path::new("/tmp/cicada-never-created.sqlite").exists());
+    }
+
+    #[test]
+    fn test_insert_items() {
+        let mut conn = Conn::open_in_memory().unwrap();
+        let htable = "test_history";
+        create_table(&conn, htable).unwrap();
+        create_table(&conn, htable).unwrap();
+
+        let inputs = [
+            "echo 'it''s'",
+            "echo \"a\" \\ \\' '",
+            "echo a\0b",
+            "echo 你好 🚀 é",
+            "'); DROP TABLE test_history; --",
+            "for i in 1 2\ndo\n    echo $i\ndone",
+        ];
+        for (i, inp) in inputs.iter().enumerate() {
+            let item = Item {
+                inp: inp.to_string(),
+                tsb: i as f64,
+                cwd: "/tmp/it's/目录".to_string(),
+                ..Default::default()
+            };
+            insert_item(&conn, htable, &item).unwrap();
+        }
+        assert_eq!(select_inputs(&conn, htable), inputs);
+
+        let sql = format!("SELECT DISTINCT cwd, info FROM {}", htable);
+        let row: (String, String) = conn
+            .query_row(&sql, [], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(row.0, "/tmp/it's/目录");
+        assert_eq!(row.1, "dir:/tmp/it's/目录|");
+
+        // items with same input, start time and session are skipped
+        let items = vec![
+            Item::new("echo 'it''s'", 0.0, 0.0, ""),
+            Item::new("echo 'it''s'", 9.0, 0.0, ""),
+            Item::new("echo a\0b", 2.0, 0.0, "other"),
+            Item::new("  ", 3.0, 0.0, ""),
+        ];
+        assert_eq!(insert_items(&mut conn, htable, &items).unwrap(), 2);
+        assert_eq!(insert_items(&mut conn, htable, &items).unwrap(), 0);
+        assert_eq!(select_inputs(&conn, htable).len(), inputs.len() + 2);
+    }
+
+    #[test]
+    fn test_migrate_db() {
+        let conn = Conn::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE h (inp TEXT, rtn INTEGER, tsb REAL, tse REAL,
+                             sessionid TEXT, out TEXT, info TEXT);
+             INSERT INTO h VALUES ('ls', 0, 1.0, 3.5, 's', NULL, 'dir:/tmp/a''b|');",
+        )
+        .unwrap();
+        create_table(&conn, "h").unwrap();
+        let row: (String, f64) = conn
+            .query_row("SELECT cwd, duration FROM h", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(row, ("/tmp/a'b".to_string(), 2.5));
+    }
+
+    #[test]
+    fn test_query_suggestion() {
+        let conn = Conn::open_in_memory().unwrap();
+        let htable = "h";
+        create_table(&conn, htable).unwrap();
+        let items = [
+            ("git status", 0, "/a", 1.0),
+            ("git stash", 0, "/b", 2.0),
+            ("git stat", 1, "/a", 3.0),
+            ("git log\n-3", 0, "/a", 4.0),
+            ("gitk", 0, "/a", 5.0),
+        ];
+        for (inp, rtn, cwd, tsb) in items {
+            let item = Item {
+                inp: inp.to_string(),
+                rtn,
+                cwd: cwd.to_string(),
+                tsb,
+                ..Default::default()
+            };
+            insert_item(&conn, htable, &item).unwrap();
+        }
+
+        let find = |prefix: &str, cwd: &str| query_suggestion(&conn, htable, prefix, cwd);
+        assert_eq!(find("git st", "/a"), Some("git status".to_string()));
+        assert_eq!(find("git st", "/b"), Some("git stash".to_string()));
+        assert_eq!(find("git st", "/c"), Some("git stash".to_string()));
+        assert_eq!(find("git l", "/a"), None);
+        assert_eq!(find("git status", "/a"), None);
+        assert_eq!(find("g", "/a"), Some("gitk".to_string()));
+        assert_eq!(find("x", "/a"), None);
+    }
+
+    #[test]
+    fn test_capture_output() {
+        assert!(match_capture_prefixes(
+            "kubectl:git log",
+            "  kubectl get pods"
+        ));
+        assert!(match_capture_prefixes("kubectl:git log", "git log -3"));
+        assert!(!match_capture_prefixes("kubectl:git log", "git status"));
+        assert!(!match_capture_prefixes("", "ls"));
+        assert!(!match_capture_prefixes("::", "ls"));
+
+        let mut buf = b"abcdef".to_vec();
+        trim_output(&mut buf, 10);
+        assert_eq!(buf, b"abcdef");
+        trim_output(&mut buf, 4);
+        assert_eq!(buf, b"cdef");
+
+        let mut buf = "a你好".as_bytes().to_vec();
+        trim_output(&mut buf, 5);
+        assert_eq!(String::from_utf8(buf).unwrap(), "好");
+    }
+
+    #[test]
+    fn test_item_json() {
+        let item = Item {
+            inp: "echo \"hi\"\nls".to_string(),
+            rtn: 1,
+            tsb: 1700000000.25,
+            tse: 1700000001.5,
+            sessionid: "abc".to_string(),
+            cwd: "/tmp".to_string(),
+            hostname: "host".to_string(),
+            duration: 1.25,
+            tty: String::new(),
+            out: "hi\n\x1b[31mred\x1b[0m\n".to_string(),
+        };
+        assert_eq!(Item::from_json(&item.to_json()), Ok(item));
+        assert!(Item::from_json("{\"rtn\": 0}").is_err());
+        assert_eq!(
+            parse_jsonl_history("{\"inp\": \"ls\"}\n\n").unwrap().len(),
+            1
+        );
+        assert!(parse_jsonl_history("{\"inp\": \"ls\"}\nfoo\n").is_err());
+    }
+}
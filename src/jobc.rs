@@ -190,6 +190,66 @@ pub fn wait_fg_job(sh: &mut shell::Shell, gid: i32, pids: &[i32]) -> CommandResu
     cmd_result
 }
 
+/// Find the job by job specs like `%1`, `%%` or `%+` (the current job),
+/// `%-` (the previous job), and `%name` (the job starting with `name`).
+pub fn get_job_by_spec(sh: &shell::Shell, spec: &str) -> Option<types::Job> {
+    let spec = spec.strip_prefix('%')?;
+    let mut ids: Vec<i32> = sh.jobs.keys().cloned().collect();
+    ids.sort();
+    let id = match spec {
+        "" | "%" | "+" => ids.last().cloned()?,
+        "-" => ids.iter().rev().nth(1).cloned()?,
+        _ => match spec.parse::<i32>() {
+            Ok(x) => x,
+            Err(_) => ids
+                .iter()
+                .rev()
+                .find(|x| sh.jobs[x].cmd.starts_with(spec))
+                .cloned()?,
+        },
+    };
+    sh.get_job_by_id(id).cloned()
+}
+
+/// Wait a child of a background job until it terminates or stops,
+/// returns its exit status.
+pub fn wait_bg_pid(sh: &mut shell::Shell, gid: i32, pid: i32) -> i32 {
+    // it could be already reaped by the SIGCHLD handler
+    if let Some(status) = signals::pop_reap_map(pid) {
+        mark_job_as_done(sh, gid, pid, "Done");
+        return status;
+    }
+    if let Some(sig) = signals::killed_map_pop(pid) {
+        mark_job_as_done(sh, gid, pid, "Killed");
+        return sig + 128;
+    }
+
+    loop {
+        let ws = waitpidx(pid, true);
+        if ws.is_error() {
+            if let Some(status) = signals::pop_reap_map(pid) {
+                mark_job_as_done(sh, gid, pid, "Done");
+                return status;
+            }
+            log!("jobc wait_bg_pid waitpid error: {}", ws.get_errno());
+            sh.remove_pid_from_job(gid, pid);
+            return 127;
+        }
+
+        if ws.is_exited() {
+            mark_job_as_done(sh, gid, pid, "Done");
+        } else if ws.is_signaled() {
+            mark_job_as_done(sh, gid, pid, "Killed");
+        } else if ws.is_stopped() {
+            mark_job_member_stopped(sh, pid, gid, true);
+        } else {
+            // continued
+            continue;
+        }
+        return ws.get_status();
+    }
+}
+
 pub fn try_wait_bg_jobs(sh: &mut shell::Shell, report: bool, sig_handler_enabled: bool) {
     if sh.jobs.is_empty() {
         return;
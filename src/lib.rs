@@ -75,6 +75,7 @@ mod tools;
 
 mod builtins;
 mod calculator;
+mod cmdcache;
 mod core;
 mod execute;
 mod history;
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+/// Values of flat JSON objects, which is enough for lines of history.
+#[derive(Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    Str(String),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Str(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+}
+
+/// Returns `text` as a quoted JSON string.
+pub fn quote(text: &str) -> String {
+    let mut result = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Parse an object like `{"inp": "ls", "rtn": 0}`. Nested objects and
+/// arrays are not supported.
+pub fn parse_object(text: &str) -> Result<HashMap<String, Value>, String> {
+    let mut parser = Parser {
+        chars: text.trim().chars().collect(),
+        pos: 0,
+    };
+    let mut result = HashMap::new();
+    parser.expect('{')?;
+    if parser.peek() == Some('}') {
+        parser.pos += 1;
+        return parser.finish(result);
+    }
+    loop {
+        let key = parser.parse_string()?;
+        parser.expect(':')?;
+        let value = parser.parse_value()?;
+        result.insert(key, value);
+        match parser.next() {
+            Some(',') => continue,
+            Some('}') => return parser.finish(result),
+            _ => return Err(format!("expect , or }} at {}", parser.pos)),
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn skip_spaces(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_spaces();
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek();
+        self.pos += 1;
+        c
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        if self.next() == Some(c) {
+            Ok(())
+        } else {
+            Err(format!("expect {} at {}", c, self.pos - 1))
+        }
+    }
+
+    fn finish<T>(&mut self, result: T) -> Result<T, String> {
+        if self.peek().is_some() {
+            return Err(format!("unexpected chars at {}", self.pos));
+        }
+        Ok(result)
+    }
+
+    fn parse_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut result = String::new();
+        loop {
+            let c = match self.chars.get(self.pos) {
+                Some(x) => *x,
+                None => return Err("unterminated string".to_string()),
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(result),
+                '\\' => {
+                    let c = self.chars.get(self.pos).cloned();
+                    self.pos += 1;
+                    match c {
+                        Some('n') => result.push('\n'),
+                        Some('r') => result.push('\r'),
+                        Some('t') => result.push('\t'),
+                        Some('b') => result.push('\x08'),
+                        Some('f') => result.push('\x0c'),
+                        Some('u') => result.push(self.parse_unicode()?),
+                        Some(x) => result.push(x),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                c => result.push(c),
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let end = self.pos + 4;
+        if end > self.chars.len() {
+            return Err("invalid unicode escape".to_string());
+        }
+        let hex: String = self.chars[self.pos..end].iter().collect();
+        self.pos = end;
+        u32::from_str_radix(&hex, 16).map_err(|_| "invalid unicode escape".to_string())
+    }
+
+    fn parse_unicode(&mut self) -> Result<char, String> {
+        let mut code = self.parse_hex4()?;
+        // surrogate pairs like `\ud83d\ude00`
+        if (0xd800..0xdc00).contains(&code) && self.chars[self.pos..].starts_with(&['\\', 'u']) {
+            self.pos += 2;
+            let low = self.parse_hex4()?;
+            code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+        }
+        Ok(char::from_u32(code).unwrap_or('\u{fffd}'))
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => return Ok(Value::Str(self.parse_string()?)),
+            None => return Err("expect value".to_string()),
+            _ => {}
+        }
+
+        let start = self.pos;
+        while self.pos < self.chars.len() && !",}".contains(self.chars[self.pos]) {
+            self.pos += 1;
+        }
+        let word: String = self.chars[start..self.pos].iter().collect();
+        match word.trim() {
+            "null" => Ok(Value::Null),
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            x => match x.parse::<f64>() {
+                Ok(n) => Ok(Value::Number(n)),
+                Err(_) => Err(format!("invalid value: {}", x)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_object, quote, Value};
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("ls"), "\"ls\"");
+        assert_eq!(quote("echo \"a\\b\"\n"), "\"echo \\\"a\\\\b\\\"\\n\"");
+        assert_eq!(quote("\x1b"), "\"\\u001b\"");
+    }
+
+    #[test]
+    fn test_parse_object() {
+        let obj =
+            parse_object(r#"{"inp": "echo \"hi\"\n", "rtn": -1, "tsb": 1.5, "x": null}"#).unwrap();
+        assert_eq!(obj["inp"], Value::Str("echo \"hi\"\n".to_string()));
+        assert_eq!(obj["rtn"], Value::Number(-1.0));
+        assert_eq!(obj["tsb"].as_f64(), Some(1.5));
+        assert_eq!(obj["x"], Value::Null);
+        assert_eq!(parse_object("{}").unwrap().len(), 0);
+
+        let obj = parse_object(r#"{"a":"\u00e9\ud83d\ude00","b":true}"#).unwrap();
+        assert_eq!(obj["a"].as_str(), Some("é😀"));
+        assert_eq!(obj["b"], Value::Bool(true));
+
+        let text = "a\"b\\c\nd\x01";
+        let obj = parse_object(&format!("{{\"k\": {}}}", quote(text))).unwrap();
+        assert_eq!(obj["k"].as_str(), Some(text));
+
+        assert!(parse_object("").is_err());
+        assert!(parse_object(r#"{"a": 1"#).is_err());
+        assert!(parse_object(r#"{"a": foo}"#).is_err());
+        assert!(parse_object(r#"{"a": 1} x"#).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 pub mod colored;
 pub mod fork;
+pub mod json;
 pub mod os_type;
 pub mod path;
 pub mod pipes;
@@ -31,20 +31,25 @@ mod tools;
 
 mod builtins;
 mod calculator;
+mod cmdcache;
 mod completers;
 mod core;
 mod ctime;
 mod execute;
+mod fuzzy;
+mod gitrepo;
 mod highlight;
 mod history;
 mod jobc;
 mod libs;
+mod notify;
 mod parsers;
 mod prompt;
 mod rcfile;
 mod scripting;
 mod shell;
 mod signals;
+mod suggest;
 mod types;
 
 // #[allow(clippy::cast_lossless)]
@@ -67,19 +72,23 @@ fn main() {
     }
 
     let mut sh = shell::Shell::new();
+    sh.is_interactive = !libs::progopts::is_script(&args)
+        && !libs::progopts::is_command_string(&args)
+        && !libs::progopts::is_non_tty();
 
     if libs::progopts::is_login(&args) {
         rcfile::load_rc_files(&mut sh);
         sh.is_login = true;
     }
 
-    // Initialize command cache for highlighting
-    highlight::init_command_cache();
+    // scan PATH for highlighting and completion
+    cmdcache::refresh();
     highlight::update_aliases(&sh);
 
     if libs::progopts::is_script(&args) {
         log!("run script: {:?} ", &args);
         let status = scripting::run_script(&mut sh, &args);
+        execute::run_exit_trap(&mut sh);
         std::process::exit(status);
     }
 
@@ -89,6 +98,7 @@ fn main() {
         let line = tools::env_args_to_command_line();
         log!("run with -c args: {}", &line);
         execute::run_command_line(&mut sh, &line, false, false);
+        execute::run_exit_trap(&mut sh);
         std::process::exit(sh.previous_status);
     }
 
@@ -112,8 +122,17 @@ fn main() {
     rl.define_function("enter-function", Arc::new(prompt::EnterFunction));
     rl.bind_sequence("\r", Command::from_str("enter-function"));
 
+    let fuzzy_search = fuzzy::FuzzySearchFunction {
+        session_id: sh.session_id.clone(),
+    };
+    rl.define_function("history-fuzzy-search", Arc::new(fuzzy_search));
+    rl.bind_sequence("\x12", Command::from_str("history-fuzzy-search"));
+
     let highlighter = highlight::create_highlighter();
     rl.set_highlighter(highlighter);
+    if suggest::is_enabled() {
+        suggest::bind_keys(&rl);
+    }
 
     history::init(&mut rl);
     rl.set_completer(Arc::new(completers::CicadaCompleter {
@@ -130,7 +149,8 @@ fn main() {
     }
 
     loop {
-        let prompt = prompt::get_prompt(&sh);
+        let (prompt, rprompt) = prompt::get_prompt(&sh);
+        highlight::set_prompt_suffix(&prompt);
         match rl.set_prompt(&prompt) {
             Ok(_) => {}
             Err(e) => {
@@ -145,7 +165,7 @@ fn main() {
             // - https://man7.org/linux/man-pages/man7/signal-safety.7.html
             signals::unblock_signals();
         }
-        match rl.read_line() {
+        match prompt::read_line(&rl, &sh, &prompt, &rprompt) {
             Ok(ReadResult::Input(line)) => {
                 if sig_handler_enabled {
                     signals::block_signals();
@@ -166,11 +186,14 @@ fn main() {
                 tools::extend_bangbang(&sh, &mut line);
 
                 let mut status = 0;
+                sh.captured_output.clear();
                 let cr_list = execute::run_command_line(&mut sh, &line, true, false);
                 if let Some(last) = cr_list.last() {
                     status = last.status;
                 }
                 let tse = ctime::DateTime::now().unix_timestamp();
+                sh.previous_duration = tse - tsb;
+                notify::command_done(&line, status, tse - tsb);
 
                 if !sh.cmd.starts_with(' ') && line != sh.previous_cmd {
                     history::add(&sh, &mut rl, &line, status, tsb, tse);
@@ -202,6 +225,7 @@ fn main() {
                     }
                 } else {
                     println!("exit");
+                    execute::run_exit_trap(&mut sh);
                     break;
                 }
             }
@@ -0,0 +1,147 @@
+//! Notifications of long-running foreground commands, by ringing the bell
+//! or emitting desktop-notification escapes (OSC 9 / OSC 777), when the
+//! terminal is not focused.
+use std::env;
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::tools;
+
+const MAX_CMD_LEN: usize = 64;
+
+enum Style {
+    Bell,
+    Osc9,
+    Osc777,
+}
+
+fn get_style() -> Option<Style> {
+    match env::var("CICADA_NOTIFY").ok()?.as_str() {
+        "" => None,
+        "bell" => Some(Style::Bell),
+        "osc9" => Some(Style::Osc9),
+        "osc777" => Some(Style::Osc777),
+        x => {
+            log!("cicada: unknown CICADA_NOTIFY: {:?}", x);
+            None
+        }
+    }
+}
+
+fn get_threshold() -> f64 {
+    env::var("CICADA_NOTIFY_AFTER")
+        .ok()
+        .and_then(|x| x.parse::<f64>().ok())
+        .unwrap_or(10.0)
+}
+
+/// Run the command line by cicada, and see if it exits with 0 in a second.
+fn run_focus_command(line: &str) -> bool {
+    let exe = match env::current_exe() {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    let mut child = match Command::new(exe)
+        .arg("-c")
+        .arg(line)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(x) => x,
+        Err(e) => {
+            log!("notify: run {:?} error: {:?}", line, e);
+            return false;
+        }
+    };
+    let deadline = Instant::now() + Duration::from_secs(1);
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return status.success(),
+            Ok(None) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            }
+            _ => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return false;
+            }
+        }
+    }
+}
+
+/// Whether the terminal window has the focus. It's told by the exit status
+/// of `CICADA_NOTIFY_FOCUS_CMD` if set, or else by comparing `$WINDOWID`
+/// with the active window from `xdotool` on X11. It's taken as not
+/// focused when unknown.
+fn is_focused() -> bool {
+    if let Ok(line) = env::var("CICADA_NOTIFY_FOCUS_CMD") {
+        if !line.is_empty() {
+            return run_focus_command(&line);
+        }
+    }
+    let window = match env::var("WINDOWID") {
+        Ok(x) if !x.is_empty() => x,
+        _ => return false,
+    };
+    match Command::new("xdotool")
+        .arg("getactivewindow")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+    {
+        Ok(x) => String::from_utf8_lossy(&x.stdout).trim() == window.trim(),
+        Err(_) => false,
+    }
+}
+
+fn get_message(line: &str, status: i32, duration: f64) -> String {
+    let mut cmd: String = line
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(MAX_CMD_LEN)
+        .collect();
+    if line.trim().chars().count() > MAX_CMD_LEN {
+        cmd.push('…');
+    }
+    let result = if status == 0 {
+        "done".to_string()
+    } else {
+        format!("exit {}", status)
+    };
+    format!("{} ({}, {})", cmd, result, tools::format_duration(duration))
+}
+
+/// Escapes are passed through tmux to the outer terminal.
+fn wrap_for_tmux(seq: &str) -> String {
+    if env::var("TMUX").map_or(true, |x| x.is_empty()) {
+        return seq.to_string();
+    }
+    format!("\x1bPtmux;{}\x1b\\", seq.replace('\x1b', "\x1b\x1b"))
+}
+
+/// Notify that the foreground command line is done, if it took at least
+/// `CICADA_NOTIFY_AFTER` seconds and the terminal is not focused.
+pub fn command_done(line: &str, status: i32, duration: f64) {
+    let style = match get_style() {
+        Some(x) => x,
+        None => return,
+    };
+    if duration < get_threshold() || is_focused() {
+        return;
+    }
+
+    let message = get_message(line, status, duration);
+    let seq = match style {
+        Style::Bell => "\x07".to_string(),
+        Style::Osc9 => wrap_for_tmux(&format!("\x1b]9;{}\x07", message)),
+        Style::Osc777 => wrap_for_tmux(&format!("\x1b]777;notify;cicada;{}\x07", message)),
+    };
+    let mut stdout = io::stdout();
+    let _ = stdout.write_all(seq.as_bytes());
+    let _ = stdout.flush();
+}
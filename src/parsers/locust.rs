@@ -2,19 +2,108 @@ use pest::error::Error;
 use pest::iterators::Pairs;
 use pest::Parser;
 
+use crate::parsers::parser_line::{find_heredoc_ops, read_heredoc_bodies};
+
 #[derive(Parser)]
 #[grammar = "parsers/grammar.pest"]
 struct Locust;
 
+/// The lines given must have been through `unify_heredoc_delims()`.
 pub fn parse_lines(
     lines: &str,
 ) -> Result<Pairs<'_, crate::parsers::locust::Rule>, Error<crate::parsers::locust::Rule>> {
     Locust::parse(Rule::EXP, lines)
 }
 
+/// The grammar matches the here-document bodies with a stack of their
+/// delimiters, i.e. in the reverse order of the operators. So the lines
+/// with several here-documents like `cmd <<A <<B` get the same delimiter
+/// for all of them, which is not a line of any of their bodies, and the
+/// bodies of `<<-` get their leading tabs stripped already.
+pub fn unify_heredoc_delims(lines: &str) -> String {
+    let mut result = String::new();
+    let mut rest = lines;
+    while !rest.is_empty() {
+        let (line, next) = match rest.find('\n') {
+            Some(i) => rest.split_at(i + 1),
+            None => (rest, ""),
+        };
+        rest = next;
+        let ops = find_heredoc_ops(line);
+        let (bodies, consumed, is_complete) = read_heredoc_bodies(next, &ops);
+        if !is_complete {
+            result.push_str(line);
+            continue;
+        }
+        rest = &next[consumed..];
+        let first = match ops.first() {
+            Some(x) => x,
+            None => {
+                result.push_str(line);
+                continue;
+            }
+        };
+        if ops
+            .iter()
+            .all(|x| x.delim == first.delim && x.strip_tabs == first.strip_tabs)
+        {
+            result.push_str(line);
+            result.push_str(&next[..consumed]);
+            continue;
+        }
+
+        let mut delim = if first.delim.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            first.delim.clone()
+        } else {
+            "EOF".to_string()
+        };
+        while bodies.iter().any(|x| x.lines().any(|y| y == delim)) {
+            delim.push('_');
+        }
+
+        let mut last = 0;
+        for op in &ops {
+            result.push_str(&line[last..op.start]);
+            if op.quoted {
+                result.push_str(&format!("<<'{}'", delim));
+            } else {
+                result.push_str(&format!("<<{}", delim));
+            }
+            last = op.end;
+        }
+        result.push_str(&line[last..]);
+        for body in &bodies {
+            result.push_str(body);
+            result.push_str(&delim);
+            result.push('\n');
+        }
+    }
+    result
+}
+
+/// Whether the text starts with a complete function definition,
+/// e.g. `foo() { echo hi; }`.
+pub fn is_func_def(text: &str) -> bool {
+    Locust::parse(Rule::EXP_FUNC, text.trim_start()).is_ok()
+}
+
+/// Whether the text starts with a case statement, e.g.
+/// `case $x in a) echo a;; esac`.
+pub fn is_case_stmt(text: &str) -> bool {
+    Locust::parse(Rule::CASE_HEAD, text.trim_start()).is_ok()
+}
+
+/// Whether the text starts a function definition, which has not been
+/// closed with `}` yet.
+pub fn is_func_def_incomplete(text: &str) -> bool {
+    let text = text.trim_start();
+    Locust::parse(Rule::FUNC_HEAD, text).is_ok() && Locust::parse(Rule::EXP_FUNC, text).is_err()
+}
+
 #[cfg(test)]
 mod tests {
     use super::parse_lines;
+    use super::unify_heredoc_delims;
     use super::Pairs;
     use super::Rule;
 
@@ -37,7 +126,13 @@ mod tests {
             }
 
             output.push_str(&format!("[{:?}]", rule));
-            if rule == Rule::CMD || rule == Rule::TEST {
+            if rule == Rule::CMD
+                || rule == Rule::TEST
+                || rule == Rule::CASE_WORD
+                || rule == Rule::CASE_PATTERN
+                || rule == Rule::FUNC_NAME
+                || rule == Rule::FUNC_INLINE
+            {
                 output.push_str(&format!(" {}", value));
             }
 
@@ -312,4 +407,215 @@ mod tests {
         let output = _parse_exp(lines);
         assert_eq!(output, expected);
     }
+
+    #[test]
+    fn test_locust_parse_lines_heredoc_001() {
+        let lines = include_str!("../../tests/locusts/heredoc-001.sh");
+        let expected = "\
+            [EXP]\
+            --[CMD] echo begin\
+            --[CMD] cat <<EOF | wc -l\nif echo not a test\ndone\nEOF\
+            ----[HEREDOC]\
+            --[EXP_IF]\
+            ----[IF_IF_BR]\
+            ------[IF_HEAD]\
+            --------[TEST] true\
+            ------[EXP_BODY]\
+            --------[CMD] cat <<-'END'\n\tfi\n\tEND\
+            ----------[HEREDOC]\
+            --[CMD] echo $((1 << 2))";
+        let output = _parse_exp(lines);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_locust_parse_lines_func_001() {
+        let lines = include_str!("../../tests/locusts/func-001.sh");
+        let expected = "\
+            [EXP]\
+            --[EXP_FUNC]\
+            ----[FUNC_HEAD]\
+            ------[FUNC_NAME] foo\
+            ----[EXP_BODY]\
+            ------[CMD] echo foo\
+            --[EXP_FUNC]\
+            ----[FUNC_HEAD]\
+            ------[FUNC_NAME] bar\
+            ----[EXP_BODY]\
+            ------[EXP_IF]\
+            --------[IF_IF_BR]\
+            ----------[IF_HEAD]\
+            ------------[TEST] [ -n \"$1\" ]\
+            ----------[EXP_BODY]\
+            ------------[CMD] echo bar $1\
+            --[EXP_FUNC]\
+            ----[FUNC_HEAD]\
+            ------[FUNC_NAME] baz\
+            ----[FUNC_INLINE] echo baz; echo $#\
+            --[CMD] baz 1 2\
+            --[EXP_FUNC]\
+            ----[FUNC_HEAD]\
+            ------[FUNC_NAME] outer\
+            ----[EXP_BODY]\
+            ------[EXP_FUNC]\
+            --------[FUNC_HEAD]\
+            ----------[FUNC_NAME] inner\
+            --------[FUNC_INLINE] echo inner\
+            ------[CMD] inner\
+            --[CMD] outer";
+        let output = _parse_exp(lines);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_locust_parse_lines_case_001() {
+        let lines = include_str!("../../tests/locusts/case-001.sh");
+        let expected = "\
+            [EXP]\
+            --[EXP_CASE]\
+            ----[CASE_HEAD]\
+            ------[CASE_WORD] \"$1\"\
+            ----[CASE_BR]\
+            ------[CASE_BR_HEAD]\
+            --------[CASE_PATTERN] start\
+            --------[CASE_PATTERN] begin\
+            ------[EXP_BODY]\
+            --------[CMD] echo starting\
+            ----[CASE_BR]\
+            ------[CASE_BR_HEAD]\
+            --------[CASE_PATTERN] stop\
+            ------[EXP_BODY]\
+            --------[CMD] echo stopping\
+            ----[CASE_BR]\
+            ------[CASE_BR_HEAD]\
+            --------[CASE_PATTERN] 'a b'\
+            --------[CASE_PATTERN] [0-9]*\
+            ------[EXP_BODY]\
+            --------[CMD] echo quoted or digits\
+            ----[CASE_BR]\
+            ------[CASE_BR_HEAD]\
+            --------[CASE_PATTERN] *\
+            ------[EXP_BODY]\
+            --------[EXP_IF]\
+            ----------[IF_IF_BR]\
+            ------------[IF_HEAD]\
+            --------------[TEST] echo $1 | grep -q x\
+            ------------[EXP_BODY]\
+            --------------[CMD] echo has x\
+            --------[CMD] echo default\
+            --[CMD] echo after esac";
+        let output = _parse_exp(lines);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_locust_parse_lines_case_002() {
+        let lines = include_str!("../../tests/locusts/case-002.sh");
+        let expected = "\
+            [EXP]\
+            --[EXP_FOR]\
+            ----[FOR_HEAD]\
+            ------[FOR_INIT]\
+            --------[FOR_VAR]\
+            --------[TEST] a b c\
+            ----[EXP_BODY]\
+            ------[EXP_CASE]\
+            --------[CASE_HEAD]\
+            ----------[CASE_WORD] $x\
+            --------[CASE_BR]\
+            ----------[CASE_BR_HEAD]\
+            ------------[CASE_PATTERN] a\
+            ----------[EXP_BODY]\
+            ------------[CMD] continue\
+            --------[CASE_BR]\
+            ----------[CASE_BR_HEAD]\
+            ------------[CASE_PATTERN] b\
+            ----------[EXP_BODY]\
+            ------------[EXP_CASE]\
+            --------------[CASE_HEAD]\
+            ----------------[CASE_WORD] \"$2\"\
+            --------------[CASE_BR]\
+            ----------------[CASE_BR_HEAD]\
+            ------------------[CASE_PATTERN] *\
+            ----------------[EXP_BODY]\
+            ------------------[CMD] echo nested\
+            --------[CASE_BR]\
+            ----------[CASE_BR_HEAD]\
+            ------------[CASE_PATTERN] c";
+        let output = _parse_exp(lines);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_locust_parse_lines_case_003() {
+        let lines = include_str!("../../tests/locusts/case-003.sh");
+        let expected = "\
+            [EXP]\
+            --[EXP_CASE]\
+            ----[CASE_HEAD]\
+            ------[CASE_WORD] \"$1\"\
+            ----[CASE_BR]\
+            ------[CASE_BR_HEAD]\
+            --------[CASE_PATTERN] a*\
+            ------[EXP_BODY]\
+            --------[CMD] echo one\
+            ----[CASE_BR]\
+            ------[CASE_BR_HEAD]\
+            --------[CASE_PATTERN] b\
+            --------[CASE_PATTERN] c\
+            ------[EXP_BODY]\
+            --------[CMD] echo two; echo three\
+            --[EXP_CASE]\
+            ----[CASE_HEAD]\
+            ------[CASE_WORD] $x\
+            ----[CASE_BR]\
+            ------[CASE_BR_HEAD]\
+            --------[CASE_PATTERN] x\
+            ------[EXP_BODY]\
+            --------[CMD] echo x\
+            ----[CASE_BR]\
+            ------[CASE_BR_HEAD]\
+            --------[CASE_PATTERN] *\
+            ------[EXP_BODY]\
+            --------[CMD] echo any\
+            --[CMD] echo after";
+        let output = _parse_exp(lines);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_locust_parse_lines_syntax_error() {
+        assert!(parse_lines("echo a;; echo b\n").is_err());
+        assert!(parse_lines("case $x in\n    a) echo a;;\n").is_err());
+        assert!(parse_lines("echo \"a;;b\"\n").is_ok());
+    }
+
+    #[test]
+    fn test_locust_parse_lines_heredoc_002() {
+        let lines = unify_heredoc_delims("cat <<A; cat <<B\na\nB\nA\nb\nB\necho done\n");
+        assert_eq!(lines, "cat <<A; cat <<A\na\nB\nA\nb\nA\necho done\n");
+        let expected = "\
+            [EXP]\
+            --[CMD] cat <<A; cat <<A\na\nB\nA\nb\nA\
+            ----[HEREDOC]\
+            --[CMD] echo done";
+        assert_eq!(_parse_exp(&lines), expected);
+
+        let lines = unify_heredoc_delims("if true\n    cat <<-A <<'B'\n\ta\n\tA\nA\nB\nfi\n");
+        assert_eq!(lines, "if true\n    cat <<A_ <<'A_'\na\nA_\nA\nA_\nfi\n");
+        let expected = "\
+            [EXP]\
+            --[EXP_IF]\
+            ----[IF_IF_BR]\
+            ------[IF_HEAD]\
+            --------[TEST] true\
+            ------[EXP_BODY]\
+            --------[CMD] cat <<A_ <<'A_'\na\nA_\nA\nA_\
+            ----------[HEREDOC]";
+        assert_eq!(_parse_exp(&lines), expected);
+
+        let lines = "cat <<A <<A; echo '<<B'\na\nA\ncat <<B\nA\n";
+        assert_eq!(unify_heredoc_delims(lines), lines);
+        assert!(parse_lines(lines).is_ok());
+    }
 }
@@ -54,8 +54,21 @@ pub fn line_to_cmds(line: &str) -> Vec<String> {
     let mut sep = String::new();
     let mut token = String::new();
     let mut has_backslash = false;
+    let mut in_comment = false;
+    let mut skip_until = 0;
+    let mut line_start = 0;
+    // parentheses not closed yet in `$((...))`
+    let mut arith_parens = 0;
     let len = line.chars().count();
-    for (i, c) in line.chars().enumerate() {
+    for (i, (pos, c)) in line.char_indices().enumerate() {
+        if pos < skip_until {
+            continue;
+        }
+        if in_comment && c != '\n' {
+            continue;
+        }
+        in_comment = false;
+
         if has_backslash {
             token.push('\\');
             token.push(c);
@@ -68,9 +81,65 @@ pub fn line_to_cmds(line: &str) -> Vec<String> {
             continue;
         }
 
-        if c == '#' {
+        // things like `&&` and `;` in `$((...))` are not separators
+        if arith_parens > 0 {
+            if c == '(' {
+                arith_parens += 1;
+            } else if c == ')' {
+                arith_parens -= 1;
+            }
+            token.push(c);
+            continue;
+        }
+        if c == '$' && sep.is_empty() && line[pos..].starts_with("$((") {
+            arith_parens = 2;
+            token.push_str("$((");
+            skip_until = pos + 3;
+            continue;
+        }
+
+        if c == '\n' && sep.is_empty() {
+            // the lines after here-document operators are their bodies,
+            // and belong to the commands having the operators.
+            let mut consumed_all = 0;
+            for cmd in result[line_start..]
+                .iter_mut()
+                .chain(std::iter::once(&mut token))
+            {
+                let ops = find_heredoc_ops(cmd.rsplit('\n').next().unwrap_or(""));
+                if ops.is_empty() {
+                    continue;
+                }
+                let text = &line[pos + 1 + consumed_all..];
+                let (_, consumed, _) = read_heredoc_bodies(text, &ops);
+                *cmd = format!("{}\n{}", cmd.trim_end(), text[..consumed].trim_end());
+                consumed_all += consumed;
+            }
+
+            if consumed_all > 0 {
+                skip_until = pos + 1 + consumed_all;
+                let _token = token.trim().to_string();
+                if !_token.is_empty() {
+                    result.push(_token);
+                }
+                token = String::new();
+                let has_more = !line[skip_until..].trim().is_empty();
+                if has_more && result.last().is_some_and(|x| x != ";") {
+                    result.push(String::from(";"));
+                }
+                line_start = result.len();
+                continue;
+            }
+        }
+
+        // `#` in words like `${foo#bar}` does not start a comment
+        if c == '#' && (token.is_empty() || token.ends_with(char::is_whitespace)) {
             if sep.is_empty() {
-                break;
+                if find_heredoc_ops(token.rsplit('\n').next().unwrap_or("")).is_empty() {
+                    break;
+                }
+                in_comment = true;
+                continue;
             } else {
                 token.push(c);
                 continue;
@@ -170,6 +239,10 @@ pub fn line_to_cmds(line: &str) -> Vec<String> {
 // #[allow(clippy::cyclomatic_complexity)]
 pub fn parse_line(line: &str) -> LineInfo {
     // FIXME: let rewrite this parse part and make it a separated lib
+    if let Some(linfo) = parse_line_with_heredoc(line) {
+        return linfo;
+    }
+
     let mut result = Vec::new();
     if tools::is_arithmetic(line) {
         for x in line.split(' ') {
@@ -186,6 +259,9 @@ pub fn parse_line(line: &str) -> LineInfo {
     let mut token = String::new();
     let mut has_backslash = false;
     let mut met_parenthesis = false;
+    // for nested ones like: echo $(( (1 + 2) * 3 ))
+    let mut parens_depth = 0;
+    let mut braces_depth = 0;
     let mut new_round = true;
     let mut skip_next = false;
     let mut has_dollar = false;
@@ -226,6 +302,14 @@ pub fn parse_line(line: &str) -> LineInfo {
             continue;
         }
 
+        // escapes in words like `${p//\//:}` are handled in the expansion
+        if has_backslash && sep.is_empty() && braces_depth > 0 {
+            token.push('\\');
+            token.push(c);
+            has_backslash = false;
+            continue;
+        }
+
         if has_backslash {
             if new_round && sep.is_empty() && (c == '|' || c == '$') && token.is_empty() {
                 sep = String::from("\\");
@@ -250,6 +334,16 @@ pub fn parse_line(line: &str) -> LineInfo {
                 continue;
             }
             met_parenthesis = true;
+            parens_depth += 1;
+        }
+        // for cases like: echo ${foo:-a b}
+        if c == '{' && sep.is_empty() && token.ends_with('$') {
+            met_parenthesis = true;
+            braces_depth += 1;
+        }
+        if c == '}' && sep.is_empty() && braces_depth > 0 {
+            braces_depth -= 1;
+            met_parenthesis = parens_depth > 0 || braces_depth > 0;
         }
         if c == ')' {
             if parens_left_ignored && !has_dollar {
@@ -261,7 +355,10 @@ pub fn parse_line(line: &str) -> LineInfo {
                 }
             }
             if sep.is_empty() {
-                met_parenthesis = false;
+                if parens_depth > 0 {
+                    parens_depth -= 1;
+                }
+                met_parenthesis = parens_depth > 0 || braces_depth > 0;
             }
         }
 
@@ -424,7 +521,9 @@ pub fn parse_line(line: &str) -> LineInfo {
             }
 
             if sep.is_empty() {
-                let is_an_env = libs::re::re_contains(&token, r"^[a-zA-Z0-9_]+=.*$");
+                // also `arr[1]="a b"` and `foo+="a b"`
+                let is_an_env =
+                    libs::re::re_contains(&token, r"^[a-zA-Z0-9_]+(\[[^\]]*\])?\+?=.*$");
                 if !is_an_env && (c == '\'' || c == '"') {
                     sep = c.to_string();
                     continue;
@@ -482,6 +581,217 @@ pub fn parse_line(line: &str) -> LineInfo {
     }
 }
 
+/// A here-document operator in a command line, e.g. `<<EOF`, `<<-EOF`
+/// or `<< 'EOF'`. `start` and `end` are the byte range of the operator
+/// (with its delimiter) in the line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HereDocOp {
+    pub start: usize,
+    pub end: usize,
+    pub delim: String,
+    pub strip_tabs: bool,
+    pub quoted: bool,
+}
+
+/// Find the here-document operators in the first line of `line`.
+///
+/// ```ignore
+/// let ops = find_heredoc_ops("cat <<-'EOF' | wc");
+/// // Returns vec![HereDocOp {
+/// //     start: 4, end: 12, delim: "EOF", strip_tabs: true, quoted: true,
+/// // }]
+/// ```
+pub fn find_heredoc_ops(line: &str) -> Vec<HereDocOp> {
+    let mut result = Vec::new();
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let len = chars.len();
+    let mut sep: Option<char> = None;
+    let mut has_backslash = false;
+    let mut parens = 0;
+    let mut i = 0;
+    while i < len {
+        let c = chars[i].1;
+        if c == '\n' {
+            break;
+        }
+        if has_backslash {
+            has_backslash = false;
+            i += 1;
+            continue;
+        }
+        if c == '\\' && sep != Some('\'') {
+            has_backslash = true;
+            i += 1;
+            continue;
+        }
+        if let Some(x) = sep {
+            if c == x {
+                sep = None;
+            }
+            i += 1;
+            continue;
+        }
+        if c == '\'' || c == '"' || c == '`' {
+            sep = Some(c);
+            i += 1;
+            continue;
+        }
+        if c == '#' && (i == 0 || chars[i - 1].1.is_whitespace()) {
+            break;
+        }
+        // skip things like `$((1 << 2))`
+        if c == '(' {
+            parens += 1;
+        } else if c == ')' && parens > 0 {
+            parens -= 1;
+        }
+        if c != '<' || parens > 0 {
+            i += 1;
+            continue;
+        }
+
+        let mut count = 0;
+        while i + count < len && chars[i + count].1 == '<' {
+            count += 1;
+        }
+        if count != 2 {
+            i += count;
+            continue;
+        }
+
+        let mut j = i + 2;
+        let strip_tabs = j < len && chars[j].1 == '-';
+        if strip_tabs {
+            j += 1;
+        }
+        while j < len && (chars[j].1 == ' ' || chars[j].1 == '\t') {
+            j += 1;
+        }
+
+        // quoting any part of the delimiter turns off the expansions
+        let mut delim = String::new();
+        let mut quoted = false;
+        while j < len {
+            let x = chars[j].1;
+            if x == '\'' || x == '"' {
+                quoted = true;
+                j += 1;
+                while j < len && chars[j].1 != x && chars[j].1 != '\n' {
+                    delim.push(chars[j].1);
+                    j += 1;
+                }
+                if j < len && chars[j].1 == x {
+                    j += 1;
+                }
+                continue;
+            }
+            if x == '\\' && j + 1 < len {
+                quoted = true;
+                delim.push(chars[j + 1].1);
+                j += 2;
+                continue;
+            }
+            if x.is_whitespace() || ";|&<>()".contains(x) {
+                break;
+            }
+            delim.push(x);
+            j += 1;
+        }
+
+        if !delim.is_empty() {
+            let end = if j < len { chars[j].0 } else { line.len() };
+            result.push(HereDocOp {
+                start: chars[i].0,
+                end,
+                delim,
+                strip_tabs,
+                quoted,
+            });
+        }
+        i = j;
+    }
+    result
+}
+
+/// Read the bodies of here-documents `ops` from `text`, which are the
+/// lines following the command line. Returns the bodies, the count of
+/// bytes consumed from `text`, and whether all delimiters were found.
+pub fn read_heredoc_bodies(text: &str, ops: &[HereDocOp]) -> (Vec<String>, usize, bool) {
+    let mut bodies = Vec::new();
+    let mut consumed = 0;
+    let mut lines = text.split_inclusive('\n');
+    for op in ops {
+        let mut body = String::new();
+        let mut found = false;
+        for line in lines.by_ref() {
+            consumed += line.len();
+            let mut content = line.strip_suffix('\n').unwrap_or(line);
+            if op.strip_tabs {
+                content = content.trim_start_matches('\t');
+            }
+            if content == op.delim {
+                found = true;
+                break;
+            }
+            body.push_str(content);
+            body.push('\n');
+        }
+        bodies.push(body);
+        if !found {
+            return (bodies, consumed, false);
+        }
+    }
+    (bodies, consumed, true)
+}
+
+/// Here-documents are turned into a `<<` token followed by its body,
+/// which is quoted with `'` when the delimiter is quoted (no expansions),
+/// and with `"` otherwise.
+fn parse_line_with_heredoc(line: &str) -> Option<LineInfo> {
+    let (head, rest) = match line.split_once('\n') {
+        Some((head, rest)) => (head, rest),
+        None => (line, ""),
+    };
+    let ops = find_heredoc_ops(head);
+    if ops.is_empty() {
+        return None;
+    }
+
+    let (bodies, consumed, mut is_complete) = read_heredoc_bodies(rest, &ops);
+    let mut segments = Vec::new();
+    let mut tokens = Vec::new();
+    let mut last = 0;
+    for (i, op) in ops.iter().enumerate() {
+        segments.push(parse_line(&head[last..op.start]));
+        let sep = if op.quoted { "'" } else { "\"" };
+        let body = bodies.get(i).cloned().unwrap_or_default();
+        segments.push(LineInfo {
+            tokens: vec![(String::new(), "<<".to_string()), (sep.to_string(), body)],
+            is_complete: true,
+        });
+        last = op.end;
+    }
+    segments.push(parse_line(&head[last..]));
+    segments.push(parse_line(&rest[consumed..]));
+
+    for linfo in segments {
+        let ends_with_pipe = linfo
+            .tokens
+            .last()
+            .is_some_and(|x| x.0.is_empty() && x.1 == "|");
+        is_complete = is_complete && (linfo.is_complete || ends_with_pipe);
+        tokens.extend(linfo.tokens);
+    }
+    if tokens.last().is_some_and(|x| x.0.is_empty() && x.1 == "|") {
+        is_complete = false;
+    }
+
+    Some(LineInfo {
+        tokens,
+        is_complete,
+    })
+}
+
 pub fn tokens_to_redirections(tokens: &Tokens) -> Result<(Tokens, Vec<Redirection>), String> {
     let mut tokens_new = Vec::new();
     let mut redirects = Vec::new();
@@ -593,6 +903,7 @@ pub fn unquote(text: &str) -> String {
 
 #[cfg(test)]
 mod tests {
+    use super::find_heredoc_ops;
     use super::line_to_cmds;
     use super::line_to_plain_tokens;
     use super::parse_line;
@@ -771,6 +1082,12 @@ mod tests {
                 "Foo=\"a b c\" ./foo.sh",
                 vec![("", "Foo=\"a b c\""), ("", "./foo.sh")],
             ),
+            (
+                "arr[3]=\"hello world\"",
+                vec![("", "arr[3]=\"hello world\"")],
+            ),
+            ("m[host2]='db server'", vec![("", "m[host2]='db server'")]),
+            ("foo+=\" bar\"", vec![("", "foo+=\" bar\"")]),
             (
                 "echo $(foo bar baz)",
                 vec![("", "echo"), ("", "$(foo bar baz)")],
@@ -779,6 +1096,14 @@ mod tests {
                 "echo A$(foo bar)B",
                 vec![("", "echo"), ("", "A$(foo bar)B")],
             ),
+            (
+                "echo $(( (1 + 2) * 3 ))x",
+                vec![("", "echo"), ("", "$(( (1 + 2) * 3 ))x")],
+            ),
+            (
+                "echo ${foo:-a b} c",
+                vec![("", "echo"), ("", "${foo:-a b}"), ("", "c")],
+            ),
             (
                 "echo A$(foo bar | cat)B",
                 vec![("", "echo"), ("", "A$(foo bar | cat)B")],
@@ -866,6 +1191,87 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_line_heredoc() {
+        let v = vec![
+            (
+                "cat <<EOF\nhi $USER\nEOF",
+                vec![("", "cat"), ("", "<<"), ("\"", "hi $USER\n")],
+                true,
+            ),
+            (
+                "cat << 'EOF' | wc\na\n\nb\nEOF",
+                vec![
+                    ("", "cat"),
+                    ("", "<<"),
+                    ("'", "a\n\nb\n"),
+                    ("", "|"),
+                    ("", "wc"),
+                ],
+                true,
+            ),
+            (
+                "cat <<-END\n\t\tfoo\n\tEND\n",
+                vec![("", "cat"), ("", "<<"), ("\"", "foo\n")],
+                true,
+            ),
+            (
+                "cat <<EOF\nEOF",
+                vec![("", "cat"), ("", "<<"), ("\"", "")],
+                true,
+            ),
+            (
+                "cat <<EOF\nfoo\n EOF",
+                vec![("", "cat"), ("", "<<"), ("\"", "foo\n EOF\n")],
+                false,
+            ),
+            (
+                "cat <<EOF",
+                vec![("", "cat"), ("", "<<"), ("\"", "")],
+                false,
+            ),
+            (
+                "cat <<< foo",
+                vec![("", "cat"), ("", "<<<"), ("", "foo")],
+                true,
+            ),
+            ("echo '<<EOF'", vec![("", "echo"), ("'", "<<EOF")], true),
+        ];
+        for (left, right, is_complete) in v {
+            println!("\ninput: {:?}", left);
+            let linfo = parse_line(left);
+            println!("real    : {:?}", linfo.tokens);
+            assert_eq!(linfo.is_complete, is_complete);
+            _assert_vec_tuple_eq(linfo.tokens, right);
+        }
+    }
+
+    #[test]
+    fn test_find_heredoc_ops() {
+        let ops = find_heredoc_ops("cat <<-'EOF' | wc");
+        assert_eq!(ops.len(), 1);
+        assert_eq!((ops[0].start, ops[0].end), (4, 12));
+        assert_eq!(ops[0].delim, "EOF");
+        assert!(ops[0].strip_tabs);
+        assert!(ops[0].quoted);
+
+        let ops = find_heredoc_ops("cat <<E\\OF; cat <<\"A B\" x");
+        assert_eq!(ops.len(), 2);
+        assert_eq!(ops[0].delim, "EOF");
+        assert!(ops[0].quoted);
+        assert_eq!(ops[1].delim, "A B");
+
+        let ops = find_heredoc_ops("cat <<EOF");
+        assert_eq!(ops[0].delim, "EOF");
+        assert!(!ops[0].strip_tabs);
+        assert!(!ops[0].quoted);
+
+        assert!(find_heredoc_ops("cat <<< EOF").is_empty());
+        assert!(find_heredoc_ops("echo \"<<EOF\"").is_empty());
+        assert!(find_heredoc_ops("echo $((1 << 2))").is_empty());
+        assert!(find_heredoc_ops("echo foo # <<EOF").is_empty());
+    }
+
     #[test]
     fn test_line_to_plain_tokens() {
         let v = vec![
@@ -943,6 +1349,11 @@ mod tests {
                 vec!["echo foo", "&&", "echo bar", ";", "echo end"],
             ),
             ("echo \"\\\"\"", vec!["echo \"\\\"\""]),
+            ("echo ${f#*.} #foo", vec!["echo ${f#*.}"]),
+            (
+                "echo $((1 && (0 || 2))) && echo $((3 | 4))",
+                vec!["echo $((1 && (0 || 2)))", "&&", "echo $((3 | 4))"],
+            ),
             (
                 "man awk| awk -F \"[ ,.\\\"]+\" 'foo' |sort -k2nr|head",
                 vec!["man awk| awk -F \"[ ,.\\\"]+\" 'foo' |sort -k2nr|head"],
@@ -952,6 +1363,14 @@ mod tests {
             ("&&", vec!["&&"]),
             ("ls foo\\#bar", vec!["ls foo\\#bar"]),
             ("ls \\|\\|foo", vec!["ls \\|\\|foo"]),
+            (
+                "cat <<EOF; echo a\nfoo; bar\nEOF\necho b",
+                vec!["cat <<EOF\nfoo; bar\nEOF", ";", "echo a", ";", "echo b"],
+            ),
+            (
+                "cat <<EOF # foo\nbar # baz\nEOF",
+                vec!["cat <<EOF\nbar # baz\nEOF"],
+            ),
         ];
 
         for (left, right) in v {
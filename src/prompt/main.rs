@@ -1,6 +1,7 @@
 use std::env;
+use std::path::Path;
 
-use crate::execute;
+use crate::gitrepo;
 use crate::libs;
 use crate::shell;
 
@@ -9,6 +10,8 @@ const DEFAULT_PROMPT: &str = "${COLOR_STATUS}$USER${RESET}\
     ${COLOR_STATUS}$CWD${RESET}$ ";
 use super::preset::apply_preset_item;
 use super::preset::apply_pyenv;
+use super::segments;
+use super::theme;
 
 fn is_prefix_char(c: char) -> bool {
     c == '[' || c == '{'
@@ -34,20 +37,39 @@ pub fn get_prompt_string() -> String {
     DEFAULT_PROMPT.to_string()
 }
 
-fn apply_prompt_item(sh: &shell::Shell, result: &mut String, token: &str) {
+fn apply_prompt_item(sh: &shell::Shell, result: &mut String, token: &str, depth: usize) {
     if let Some(x) = sh.get_env(token) {
         result.push_str(&x);
         return;
     }
-    apply_preset_item(sh, result, token);
+    if apply_preset_item(sh, result, token) {
+        return;
+    }
+    // segments of the theme can contain other items and segments
+    if let Some(x) = theme::get_segment(&token.to_ascii_lowercase(), depth) {
+        result.push_str(&render_items_at(sh, &x, depth + 1));
+    }
 }
 
+/// Commands are run again for every prompt, and the output of last run
+/// in the same dir (and git index) is shown before the new one arrives.
 fn apply_command(result: &mut String, token: &str, prefix: &str, suffix: &str) {
-    let cr = execute::run(token);
-    let output = cr.stdout.trim();
+    let cwd = libs::path::current_dir();
+    let file_index = match gitrepo::discover(Path::new(&cwd)) {
+        Some(repo) => repo.git_dir.join("index").to_string_lossy().to_string(),
+        None => String::new(),
+    };
+    let key = format!("{}:{}", cwd, segments::mtime(&file_index));
+    let line = token.to_string();
+    let output = match segments::get(&format!("$({})", token), &key, true, move || {
+        segments::run_command(&line)
+    }) {
+        Some(x) => x.trim().to_string(),
+        None => segments::placeholder(),
+    };
     if !output.is_empty() {
         result.push_str(prefix);
-        result.push_str(output);
+        result.push_str(&output);
         result.push_str(suffix);
     }
 }
@@ -55,7 +77,22 @@ fn apply_command(result: &mut String, token: &str, prefix: &str, suffix: &str) {
 pub fn render_prompt(sh: &shell::Shell, ps: &str) -> String {
     let mut prompt = String::new();
     apply_pyenv(&mut prompt);
+    prompt.push_str(&render_items(sh, ps));
+
+    if prompt.trim().is_empty() {
+        return format!("cicada-{} >> ", env!("CARGO_PKG_VERSION"));
+    }
+    prompt
+}
+
+/// Render the items in the prompt string, without the prefix of python
+/// virtual env and the fallback of `render_prompt()`.
+pub fn render_items(sh: &shell::Shell, ps: &str) -> String {
+    render_items_at(sh, ps, 0)
+}
 
+fn render_items_at(sh: &shell::Shell, ps: &str, depth: usize) -> String {
+    let mut prompt = String::new();
     let mut met_dollar = false;
     let mut met_brace = false;
     let mut met_paren = false;
@@ -81,7 +118,7 @@ pub fn render_prompt(sh: &shell::Shell, ps: &str) -> String {
                 met_brace = true;
                 continue;
             } else if c == '}' && met_brace {
-                apply_prompt_item(sh, &mut prompt, &token);
+                apply_prompt_item(sh, &mut prompt, &token, depth);
                 token.clear();
                 met_dollar = false;
                 met_brace = false;
@@ -93,7 +130,7 @@ pub fn render_prompt(sh: &shell::Shell, ps: &str) -> String {
                     met_dollar = true;
                     continue;
                 } else {
-                    apply_prompt_item(sh, &mut prompt, &token);
+                    apply_prompt_item(sh, &mut prompt, &token, depth);
                     token.clear();
                     // met_dollar is still true
                     continue;
@@ -124,7 +161,7 @@ pub fn render_prompt(sh: &shell::Shell, ps: &str) -> String {
         }
 
         if !token.is_empty() {
-            apply_prompt_item(sh, &mut prompt, &token);
+            apply_prompt_item(sh, &mut prompt, &token, depth);
             token.clear();
         }
         prompt.push(c);
@@ -132,7 +169,7 @@ pub fn render_prompt(sh: &shell::Shell, ps: &str) -> String {
     }
 
     if !token.is_empty() {
-        apply_prompt_item(sh, &mut prompt, &token);
+        apply_prompt_item(sh, &mut prompt, &token, depth);
         met_dollar = false;
     }
 
@@ -140,10 +177,6 @@ pub fn render_prompt(sh: &shell::Shell, ps: &str) -> String {
         // for cases like PROMPT='$$'
         prompt.push('$');
     }
-
-    if prompt.trim().is_empty() {
-        return format!("cicada-{} >> ", env!("CARGO_PKG_VERSION"));
-    }
     prompt
 }
 
@@ -1,13 +1,21 @@
+use std::env;
+use std::time::Duration;
+
 mod main;
 mod multilines;
 mod preset;
+mod reader;
+mod segments;
+mod theme;
 
 use crate::libs;
 use crate::shell;
 
 use self::main::get_prompt_string;
+use self::main::render_items;
 use self::main::render_prompt;
 pub use self::multilines::EnterFunction;
+pub use self::reader::read_line;
 
 fn get_prompt_len(prompt: &str) -> i32 {
     let mut count = 0;
@@ -27,7 +35,7 @@ fn get_prompt_len(prompt: &str) -> i32 {
     count
 }
 
-pub fn get_prompt(sh: &shell::Shell) -> String {
+fn build_prompt(sh: &shell::Shell) -> String {
     let ps = get_prompt_string();
     let mut prompt = render_prompt(sh, &ps);
     if let Some((w, _h)) = libs::term_size::dimensions() {
@@ -41,3 +49,40 @@ pub fn get_prompt(sh: &shell::Shell) -> String {
     }
     prompt
 }
+
+/// Render a prompt string from the env, which is a single line without
+/// the left prompt's prefix and fallback.
+fn build_extra_prompt(sh: &shell::Shell, name: &str) -> String {
+    match env::var(name) {
+        Ok(x) if !x.is_empty() => render_items(sh, &x).replace('\n', " "),
+        _ => String::new(),
+    }
+}
+
+/// The prompt, and the right prompt from `RPROMPT`.
+pub fn get_prompt(sh: &shell::Shell) -> (String, String) {
+    segments::next_round();
+    let prompt = build_prompt(sh);
+    let rprompt = build_extra_prompt(sh, "RPROMPT");
+    // most segments are ready in a moment, do not flash placeholders
+    segments::wait(Duration::from_millis(50));
+    if segments::take_updated() {
+        return (build_prompt(sh), build_extra_prompt(sh, "RPROMPT"));
+    }
+    (prompt, rprompt)
+}
+
+/// The prompts rendered again, if some segments got fresh values.
+pub fn get_updated_prompt(sh: &shell::Shell) -> Option<(String, String)> {
+    if segments::take_updated() {
+        Some((build_prompt(sh), build_extra_prompt(sh, "RPROMPT")))
+    } else {
+        None
+    }
+}
+
+/// The compact prompt from `TRANSIENT_PROMPT`, to replace the prompt of
+/// accepted lines with. Empty if it's not set.
+pub fn get_transient_prompt(sh: &shell::Shell) -> String {
+    build_extra_prompt(sh, "TRANSIENT_PROMPT")
+}
@@ -1,15 +1,19 @@
 use lineread::{Function, Prompter, Terminal};
 use std::io;
 
-use crate::parsers::parser_line;
+use crate::parsers::{locust, parser_line};
+use crate::shell;
 
 pub struct EnterFunction;
 
 impl<T: Terminal> Function<T> for EnterFunction {
     fn execute(&self, prompter: &mut Prompter<T>, count: i32, _ch: char) -> io::Result<()> {
         let buf = prompter.buffer();
-        let linfo = parser_line::parse_line(buf);
-        if linfo.is_complete {
+        // strip the `>> ` sub-prompts, or here-document delimiters would
+        // never match their lines.
+        let line = shell::trim_multiline_prompts(buf);
+        let linfo = parser_line::parse_line(&line);
+        if linfo.is_complete && !locust::is_func_def_incomplete(&line) {
             prompter.accept_input()
         } else if count > 0 {
             match prompter.insert(count as usize, '\n') {
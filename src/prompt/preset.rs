@@ -1,12 +1,19 @@
 use std::env;
-use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Write;
 use std::path::Path;
+use std::time::Instant;
 
+use nix::sys::signal::Signal;
+
+use crate::gitrepo::status::{self, Status};
+use crate::gitrepo::{self, Head};
 use crate::libs;
 use crate::shell;
 use crate::tools;
 
+use super::segments;
+use super::theme;
+
 fn apply_seq(prompt: &mut String) {
     prompt.push_str(libs::colored::SEQ);
 }
@@ -19,313 +26,181 @@ fn apply_esc(prompt: &mut String) {
     prompt.push_str(libs::colored::ESC);
 }
 
-fn apply_underlined(prompt: &mut String) {
-    prompt.push_str(libs::colored::UNDERLINED);
-}
-
 fn apply_user(prompt: &mut String) {
     let username = tools::get_user_name();
     prompt.push_str(&username);
 }
 
-fn apply_black(prompt: &mut String) {
-    prompt.push_str(libs::colored::BLACK);
-}
-
-fn apply_black_b(prompt: &mut String) {
-    prompt.push_str(libs::colored::BLACK_B);
-}
-
-fn apply_black_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::BLACK_BG);
-}
-
-fn apply_blue(prompt: &mut String) {
-    prompt.push_str(libs::colored::BLUE);
-}
-
-fn apply_blue_b(prompt: &mut String) {
-    prompt.push_str(libs::colored::BLUE_B);
-}
-
-fn apply_blue_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::BLUE_BG);
-}
-
-fn apply_bold(prompt: &mut String) {
-    prompt.push_str(libs::colored::BOLD);
-}
-
-fn apply_green(prompt: &mut String) {
-    prompt.push_str(libs::colored::GREEN);
-}
-
-fn apply_green_b(prompt: &mut String) {
-    prompt.push_str(libs::colored::GREEN_B);
-}
-
-fn apply_green_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::GREEN_BG);
-}
-
-fn apply_red(prompt: &mut String) {
-    prompt.push_str(libs::colored::RED);
-}
-
-fn apply_red_b(prompt: &mut String) {
-    prompt.push_str(libs::colored::RED_B);
-}
-
-fn apply_red_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::RED_BG);
-}
-
-fn apply_white(prompt: &mut String) {
-    prompt.push_str(libs::colored::WHITE);
-}
-
-fn apply_white_b(prompt: &mut String) {
-    prompt.push_str(libs::colored::WHITE_B);
-}
-
-fn apply_white_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::WHITE_BG);
-}
-
-fn apply_hidden(prompt: &mut String) {
-    prompt.push_str(libs::colored::HIDDEN);
-}
-
-fn apply_reset(prompt: &mut String) {
-    prompt.push_str(libs::colored::RESET);
-}
-
-fn apply_reverse(prompt: &mut String) {
-    prompt.push_str(libs::colored::REVERSE);
-}
-
-fn apply_dim(prompt: &mut String) {
-    prompt.push_str(libs::colored::DIM);
-}
-
-fn apply_blink(prompt: &mut String) {
-    prompt.push_str(libs::colored::BLINK);
-}
-
-fn apply_reset_underlined(prompt: &mut String) {
-    prompt.push_str(libs::colored::RESET_UNDERLINED);
-}
-
-fn apply_reset_dim(prompt: &mut String) {
-    prompt.push_str(libs::colored::RESET_DIM);
-}
-
-fn apply_reset_reverse(prompt: &mut String) {
-    prompt.push_str(libs::colored::RESET_REVERSE);
-}
-
-fn apply_reset_hidden(prompt: &mut String) {
-    prompt.push_str(libs::colored::RESET_HIDDEN);
-}
-
-fn apply_reset_blink(prompt: &mut String) {
-    prompt.push_str(libs::colored::RESET_BLINK);
-}
-
-fn apply_reset_bold(prompt: &mut String) {
-    prompt.push_str(libs::colored::RESET_BOLD);
-}
-
-fn apply_default(prompt: &mut String) {
-    prompt.push_str(libs::colored::DEFAULT);
-}
-
-fn apply_default_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::DEFAULT_BG);
-}
-
-fn apply_cyan(prompt: &mut String) {
-    prompt.push_str(libs::colored::CYAN);
-}
-
-fn apply_cyan_l(prompt: &mut String) {
-    prompt.push_str(libs::colored::CYAN_L);
-}
-
-fn apply_cyan_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::CYAN_BG);
-}
-
-fn apply_cyan_l_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::CYAN_L_BG);
-}
-
-fn apply_red_l(prompt: &mut String) {
-    prompt.push_str(libs::colored::RED_L);
-}
-
-fn apply_red_l_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::RED_L_BG);
-}
-
-fn apply_green_l(prompt: &mut String) {
-    prompt.push_str(libs::colored::GREEN_L);
-}
-
-fn apply_green_l_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::GREEN_L_BG);
-}
-
-fn apply_gray_l(prompt: &mut String) {
-    prompt.push_str(libs::colored::GRAY_L);
-}
-
-fn apply_gray_l_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::GRAY_L_BG);
-}
-
-fn apply_gray_d(prompt: &mut String) {
-    prompt.push_str(libs::colored::GRAY_D);
-}
-
-fn apply_gray_d_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::GRAY_D_BG);
-}
-
-fn apply_magenta(prompt: &mut String) {
-    prompt.push_str(libs::colored::MAGENTA);
-}
-
-fn apply_magenta_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::MAGENTA_BG);
-}
-
-fn apply_magenta_l(prompt: &mut String) {
-    prompt.push_str(libs::colored::MAGENTA_L);
-}
-
-fn apply_magenta_l_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::MAGENTA_L_BG);
-}
-
-fn apply_yellow(prompt: &mut String) {
-    prompt.push_str(libs::colored::YELLOW);
-}
-
-fn apply_yellow_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::YELLOW_BG);
-}
-
-fn apply_yellow_l(prompt: &mut String) {
-    prompt.push_str(libs::colored::YELLOW_L);
-}
-
-fn apply_yellow_l_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::YELLOW_L_BG);
-}
-
-fn apply_blue_l(prompt: &mut String) {
-    prompt.push_str(libs::colored::BLUE_L);
-}
-
-fn apply_blue_l_bg(prompt: &mut String) {
-    prompt.push_str(libs::colored::BLUE_L_BG);
-}
-
 fn apply_color_status(sh: &shell::Shell, prompt: &mut String) {
     if sh.previous_status == 0 {
-        prompt.push_str(libs::colored::GREEN_B);
+        theme::apply_style(prompt, "status_ok");
     } else {
-        prompt.push_str(libs::colored::RED_B);
+        theme::apply_style(prompt, "status_error");
     }
 }
 
-fn _find_git_root() -> String {
-    let current_dir = libs::path::current_dir();
-    let dir_git = format!("{}/.git", current_dir);
-    if Path::new(&dir_git).exists() {
-        return current_dir;
+/// How long the last command took, if it's at least
+/// `CICADA_CMD_DURATION_MIN` seconds.
+fn apply_cmd_duration(sh: &shell::Shell, prompt: &mut String) {
+    let min = env::var("CICADA_CMD_DURATION_MIN")
+        .ok()
+        .and_then(|x| x.parse::<f64>().ok())
+        .unwrap_or(2.0);
+    if sh.previous_duration >= min {
+        prompt.push_str(&tools::format_duration(sh.previous_duration));
     }
+}
 
-    let mut _dir = current_dir.clone();
-    while Path::new(&_dir).parent().is_some() {
-        match Path::new(&_dir).parent() {
-            Some(p) => {
-                _dir = p.to_string_lossy().to_string();
-                let dir_git = format!("{}/.git", _dir);
-                if Path::new(&dir_git).exists() {
-                    return _dir;
-                }
-            }
-            None => {
-                break;
-            }
-        }
+fn apply_exit_code(sh: &shell::Shell, prompt: &mut String) {
+    if sh.previous_status != 0 {
+        prompt.push_str(&sh.previous_status.to_string());
     }
-
-    String::new()
 }
 
-fn apply_gitbr(prompt: &mut String) {
-    let git_root = _find_git_root();
-    if git_root.is_empty() {
+/// The name of the signal which killed the last command, like `INT`.
+fn apply_exit_signal(sh: &shell::Shell, prompt: &mut String) {
+    if sh.previous_status <= 128 {
         return;
     }
-
-    let file_head = format!("{}/.git/HEAD", git_root);
-    if !Path::new(&file_head).exists() {
-        return;
+    if let Ok(x) = Signal::try_from(sh.previous_status - 128) {
+        prompt.push_str(x.as_str().trim_start_matches("SIG"));
     }
+}
 
-    let mut file;
-    match File::open(&file_head) {
-        Ok(x) => file = x,
-        Err(e) => {
-            println!("cicada: .git/HEAD err: {:?}", e);
-            return;
+fn render_gitbr(repo: &gitrepo::Repo) -> String {
+    let mut result = String::new();
+    let branch = match repo.head() {
+        Some(Head::Branch(x)) => x,
+        Some(Head::Detached(oid)) => gitrepo::to_hex(&oid)[..7].to_string(),
+        None => {
+            log!("cicada: read HEAD failed in {:?}", repo.git_dir);
+            return result;
         }
+    };
+
+    if let Ok(x) = env::var("CICADA_GITBR_PREFIX") {
+        result.push_str(&x);
     }
-    let mut text = String::new();
-    match file.read_to_string(&mut text) {
-        Ok(_) => {}
-        Err(e) => {
-            println!("cicada: read_to_string error: {:?}", e);
-            return;
+
+    let _len_default: i32 = 32;
+    let mut len_max = if let Ok(x) = env::var("CICADA_GITBR_MAX_LEN") {
+        match x.parse::<i32>() {
+            Ok(n) => n,
+            Err(_) => _len_default,
         }
+    } else {
+        _len_default
+    };
+    if len_max <= 0 {
+        len_max = _len_default;
+    }
+
+    if branch.len() as i32 <= len_max {
+        result.push_str(&branch);
+    } else {
+        let len = branch.len() as i32;
+        let offset = (len - len_max + 2) as usize;
+        let branch_short = format!("..{}", &branch[offset..]);
+        result.push_str(&branch_short);
+    }
+    if let Ok(x) = env::var("CICADA_GITBR_SUFFIX") {
+        result.push_str(&x);
     }
+    result
+}
+
+fn find_repo() -> Option<gitrepo::Repo> {
+    gitrepo::discover(Path::new(&libs::path::current_dir()))
+}
+
+/// The branch is read again only when `HEAD` or the options change.
+fn apply_gitbr(prompt: &mut String) {
+    let repo = match find_repo() {
+        Some(x) => x,
+        None => return,
+    };
 
-    if let Some(branch) = libs::re::find_first_group(r"^[a-z]+: ?[a-z]+/[a-z]+/(.+)$", text.trim())
-    {
-        apply_blue_b(prompt);
-        if let Ok(x) = env::var("CICADA_GITBR_PREFIX") {
+    let options: Vec<String> = [
+        "CICADA_GITBR_PREFIX",
+        "CICADA_GITBR_SUFFIX",
+        "CICADA_GITBR_MAX_LEN",
+    ]
+    .iter()
+    .map(|x| env::var(x).unwrap_or_default())
+    .collect();
+    let file_head = repo.git_dir.join("HEAD").to_string_lossy().to_string();
+    let key = format!(
+        "{}:{}:{:?}",
+        file_head,
+        segments::mtime(&file_head),
+        options
+    );
+    match segments::get("gitbr", &key, false, move || Some(render_gitbr(&repo))) {
+        Some(x) if x.is_empty() => {}
+        Some(x) => {
+            theme::apply_style(prompt, "branch");
             prompt.push_str(&x);
+            theme::apply_style(prompt, "reset");
         }
+        None => prompt.push_str(&segments::placeholder()),
+    }
+}
 
-        let _len_default: i32 = 32;
-        let mut len_max = if let Ok(x) = env::var("CICADA_GITBR_MAX_LEN") {
-            match x.parse::<i32>() {
-                Ok(n) => n,
-                Err(_) => _len_default,
-            }
-        } else {
-            _len_default
-        };
-        if len_max <= 0 {
-            len_max = _len_default;
+enum GitItem {
+    Dirty,
+    Ahead,
+    Behind,
+    State,
+    Stash,
+}
+
+/// All git status items share one segment, whose value has the text of
+/// each item separated by tabs.
+fn render_gitstatus(status: &Status) -> String {
+    let mut dirty = String::new();
+    for (on, marker) in [
+        (status.conflicted, '='),
+        (status.staged, '+'),
+        (status.modified, '!'),
+        (status.untracked, '?'),
+    ] {
+        if on {
+            dirty.push(marker);
         }
-
-        if branch.len() as i32 <= len_max {
-            prompt.push_str(&branch);
+    }
+    let count = |n: usize, marker: &str| {
+        if n > 0 {
+            format!("{}{}", marker, n)
         } else {
-            let len = branch.len() as i32;
-            let offset = (len - len_max + 2) as usize;
-            let branch_short = format!("..{}", &branch[offset..]);
-            prompt.push_str(&branch_short);
+            String::new()
         }
-        if let Ok(x) = env::var("CICADA_GITBR_SUFFIX") {
-            prompt.push_str(&x);
+    };
+    [
+        dirty,
+        count(status.ahead, "⇡"),
+        count(status.behind, "⇣"),
+        status.state.clone(),
+        count(status.stash, "≡"),
+    ]
+    .join("\t")
+}
+
+/// The status is computed again for every prompt, and items show nothing
+/// before the first one is ready.
+fn apply_gitstatus(prompt: &mut String, item: GitItem) {
+    let repo = match find_repo() {
+        Some(x) => x,
+        None => return,
+    };
+    let key = repo.git_dir.to_string_lossy().to_string();
+    let value = segments::get("gitstatus", &key, true, move || {
+        let deadline = Instant::now() + segments::get_timeout();
+        status::get_status(&repo, deadline).map(|x| render_gitstatus(&x))
+    });
+    if let Some(value) = value {
+        if let Some(x) = value.split('\t').nth(item as usize) {
+            prompt.push_str(x);
         }
-        apply_reset(prompt);
     }
 }
 
@@ -386,78 +261,38 @@ pub fn apply_pyenv(prompt: &mut String) {
                 }
             };
 
-            apply_blue_b(prompt);
+            theme::apply_style(prompt, "venv");
             prompt.push('(');
             prompt.push_str(env_name);
             prompt.push(')');
-            apply_reset(prompt);
+            theme::apply_style(prompt, "reset");
         }
     }
 }
 
-pub fn apply_preset_item(sh: &shell::Shell, prompt: &mut String, token: &str) {
-    match token.to_ascii_lowercase().as_ref() {
-        "black" => apply_black(prompt),
-        "black_b" => apply_black_b(prompt),
-        "black_bg" => apply_black_bg(prompt),
-        "blink" => apply_blink(prompt),
-        "blue" => apply_blue(prompt),
-        "blue_b" => apply_blue_b(prompt),
-        "blue_bg" => apply_blue_bg(prompt),
-        "blue_l" => apply_blue_l(prompt),
-        "blue_l_bg" => apply_blue_l_bg(prompt),
-        "bold" => apply_bold(prompt),
+/// Apply the item like `cwd` or `gitbr`, or a style of the theme like
+/// `blue_b`. Returns false if the item is unknown.
+pub fn apply_preset_item(sh: &shell::Shell, prompt: &mut String, token: &str) -> bool {
+    let name = token.to_ascii_lowercase();
+    match name.as_ref() {
+        "cmd_duration" => apply_cmd_duration(sh, prompt),
         "color_status" => apply_color_status(sh, prompt),
         "cwd" => apply_cwd(prompt),
-        "cyan" => apply_cyan(prompt),
-        "cyan_bg" => apply_cyan_bg(prompt),
-        "cyan_l" => apply_cyan_l(prompt),
-        "cyan_l_bg" => apply_cyan_l_bg(prompt),
-        "default" => apply_default(prompt),
-        "default_bg" => apply_default_bg(prompt),
-        "dim" => apply_dim(prompt),
         "end_seq" => apply_end_seq(prompt),
         "esc" => apply_esc(prompt),
+        "exit_code" => apply_exit_code(sh, prompt),
+        "exit_signal" => apply_exit_signal(sh, prompt),
         "gitbr" => apply_gitbr(prompt),
-        "gray_d" => apply_gray_d(prompt),
-        "gray_d_bg" => apply_gray_d_bg(prompt),
-        "gray_l" => apply_gray_l(prompt),
-        "gray_l_bg" => apply_gray_l_bg(prompt),
-        "green" => apply_green(prompt),
-        "green_b" => apply_green_b(prompt),
-        "green_bg" => apply_green_bg(prompt),
-        "green_l" => apply_green_l(prompt),
-        "green_l_bg" => apply_green_l_bg(prompt),
-        "hidden" => apply_hidden(prompt),
+        "git_ahead" => apply_gitstatus(prompt, GitItem::Ahead),
+        "git_behind" => apply_gitstatus(prompt, GitItem::Behind),
+        "git_dirty" => apply_gitstatus(prompt, GitItem::Dirty),
+        "git_stash" => apply_gitstatus(prompt, GitItem::Stash),
+        "git_state" => apply_gitstatus(prompt, GitItem::State),
         "hostname" => apply_hostname(prompt),
-        "magenta" => apply_magenta(prompt),
-        "magenta_bg" => apply_magenta_bg(prompt),
-        "magenta_l" => apply_magenta_l(prompt),
-        "magenta_l_bg" => apply_magenta_l_bg(prompt),
         "newline" => apply_newline(prompt),
-        "red" => apply_red(prompt),
-        "red_b" => apply_red_b(prompt),
-        "red_bg" => apply_red_bg(prompt),
-        "red_l" => apply_red_l(prompt),
-        "red_l_bg" => apply_red_l_bg(prompt),
-        "reset" => apply_reset(prompt),
-        "reset_blink" => apply_reset_blink(prompt),
-        "reset_bold" => apply_reset_bold(prompt),
-        "reset_dim" => apply_reset_dim(prompt),
-        "reset_hidden" => apply_reset_hidden(prompt),
-        "reset_reverse" => apply_reset_reverse(prompt),
-        "reset_underlined" => apply_reset_underlined(prompt),
-        "reverse" => apply_reverse(prompt),
         "seq" => apply_seq(prompt),
-        "underlined" => apply_underlined(prompt),
         "user" => apply_user(prompt),
-        "white" => apply_white(prompt),
-        "white_b" => apply_white_b(prompt),
-        "white_bg" => apply_white_bg(prompt),
-        "yellow" => apply_yellow(prompt),
-        "yellow_bg" => apply_yellow_bg(prompt),
-        "yellow_l" => apply_yellow_l(prompt),
-        "yellow_l_bg" => apply_yellow_l_bg(prompt),
-        _ => (),
+        _ => return theme::apply_style(prompt, &name),
     }
+    true
 }
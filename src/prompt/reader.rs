@@ -0,0 +1,340 @@
+//! Reading lines with the prompts. lineread is driven step by step, and
+//! between the steps the right prompt and autosuggestions are drawn, and
+//! the prompt is repainted when its segments get fresh values. The
+//! transient prompt replaces the prompt of accepted lines.
+use std::io::{self, Write};
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use lineread::terminal::DefaultTerminal;
+use lineread::util::{filter_visible, grapheme_width};
+use lineread::{Interface, ReadResult};
+
+use crate::highlight;
+use crate::libs;
+use crate::shell;
+use crate::suggest;
+
+use super::segments::{drain_notify, is_pending, notify_fd};
+use super::{get_transient_prompt, get_updated_prompt};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn display_width(text: &str) -> Option<usize> {
+    let mut width = 0;
+    let mut buf = [0u8; 4];
+    for c in text.chars() {
+        if c.is_control() {
+            return None;
+        }
+        width += grapheme_width(c.encode_utf8(&mut buf));
+    }
+    Some(width)
+}
+
+/// Keep the terminal quiet (no echo, no line buffering) while the
+/// suggestion is on the screen and lineread is not reading.
+struct NoEcho {
+    saved: Option<libc::termios>,
+}
+
+impl NoEcho {
+    fn new() -> Self {
+        unsafe {
+            let mut tio: libc::termios = mem::zeroed();
+            if libc::tcgetattr(0, &mut tio) != 0 {
+                return NoEcho { saved: None };
+            }
+            let saved = tio;
+            tio.c_lflag &= !(libc::ICANON | libc::ECHO);
+            tio.c_cc[libc::VMIN] = 1;
+            tio.c_cc[libc::VTIME] = 0;
+            libc::tcsetattr(0, libc::TCSANOW, &tio);
+            NoEcho { saved: Some(saved) }
+        }
+    }
+}
+
+impl Drop for NoEcho {
+    fn drop(&mut self) {
+        if let Some(tio) = self.saved {
+            unsafe {
+                libc::tcsetattr(0, libc::TCSANOW, &tio);
+            }
+        }
+    }
+}
+
+extern "C" fn handle_sigint(_sig: i32) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+enum Wake {
+    Input,
+    // by `Ctrl-C`, which lineread is not around to catch
+    Interrupted,
+    // some prompt segments are done
+    Prompt,
+}
+
+/// Wait until some input arrives, or the prompt segments are done if
+/// `watch_prompt` is set.
+fn wait_input(watch_prompt: bool) -> Wake {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+    let mut fds = [
+        libc::pollfd {
+            fd: 0,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            // negative fds are ignored by poll()
+            fd: if watch_prompt { notify_fd() } else { -1 },
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle_sigint as *const () as usize;
+        libc::sigemptyset(&mut action.sa_mask);
+        let mut old: libc::sigaction = mem::zeroed();
+        libc::sigaction(libc::SIGINT, &action, &mut old);
+        loop {
+            if libc::poll(fds.as_mut_ptr(), 2, -1) > 0 || INTERRUPTED.load(Ordering::SeqCst) {
+                break;
+            }
+            // e.g. interrupted by SIGCHLD
+            if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+                break;
+            }
+        }
+        libc::sigaction(libc::SIGINT, &old, std::ptr::null_mut());
+    }
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        Wake::Interrupted
+    } else if fds[0].revents == 0 && fds[1].revents != 0 {
+        drain_notify();
+        Wake::Prompt
+    } else {
+        Wake::Input
+    }
+}
+
+/// The width of the last line of the prompt.
+fn prompt_width(prompt: &str) -> usize {
+    let prompt = prompt.rsplit('\n').next().unwrap_or("");
+    display_width(&filter_visible(prompt)).unwrap_or(0)
+}
+
+/// Draw the suggestion after the input, if it fits in the row before
+/// the column `limit`. Returns the width drawn.
+fn draw_suggestion(
+    prompt: &str,
+    buffer: &str,
+    suggestion: &str,
+    limit: Option<usize>,
+) -> io::Result<usize> {
+    if suggestion.is_empty() {
+        return Ok(0);
+    }
+    let columns = match libs::term_size::dimensions() {
+        Some((w, _)) => w,
+        None => return Ok(0),
+    };
+    let col = match display_width(buffer) {
+        Some(x) => (prompt_width(prompt) + x) % columns,
+        None => return Ok(0),
+    };
+    // never touch the last column, so that the line does not wrap
+    suggest::draw(col, suggestion, limit.unwrap_or(columns))
+}
+
+/// Draw the right prompt flush right on the row of the input, if the
+/// input does not reach it, otherwise erase it if it's `drawn` before.
+/// Returns the column it starts at.
+fn draw_rprompt(
+    prompt: &str,
+    buffer: &str,
+    rprompt: &str,
+    drawn: &mut bool,
+) -> io::Result<Option<usize>> {
+    let columns = match libs::term_size::dimensions() {
+        Some((w, _)) => w,
+        None => return Ok(None),
+    };
+    let width = display_width(&filter_visible(rprompt)).unwrap_or(0);
+    // the cursor may not be on the row of the prompt
+    let end = match display_width(buffer) {
+        Some(x) => prompt_width(prompt) + x,
+        None => return Ok(None),
+    };
+    if width == 0 || end >= columns {
+        return Ok(None);
+    }
+
+    let mut stdout = io::stdout();
+    // keep a space after the input, and the last column untouched
+    let start = columns.saturating_sub(width + 1);
+    if end + 1 >= start {
+        if *drawn && start > 0 {
+            write!(stdout, "\x1b7\r\x1b[{}C\x1b[K\x1b8", start.max(end))?;
+            stdout.flush()?;
+        }
+        *drawn = false;
+        return Ok(None);
+    }
+    let text: String = rprompt
+        .chars()
+        .filter(|c| *c != '\x01' && *c != '\x02')
+        .collect();
+    write!(stdout, "\x1b7\r\x1b[{}C{}\x1b[0m\x1b8", start, text)?;
+    stdout.flush()?;
+    *drawn = true;
+    Ok(Some(start))
+}
+
+/// The rows from the start of the prompt to the end of the input, as
+/// lineread draws them.
+fn count_rows(prompt: &str, buffer: &str, columns: usize) -> usize {
+    let mut col = 0;
+    let mut buf = [0u8; 4];
+    let text = format!("{}{}", filter_visible(prompt), buffer);
+    for c in text.chars() {
+        match c {
+            '\n' => col += columns - col % columns,
+            '\t' => col += 8 - col % 8,
+            _ if c.is_control() => col += 2,
+            _ => {
+                let width = grapheme_width(c.encode_utf8(&mut buf));
+                if width == 2 && col % columns == columns - 1 {
+                    col += 1;
+                }
+                col += width;
+            }
+        }
+    }
+    col / columns + 1
+}
+
+/// Replace the prompt of the accepted line with the transient prompt.
+/// The cursor is at the start of the row after the input.
+fn draw_transient(prompt: &str, line: &str, transient: &str) -> io::Result<()> {
+    let (columns, rows) = match libs::term_size::dimensions() {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let count = count_rows(prompt, line, columns);
+    // it's scrolled out of the screen
+    if count >= rows {
+        return Ok(());
+    }
+
+    let transient: String = transient
+        .chars()
+        .filter(|c| *c != '\x01' && *c != '\x02')
+        .collect();
+    let line = highlight::render_line(line).replace('\n', "\r\n");
+    let mut stdout = io::stdout();
+    write!(
+        stdout,
+        "\x1b[{}A\r\x1b[J{}\x1b[0m{}\r\n",
+        count, transient, line
+    )?;
+    stdout.flush()
+}
+
+/// Read a line like `Interface::read_line()`, with autosuggestions and
+/// the right prompt, and repaint the prompts when their segments get
+/// fresh values.
+pub fn read_line(
+    rl: &Interface<DefaultTerminal>,
+    sh: &shell::Shell,
+    prompt: &str,
+    rprompt: &str,
+) -> io::Result<ReadResult> {
+    let enabled = suggest::is_enabled();
+    let mut prompt = prompt.to_string();
+    let mut rprompt = rprompt.to_string();
+    let mut rprompt_drawn = false;
+    suggest::reset_state();
+    // only draw the prompt in the first step
+    let mut timeout = Some(Duration::from_millis(0));
+    loop {
+        if let Some(result) = rl.read_line_step(timeout)? {
+            if let ReadResult::Input(line) = &result {
+                let transient = get_transient_prompt(sh);
+                if !transient.is_empty() {
+                    draw_transient(&prompt, line, &transient)?;
+                }
+            }
+            return Ok(result);
+        }
+        timeout = None;
+
+        let _no_echo = NoEcho::new();
+        let buffer = rl.buffer();
+        let suggestion = if enabled {
+            suggest::update_suggestion(&buffer)
+        } else {
+            String::new()
+        };
+        loop {
+            let pending = is_pending();
+            let limit = draw_rprompt(&prompt, &buffer, &rprompt, &mut rprompt_drawn)?;
+            let drawn = draw_suggestion(&prompt, &buffer, &suggestion, limit)?;
+            if drawn == 0 && !pending {
+                break;
+            }
+            let wake = wait_input(pending);
+            if drawn > 0 {
+                suggest::erase(drawn)?;
+            }
+            match wake {
+                Wake::Input => break,
+                Wake::Interrupted => {
+                    // do what lineread does for `Ctrl-C`
+                    let mut stdout = io::stdout();
+                    stdout.write_all(b"^C")?;
+                    stdout.flush()?;
+                    rl.cancel_read_line()?;
+                    suggest::reset_state();
+                    rprompt_drawn = false;
+                    timeout = Some(Duration::from_millis(0));
+                    break;
+                }
+                Wake::Prompt => {
+                    if let Some((x, y)) = get_updated_prompt(sh) {
+                        prompt = x;
+                        rprompt = y;
+                        highlight::set_prompt_suffix(&prompt);
+                        rl.set_prompt(&prompt)?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(display_width("ls -l"), Some(5));
+        assert_eq!(display_width("echo 你好"), Some(9));
+        assert_eq!(display_width("a\tb"), None);
+    }
+
+    #[test]
+    fn test_count_rows() {
+        assert_eq!(count_rows("$ ", "ls", 80), 1);
+        assert_eq!(count_rows("line1\n$ ", "ls", 80), 2);
+        assert_eq!(count_rows("\x01\x1b[32m\x02$\x01\x1b[0m\x02 ", "ls", 4), 2);
+        assert_eq!(count_rows("$ ", "echo 'a\n>> b'", 80), 2);
+        assert_eq!(count_rows("$ ", &"x".repeat(100), 80), 2);
+        assert_eq!(count_rows("$ ", "你好", 5), 2);
+    }
+}
@@ -0,0 +1,268 @@
+//! Prompt segments computed by background workers, i.e. `$(cmd)` items
+//! and the git items. A segment shows the value cached for its current key
+//! (e.g. the cwd plus mtime of the git index) at once, or a placeholder,
+//! and the prompt is repainted when the fresh value arrives.
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+const MAX_ENTRIES: usize = 512;
+
+#[derive(Debug, Default)]
+struct Entry {
+    value: Option<String>,
+    // the prompt round it's computed in
+    round: u64,
+    pending: bool,
+}
+
+#[derive(Debug, Default)]
+struct Segments {
+    entries: HashMap<(String, String), Entry>,
+    round: u64,
+    // some values changed since the prompt is rendered
+    updated: bool,
+}
+
+lazy_static! {
+    static ref SEGMENTS: Mutex<Segments> = Mutex::new(Segments::default());
+    // workers wake up the reading loop via this pipe
+    static ref NOTIFY: (i32, i32) = create_pipe();
+}
+
+fn with_segments<T, F: FnOnce(&mut Segments) -> T>(f: F) -> T {
+    let mut segments = match SEGMENTS.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+    f(&mut segments)
+}
+
+fn create_pipe() -> (i32, i32) {
+    let mut fds = [-1; 2];
+    unsafe {
+        if libc::pipe(fds.as_mut_ptr()) != 0 {
+            return (-1, -1);
+        }
+        for fd in fds {
+            libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK);
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+    }
+    (fds[0], fds[1])
+}
+
+fn notify() {
+    unsafe {
+        libc::write(NOTIFY.1, b"x".as_ptr() as *const libc::c_void, 1);
+    }
+}
+
+/// The fd which becomes readable when some worker is done.
+pub fn notify_fd() -> i32 {
+    NOTIFY.0
+}
+
+pub fn drain_notify() {
+    let mut buf = [0u8; 64];
+    unsafe { while libc::read(NOTIFY.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) > 0 {} }
+}
+
+/// Start rendering a new prompt, so that segments with `refresh` are
+/// computed again.
+pub fn next_round() {
+    with_segments(|segments| segments.round += 1);
+}
+
+/// Get the cached value of the segment for the key. `compute` is run in
+/// a worker if there is no value yet, or with `refresh` once every prompt.
+/// `None` from `compute` keeps the old value.
+pub fn get<F>(id: &str, key: &str, refresh: bool, compute: F) -> Option<String>
+where
+    F: FnOnce() -> Option<String> + Send + 'static,
+{
+    let k = (id.to_string(), key.to_string());
+    let (cached, spawn) = with_segments(|segments| {
+        if segments.entries.len() > MAX_ENTRIES {
+            segments.entries.retain(|_, x| x.pending);
+        }
+        let round = segments.round;
+        let entry = segments.entries.entry(k.clone()).or_default();
+        let spawn = !entry.pending && (entry.value.is_none() || refresh) && entry.round < round;
+        if spawn {
+            entry.pending = true;
+            entry.round = round;
+        }
+        (entry.value.clone(), spawn)
+    });
+
+    if spawn {
+        thread::spawn(move || {
+            let value = compute();
+            with_segments(|segments| {
+                if let Some(entry) = segments.entries.get_mut(&k) {
+                    entry.pending = false;
+                    if value.is_some() && value != entry.value {
+                        entry.value = value;
+                        segments.updated = true;
+                    }
+                }
+            });
+            notify();
+        });
+    }
+    cached
+}
+
+pub fn is_pending() -> bool {
+    with_segments(|segments| segments.entries.values().any(|x| x.pending))
+}
+
+/// Whether some values changed since last call.
+pub fn take_updated() -> bool {
+    with_segments(|segments| {
+        let updated = segments.updated;
+        segments.updated = false;
+        updated
+    })
+}
+
+/// Wait at most `timeout` for the workers running.
+pub fn wait(timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while is_pending() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        let mut fds = libc::pollfd {
+            fd: notify_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ms = (deadline - now).as_millis().max(1) as i32;
+        unsafe {
+            libc::poll(&mut fds, 1, ms);
+        }
+        drain_notify();
+    }
+}
+
+/// Shown for segments without cached values, `CICADA_PROMPT_PLACEHOLDER`.
+pub fn placeholder() -> String {
+    env::var("CICADA_PROMPT_PLACEHOLDER").unwrap_or_else(|_| "…".to_string())
+}
+
+/// How long a segment may take, `CICADA_PROMPT_TIMEOUT`.
+pub fn get_timeout() -> Duration {
+    let ms = env::var("CICADA_PROMPT_TIMEOUT")
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or(2000);
+    Duration::from_millis(ms)
+}
+
+/// Modified time of the file as a string, empty if missing.
+pub fn mtime(path: &str) -> String {
+    match fs::metadata(path).and_then(|x| x.modified()) {
+        Ok(x) => match x.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_nanos().to_string(),
+            Err(_) => String::new(),
+        },
+        Err(_) => String::new(),
+    }
+}
+
+/// Run the command line by cicada in a new session, and get its output.
+/// It's killed with its children after `CICADA_PROMPT_TIMEOUT`.
+pub fn run_command(line: &str) -> Option<String> {
+    let exe = env::current_exe().ok()?;
+    let mut command = Command::new(exe);
+    command
+        .arg("-c")
+        .arg(line)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    let mut child = match command.spawn() {
+        Ok(x) => x,
+        Err(e) => {
+            log!("prompt: run {:?} error: {:?}", line, e);
+            return None;
+        }
+    };
+    let pgid = child.id() as i32;
+    let mut stdout = child.stdout.take()?;
+    let reader = thread::spawn(move || {
+        let mut output = String::new();
+        let _ = stdout.read_to_string(&mut output);
+        output
+    });
+
+    let deadline = Instant::now() + get_timeout();
+    let mut timed_out = false;
+    loop {
+        match child.try_wait() {
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(5)),
+            Ok(Some(_)) | Err(_) => break,
+            Ok(None) => {
+                log!("prompt: {:?} timed out", line);
+                timed_out = true;
+                break;
+            }
+        }
+    }
+    unsafe {
+        libc::kill(-pgid, libc::SIGKILL);
+    }
+    let _ = child.wait();
+    let output = reader.join().unwrap_or_default();
+    if timed_out {
+        None
+    } else {
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(x: &str) -> Option<String> {
+        Some(x.to_string())
+    }
+
+    #[test]
+    fn test_segments() {
+        next_round();
+        assert_eq!(get("test-a", "k1", false, || value("a1")), None);
+        wait(Duration::from_secs(5));
+        assert_eq!(get("test-a", "k1", false, || value("a2")), value("a1"));
+        assert_eq!(get("test-a", "k2", false, || None), None);
+        wait(Duration::from_secs(5));
+        assert_eq!(get("test-a", "k2", false, || value("a3")), None);
+
+        // computed once every round
+        assert_eq!(get("test-b", "k", true, || value("b1")), None);
+        wait(Duration::from_secs(5));
+        assert_eq!(get("test-b", "k", true, || value("b2")), value("b1"));
+        wait(Duration::from_secs(5));
+        assert_eq!(get("test-b", "k", true, || value("b3")), value("b1"));
+        next_round();
+        assert_eq!(get("test-b", "k", true, || value("b4")), value("b1"));
+        wait(Duration::from_secs(5));
+        assert_eq!(get("test-b", "k", true, || value("b5")), value("b4"));
+    }
+}
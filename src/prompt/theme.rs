@@ -0,0 +1,445 @@
+//! Colors, styles and segments of prompt items, i.e. `${BLUE_B}`, defined
+//! by the built-in theme and the theme file `theme.yaml`. Colors are
+//! converted to what the terminal supports, as told by `TERM` and
+//! `COLORTERM`.
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::libs::colored;
+use crate::tools;
+
+use super::segments;
+
+/// The 16 basic colors by their index.
+const BASIC_COLORS: [(&str, u8); 17] = [
+    ("black", 0),
+    ("red", 1),
+    ("green", 2),
+    ("yellow", 3),
+    ("blue", 4),
+    ("magenta", 5),
+    ("cyan", 6),
+    ("gray_l", 7),
+    ("gray", 7),
+    ("gray_d", 8),
+    ("red_l", 9),
+    ("green_l", 10),
+    ("yellow_l", 11),
+    ("blue_l", 12),
+    ("magenta_l", 13),
+    ("cyan_l", 14),
+    ("white", 15),
+];
+
+/// The xterm default RGB values of the basic colors.
+const BASIC_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+const ATTRIBUTES: [(&str, u8, u8); 7] = [
+    ("bold", 1, 22),
+    ("dim", 2, 22),
+    ("italic", 3, 23),
+    ("underlined", 4, 24),
+    ("blink", 5, 25),
+    ("reverse", 7, 27),
+    ("hidden", 8, 28),
+];
+
+/// Styles of the built-in theme besides the basic colors, and their
+/// `_b` (bold) and `_bg` (background) variants.
+const BUILTIN_STYLES: [(&str, &str); 21] = [
+    ("reset", "reset"),
+    ("default", "default"),
+    ("default_bg", "bg:default"),
+    ("bold", "bold"),
+    ("dim", "dim"),
+    ("italic", "italic"),
+    ("underlined", "underlined"),
+    ("blink", "blink"),
+    ("reverse", "reverse"),
+    ("hidden", "hidden"),
+    ("reset_bold", "no-bold"),
+    ("reset_dim", "no-dim"),
+    ("reset_italic", "no-italic"),
+    ("reset_underlined", "no-underlined"),
+    ("reset_blink", "no-blink"),
+    ("reset_reverse", "no-reverse"),
+    ("reset_hidden", "no-hidden"),
+    ("status_ok", "bold green"),
+    ("status_error", "bold red"),
+    ("branch", "bold blue"),
+    ("venv", "bold blue"),
+];
+
+const MAX_SEGMENT_DEPTH: usize = 8;
+
+/// How many colors the terminal supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    NoColor,
+    Basic,
+    Ansi256,
+    TrueColor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Color {
+    Default,
+    Basic(u8),
+    Fixed(u8),
+    Rgb(u8, u8, u8),
+}
+
+fn detect_level() -> Level {
+    let term = env::var("TERM").unwrap_or_default();
+    let colorterm = env::var("COLORTERM").unwrap_or_default();
+    if term == "dumb" {
+        Level::NoColor
+    } else if colorterm == "truecolor" || colorterm == "24bit" || term.ends_with("-direct") {
+        Level::TrueColor
+    } else if term.contains("256color") {
+        Level::Ansi256
+    } else {
+        Level::Basic
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn fixed_to_rgb(n: u8) -> (u8, u8, u8) {
+    match n {
+        0..=15 => BASIC_RGB[n as usize],
+        16..=231 => {
+            let n = n - 16;
+            let level = |x: u8| CUBE_LEVELS[x as usize];
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let x = 8 + (n - 232) * 10;
+            (x, x, x)
+        }
+    }
+}
+
+/// The nearest color in the 6x6x6 cube or the gray ramp of 256 colors.
+fn rgb_to_fixed(rgb: (u8, u8, u8)) -> u8 {
+    let nearest_level = |x: u8| {
+        (0..6)
+            .min_by_key(|i| (CUBE_LEVELS[*i] as i32 - x as i32).abs())
+            .unwrap_or(0) as u8
+    };
+    let cube = 16 + 36 * nearest_level(rgb.0) + 6 * nearest_level(rgb.1) + nearest_level(rgb.2);
+    let avg = (rgb.0 as u32 + rgb.1 as u32 + rgb.2 as u32) / 3;
+    let gray = 232 + (avg.saturating_sub(3) / 10).min(23) as u8;
+    if distance(fixed_to_rgb(gray), rgb) < distance(fixed_to_rgb(cube), rgb) {
+        gray
+    } else {
+        cube
+    }
+}
+
+fn rgb_to_basic(rgb: (u8, u8, u8)) -> u8 {
+    (0..16)
+        .min_by_key(|i| distance(BASIC_RGB[*i], rgb))
+        .unwrap_or(0) as u8
+}
+
+impl Color {
+    fn parse(text: &str) -> Option<Color> {
+        if text == "default" {
+            return Some(Color::Default);
+        }
+        if let Some((_, n)) = BASIC_COLORS.iter().find(|x| x.0 == text) {
+            return Some(Color::Basic(*n));
+        }
+        if let Some(hex) = text.strip_prefix('#') {
+            if hex.len() != 6 {
+                return None;
+            }
+            let n = u32::from_str_radix(hex, 16).ok()?;
+            return Some(Color::Rgb((n >> 16) as u8, (n >> 8) as u8, n as u8));
+        }
+        text.parse::<u8>().ok().map(Color::Fixed)
+    }
+
+    /// Convert the color to one the terminal supports.
+    fn degrade(self, level: Level) -> Option<Color> {
+        match (self, level) {
+            (_, Level::NoColor) => None,
+            (Color::Fixed(n), _) if n < 16 => Some(Color::Basic(n)),
+            (Color::Rgb(r, g, b), Level::Ansi256) => Some(Color::Fixed(rgb_to_fixed((r, g, b)))),
+            (Color::Rgb(r, g, b), Level::Basic) => Some(Color::Basic(rgb_to_basic((r, g, b)))),
+            (Color::Fixed(n), Level::Basic) => Some(Color::Basic(rgb_to_basic(fixed_to_rgb(n)))),
+            _ => Some(self),
+        }
+    }
+
+    fn to_params(self, bg: bool) -> String {
+        let base = if bg { 40 } else { 30 };
+        match self {
+            Color::Default => (base + 9).to_string(),
+            Color::Basic(n) if n < 8 => (base + n).to_string(),
+            Color::Basic(n) => (base + 60 + n - 8).to_string(),
+            Color::Fixed(n) => format!("{};5;{}", base + 8, n),
+            Color::Rgb(r, g, b) => format!("{};2;{};{};{}", base + 8, r, g, b),
+        }
+    }
+}
+
+/// Convert a style spec like `bold #ff8700 bg:236` to SGR parameters.
+fn parse_spec(spec: &str, level: Level) -> Result<String, String> {
+    let mut params = Vec::new();
+    for word in spec.split_whitespace() {
+        let word = word.to_ascii_lowercase();
+        if word == "reset" {
+            params.push("0".to_string());
+            continue;
+        }
+        let attr = word.strip_prefix("no-").unwrap_or(&word);
+        if let Some((_, on, off)) = ATTRIBUTES.iter().find(|x| x.0 == attr) {
+            let code = if attr == word { on } else { off };
+            params.push(code.to_string());
+            continue;
+        }
+        let (bg, color) = match word.strip_prefix("bg:") {
+            Some(x) => (true, x),
+            None => (false, word.strip_prefix("fg:").unwrap_or(&word)),
+        };
+        match Color::parse(color) {
+            Some(x) => {
+                if let Some(x) = x.degrade(level) {
+                    params.push(x.to_params(bg));
+                }
+            }
+            None => return Err(format!("bad style {:?}", word)),
+        }
+    }
+    Ok(params.join(";"))
+}
+
+fn builtin_specs() -> Vec<(String, String)> {
+    let mut specs = Vec::new();
+    for (name, _) in BASIC_COLORS.iter() {
+        specs.push((name.to_string(), name.to_string()));
+        specs.push((format!("{}_b", name), format!("bold {}", name)));
+        specs.push((format!("{}_bg", name), format!("bg:{}", name)));
+    }
+    for (name, spec) in BUILTIN_STYLES.iter() {
+        specs.push((name.to_string(), spec.to_string()));
+    }
+    specs
+}
+
+fn yaml_to_string(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(x) => Some(x.clone()),
+        Yaml::Integer(x) => Some(x.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_section(doc: &Yaml, section: &str) -> Result<Vec<(String, String)>, String> {
+    let h = match &doc[section] {
+        Yaml::Hash(h) => h,
+        Yaml::BadValue | Yaml::Null => return Ok(Vec::new()),
+        _ => return Err(format!("{} is not a map", section)),
+    };
+    let mut items = Vec::new();
+    for (k, v) in h.iter() {
+        match (yaml_to_string(k), yaml_to_string(v)) {
+            (Some(k), Some(v)) => items.push((k.to_ascii_lowercase(), v)),
+            _ => return Err(format!("bad item in {}: {:?}", section, k)),
+        }
+    }
+    Ok(items)
+}
+
+#[derive(Debug, Default)]
+pub struct Theme {
+    // the SGR sequences of styles, wrapped for the prompt
+    styles: HashMap<String, String>,
+    segments: HashMap<String, String>,
+}
+
+impl Theme {
+    /// Load the theme file upon the built-in theme. Errors are returned
+    /// along with the theme without the bad items.
+    fn load(text: &str, level: Level) -> (Theme, Vec<String>) {
+        let mut errors = Vec::new();
+        let mut specs = builtin_specs();
+        let mut theme = Theme::default();
+        match YamlLoader::load_from_str(text) {
+            Ok(docs) => {
+                for doc in docs.iter() {
+                    match parse_section(doc, "colors") {
+                        Ok(x) => specs.extend(x),
+                        Err(e) => errors.push(e),
+                    }
+                    match parse_section(doc, "segments") {
+                        Ok(x) => theme.segments.extend(x),
+                        Err(e) => errors.push(e),
+                    }
+                }
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+
+        for (name, spec) in specs {
+            match parse_spec(&spec, level) {
+                Ok(x) if x.is_empty() => {
+                    theme.styles.insert(name, String::new());
+                }
+                Ok(x) => {
+                    theme.styles.insert(name, colored::sgr(&x));
+                }
+                Err(e) => errors.push(format!("{}: {}", name, e)),
+            }
+        }
+        (theme, errors)
+    }
+
+    pub fn style(&self, name: &str) -> Option<&str> {
+        self.styles.get(name).map(|x| x.as_str())
+    }
+
+    pub fn segment(&self, name: &str) -> Option<&str> {
+        self.segments.get(name).map(|x| x.as_str())
+    }
+}
+
+lazy_static! {
+    static ref THEME: Mutex<(String, Arc<Theme>)> = Mutex::new((String::new(), Arc::default()));
+}
+
+fn get_theme_file() -> String {
+    match env::var("CICADA_THEME") {
+        Ok(x) if !x.is_empty() => x,
+        _ => format!("{}/theme.yaml", tools::get_config_dir()),
+    }
+}
+
+/// The current theme, which is loaded again when the theme file or the
+/// color support changes.
+pub fn get() -> Arc<Theme> {
+    let file = get_theme_file();
+    let level = detect_level();
+    let key = format!("{}:{}:{:?}", file, segments::mtime(&file), level);
+    let mut theme = match THEME.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+    if theme.0 != key {
+        let text = if Path::new(&file).exists() {
+            fs::read_to_string(&file).unwrap_or_else(|e| {
+                println_stderr!("cicada: {}: {}", file, e);
+                String::new()
+            })
+        } else {
+            String::new()
+        };
+        let (x, errors) = Theme::load(&text, level);
+        for e in errors {
+            println_stderr!("cicada: {}: {}", file, e);
+        }
+        *theme = (key, Arc::new(x));
+    }
+    theme.1.clone()
+}
+
+/// Push the style of the theme, returns false if it's not defined.
+pub fn apply_style(prompt: &mut String, name: &str) -> bool {
+    match get().style(name) {
+        Some(x) => {
+            prompt.push_str(x);
+            true
+        }
+        None => false,
+    }
+}
+
+/// The segment of the theme, when segments are not nested too deep.
+pub fn get_segment(name: &str, depth: usize) -> Option<String> {
+    if depth >= MAX_SEGMENT_DEPTH {
+        return None;
+    }
+    get().segment(name).map(|x| x.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_spec() {
+        let level = Level::TrueColor;
+        assert_eq!(parse_spec("bold red", level).unwrap(), "1;31");
+        assert_eq!(parse_spec("blue_l bg:gray_d", level).unwrap(), "94;100");
+        assert_eq!(parse_spec("no-bold reset", level).unwrap(), "22;0");
+        assert_eq!(
+            parse_spec("208 bg:236", level).unwrap(),
+            "38;5;208;48;5;236"
+        );
+        assert_eq!(
+            parse_spec("#ff8700 bg:#303030", level).unwrap(),
+            "38;2;255;135;0;48;2;48;48;48"
+        );
+        assert_eq!(parse_spec("9 bg:default", level).unwrap(), "91;49");
+        assert!(parse_spec("bold purple", level).is_err());
+        assert!(parse_spec("#fff", level).is_err());
+
+        assert_eq!(parse_spec("#ff8700", Level::Ansi256).unwrap(), "38;5;208");
+        assert_eq!(
+            parse_spec("bg:#303030", Level::Ansi256).unwrap(),
+            "48;5;236"
+        );
+        assert_eq!(parse_spec("#ff8700", Level::Basic).unwrap(), "33");
+        assert_eq!(parse_spec("bg:236", Level::Basic).unwrap(), "40");
+        assert_eq!(parse_spec("bold #ff8700", Level::NoColor).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_load() {
+        let text = "colors:\n  accent: '#ff8700'\n  blue_b: bold 33\n  warn: 208\n  \
+                    bad: bold purple\nsegments:\n  Host: '${ACCENT}$HOSTNAME${RESET}'\n";
+        let (theme, errors) = Theme::load(text, Level::TrueColor);
+        assert_eq!(theme.style("accent"), Some("\x01\x1b[38;2;255;135;0m\x02"));
+        assert_eq!(theme.style("blue_b"), Some("\x01\x1b[1;38;5;33m\x02"));
+        assert_eq!(theme.style("warn"), Some("\x01\x1b[38;5;208m\x02"));
+        assert_eq!(theme.style("red_bg"), Some("\x01\x1b[41m\x02"));
+        assert_eq!(theme.style("bad"), None);
+        assert_eq!(theme.segment("host"), Some("${ACCENT}$HOSTNAME${RESET}"));
+        assert_eq!(errors.len(), 1);
+
+        let (theme, errors) = Theme::load("", Level::NoColor);
+        assert_eq!(theme.style("red_b"), Some("\x01\x1b[1m\x02"));
+        assert_eq!(theme.style("red"), Some(""));
+        assert!(errors.is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 use std::fs::File;
 use std::io::{ErrorKind, Read, Write};
+use std::mem;
 use std::path::Path;
 
 use pest::iterators::Pair;
@@ -82,37 +83,8 @@ pub fn run_script(sh: &mut shell::Shell, args: &Vec<String>) -> i32 {
         text = re.replace_all(&text, "").to_string();
     }
 
-    let re_func_head =
-        Regex::new(r"^function ([a-zA-Z_-][a-zA-Z0-9_-]*) *(?:\(\))? *\{$").unwrap();
-    let re_func_tail = Regex::new(r"^\}$").unwrap();
-    let mut text_new = String::new();
-    let mut enter_func = false;
-    let mut func_name = String::new();
-    let mut func_body = String::new();
-    for line in text.clone().lines() {
-        if re_func_head.is_match(line.trim()) {
-            enter_func = true;
-            let cap = re_func_head.captures(line.trim()).unwrap();
-            func_name = cap[1].to_string();
-            func_body = String::new();
-            continue;
-        }
-        if re_func_tail.is_match(line.trim()) {
-            sh.set_func(&func_name, &func_body);
-            enter_func = false;
-            continue;
-        }
-        if enter_func {
-            func_body.push_str(line);
-            func_body.push('\n');
-        } else {
-            text_new.push_str(line);
-            text_new.push('\n');
-        }
-    }
-
     let mut status = 0;
-    let cr_list = run_lines(sh, &text_new, args, false);
+    let cr_list = run_lines(sh, &text, args, false);
     if let Some(last) = cr_list.last() {
         status = last.status;
     }
@@ -126,6 +98,10 @@ pub fn run_script(sh: &mut shell::Shell, args: &Vec<String>) -> i32 {
     status
 }
 
+/// Run the lines of scripts or functions, where `args` are like the
+/// arguments of the shell, i.e. `args[1]` is the name of the script or
+/// function, followed by the positional parameters. Only `args[0]` is
+/// given for lines without positional parameters.
 pub fn run_lines(
     sh: &mut shell::Shell,
     lines: &str,
@@ -133,7 +109,8 @@ pub fn run_lines(
     capture: bool,
 ) -> Vec<CommandResult> {
     let mut cr_list = Vec::new();
-    match parsers::locust::parse_lines(lines) {
+    let lines = parsers::locust::unify_heredoc_delims(lines);
+    match parsers::locust::parse_lines(&lines) {
         Ok(pairs_exp) => {
             for pair in pairs_exp {
                 let (mut _cr_list, _cont, _brk) = run_exp(sh, pair, args, false, capture);
@@ -141,36 +118,112 @@ pub fn run_lines(
             }
         }
         Err(e) => {
-            println_stderr!("syntax error: {:?}", e);
-            return cr_list;
+            // nothing runs if the script has a syntax error
+            println_stderr!("cicada: syntax error: {}", e);
+            sh.previous_status = 2;
+            cr_list.push(CommandResult::from_status(0, 2));
         }
     }
     cr_list
 }
 
-fn expand_args(line: &str, args: &[String]) -> String {
-    let linfo = parsers::parser_line::parse_line(line);
-    let mut tokens = linfo.tokens;
-    expand_args_in_tokens(&mut tokens, args);
-    parsers::parser_line::tokens_to_line(&tokens)
+/// Expand positional parameters in the line. Errors like `${1:?usage}`
+/// are returned, and the line must not run then.
+fn expand_args(line: &str, args: &[String]) -> Result<String, String> {
+    let (head, rest) = line.split_once('\n').unwrap_or((line, ""));
+    let ops = parsers::parser_line::find_heredoc_ops(head);
+    if ops.is_empty() {
+        let linfo = parsers::parser_line::parse_line(line);
+        let mut tokens = linfo.tokens;
+        expand_args_in_tokens(&mut tokens, args)?;
+        return Ok(parsers::parser_line::tokens_to_line(&tokens));
+    }
+
+    // here-documents must not go through tokenizing, only the pieces
+    // around their operators are.
+    let mut pieces = Vec::new();
+    let mut last = 0;
+    for op in &ops {
+        pieces.push(expand_args(&head[last..op.start], args)?);
+        pieces.push(head[op.start..op.end].to_string());
+        last = op.end;
+    }
+    pieces.push(expand_args(&head[last..], args)?);
+    pieces.retain(|x| !x.is_empty());
+
+    let mut result = pieces.join(" ");
+    for x in rest.split_inclusive('\n') {
+        result.push('\n');
+        let x = x.strip_suffix('\n').unwrap_or(x);
+        if ops.iter().any(|op| !op.quoted) {
+            result.push_str(&expand_args_for_single_token(x, args)?);
+        } else {
+            result.push_str(x);
+        }
+    }
+    Ok(result)
 }
 
-fn expand_line_to_toknes(line: &str, args: &[String], sh: &mut shell::Shell) -> types::Tokens {
+fn expand_line_to_toknes(
+    line: &str,
+    args: &[String],
+    sh: &mut shell::Shell,
+) -> Result<types::Tokens, String> {
     let linfo = parsers::parser_line::parse_line(line);
     let mut tokens = linfo.tokens;
-    expand_args_in_tokens(&mut tokens, args);
-    shell::do_expansion(sh, &mut tokens);
-    tokens
+    expand_args_in_tokens(&mut tokens, args)?;
+    shell::do_expansion(sh, &mut tokens)?;
+    Ok(tokens)
 }
 
 fn is_args_in_token(token: &str) -> bool {
-    libs::re::re_contains(token, r"\$\{?[0-9@]+\}?")
+    libs::re::re_contains(token, r"\$(\{([0-9]+|[@*#])\}|\{#?[0-9]+|[0-9]+|[@*#])")
 }
 
-fn expand_args_for_single_token(token: &str, args: &[String]) -> String {
-    let re = Regex::new(r"^(.*?)\$\{?([0-9]+|@)\}?(.*)$").unwrap();
+/// Expand positional parameters in braces with operators, e.g.
+/// `${1:-default}`, `${#1}`, `${1%.txt}`.
+fn expand_braced_args(token: &str, args: &[String]) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = token.to_string();
+    while let Some((start, end, inner)) = shell::find_braced_param(&rest) {
+        result.push_str(&rest[..start]);
+        match shell::split_braced_param(&inner) {
+            Some((is_length, name, op)) if name.chars().all(|c| c.is_ascii_digit()) => {
+                let value = name.parse::<usize>().ok().and_then(|i| args.get(i));
+                if is_length {
+                    let len = value.map_or(0, |x| x.chars().count());
+                    result.push_str(&len.to_string());
+                } else {
+                    let value = value.map(|x| x.as_str());
+                    let mut expand = |x: &str| expand_args_for_single_token(x, args);
+                    match shell::apply_param_operator(&name, value, &op, &mut expand)? {
+                        (_, Some(_)) => {
+                            return Err(format!("${}: cannot assign in this way", name));
+                        }
+                        (text, None) => result.push_str(&text),
+                    }
+                }
+            }
+            // others are not positional parameters
+            _ => result.push_str(&rest[start..end]),
+        }
+        rest = rest[end..].to_string();
+    }
+    result.push_str(&rest);
+    Ok(result)
+}
+
+/// Expand positional parameters `$N`, `${N}`, `$@`, `$*` and `$#` in
+/// the token, where `args[0]` is the name of script or function. Without
+/// `args`, e.g. for lines typed in prompt, they are kept as they are.
+fn expand_args_for_single_token(token: &str, args: &[String]) -> Result<String, String> {
+    if args.is_empty() {
+        return Ok(token.to_string());
+    }
+    let token = &expand_braced_args(token, args)?;
+    let re = Regex::new(r"^(.*?)\$(\{[0-9]+\}|\{[@*#]\}|[0-9]+|[@*#])(.*)$").unwrap();
     if !re.is_match(token) {
-        return token.to_string();
+        return Ok(token.to_string());
     }
 
     let mut result = String::new();
@@ -188,9 +241,12 @@ fn expand_args_for_single_token(token: &str, args: &[String]) -> String {
         for cap in re.captures_iter(&_token) {
             _head = cap[1].to_string();
             _tail = cap[3].to_string();
-            let _key = cap[2].to_string();
-            if _key == "@" {
+            let _key = cap[2].trim_matches(|c| c == '{' || c == '}').to_string();
+            if _key == "@" || _key == "*" {
                 result.push_str(format!("{}{}", _head, args[1..].join(" ")).as_str());
+            } else if _key == "#" {
+                let count = args.len().saturating_sub(1);
+                result.push_str(format!("{}{}", _head, count).as_str());
             } else if let Ok(arg_idx) = _key.parse::<usize>() {
                 if arg_idx < args.len() {
                     result.push_str(format!("{}{}", _head, args[arg_idx]).as_str());
@@ -207,10 +263,10 @@ fn expand_args_for_single_token(token: &str, args: &[String]) -> String {
         }
         _token = _tail.clone();
     }
-    result
+    Ok(result)
 }
 
-fn expand_args_in_tokens(tokens: &mut types::Tokens, args: &[String]) {
+fn expand_args_in_tokens(tokens: &mut types::Tokens, args: &[String]) -> Result<(), String> {
     let mut idx: usize = 0;
     let mut buff = Vec::new();
 
@@ -220,7 +276,7 @@ fn expand_args_in_tokens(tokens: &mut types::Tokens, args: &[String]) {
             continue;
         }
 
-        let _token = expand_args_for_single_token(token, args);
+        let _token = expand_args_for_single_token(token, args)?;
         buff.push((idx, _token));
         idx += 1;
     }
@@ -228,6 +284,7 @@ fn expand_args_in_tokens(tokens: &mut types::Tokens, args: &[String]) {
     for (i, text) in buff.iter().rev() {
         tokens[*i].1 = text.to_string();
     }
+    Ok(())
 }
 
 fn run_exp_test_br(
@@ -249,8 +306,16 @@ fn run_exp_test_br(
             let pairs_test: Vec<Pair<parsers::locust::Rule>> = pair.into_inner().collect();
             let pair_test = &pairs_test[0];
             let line = pair_test.as_str().trim();
-            let line_new = expand_args(line, &args[1..]);
+            let line_new = match expand_args(line, &args[1..]) {
+                Ok(x) => x,
+                Err(e) => {
+                    cr_list.push(execute::abort_on_expansion_error(sh, &e));
+                    continue;
+                }
+            };
+            let in_condition = mem::replace(&mut sh.in_condition, true);
             let mut _cr_list = execute::run_command_line(sh, &line_new, true, capture);
+            sh.in_condition = in_condition;
             if let Some(last) = _cr_list.last() {
                 if last.status == 0 {
                     test_pass = true;
@@ -315,7 +380,13 @@ fn get_for_result_from_init(
         let rule = pair.as_rule();
         if rule == parsers::locust::Rule::TEST {
             let line = pair.as_str().trim();
-            let tokens = expand_line_to_toknes(line, &args[1..], sh);
+            let tokens = match expand_line_to_toknes(line, &args[1..], sh) {
+                Ok(x) => x,
+                Err(e) => {
+                    execute::abort_on_expansion_error(sh, &e);
+                    continue;
+                }
+            };
             for (sep, token) in tokens {
                 if sep.is_empty() {
                     for x in token.split_whitespace() {
@@ -385,7 +456,7 @@ fn run_exp_for(
                 sh.set_env(&var_name, value);
                 let (mut _cr_list, _cont, _brk) = run_exp(sh, pair.clone(), args, true, capture);
                 cr_list.append(&mut _cr_list);
-                if _brk {
+                if _brk || sh.is_func_returning() {
                     break;
                 }
             }
@@ -405,13 +476,135 @@ fn run_exp_while(
         let (mut _cr_list, passed, _cont, _brk) =
             run_exp_test_br(sh, pair_while.clone(), args, true, capture);
         cr_list.append(&mut _cr_list);
-        if !passed || _brk {
+        if !passed || _brk || sh.is_func_returning() {
             break;
         }
     }
     cr_list
 }
 
+/// Expand the word and patterns of case statements. Unlike commands,
+/// there is no globbing or word splitting on them.
+fn expand_case_word(
+    sh: &mut shell::Shell,
+    text: &str,
+    args: &[String],
+) -> Result<types::Tokens, String> {
+    let linfo = parsers::parser_line::parse_line(text);
+    let mut tokens = linfo.tokens;
+    expand_args_in_tokens(&mut tokens, args)?;
+    shell::expand_env(sh, &mut tokens);
+    shell::do_command_substitution(sh, &mut tokens);
+    Ok(tokens)
+}
+
+fn is_case_pattern_matched(pattern: &types::Tokens, word: &str) -> bool {
+    // quoted parts of a pattern match literally
+    let mut ptn = String::new();
+    for (sep, text) in pattern {
+        if sep.is_empty() {
+            ptn.push_str(text);
+        } else {
+            ptn.push_str(&glob::Pattern::escape(text));
+        }
+    }
+    match glob::Pattern::new(&ptn) {
+        Ok(x) => x.matches(word),
+        Err(_) => ptn == word,
+    }
+}
+
+fn run_exp_case(
+    sh: &mut shell::Shell,
+    pair_case: Pair<parsers::locust::Rule>,
+    args: &Vec<String>,
+    in_loop: bool,
+    capture: bool,
+) -> (Vec<CommandResult>, bool, bool) {
+    let mut word = String::new();
+    for pair in pair_case.into_inner() {
+        let rule = pair.as_rule();
+        if rule == parsers::locust::Rule::CASE_HEAD {
+            for pair_head in pair.into_inner() {
+                if pair_head.as_rule() == parsers::locust::Rule::CASE_WORD {
+                    let tokens = match expand_case_word(sh, pair_head.as_str().trim(), &args[1..])
+                    {
+                        Ok(x) => x,
+                        Err(e) => {
+                            let cr = execute::abort_on_expansion_error(sh, &e);
+                            return (vec![cr], false, false);
+                        }
+                    };
+                    word = tokens.iter().map(|x| x.1.as_str()).collect();
+                }
+            }
+            continue;
+        }
+        if rule != parsers::locust::Rule::CASE_BR {
+            continue;
+        }
+
+        // run the body of the first branch that matches
+        let mut matched = false;
+        for pair_br in pair.into_inner() {
+            let rule_br = pair_br.as_rule();
+            if rule_br == parsers::locust::Rule::CASE_BR_HEAD {
+                for pair_ptn in pair_br.into_inner() {
+                    if matched {
+                        continue;
+                    }
+                    let ptn = pair_ptn.as_str().trim();
+                    match expand_case_word(sh, ptn, &args[1..]) {
+                        Ok(x) => matched = is_case_pattern_matched(&x, &word),
+                        Err(e) => {
+                            let cr = execute::abort_on_expansion_error(sh, &e);
+                            return (vec![cr], false, false);
+                        }
+                    }
+                }
+            } else if rule_br == parsers::locust::Rule::EXP_BODY && matched {
+                return run_exp(sh, pair_br, args, in_loop, capture);
+            }
+        }
+        if matched {
+            break;
+        }
+    }
+    (Vec::new(), false, false)
+}
+
+/// Save the function defined in `EXP_FUNC` into the shell, its body is
+/// kept as plain text, and would be parsed again when called.
+fn define_func(sh: &mut shell::Shell, pair: Pair<parsers::locust::Rule>) {
+    let input = pair.get_input();
+    let mut name = String::new();
+    let mut body = String::new();
+    let mut body_start = 0;
+    for p in pair.into_inner() {
+        match p.as_rule() {
+            parsers::locust::Rule::FUNC_HEAD => {
+                for x in p.clone().into_inner() {
+                    if x.as_rule() == parsers::locust::Rule::FUNC_NAME {
+                        name = x.as_str().to_string();
+                    }
+                }
+                body_start = p.as_span().end();
+            }
+            parsers::locust::Rule::EXP_BODY => {
+                // take it from the input to keep the indents of first line
+                let text = &input[body_start..p.as_span().end()];
+                body = text.trim_start_matches([' ', '\t']).to_string();
+                body = body.strip_prefix('\n').unwrap_or(&body).to_string();
+            }
+            parsers::locust::Rule::FUNC_INLINE => {
+                body = format!("{}\n", p.as_str().trim());
+            }
+            _ => {}
+        }
+    }
+    sh.set_func(&name, &body);
+}
+
 fn run_exp(
     sh: &mut shell::Shell,
     pair_in: Pair<parsers::locust::Rule>,
@@ -446,7 +639,13 @@ fn run_exp(
                 }
             }
 
-            let line_new = expand_args(line, &args[1..]);
+            let line_new = match expand_args(line, &args[1..]) {
+                Ok(x) => x,
+                Err(e) => {
+                    cr_list.push(execute::abort_on_expansion_error(sh, &e));
+                    continue;
+                }
+            };
             let mut _cr_list = execute::run_command_line(sh, &line_new, true, capture);
             cr_list.append(&mut _cr_list);
             if let Some(last) = cr_list.last() {
@@ -470,6 +669,24 @@ fn run_exp(
         } else if rule == parsers::locust::Rule::EXP_WHILE {
             let mut _cr_list = run_exp_while(sh, pair, args, capture);
             cr_list.append(&mut _cr_list);
+        } else if rule == parsers::locust::Rule::EXP_FUNC {
+            define_func(sh, pair);
+            sh.previous_status = 0;
+            cr_list.push(CommandResult::new());
+        } else if rule == parsers::locust::Rule::EXP_CASE {
+            let (mut _cr_list, _cont, _brk) = run_exp_case(sh, pair, args, in_loop, capture);
+            cr_list.append(&mut _cr_list);
+            if _cont {
+                return (cr_list, true, false);
+            }
+            if _brk {
+                return (cr_list, false, true);
+            }
+        }
+
+        // the `return` builtin was called in current function
+        if sh.is_func_returning() {
+            break;
         }
     }
     (cr_list, false, false)
@@ -490,32 +707,32 @@ mod tests {
         ];
 
         let line = "echo $@";
-        let line_new = expand_args(line, &args);
+        let line_new = expand_args(line, &args).unwrap();
         assert_eq!(line_new, "echo foo bar baz");
 
         let line = "echo \"a\\\"b\"";
-        let line_new = expand_args(line, &args);
+        let line_new = expand_args(line, &args).unwrap();
         assert_eq!(line_new, "echo \"a\\\"b\"");
 
         let line = "echo \"$@\"";
-        let line_new = expand_args(line, &args);
+        let line_new = expand_args(line, &args).unwrap();
         assert_eq!(line_new, "echo \"foo bar baz\"");
 
         let line = "echo $1";
-        let line_new = expand_args(line, &args);
+        let line_new = expand_args(line, &args).unwrap();
         assert_eq!(line_new, "echo foo");
 
         let line = "echo $2 $1";
-        let line_new = expand_args(line, &args);
+        let line_new = expand_args(line, &args).unwrap();
         assert_eq!(line_new, "echo bar foo");
 
         let line = "echo $3 $1 $2";
-        let line_new = expand_args(line, &args);
+        let line_new = expand_args(line, &args).unwrap();
         assert_eq!(line_new, "echo baz foo bar");
 
         let line = "echo $3 $1 $2 $4 $5";
         let ptn_expected = r"^echo baz foo bar *$";
-        let line_new = expand_args(line, &args);
+        let line_new = expand_args(line, &args).unwrap();
         if !libs::re::re_contains(&line_new, ptn_expected) {
             println!("expect RE: {:?}", ptn_expected);
             println!("real: {:?}", line_new);
@@ -523,12 +740,31 @@ mod tests {
         }
 
         let line = "echo \"==$3--$$==$1--$2==$4--$5==$$--$2==\"";
-        let line_new = expand_args(line, &args);
+        let line_new = expand_args(line, &args).unwrap();
         let ptn_expected = r"^echo .==baz--\$\$==foo--bar==--==\$\$--bar==.$";
         if !libs::re::re_contains(&line_new, ptn_expected) {
             println!("expect RE: {:?}", ptn_expected);
             println!("real: {:?}", line_new);
             assert!(false);
         }
+
+        let line = "echo $# $*";
+        let line_new = expand_args(line, &args).unwrap();
+        assert_eq!(line_new, "echo 3 foo bar baz");
+
+        let line = "echo ${#} ${*} ${2}";
+        let line_new = expand_args(line, &args).unwrap();
+        assert_eq!(line_new, "echo 3 foo bar baz bar");
+
+        let line = "echo ${1:-x} ${5:-y} ${#2} ${3%z} ${2/a/A}";
+        let line_new = expand_args(line, &args).unwrap();
+        assert_eq!(line_new, "echo foo y 3 ba bAr");
+
+        let line = "echo ${#FOO}";
+        let line_new = expand_args(line, &args).unwrap();
+        assert_eq!(line_new, "echo ${#FOO}");
+
+        assert!(expand_args("echo ${5:?usage}", &args).is_err());
+        assert!(expand_args("echo ${1:?usage}", &args).is_ok());
     }
 }
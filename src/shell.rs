@@ -3,11 +3,13 @@ use std::collections::{HashMap, HashSet};
 use std::env;
 use std::io::Write;
 use std::mem;
+use std::os::fd::RawFd;
 use std::path::{Path, PathBuf};
 
 use regex::Regex;
 use uuid::Uuid;
 
+use crate::calculator;
 use crate::core;
 use crate::libs;
 use crate::parsers;
@@ -19,13 +21,35 @@ pub struct Shell {
     pub jobs: HashMap<i32, types::Job>,
     pub aliases: HashMap<String, String>,
     pub envs: HashMap<String, String>,
+    pub arrays: HashMap<String, types::Array>,
     pub funcs: HashMap<String, String>,
+    // rules defined by the `complete` builtin
+    pub completion_rules: Vec<types::CompletionRule>,
+    pub func_frames: Vec<types::FuncFrame>,
+    // handlers of the `trap` builtin, e.g. "EXIT", "INT", "ERR"
+    pub traps: HashMap<String, String>,
+    pub in_trap: bool,
+    // no ERR traps for conditions of `if` and `while`
+    pub in_condition: bool,
+    // pipes and PIDs of process substitutions, e.g. `<(cmd)`
+    pub proc_subs: Vec<(RawFd, i32)>,
+    // in the subshell of a process substitution, whose commands stay in
+    // its process group
+    pub in_proc_sub: bool,
+    // output of current command line to be saved into history
+    pub captured_output: String,
     pub cmd: String,
     pub current_dir: String,
     pub previous_dir: String,
     pub previous_cmd: String,
     pub previous_status: i32,
+    // seconds the last command line took, in interactive mode
+    pub previous_duration: f64,
+    // the PID of last background command, i.e. `$!`
+    pub last_bg_pid: Option<i32>,
     pub is_login: bool,
+    // not running a script or `-c` commands
+    pub is_interactive: bool,
     pub exit_on_error: bool,
     pub has_terminal: bool,
     pub session_id: String,
@@ -44,13 +68,25 @@ impl Shell {
             jobs: HashMap::new(),
             aliases: HashMap::new(),
             envs: HashMap::new(),
+            arrays: HashMap::new(),
             funcs: HashMap::new(),
+            completion_rules: Vec::new(),
+            func_frames: Vec::new(),
+            traps: HashMap::new(),
+            in_trap: false,
+            in_condition: false,
+            proc_subs: Vec::new(),
+            in_proc_sub: false,
+            captured_output: String::new(),
             cmd: String::new(),
             current_dir: current_dir.clone(),
             previous_dir: String::new(),
             previous_cmd: String::new(),
             previous_status: 0,
+            previous_duration: 0.0,
+            last_bg_pid: None,
             is_login: false,
+            is_interactive: false,
             exit_on_error: false,
             has_terminal,
             session_id: session_id.to_string(),
@@ -241,8 +277,19 @@ impl Shell {
     /// Update existing *ENV Variable* if such name exists in ENVs,
     /// otherwise, we define a local *Shell Variable*, which would not
     /// be exported into child processes.
+    /// `local` variables of running functions shadow both of them.
     pub fn set_env(&mut self, name: &str, value: &str) {
-        if env::var(name).is_ok() {
+        for frame in self.func_frames.iter_mut().rev() {
+            if let Some(x) = frame.locals.get_mut(name) {
+                *x = value.to_string();
+                return;
+            }
+        }
+
+        if let Some(x) = self.arrays.get_mut(name) {
+            // same as `name[0]=value`
+            x.set("0", value);
+        } else if env::var(name).is_ok() {
             env::set_var(name, value);
         } else {
             self.envs.insert(name.to_string(), value.to_string());
@@ -251,10 +298,55 @@ impl Shell {
 
     /// get *Shell Variable*, or *ENV Variable*.
     pub fn get_env(&self, name: &str) -> Option<String> {
-        match self.envs.get(name) {
-            Some(x) => Some(x.to_string()),
-            None => env::var(name).ok(),
+        if let Some(x) = self.get_local(name) {
+            return Some(x);
+        }
+        if let Some(x) = self.envs.get(name) {
+            return Some(x.to_string());
+        }
+        if let Some(x) = self.arrays.get(name) {
+            return x.get("0").cloned();
         }
+        env::var(name).ok()
+    }
+
+    pub fn get_array(&self, name: &str) -> Option<&types::Array> {
+        self.arrays.get(name)
+    }
+
+    /// Define an array variable, which replaces the *Shell Variable*
+    /// with the same name.
+    pub fn set_array(&mut self, name: &str, array: types::Array) {
+        self.envs.remove(name);
+        self.arrays.insert(name.to_string(), array);
+    }
+
+    /// get the `local` variable from the innermost running function
+    /// that defines it.
+    pub fn get_local(&self, name: &str) -> Option<String> {
+        self.func_frames
+            .iter()
+            .rev()
+            .find_map(|x| x.locals.get(name).cloned())
+    }
+
+    /// Define a `local` variable in current running function,
+    /// returns false if not in a function.
+    pub fn set_local(&mut self, name: &str, value: &str) -> bool {
+        match self.func_frames.last_mut() {
+            Some(frame) => {
+                frame.locals.insert(name.to_string(), value.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether the `return` builtin was called in current running function.
+    pub fn is_func_returning(&self) -> bool {
+        self.func_frames
+            .last()
+            .is_some_and(|x| x.return_status.is_some())
     }
 
     /// Remove environment variable, function from the environment of
@@ -266,8 +358,15 @@ impl Shell {
             return false;
         }
 
+        for frame in self.func_frames.iter_mut().rev() {
+            if frame.locals.remove(name).is_some() {
+                return true;
+            }
+        }
+
         env::remove_var(name);
         self.envs.remove(name);
+        self.arrays.remove(name);
         self.remove_func(name);
         true
     }
@@ -293,6 +392,16 @@ impl Shell {
         self.funcs.get(name).map(|x| x.to_string())
     }
 
+    /// get all functions and their bodies, sorted by names.
+    pub fn get_func_list(&self) -> Vec<(String, String)> {
+        let mut result = Vec::new();
+        for (name, body) in &self.funcs {
+            result.push((name.clone(), body.clone()));
+        }
+        result.sort();
+        result
+    }
+
     pub fn get_alias_list(&self) -> Vec<(String, String)> {
         let mut result = Vec::new();
         for (name, value) in &self.aliases {
@@ -433,8 +542,9 @@ pub fn expand_glob(tokens: &mut types::Tokens) {
 fn expand_one_env(sh: &Shell, token: &str) -> String {
     // do not combine these two into one: `\{?..\}?`,
     // otherwize `}` in `{print $NF}` would gone.
-    let re1 = Regex::new(r"^(.*?)\$([A-Za-z0-9_]+|\$|\?)(.*)$").unwrap();
-    let re2 = Regex::new(r"(.*?)\$\{([A-Za-z0-9_]+|\$|\?)\}(.*)$").unwrap();
+    // `(?s)`: tokens like here-document bodies could have multiple lines
+    let re1 = Regex::new(r"(?s)^(.*?)\$([A-Za-z0-9_]+|\$|\?|!)(.*)$").unwrap();
+    let re2 = Regex::new(r"(?s)(.*?)\$\{([A-Za-z0-9_]+|\$|\?)\}(.*)$").unwrap();
     if !re1.is_match(token) && !re2.is_match(token) {
         return token.to_string();
     }
@@ -458,11 +568,16 @@ fn expand_one_env(sh: &Shell, token: &str) -> String {
         let key = cap[2].to_string();
         if key == "?" {
             result.push_str(format!("{}{}", head, sh.previous_status).as_str());
+        } else if key == "!" {
+            let val = sh.last_bg_pid.map(|x| x.to_string()).unwrap_or_default();
+            result.push_str(format!("{}{}", head, val).as_str());
         } else if key == "$" {
             unsafe {
                 let val = libc::getpid();
                 result.push_str(format!("{}{}", head, val).as_str());
             }
+        } else if let Some(val) = sh.get_local(&key) {
+            result.push_str(format!("{}{}", head, val).as_str());
         } else if let Ok(val) = env::var(&key) {
             result.push_str(format!("{}{}", head, val).as_str());
         } else if let Some(val) = sh.get_env(&key) {
@@ -749,8 +864,20 @@ fn expand_home(tokens: &mut types::Tokens) {
     }
 }
 
+/// do not expand env in a command substitution, e.g.:
+/// - echo $(echo '$HOME')
+/// - VERSION=$(foobar -h | grep 'version: v' | awk '{print $NF}')
+fn is_command_substitution(token: &str) -> bool {
+    let ptn_env_name = r"[a-zA-Z_][a-zA-Z0-9_]*";
+    let ptn_cmd_sub1 = format!(r"^{}=`.*`$", ptn_env_name);
+    let ptn_cmd_sub2 = format!(r"^{}=\$\(.*\)$", ptn_env_name);
+    libs::re::re_contains(token, &ptn_cmd_sub1)
+        || libs::re::re_contains(token, &ptn_cmd_sub2)
+        || libs::re::re_contains(token, r"^\$\(.+\)$")
+}
+
 fn env_in_token(token: &str) -> bool {
-    if libs::re::re_contains(token, r"\$\{?[\$\?]\}?") {
+    if libs::re::re_contains(token, r"\$\{?[\$\?]\}?|\$!") {
         return true;
     }
 
@@ -760,15 +887,7 @@ fn env_in_token(token: &str) -> bool {
         return false;
     }
 
-    // do not expand env in a command substitution, e.g.:
-    // - echo $(echo '$HOME')
-    // - VERSION=$(foobar -h | grep 'version: v' | awk '{print $NF}')
-    let ptn_cmd_sub1 = format!(r"^{}=`.*`$", ptn_env_name);
-    let ptn_cmd_sub2 = format!(r"^{}=\$\(.*\)$", ptn_env_name);
-    if libs::re::re_contains(token, &ptn_cmd_sub1)
-        || libs::re::re_contains(token, &ptn_cmd_sub2)
-        || libs::re::re_contains(token, r"^\$\(.+\)$")
-    {
+    if is_command_substitution(token) {
         return false;
     }
 
@@ -794,7 +913,12 @@ pub fn expand_env(sh: &Shell, tokens: &mut types::Tokens) {
 
         let mut _token = token.clone();
         while env_in_token(&_token) {
-            _token = expand_one_env(sh, &_token);
+            let expanded = expand_one_env(sh, &_token);
+            if expanded == _token {
+                // e.g. an unclosed `${foo`
+                break;
+            }
+            _token = expanded;
         }
         buff.push((idx, _token));
         idx += 1;
@@ -805,6 +929,793 @@ pub fn expand_env(sh: &Shell, tokens: &mut types::Tokens) {
     }
 }
 
+/// Find the first `$((...))` in the text, returns its range and the
+/// expression inside it. Things like `$((ls) | wc)` are command
+/// substitutions instead, and are not counted in.
+fn find_arithmetic(text: &str) -> Option<(usize, usize, String)> {
+    let mut from = 0;
+    while let Some(pos) = text[from..].find("$((") {
+        let start = from + pos;
+        let body = &text[start + 3..];
+        let mut depth = 2;
+        let mut closed_at = None;
+        for (i, c) in body.char_indices() {
+            if c == '(' {
+                depth += 1;
+            } else if c == ')' {
+                depth -= 1;
+                if depth == 1 {
+                    if body[i + 1..].starts_with(')') {
+                        closed_at = Some(i);
+                    }
+                    break;
+                }
+            }
+        }
+        if let Some(i) = closed_at {
+            return Some((start, start + 3 + i + 2, body[..i].to_string()));
+        }
+        from = start + 3;
+    }
+    None
+}
+
+/// Expand `$((...))` with the results of the arithmetic expressions.
+/// Variables are expanded together here from left to right, since they
+/// may be changed in the expressions, e.g. `echo $((i++)) $i`.
+fn expand_arithmetic(sh: &mut Shell, tokens: &mut types::Tokens) -> Result<(), String> {
+    if !tokens.iter().any(|(_, x)| x.contains("$((")) {
+        return Ok(());
+    }
+
+    for (sep, token) in tokens.iter_mut() {
+        if sep == "`" || sep == "'" {
+            continue;
+        }
+
+        let mut result = String::new();
+        let mut rest = token.as_str();
+        while let Some((start, end, expr)) = find_arithmetic(rest) {
+            result.push_str(&expand_env_in_text(sh, &rest[..start]));
+            // e.g. `$(( $(wc -l < foo) + 1 ))`
+            let mut tokens = vec![(String::new(), expr)];
+            do_command_substitution(sh, &mut tokens);
+            let expr = expand_env_in_text(sh, &tokens[0].1);
+            let value = calculator::arith::eval(sh, &expr)?;
+            result.push_str(&value.to_string());
+            rest = &rest[end..];
+        }
+        result.push_str(&expand_env_in_text(sh, rest));
+        *token = result;
+    }
+    Ok(())
+}
+
+fn expand_env_in_text(sh: &Shell, text: &str) -> String {
+    let mut tokens = vec![(String::new(), text.to_string())];
+    expand_env(sh, &mut tokens);
+    tokens.remove(0).1
+}
+
+/// Find the first `${...}` in the text, returns its range and the text
+/// inside the braces, which could have nested ones, e.g. `${a:-${b}}`.
+pub fn find_braced_param(text: &str) -> Option<(usize, usize, String)> {
+    let start = text.find("${")?;
+    let body = &text[start + 2..];
+    let mut depth = 1;
+    for (i, c) in body.char_indices() {
+        if c == '{' {
+            depth += 1;
+        } else if c == '}' {
+            depth -= 1;
+            if depth == 0 {
+                return Some((start, start + 2 + i + 1, body[..i].to_string()));
+            }
+        }
+    }
+    None
+}
+
+/// Split the text inside `${...}` into `(is_length, name, operator)`,
+/// e.g. `foo:-bar` into `(false, "foo", ":-bar")`, `#foo` into
+/// `(true, "foo", "")`. Names of array elements keep their subscripts,
+/// e.g. `#arr[@]` into `(true, "arr[@]", "")`.
+pub fn split_braced_param(inner: &str) -> Option<(bool, String, String)> {
+    let re = Regex::new(r"(?s)^(#)?([A-Za-z_][A-Za-z0-9_]*(?:\[[^\]]*\])?|[0-9]+|[?$#@*])(.*)$")
+        .unwrap();
+    let cap = re.captures(inner)?;
+    let is_length = cap.get(1).is_some();
+    if is_length && !cap[3].is_empty() {
+        return None;
+    }
+    Some((is_length, cap[2].to_string(), cap[3].to_string()))
+}
+
+fn split_param_operator(op: &str) -> (&str, &str) {
+    let kinds = [
+        ":-", ":=", ":?", ":+", "##", "%%", "//", "/#", "/%", "-", "=", "?", "+", "#", "%", "/",
+        ":",
+    ];
+    for kind in kinds {
+        if let Some(word) = op.strip_prefix(kind) {
+            return (kind, word);
+        }
+    }
+    ("", op)
+}
+
+/// Split the word of `${name/pattern/string}` at the first `/` which is
+/// not escaped or quoted.
+fn split_replace_word(word: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in word.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if quote == Some(c) {
+            quote = None;
+        } else if quote.is_some() {
+            continue;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '\'' || c == '"' {
+            quote = Some(c);
+        } else if c == '/' {
+            return (&word[..i], &word[i + 1..]);
+        }
+    }
+    (word, "")
+}
+
+/// Remove the quotes and backslashes in the word of a parameter
+/// expansion, e.g. `"a b"` in `${name:-"a b"}`. For patterns, the quoted
+/// and escaped chars are escaped for globbing instead, e.g. `\*` into `[*]`.
+fn unquote_word(word: &str, is_pattern: bool) -> String {
+    let mut result = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in word.chars() {
+        let is_literal = if escaped {
+            escaped = false;
+            true
+        } else if quote == Some(c) {
+            quote = None;
+            continue;
+        } else if c == '\\' && quote != Some('\'') {
+            escaped = true;
+            continue;
+        } else if quote.is_some() {
+            true
+        } else if c == '\'' || c == '"' {
+            quote = Some(c);
+            continue;
+        } else {
+            false
+        };
+        if is_literal && is_pattern {
+            result.push_str(&glob::Pattern::escape(&c.to_string()));
+        } else {
+            result.push(c);
+        }
+    }
+    if escaped {
+        result.push('\\');
+    }
+    result
+}
+
+fn is_pattern_matched(pattern: &str, text: &str) -> bool {
+    match glob::Pattern::new(pattern) {
+        Ok(x) => x.matches(text),
+        Err(_) => pattern == text,
+    }
+}
+
+fn parse_substring_num(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let text = text
+        .strip_prefix('(')
+        .and_then(|x| x.strip_suffix(')'))
+        .unwrap_or(text);
+    text.trim().parse::<i64>().ok()
+}
+
+/// Get the range of `${name:offset:length}` on a value with `count`
+/// chars (or elements of arrays), `None` if the offset is out of range.
+/// The `word` is the part after `:`, e.g. `1:2`.
+fn get_substring_range(count: usize, word: &str) -> Result<Option<(usize, usize)>, String> {
+    let op = format!(":{}", word);
+    let (offset, length) = match word.split_once(':') {
+        Some((a, b)) => (a, Some(b)),
+        None => (word, None),
+    };
+    let count = count as i64;
+    let offset = match parse_substring_num(offset) {
+        Some(x) => x,
+        None => return Err(format!("{}: bad substitution", op)),
+    };
+    let start = if offset < 0 { count + offset } else { offset };
+    if start < 0 || start > count {
+        return Ok(None);
+    }
+    let end = match length {
+        Some(x) => match parse_substring_num(x) {
+            Some(n) if n < 0 => count + n,
+            Some(n) => (start + n).min(count),
+            None => return Err(format!("{}: bad substitution", op)),
+        },
+        None => count,
+    };
+    if end < start {
+        return Err(format!("{}: substring expression < 0", op));
+    }
+    Ok(Some((start as usize, end as usize)))
+}
+
+/// Apply the operator of a parameter expansion, i.e. the text after the
+/// name in `${name...}`, on the value of the parameter (`None` if unset).
+/// Returns the expanded text, and the new value when the parameter should
+/// be assigned, e.g. with `${name:=word}`. The word is expanded with
+/// `expand` only when it is used, e.g. not for `${name:-word}` when the
+/// parameter is set.
+pub fn apply_param_operator(
+    name: &str,
+    value: Option<&str>,
+    op: &str,
+    expand: &mut dyn FnMut(&str) -> Result<String, String>,
+) -> Result<(String, Option<String>), String> {
+    let val = value.unwrap_or("");
+    let (kind, word) = split_param_operator(op);
+    let is_unset = if kind.starts_with(':') {
+        val.is_empty()
+    } else {
+        value.is_none()
+    };
+
+    // positions of all chars, plus the end
+    let mut bounds: Vec<usize> = val.char_indices().map(|(i, _)| i).collect();
+    bounds.push(val.len());
+
+    let result = match kind {
+        "" if op.is_empty() => val.to_string(),
+        ":-" | "-" => {
+            if is_unset {
+                unquote_word(&expand(word)?, false)
+            } else {
+                val.to_string()
+            }
+        }
+        ":=" | "=" => {
+            if is_unset {
+                let word = unquote_word(&expand(word)?, false);
+                return Ok((word.clone(), Some(word)));
+            }
+            val.to_string()
+        }
+        ":?" | "?" => {
+            if is_unset {
+                let msg = if word.is_empty() {
+                    "parameter null or not set".to_string()
+                } else {
+                    unquote_word(&expand(word)?, false)
+                };
+                return Err(format!("{}: {}", name, msg));
+            }
+            val.to_string()
+        }
+        ":+" | "+" => {
+            if is_unset {
+                String::new()
+            } else {
+                unquote_word(&expand(word)?, false)
+            }
+        }
+        "#" | "##" => {
+            let pattern = unquote_word(&expand(word)?, true);
+            let mut result = val.to_string();
+            let mut iter: Box<dyn Iterator<Item = &usize>> = if kind == "#" {
+                Box::new(bounds.iter())
+            } else {
+                Box::new(bounds.iter().rev())
+            };
+            if let Some(i) = iter.find(|i| is_pattern_matched(&pattern, &val[..**i])) {
+                result = val[*i..].to_string();
+            }
+            result
+        }
+        "%" | "%%" => {
+            let pattern = unquote_word(&expand(word)?, true);
+            let mut result = val.to_string();
+            let mut iter: Box<dyn Iterator<Item = &usize>> = if kind == "%" {
+                Box::new(bounds.iter().rev())
+            } else {
+                Box::new(bounds.iter())
+            };
+            if let Some(i) = iter.find(|i| is_pattern_matched(&pattern, &val[**i..])) {
+                result = val[..*i].to_string();
+            }
+            result
+        }
+        "/" | "//" | "/#" | "/%" => {
+            let (pattern, replace) = split_replace_word(word);
+            if pattern.is_empty() {
+                return Ok((val.to_string(), None));
+            }
+            let pattern = unquote_word(&expand(pattern)?, true);
+            let replace = unquote_word(&expand(replace)?, false);
+            replace_pattern(val, &bounds, kind, &pattern, &replace)
+        }
+        ":" => {
+            let chars: Vec<char> = val.chars().collect();
+            match get_substring_range(chars.len(), &expand(word)?)? {
+                Some((start, end)) => chars[start..end].iter().collect(),
+                None => String::new(),
+            }
+        }
+        _ => return Err(format!("${{{}{}}}: bad substitution", name, op)),
+    };
+    Ok((result, None))
+}
+
+/// Replace the longest matches of the pattern in value, for `${name/p/r}`
+/// (the first one), `${name//p/r}` (all), `${name/#p/r}` (at beginning),
+/// and `${name/%p/r}` (at end).
+fn replace_pattern(
+    val: &str,
+    bounds: &[usize],
+    kind: &str,
+    pattern: &str,
+    replace: &str,
+) -> String {
+    if kind == "/#" {
+        for i in bounds.iter().rev() {
+            if is_pattern_matched(pattern, &val[..*i]) {
+                return format!("{}{}", replace, &val[*i..]);
+            }
+        }
+        return val.to_string();
+    }
+    if kind == "/%" {
+        for i in bounds.iter() {
+            if is_pattern_matched(pattern, &val[*i..]) {
+                return format!("{}{}", &val[..*i], replace);
+            }
+        }
+        return val.to_string();
+    }
+
+    let mut result = String::new();
+    let mut idx = 0;
+    while idx + 1 < bounds.len() {
+        let start = bounds[idx];
+        let matched = (idx + 1..bounds.len())
+            .rev()
+            .find(|j| is_pattern_matched(pattern, &val[start..bounds[*j]]));
+        match matched {
+            Some(j) => {
+                result.push_str(replace);
+                idx = j;
+                if kind == "/" {
+                    break;
+                }
+            }
+            None => {
+                result.push_str(&val[start..bounds[idx + 1]]);
+                idx += 1;
+            }
+        }
+    }
+    result.push_str(&val[bounds[idx]..]);
+    result
+}
+
+fn get_param_value(sh: &Shell, name: &str) -> Option<String> {
+    if name == "?" {
+        return Some(sh.previous_status.to_string());
+    }
+    if name == "$" {
+        return Some(unsafe { libc::getpid() }.to_string());
+    }
+    // positional parameters are only set in scripts and functions
+    if name == "#" {
+        return Some("0".to_string());
+    }
+    sh.get_env(name)
+}
+
+/// Split `name[subscript]` into the name and the subscript.
+pub fn split_array_subscript(text: &str) -> Option<(&str, &str)> {
+    let (name, rest) = text.split_once('[')?;
+    let subscript = rest.strip_suffix(']')?;
+    Some((name, subscript))
+}
+
+/// Resolve the subscript of an array element into its key. Subscripts
+/// of indexed arrays are arithmetic expressions, where the negative ones
+/// count back from the end, e.g. `arr[-1]`.
+fn get_array_key(sh: &mut Shell, name: &str, subscript: &str) -> Result<String, String> {
+    let (is_assoc, next_index) = match sh.get_array(name) {
+        Some(x) => (x.is_assoc(), x.next_index()),
+        None => (false, usize::from(sh.get_env(name).is_some())),
+    };
+    if is_assoc {
+        if subscript.is_empty() {
+            return Err(format!("{}[]: bad array subscript", name));
+        }
+        return Ok(subscript.to_string());
+    }
+
+    let index = calculator::arith::eval(sh, subscript)?;
+    let index = if index < 0 {
+        index + next_index as i64
+    } else {
+        index
+    };
+    if index < 0 {
+        return Err(format!("{}[{}]: bad array subscript", name, subscript));
+    }
+    Ok(index.to_string())
+}
+
+fn get_array_item(sh: &mut Shell, name: &str, subscript: &str) -> Result<Option<String>, String> {
+    let key = get_array_key(sh, name, subscript)?;
+    match sh.get_array(name) {
+        Some(x) => Ok(x.get(&key).cloned()),
+        // a *Shell Variable* is the same as an array with one element
+        None if key == "0" => Ok(sh.get_env(name)),
+        None => Ok(None),
+    }
+}
+
+/// Set an element of the array, which is created if it is not defined.
+fn set_array_item(sh: &mut Shell, name: &str, key: &str, value: &str) {
+    if sh.get_array(name).is_none() {
+        let mut array = types::Array::new_indexed();
+        if let Some(x) = sh.get_env(name) {
+            array.set("0", &x);
+        }
+        sh.set_array(name, array);
+    }
+    if let Some(array) = sh.arrays.get_mut(name) {
+        array.set(key, value);
+    }
+}
+
+/// Remove an element of the array, e.g. `unset 'arr[1]'`.
+pub fn remove_array_item(sh: &mut Shell, name: &str, subscript: &str) -> Result<(), String> {
+    let key = get_array_key(sh, name, subscript)?;
+    match sh.arrays.get_mut(name) {
+        Some(x) => {
+            x.remove(&key);
+        }
+        None if key == "0" => {
+            sh.remove_env(name);
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+/// Define variables for assignments like `foo=bar`, `foo+=bar`,
+/// `arr=(a b c)`, `arr+=(d)`, `arr[1]=b`, `map=([key]=value)`.
+pub fn assign_variable(sh: &mut Shell, name: &str, value: &str) -> Result<(), String> {
+    let re = Regex::new(r"^([A-Za-z_][A-Za-z0-9_]*)(?:\[(.*)\])?(\+)?$").unwrap();
+    let cap = match re.captures(name) {
+        Some(x) => x,
+        None => return Err(format!("`{}': not a valid identifier", name)),
+    };
+    let var = &cap[1];
+    let is_append = cap.get(3).is_some();
+
+    if let Some(subscript) = cap.get(2) {
+        let key = get_array_key(sh, var, subscript.as_str())?;
+        let value = parsers::parser_line::unquote(value);
+        let value = match sh.get_array(var).and_then(|x| x.get(&key)) {
+            Some(x) if is_append => format!("{}{}", x, value),
+            _ => value,
+        };
+        set_array_item(sh, var, &key, &value);
+        return Ok(());
+    }
+
+    if !is_array_literal(value) {
+        let value = parsers::parser_line::unquote(value);
+        let value = match sh.get_env(var) {
+            Some(x) if is_append => format!("{}{}", x, value),
+            _ => value,
+        };
+        sh.set_env(var, &value);
+        return Ok(());
+    }
+
+    let mut array = match sh.get_array(var) {
+        Some(x) if is_append => x.clone(),
+        Some(x) if x.is_assoc() => types::Array::new_assoc(),
+        _ => {
+            let mut array = types::Array::new_indexed();
+            if let Some(x) = sh.get_env(var).filter(|_| is_append) {
+                array.set("0", &x);
+            }
+            array
+        }
+    };
+    let mut next_index = array.next_index();
+    for (key, item) in parse_array_literal(value) {
+        let key = match key {
+            Some(x) if array.is_assoc() => x,
+            Some(x) => {
+                let index = calculator::arith::eval(sh, &x)?;
+                if index < 0 {
+                    return Err(format!("{}[{}]: bad array subscript", var, x));
+                }
+                index.to_string()
+            }
+            None if array.is_assoc() => {
+                return Err(format!(
+                    "{}: {}: must use subscript when assigning associative array",
+                    var, item
+                ));
+            }
+            None => next_index.to_string(),
+        };
+        array.set(&key, &item);
+        next_index = array.next_index();
+    }
+    sh.set_array(var, array);
+    Ok(())
+}
+
+fn is_array_literal(value: &str) -> bool {
+    value.starts_with('(') && value.ends_with(')')
+}
+
+/// Whether the token is an assignment of array, e.g. `arr=(a b c)`.
+pub fn is_array_assignment(token: &str) -> bool {
+    libs::re::re_contains(
+        token,
+        r"(?s)^[A-Za-z_][A-Za-z0-9_]*(\[[^\]]*\])?\+?=\(.*\)$",
+    )
+}
+
+/// Parse the elements of array in `(a b c)` or `([k1]=v1 [k2]=v2)`
+/// into `(key, value)` pairs.
+fn parse_array_literal(value: &str) -> Vec<(Option<String>, String)> {
+    let re = Regex::new(r"(?s)^\[([^\]]*)\]=(.*)$").unwrap();
+    let inner = &value[1..value.len() - 1];
+    let mut result = Vec::new();
+    for (_, text) in parsers::parser_line::parse_line(inner).tokens {
+        match re.captures(&text) {
+            Some(cap) => {
+                let item = parsers::parser_line::unquote(&cap[2]);
+                result.push((Some(cap[1].to_string()), item));
+            }
+            None => result.push((None, text)),
+        }
+    }
+    result
+}
+
+/// Expand the elements in array assignments on their own, so that
+/// each of them becomes an element, e.g. `arr=(*.txt "$foo")`.
+fn expand_array_assignment(sh: &mut Shell, token: &str) -> Result<String, String> {
+    let (head, literal) = match token.split_once("=(") {
+        Some(x) => x,
+        None => return Ok(token.to_string()),
+    };
+    let literal = &literal[..literal.len() - 1];
+    let mut tokens = parsers::parser_line::parse_line(literal).tokens;
+    expand_words(sh, &mut tokens)?;
+    // spaces in unquoted tokens like `[key]=foo\ bar` need to be escaped
+    let items: Vec<String> = tokens
+        .iter()
+        .map(|(sep, text)| tools::wrap_sep_string(sep, text))
+        .collect();
+    Ok(format!("{}=({})", head, items.join(" ")))
+}
+
+/// Expand the word in the operator of `${...}`, which could have
+/// parameters too. The results are cached for the elements of arrays.
+fn expand_word(
+    sh: &mut Shell,
+    cache: &mut HashMap<String, String>,
+    word: &str,
+) -> Result<String, String> {
+    if let Some(x) = cache.get(word) {
+        return Ok(x.clone());
+    }
+    let text = expand_params_in_text(sh, word)?;
+    let text = expand_env_in_text(sh, &text);
+    cache.insert(word.to_string(), text.clone());
+    Ok(text)
+}
+
+/// Expand the text inside `${...}` into words, which could be more than
+/// one only for all elements of arrays, e.g. `${arr[@]}`.
+fn expand_braced_param(sh: &mut Shell, inner: &str) -> Result<Vec<String>, String> {
+    let bad_substitution = format!("${{{}}}: bad substitution", inner);
+    // `${!arr[@]}` gives keys of the array
+    let (is_keys, param) = match inner.strip_prefix('!') {
+        Some(x) => (true, x),
+        None => (false, inner),
+    };
+    let (is_length, name, op) = match split_braced_param(param) {
+        Some(x) => x,
+        None => return Err(bad_substitution),
+    };
+    // the word part is expanded only when it is used, and only once
+    let mut cache = HashMap::new();
+
+    let (name, subscript) = match split_array_subscript(&name) {
+        Some((a, b)) => (a.to_string(), Some(expand_env_in_text(sh, b))),
+        None => (name, None),
+    };
+
+    if let Some("@") | Some("*") = subscript.as_deref() {
+        let items = match sh.get_array(&name) {
+            Some(x) if is_keys => x.keys(),
+            Some(x) => x.values(),
+            None => match get_param_value(sh, &name) {
+                Some(_) if is_keys => vec!["0".to_string()],
+                Some(x) => vec![x],
+                None => Vec::new(),
+            },
+        };
+        if is_length {
+            return Ok(vec![items.len().to_string()]);
+        }
+        if items.is_empty() {
+            let (text, _) =
+                apply_param_operator(&name, None, &op, &mut |x| expand_word(sh, &mut cache, x))?;
+            return Ok(if text.is_empty() {
+                Vec::new()
+            } else {
+                vec![text]
+            });
+        }
+        let (kind, word) = split_param_operator(&op);
+        match kind {
+            ":" => {
+                let word = expand_word(sh, &mut cache, word)?;
+                return match get_substring_range(items.len(), &word)? {
+                    Some((start, end)) => Ok(items[start..end].to_vec()),
+                    None => Ok(Vec::new()),
+                };
+            }
+            ":-" | "-" | ":=" | "=" | ":?" | "?" => return Ok(items),
+            ":+" | "+" => {
+                let word = expand_word(sh, &mut cache, word)?;
+                return Ok(vec![unquote_word(&word, false)]);
+            }
+            _ => {}
+        }
+        let mut words = Vec::new();
+        for item in items.iter() {
+            let (text, _) = apply_param_operator(&name, Some(item), &op, &mut |x| {
+                expand_word(sh, &mut cache, x)
+            })?;
+            words.push(text);
+        }
+        return Ok(words);
+    }
+    if is_keys {
+        return Err(bad_substitution);
+    }
+
+    let value = match subscript.as_deref() {
+        Some(x) => get_array_item(sh, &name, x)?,
+        None => get_param_value(sh, &name),
+    };
+    if is_length {
+        let len = value.map_or(0, |x| x.chars().count());
+        return Ok(vec![len.to_string()]);
+    }
+
+    let (text, assign) = apply_param_operator(&name, value.as_deref(), &op, &mut |x| {
+        expand_word(sh, &mut cache, x)
+    })?;
+    if let Some(x) = assign {
+        if !libs::re::re_contains(&name, r"^[A-Za-z_][A-Za-z0-9_]*$") {
+            println_stderr!("cicada: ${}: cannot assign in this way", name);
+        } else if let Some(subscript) = subscript {
+            let key = get_array_key(sh, &name, &subscript)?;
+            set_array_item(sh, &name, &key, &x);
+        } else {
+            sh.set_env(&name, &x);
+        }
+    }
+    Ok(vec![text])
+}
+
+/// Expand parameters in braces, including the ones with operators,
+/// e.g. `${name:-default}`, `${#name}`, `${name%.txt}`, `${arr[1]}`.
+fn expand_params_in_text(sh: &mut Shell, text: &str) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = text.to_string();
+    while let Some((start, end, inner)) = find_braced_param(&rest) {
+        result.push_str(&rest[..start]);
+        let words = expand_braced_param(sh, &inner)?;
+        result.push_str(&words.join(" "));
+        rest = rest[end..].to_string();
+    }
+    result.push_str(&rest);
+    Ok(result)
+}
+
+/// Get the words of tokens like `"${arr[@]}"`, where each element of the
+/// array is a separate word. Unquoted elements are split by whitespace.
+fn expand_array_words(
+    sh: &mut Shell,
+    sep: &str,
+    token: &str,
+) -> Option<Result<Vec<String>, String>> {
+    let (start, end, inner) = find_braced_param(token)?;
+    if start != 0 || end != token.len() {
+        return None;
+    }
+    let (is_length, name, _) = split_braced_param(inner.trim_start_matches('!'))?;
+    let (_, subscript) = split_array_subscript(&name)?;
+    if is_length || !(subscript == "@" || sep.is_empty() && subscript == "*") {
+        return None;
+    }
+
+    let words = match expand_braced_param(sh, &inner) {
+        Ok(x) => x,
+        Err(e) => return Some(Err(e)),
+    };
+    if !sep.is_empty() {
+        return Some(Ok(words));
+    }
+    Some(Ok(words
+        .iter()
+        .flat_map(|x| x.split_whitespace())
+        .map(|x| x.to_string())
+        .collect()))
+}
+
+fn expand_parameters(sh: &mut Shell, tokens: &mut types::Tokens) -> Result<(), String> {
+    let mut result = Vec::new();
+    for (sep, token) in mem::take(tokens) {
+        if sep == "`" || sep == "'" || !token.contains("${") || is_command_substitution(&token) {
+            result.push((sep, token));
+            continue;
+        }
+        // for cmd-line like `alias foo='echo ${PWD}'`
+        if libs::re::re_contains(&token, r"='.*\$\{.*'$") {
+            result.push((sep, token));
+            continue;
+        }
+        if let Some(words) = expand_array_words(sh, &sep, &token) {
+            for word in words? {
+                result.push((sep.clone(), word));
+            }
+            continue;
+        }
+        let token = expand_params_in_text(sh, &token)?;
+        result.push((sep, token));
+    }
+    *tokens = result;
+    Ok(())
+}
+
+/// Run commands in process substitutions like `<(sort a)` and `>(wc -l)`,
+/// and replace them with paths to their pipes, e.g. `/dev/fd/63`.
+fn do_process_substitution(sh: &mut Shell, tokens: &mut types::Tokens) {
+    let re = Regex::new(r"(?s)^([<>])\((.+)\)$").unwrap();
+    for (sep, token) in tokens.iter_mut() {
+        if !sep.is_empty() {
+            continue;
+        }
+        let (is_output, cmd) = match re.captures(token) {
+            Some(cap) => (&cap[1] == ">", cap[2].to_string()),
+            None => continue,
+        };
+        match core::run_proc_substitution(sh, &cmd, is_output) {
+            Ok(fd) => *token = format!("/dev/fd/{}", fd),
+            Err(e) => println_stderr!("cicada: {}", e),
+        }
+    }
+}
+
 fn should_do_dollar_command_extension(line: &str) -> bool {
     libs::re::re_contains(line, r"\$\([^\)]+\)")
         && !libs::re::re_contains(line, r"='.*\$\([^\)]+\).*'$")
@@ -882,7 +1793,7 @@ fn do_command_substitution_for_dollar(sh: &mut Shell, tokens: &mut types::Tokens
 fn do_command_substitution_for_dot(sh: &mut Shell, tokens: &mut types::Tokens) {
     let mut idx: usize = 0;
     let mut buff: HashMap<usize, String> = HashMap::new();
-    let re = Regex::new(r"^([^`]*)`([^`]+)`(.*)$").unwrap();
+    let re = Regex::new(r"(?s)^([^`]*)`([^`]+)`(.*)$").unwrap();
 
     for (sep, token) in tokens.iter() {
         let new_token: String;
@@ -970,28 +1881,61 @@ fn do_command_substitution_for_dot(sh: &mut Shell, tokens: &mut types::Tokens) {
     }
 }
 
-fn do_command_substitution(sh: &mut Shell, tokens: &mut types::Tokens) {
+pub fn do_command_substitution(sh: &mut Shell, tokens: &mut types::Tokens) {
     do_command_substitution_for_dot(sh, tokens);
     do_command_substitution_for_dollar(sh, tokens);
 }
 
-pub fn do_expansion(sh: &mut Shell, tokens: &mut types::Tokens) {
+/// Expand the tokens of a command. Errors like `${foo:?not set}` and
+/// `$((1/0))` are returned, and the command must not run then.
+pub fn do_expansion(sh: &mut Shell, tokens: &mut types::Tokens) -> Result<(), String> {
     let line = parsers::parser_line::tokens_to_line(tokens);
     if tools::is_arithmetic(&line) {
-        return;
+        return Ok(());
     }
 
     if tokens.len() >= 2 && tokens[0].1 == "export" && tokens[1].1.starts_with("PROMPT=") {
-        return;
+        return Ok(());
     }
 
     expand_alias(sh, tokens);
+    expand_words(sh, tokens)
+}
+
+/// Do the expansions except aliases, where array assignments like
+/// `arr=(a b c)` are expanded separately.
+fn expand_words(sh: &mut Shell, tokens: &mut types::Tokens) -> Result<(), String> {
+    if tokens
+        .iter()
+        .any(|(sep, text)| sep.is_empty() && is_array_assignment(text))
+    {
+        let mut result = Vec::new();
+        let mut words = Vec::new();
+        for (sep, text) in mem::take(tokens) {
+            if sep.is_empty() && is_array_assignment(&text) {
+                expand_words(sh, &mut words)?;
+                result.append(&mut words);
+                result.push((sep, expand_array_assignment(sh, &text)?));
+            } else {
+                words.push((sep, text));
+            }
+        }
+        expand_words(sh, &mut words)?;
+        result.append(&mut words);
+        *tokens = result;
+        return Ok(());
+    }
+
+    do_process_substitution(sh, tokens);
     expand_home(tokens);
+    expand_parameters(sh, tokens)?;
+    expand_arithmetic(sh, tokens)?;
     expand_env(sh, tokens);
     expand_brace(tokens);
     expand_glob(tokens);
     do_command_substitution(sh, tokens);
     expand_brace_range(tokens);
+    Ok(())
 }
 
 pub fn trim_multiline_prompts(line: &str) -> String {
@@ -1013,14 +1957,20 @@ fn proc_has_terminal() -> bool {
 
 #[cfg(test)]
 mod tests {
+    use super::apply_param_operator;
+    use super::assign_variable;
     use super::env_in_token;
     use super::expand_alias;
+    use super::expand_arithmetic;
     use super::expand_brace;
     use super::expand_brace_range;
     use super::expand_env;
+    use super::expand_parameters;
+    use super::expand_params_in_text;
     use super::libs;
     use super::needs_globbing;
     use super::should_do_dollar_command_extension;
+    use super::types;
     use super::Shell;
     use std::env;
 
@@ -1049,6 +1999,162 @@ mod tests {
         assert!(should_do_dollar_command_extension("$(pwd) foo"));
     }
 
+    #[test]
+    fn test_apply_param_operator() {
+        let f = "archive.tar.gz";
+        let v = vec![
+            (Some(f), "", "archive.tar.gz"),
+            (None, ":-foo", "foo"),
+            (Some(""), ":-foo", "foo"),
+            (Some(""), "-foo", ""),
+            (Some(f), ":+alt", "alt"),
+            (None, "+alt", ""),
+            (Some(f), "#*.", "tar.gz"),
+            (Some(f), "##*.", "gz"),
+            (Some(f), "%.*", "archive.tar"),
+            (Some(f), "%%.*", "archive"),
+            (Some(f), "%.zip", "archive.tar.gz"),
+            (Some(f), "/a/A", "Archive.tar.gz"),
+            (Some(f), "//a/A", "Archive.tAr.gz"),
+            (Some(f), "//[a-c]", "rhive.tr.gz"),
+            (Some(f), "/#arch/ARCH", "ARCHive.tar.gz"),
+            (Some(f), "/%.gz/.xz", "archive.tar.xz"),
+            (Some(f), ":2:3", "chi"),
+            (Some(f), ": -2", "gz"),
+            (Some(f), ":(-6):3", "tar"),
+            (Some(f), ":0:-3", "archive.tar"),
+            (Some(f), ":20", ""),
+            (Some("中文字符"), ":1:2", "文字"),
+            (None, ":-\"a b\"", "a b"),
+            (Some("axb"), "/x/\"y z\"", "ay zb"),
+            (Some("/usr/bin"), "//\\//:", ":usr:bin"),
+            (Some("/usr/bin"), "/#\\/usr/X", "X/bin"),
+            (Some("a*b"), "/\\*/S", "aSb"),
+            (Some("a*b"), "#\"a*\"", "b"),
+            (Some("a*b"), "%'*b'", "a"),
+        ];
+        let mut keep = |x: &str| Ok(x.to_string());
+        for (value, op, expected) in v {
+            match apply_param_operator("foo", value, op, &mut keep) {
+                Ok((text, assign)) => {
+                    assert_eq!(text, expected);
+                    assert_eq!(assign, None);
+                }
+                Err(e) => panic!("{:?} {:?}: {}", value, op, e),
+            }
+        }
+
+        let result = apply_param_operator("foo", None, ":=bar", &mut keep);
+        assert_eq!(result, Ok(("bar".to_string(), Some("bar".to_string()))));
+        let result = apply_param_operator("foo", Some("x"), ":=bar", &mut keep);
+        assert_eq!(result, Ok(("x".to_string(), None)));
+
+        assert!(apply_param_operator("foo", None, ":?", &mut keep).is_err());
+        assert!(apply_param_operator("foo", Some(""), "?", &mut keep).is_ok());
+        assert!(apply_param_operator("foo", Some(f), ":1:-20", &mut keep).is_err());
+        assert!(apply_param_operator("foo", Some(f), "^bad", &mut keep).is_err());
+    }
+
+    #[test]
+    fn test_expand_params_in_text() {
+        let mut sh = Shell::new();
+        sh.set_env("test_params_f", "a/b/c.txt");
+        let v = vec![
+            ("${test_params_f##*/}", "c.txt"),
+            ("x${#test_params_f}y", "x9y"),
+            ("${test_params_none:-${test_params_f%/*}}", "a/b"),
+            ("${test_params_none:-$test_params_f}", "a/b/c.txt"),
+            ("${test_params_none}", ""),
+            (
+                "${test_params_new:=new} $test_params_new",
+                "new $test_params_new",
+            ),
+        ];
+        for (text, expected) in v {
+            assert_eq!(expand_params_in_text(&mut sh, text).unwrap(), expected);
+        }
+        assert_eq!(sh.get_env("test_params_new"), Some("new".to_string()));
+        assert!(expand_params_in_text(&mut sh, "${test_params_none:?unset}").is_err());
+        assert!(expand_params_in_text(&mut sh, "${test_params_f:x}").is_err());
+    }
+
+    #[test]
+    fn test_assign_variable() {
+        let mut sh = Shell::new();
+        assign_variable(&mut sh, "test_arr", "(a \"b c\" d)").unwrap();
+        assign_variable(&mut sh, "test_arr+", "(e)").unwrap();
+        assign_variable(&mut sh, "test_arr[6]", "g").unwrap();
+        assign_variable(&mut sh, "test_arr[1]+", "x").unwrap();
+        let array = sh.get_array("test_arr").unwrap();
+        assert_eq!(array.keys(), vec!["0", "1", "2", "3", "6"]);
+        assert_eq!(array.values(), vec!["a", "b cx", "d", "e", "g"]);
+        assert_eq!(sh.get_env("test_arr"), Some("a".to_string()));
+
+        sh.set_array("test_map", types::Array::new_assoc());
+        assign_variable(&mut sh, "test_map", "([k1]=v1 [k2]=\"v 2\")").unwrap();
+        assign_variable(&mut sh, "test_map[k3]", "v3").unwrap();
+        let array = sh.get_array("test_map").unwrap();
+        assert_eq!(array.keys(), vec!["k1", "k2", "k3"]);
+        assert_eq!(array.values(), vec!["v1", "v 2", "v3"]);
+        assert!(assign_variable(&mut sh, "test_map", "(v4)").is_err());
+
+        assign_variable(&mut sh, "test_str", "foo").unwrap();
+        assign_variable(&mut sh, "test_str+", "bar").unwrap();
+        assert_eq!(sh.get_env("test_str"), Some("foobar".to_string()));
+    }
+
+    #[test]
+    fn test_expand_array_params() {
+        let mut sh = Shell::new();
+        assign_variable(&mut sh, "test_arr", "(a.txt \"b c.txt\" d.rs)").unwrap();
+        let v = vec![
+            ("${test_arr[1]}", "b c.txt"),
+            ("${test_arr}", "a.txt"),
+            ("${test_arr[-1]}", "d.rs"),
+            ("${test_arr[1+1]}", "d.rs"),
+            ("${#test_arr[@]}", "3"),
+            ("${#test_arr[1]}", "7"),
+            ("${!test_arr[@]}", "0 1 2"),
+            ("${test_arr[@]%.*}", "a b c d"),
+            ("${test_arr[@]:1:1}", "b c.txt"),
+            ("${test_arr[5]:-none}", "none"),
+            ("${test_none[@]:-none}", "none"),
+            ("${#test_none[@]}", "0"),
+        ];
+        for (text, expected) in v {
+            assert_eq!(expand_params_in_text(&mut sh, text).unwrap(), expected);
+        }
+
+        let mut tokens = make_tokens(&vec![
+            ("", "echo"),
+            ("\"", "${test_arr[@]}"),
+            ("", "${test_arr[@]}"),
+        ]);
+        expand_parameters(&mut sh, &mut tokens).unwrap();
+        let exp_tokens = make_tokens(&vec![
+            ("", "echo"),
+            ("\"", "a.txt"),
+            ("\"", "b c.txt"),
+            ("\"", "d.rs"),
+            ("", "a.txt"),
+            ("", "b"),
+            ("", "c.txt"),
+            ("", "d.rs"),
+        ]);
+        assert_eq!(tokens, exp_tokens);
+    }
+
+    #[test]
+    fn test_expand_arithmetic() {
+        let mut sh = Shell::new();
+        let mut tokens = make_tokens(&vec![("", "echo"), ("", "$((1 + 2))x")]);
+        expand_arithmetic(&mut sh, &mut tokens).unwrap();
+        assert_eq!(tokens, make_tokens(&vec![("", "echo"), ("", "3x")]));
+
+        let mut tokens = make_tokens(&vec![("", "echo"), ("", "$((1/0))")]);
+        assert!(expand_arithmetic(&mut sh, &mut tokens).is_err());
+    }
+
     #[test]
     fn test_expand_env() {
         let sh = Shell::new();
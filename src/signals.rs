@@ -3,6 +3,8 @@ use nix::sys::signal;
 use nix::sys::wait::{waitpid, WaitPidFlag as WF, WaitStatus as WS};
 use nix::unistd::Pid;
 use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
 
 lazy_static! {
@@ -10,8 +12,16 @@ lazy_static! {
     static ref STOP_MAP: Mutex<HashSet<i32>> = Mutex::new(HashSet::new());
     static ref CONT_MAP: Mutex<HashSet<i32>> = Mutex::new(HashSet::new());
     static ref KILL_MAP: Mutex<HashMap<i32, i32>> = Mutex::new(HashMap::new());
+    // the dispositions before `trap` changed them, e.g. SIGTSTP is ignored
+    // by the interactive shell itself
+    static ref ORIGINAL_ACTIONS: Mutex<HashMap<i32, signal::SigAction>> =
+        Mutex::new(HashMap::new());
 }
 
+// bits of the signals with `trap` handlers, which are received but not
+// handled yet. Atomics are used here since they are async-signal-safe.
+static PENDING_TRAPS: AtomicU64 = AtomicU64::new(0);
+
 pub fn killed_map_insert(pid: i32, sig: i32) {
     if let Ok(mut m) = KILL_MAP.try_lock() {
         m.insert(pid, sig);
@@ -142,3 +152,66 @@ pub fn setup_sigchld_handler() {
         }
     }
 }
+
+extern "C" fn handle_trapped_signal(sig: i32) {
+    if (0..64).contains(&sig) {
+        PENDING_TRAPS.fetch_or(1 << sig, Ordering::SeqCst);
+    }
+}
+
+/// Take out the signals received for `trap` handlers.
+pub fn pop_pending_traps() -> Vec<i32> {
+    let bits = PENDING_TRAPS.swap(0, Ordering::SeqCst);
+    (1..64).filter(|x| bits & (1 << x) != 0).collect()
+}
+
+/// Setup the disposition of signals for the `trap` builtin: catch it when
+/// there is a handler, ignore it for an empty one, or reset it to the one
+/// the shell had before `trap` was used on it.
+pub fn setup_trap_handler(sig: signal::Signal, handler: Option<&str>) {
+    let mut originals = match ORIGINAL_ACTIONS.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+    let sa = match handler {
+        None => match originals.remove(&(sig as i32)) {
+            Some(x) => x,
+            // it's never changed
+            None => return,
+        },
+        Some(x) => {
+            let handler = if x.is_empty() {
+                signal::SigHandler::SigIgn
+            } else {
+                signal::SigHandler::Handler(handle_trapped_signal)
+            };
+            signal::SigAction::new(
+                handler,
+                signal::SaFlags::SA_RESTART,
+                signal::SigSet::empty(),
+            )
+        }
+    };
+    unsafe {
+        match signal::sigaction(sig, &sa) {
+            Ok(old) => {
+                if handler.is_some() {
+                    originals.entry(sig as i32).or_insert(old);
+                }
+            }
+            Err(e) => {
+                log!("sigaction error: {:?}", e);
+            }
+        }
+    }
+}
+
+/// Get the signal given like `INT`, `SIGINT`, `int` or `2`.
+pub fn signal_from_name(text: &str) -> Option<signal::Signal> {
+    let upper = text.to_uppercase();
+    let name = upper.trim_start_matches("SIG");
+    match name.parse::<i32>() {
+        Ok(x) => signal::Signal::try_from(x).ok(),
+        Err(_) => signal::Signal::from_str(&format!("SIG{}", name)).ok(),
+    }
+}
@@ -0,0 +1,233 @@
+//! Fish-style autosuggestions. The most likely completion of the line is
+//! taken from history and shown dimmed after the cursor. `Right`, `End`,
+//! `Ctrl-F` and `Ctrl-E` accept it, `Alt-F` accepts one word of it.
+//!
+//! lineread does not know about the suggestion: the line reader of the
+//! prompt module draws it after each step of reading the line, and erases
+//! it before the next key is handled, so that it never becomes part of the
+//! input by accident.
+use std::env;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use lineread::terminal::DefaultTerminal;
+use lineread::util::{forward_char, forward_word, grapheme_width};
+use lineread::{Command, Function, Interface, Prompter, Terminal};
+
+use crate::highlight;
+use crate::history;
+
+#[derive(Debug, Default)]
+struct State {
+    /// the buffer as of the last step
+    buffer: String,
+    /// whether the cursor is (believed to be) at the end of the buffer
+    at_end: bool,
+    /// set by the key functions below, which know where the cursor is
+    cursor_known: bool,
+    /// the suggested text after the buffer, which is on the screen
+    suggestion: String,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+fn with_state<T, F: FnOnce(&mut State) -> T>(f: F) -> T {
+    let mut state = match STATE.lock() {
+        Ok(x) => x,
+        Err(e) => e.into_inner(),
+    };
+    f(&mut state)
+}
+
+pub fn reset_state() {
+    with_state(|state| {
+        *state = State {
+            at_end: true,
+            ..State::default()
+        }
+    });
+}
+
+/// Autosuggestions can be turned off with `CICADA_AUTOSUGGEST=0`.
+pub fn is_enabled() -> bool {
+    env::var("CICADA_AUTOSUGGEST").map_or(true, |x| x != "0")
+}
+
+/// The text to be suggested after `buffer`.
+fn get_suggestion(buffer: &str) -> String {
+    if buffer.trim().is_empty() || buffer.contains('\n') {
+        return String::new();
+    }
+    let cwd = match env::current_dir() {
+        Ok(x) => x.to_string_lossy().to_string(),
+        Err(_) => String::new(),
+    };
+    match history::find_suggestion(buffer, &cwd) {
+        // control chars like tabs cannot be drawn as they are
+        Some(x) if !x.chars().any(|c| c.is_control()) => x[buffer.len()..].to_string(),
+        _ => String::new(),
+    }
+}
+
+/// The leading part of the suggestion up to the end of its next word.
+fn first_word<'a>(buffer: &str, suggestion: &'a str, word_break: &str) -> &'a str {
+    let line = format!("{}{}", buffer, suggestion);
+    let pos = forward_word(1, &line, buffer.len(), word_break);
+    &suggestion[..pos - buffer.len()]
+}
+
+/// The leading part of `text` fitting in `columns`, and its width.
+fn truncate_width(text: &str, columns: usize) -> (&str, usize) {
+    let mut width = 0;
+    let mut buf = [0u8; 4];
+    for (i, c) in text.char_indices() {
+        let w = grapheme_width(c.encode_utf8(&mut buf));
+        if width + w > columns {
+            return (&text[..i], width);
+        }
+        width += w;
+    }
+    (text, width)
+}
+
+/// Draw the suggestion after the cursor, which is at the end of the
+/// buffer in the column `col`, and before the column `limit`. Returns the
+/// width drawn.
+pub fn draw(col: usize, suggestion: &str, limit: usize) -> io::Result<usize> {
+    let (text, width) = truncate_width(suggestion, limit.saturating_sub(col + 1));
+    if width == 0 {
+        return Ok(0);
+    }
+
+    let mut stdout = io::stdout();
+    let color = highlight::suggestion_color();
+    write!(stdout, "{}{}\x1b[0m\x1b[{}D", color, text, width)?;
+    stdout.flush()?;
+    Ok(width)
+}
+
+/// Erase the suggestion after the cursor, but not the right prompt.
+pub fn erase(width: usize) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b[{}X", width)?;
+    stdout.flush()
+}
+
+/// The suggestion for the buffer after a step of reading.
+pub fn update_suggestion(buffer: &str) -> String {
+    with_state(|state| {
+        if state.cursor_known {
+            state.cursor_known = false;
+        } else if buffer == state.buffer {
+            // nothing changed but the cursor may be moved, e.g. `Left`
+            state.at_end = false;
+        }
+        if buffer.is_empty() {
+            state.at_end = true;
+        }
+        state.buffer = buffer.to_string();
+        state.suggestion = if state.at_end {
+            get_suggestion(buffer)
+        } else {
+            String::new()
+        };
+        state.suggestion.clone()
+    })
+}
+
+/// The keys which accept the suggestion, and what they do otherwise.
+#[derive(Clone, Copy, Debug)]
+pub enum AcceptFunction {
+    ForwardChar,
+    EndOfLine,
+    ForwardWord,
+}
+
+impl<T: Terminal> Function<T> for AcceptFunction {
+    fn execute(&self, prompter: &mut Prompter<T>, count: i32, _ch: char) -> io::Result<()> {
+        let buffer = prompter.buffer().to_string();
+        let cursor = prompter.cursor();
+        let suggestion = with_state(|state| {
+            if state.buffer == buffer {
+                state.suggestion.clone()
+            } else {
+                String::new()
+            }
+        });
+
+        let n = count.max(1) as usize;
+        if cursor == buffer.len() && !suggestion.is_empty() {
+            match self {
+                AcceptFunction::ForwardWord => {
+                    let word = first_word(&buffer, &suggestion, prompter.word_break_chars());
+                    prompter.insert_str(word)?;
+                }
+                _ => prompter.insert_str(&suggestion)?,
+            }
+        } else {
+            let pos = match self {
+                AcceptFunction::ForwardChar => forward_char(n, &buffer, cursor),
+                AcceptFunction::EndOfLine => buffer.len(),
+                AcceptFunction::ForwardWord => {
+                    forward_word(n, &buffer, cursor, prompter.word_break_chars())
+                }
+            };
+            prompter.set_cursor(pos)?;
+        }
+
+        let at_end = prompter.cursor() == prompter.buffer().len();
+        with_state(|state| {
+            state.at_end = at_end;
+            state.cursor_known = true;
+        });
+        Ok(())
+    }
+}
+
+pub fn bind_keys(rl: &Interface<DefaultTerminal>) {
+    let keys = [
+        (
+            "suggestion-or-forward-char",
+            AcceptFunction::ForwardChar,
+            &["\x1b[C", "\x1bOC", "\x06"][..],
+        ),
+        (
+            "suggestion-or-end-of-line",
+            AcceptFunction::EndOfLine,
+            &["\x1b[F", "\x1bOF", "\x05"][..],
+        ),
+        (
+            "suggestion-word-or-forward-word",
+            AcceptFunction::ForwardWord,
+            &["\x1bf"][..],
+        ),
+    ];
+    for (name, func, seqs) in keys {
+        rl.define_function(name, Arc::new(func));
+        for seq in seqs {
+            rl.bind_sequence(*seq, Command::from_str(name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_word() {
+        let word_break = " \t\n\"\\'`@$><=;|&{(";
+        assert_eq!(first_word("git", " commit -m x", word_break), " commit");
+        assert_eq!(first_word("git com", "mit -m x", word_break), "mit");
+        assert_eq!(first_word("ls", "", word_break), "");
+    }
+
+    #[test]
+    fn test_truncate_width() {
+        assert_eq!(truncate_width("echo 你好", 6), ("echo ", 5));
+        assert_eq!(truncate_width("echo 你好", 7), ("echo 你", 7));
+        assert_eq!(truncate_width("ls", 9), ("ls", 2));
+    }
+}
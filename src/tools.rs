@@ -287,9 +287,11 @@ pub fn split_into_fields(
 }
 
 pub fn is_builtin(s: &str) -> bool {
+    #[rustfmt::skip]
     let builtins = [
         "alias", "bg", "cd", "check", "cinfo", "exec", "exit", "export", "fg", "history", "jobs",
         "read", "source", "ulimit", "unalias", "vox", "minfd", "set", "unset", "unpath",
+        "complete", "declare", "functions", "hash", "kill", "local", "return", "trap", "wait",
     ];
     builtins.contains(&s)
 }
@@ -324,20 +326,40 @@ pub fn init_path_env() {
 
 pub fn is_shell_altering_command(line: &str) -> bool {
     let line = line.trim();
-    if re_contains(line, r"^[A-Za-z_][A-Za-z0-9_]*=.*$") {
+    if re_contains(line, r"^[A-Za-z_][A-Za-z0-9_]*(\[[^\]]*\])?\+?=.*$") {
         return true;
     }
     line.starts_with("alias ")
+        || line.starts_with("complete ")
+        || line.starts_with("declare ")
         || line.starts_with("export ")
         || line.starts_with("unalias ")
         || line.starts_with("unset ")
         || line.starts_with("source ")
+        || parsers::locust::is_func_def(line)
+}
+
+/// Format seconds like `2.5s`, `42s`, `1m5s` or `2h0m3s`.
+pub fn format_duration(seconds: f64) -> String {
+    if seconds < 10.0 {
+        return format!("{:.1}s", seconds.max(0.0));
+    }
+    let n = seconds as u64;
+    let (h, m, s) = (n / 3600, n % 3600 / 60, n % 60);
+    if h > 0 {
+        format!("{}h{}m{}s", h, m, s)
+    } else if m > 0 {
+        format!("{}m{}s", m, s)
+    } else {
+        format!("{}s", s)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::escape_path;
     use super::extend_bangbang;
+    use super::format_duration;
     use crate::shell;
 
     #[test]
@@ -369,4 +391,13 @@ mod tests {
             "a\\ b\\!c\\\"d\\\'\\#\\$\\&e\\(f\\)g\\*h\\,i\\;j\\<k\\>l\\?m\\\\n\\[\\]o\\`p\\{\\}q\\|\\^z.txt",
         );
     }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0.0), "0.0s");
+        assert_eq!(format_duration(2.46), "2.5s");
+        assert_eq!(format_duration(42.9), "42s");
+        assert_eq!(format_duration(65.0), "1m5s");
+        assert_eq!(format_duration(7203.0), "2h0m3s");
+    }
 }
@@ -1,8 +1,7 @@
 use regex::Regex;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 
-use crate::libs;
 use crate::parsers;
 use crate::parsers::parser_line::tokens_to_redirections;
 use crate::shell;
@@ -165,12 +164,16 @@ pub struct CommandLine {
     pub background: bool,
 }
 
+fn is_redirect_from(token: &Token) -> bool {
+    token.1 == "<" || token.1 == "<<<" || (token.0.is_empty() && token.1 == "<<")
+}
+
 impl Command {
     pub fn from_tokens(tokens: Tokens) -> Result<Command, String> {
         let mut tokens_new = tokens.clone();
         let mut redirects_from_type = String::new();
         let mut redirects_from_value = String::new();
-        let mut has_redirect_from = tokens_new.iter().any(|x| x.1 == "<" || x.1 == "<<<");
+        let mut has_redirect_from = tokens_new.iter().any(is_redirect_from);
 
         let mut len = tokens_new.len();
         while has_redirect_from {
@@ -193,7 +196,21 @@ impl Command {
                 }
             }
 
-            has_redirect_from = tokens_new.iter().any(|x| x.1 == "<" || x.1 == "<<<");
+            // here-documents are parsed into `<<` and its body
+            if let Some(idx) = tokens_new
+                .iter()
+                .position(|x| x.0.is_empty() && x.1 == "<<")
+            {
+                redirects_from_type = "<<".to_string();
+                tokens_new.remove(idx);
+                len -= 1;
+                if len > idx {
+                    redirects_from_value = tokens_new.remove(idx).1;
+                    len -= 1;
+                }
+            }
+
+            has_redirect_from = tokens_new.iter().any(is_redirect_from);
         }
 
         let tokens_final;
@@ -229,11 +246,119 @@ impl Command {
         self.redirect_from.is_some() && self.redirect_from.clone().unwrap().0 == "<<<"
     }
 
+    pub fn has_here_doc(&self) -> bool {
+        self.redirect_from.is_some() && self.redirect_from.clone().unwrap().0 == "<<"
+    }
+
     pub fn is_builtin(&self) -> bool {
         tools::is_builtin(&self.tokens[0].1)
     }
 }
 
+/// Where the candidates of a completion rule come from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompletionSource {
+    // words, or "`cmd`" items whose output words are candidates
+    Words(Vec<String>),
+    // a shell function called with the command, current and previous words,
+    // whose output words are candidates
+    Function(String),
+    // a command whose output lines are candidates
+    Command(String),
+    // files matching the glob pattern, and directories
+    Glob(String),
+}
+
+/// A rule to complete arguments of a command, defined by the `complete`
+/// builtin or the YAML files in the completer directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionRule {
+    pub cmd: String,
+    // only for arguments after the subcommand, e.g. `checkout` of `git`
+    pub subcmd: Option<String>,
+    // only for the value of the option, e.g. `--file`
+    pub option: Option<String>,
+    pub source: CompletionSource,
+}
+
+/// A frame of the function call stack, pushed when a function is called.
+#[derive(Debug, Clone, Default)]
+pub struct FuncFrame {
+    pub locals: HashMap<String, String>,
+    // set by the `return` builtin
+    pub return_status: Option<i32>,
+}
+
+/// Value of an array variable, e.g. `arr=(a b c)` or `declare -A map`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Array {
+    Indexed(BTreeMap<usize, String>),
+    Assoc(BTreeMap<String, String>),
+}
+
+impl Array {
+    pub fn new_indexed() -> Array {
+        Array::Indexed(BTreeMap::new())
+    }
+
+    pub fn new_assoc() -> Array {
+        Array::Assoc(BTreeMap::new())
+    }
+
+    pub fn is_assoc(&self) -> bool {
+        matches!(self, Array::Assoc(_))
+    }
+
+    /// The index after the last element of an indexed array.
+    pub fn next_index(&self) -> usize {
+        match self {
+            Array::Indexed(x) => x.keys().next_back().map_or(0, |i| i + 1),
+            Array::Assoc(_) => 0,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        match self {
+            Array::Indexed(x) => x.get(&key.parse::<usize>().ok()?),
+            Array::Assoc(x) => x.get(key),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self {
+            Array::Indexed(x) => {
+                if let Ok(i) = key.parse::<usize>() {
+                    x.insert(i, value.to_string());
+                }
+            }
+            Array::Assoc(x) => {
+                x.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        match self {
+            Array::Indexed(x) => x.remove(&key.parse::<usize>().ok()?),
+            Array::Assoc(x) => x.remove(key),
+        }
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        match self {
+            Array::Indexed(x) => x.keys().map(|i| i.to_string()).collect(),
+            Array::Assoc(x) => x.keys().cloned().collect(),
+        }
+    }
+
+    pub fn values(&self) -> Vec<String> {
+        match self {
+            Array::Indexed(x) => x.values().cloned().collect(),
+            Array::Assoc(x) => x.values().cloned().collect(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Job {
     pub cmd: String,
@@ -305,6 +430,19 @@ pub struct CommandOptions {
     pub isatty: bool,
     pub capture_output: bool,
     pub envs: HashMap<String, String>,
+    pub output_tee: Option<OutputTee>,
+}
+
+/// The process copying output of a pipeline to the terminal, while
+/// keeping the tail of it for history, see `HISTORY_CAPTURE_OUTPUT`.
+#[derive(Clone, Copy, Debug)]
+pub struct OutputTee {
+    pub pid: i32,
+    // write ends for stdout and stderr of the pipeline
+    pub fd_out: i32,
+    pub fd_err: i32,
+    // read end to get the kept output when the pipeline finishes
+    pub fd_result: i32,
 }
 
 fn split_tokens_by_pipes(tokens: &[Token]) -> Vec<Tokens> {
@@ -333,15 +471,21 @@ fn split_tokens_by_pipes(tokens: &[Token]) -> Vec<Tokens> {
 pub(crate) fn drain_env_tokens(tokens: &mut Tokens) -> HashMap<String, String> {
     let mut envs: HashMap<String, String> = HashMap::new();
     let mut n = 0;
-    let re = Regex::new(r"^([a-zA-Z0-9_]+)=(.*)$").unwrap();
+    // also the ones like `foo+=bar`, `arr[1]=foo`, `arr=(a b c)`
+    let re = Regex::new(r"(?s)^([a-zA-Z0-9_]+(?:\[[^\]]*\])?\+?)=(.*)$").unwrap();
     for (sep, text) in tokens.iter() {
-        if !sep.is_empty() || !libs::re::re_contains(text, r"^([a-zA-Z0-9_]+)=(.*)$") {
+        if !sep.is_empty() || !re.is_match(text) {
             break;
         }
 
         for cap in re.captures_iter(text) {
             let name = cap[1].to_string();
-            let value = parsers::parser_line::unquote(&cap[2]);
+            // elements of arrays are parsed when being assigned
+            let value = if shell::is_array_assignment(text) {
+                cap[2].to_string()
+            } else {
+                parsers::parser_line::unquote(&cap[2])
+            };
             envs.insert(name, value);
         }
 
@@ -357,8 +501,23 @@ impl CommandLine {
     pub fn from_line(line: &str, sh: &mut shell::Shell) -> Result<CommandLine, String> {
         let linfo = parsers::parser_line::parse_line(line);
         let mut tokens = linfo.tokens;
-        shell::do_expansion(sh, &mut tokens);
+        shell::do_expansion(sh, &mut tokens)?;
+        CommandLine::from_tokens(line, tokens)
+    }
+
+    /// Build the command line from tokens which are already expanded.
+    pub fn from_tokens(line: &str, mut tokens: Tokens) -> Result<CommandLine, String> {
         let envs = drain_env_tokens(&mut tokens);
+        if !tokens.is_empty() {
+            // only plain ones can be passed into the environment of commands,
+            // the others like `arr[1]=foo`, `foo+=bar` are shell variables
+            for (name, value) in envs.iter() {
+                let text = format!("{}={}", name, value);
+                if name.contains('[') || name.ends_with('+') || shell::is_array_assignment(&text) {
+                    return Err(format!("{}=: can only assign shell variables", name));
+                }
+            }
+        }
 
         let mut background = false;
         let len = tokens.len();
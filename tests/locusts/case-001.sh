@@ -0,0 +1,15 @@
+case "$1" in
+    start|begin)
+        echo starting
+        ;;
+    stop) echo stopping;;
+
+    # comments between branches are fine
+    'a b'|[0-9]*) echo quoted or digits ;;
+    *)
+        if echo $1 | grep -q x
+            echo has x
+        fi
+        echo default
+esac
+echo after esac
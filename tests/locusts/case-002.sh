@@ -0,0 +1,11 @@
+for x in a b c
+    case $x in
+        (a) continue;;
+        b)
+            case "$2" in
+                *) echo nested;;
+            esac
+            ;;
+        c) ;;
+    esac
+done
@@ -0,0 +1,5 @@
+case "$1" in a*) echo one;; b|c) echo two; echo three;; esac
+case $x in
+    x) echo x;; *) echo any;;
+esac
+echo after
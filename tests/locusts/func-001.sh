@@ -0,0 +1,16 @@
+function foo {
+    echo foo
+}
+
+bar() {
+    if [ -n "$1" ]; then
+        echo bar $1
+    fi
+}
+baz() { echo baz; echo $#; }; baz 1 2
+
+function outer() {
+    inner() { echo inner; }
+    inner
+}
+outer
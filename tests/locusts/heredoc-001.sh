@@ -0,0 +1,11 @@
+echo begin
+cat <<EOF | wc -l
+if echo not a test
+done
+EOF
+if true
+	cat <<-'END'
+	fi
+	END
+fi
+echo $((1 << 2))
@@ -0,0 +1,24 @@
+echo $((1 + 2 * 3)) $(( (1 + 2) * 3 )) $((7 / 2)) $((7 % 2)) $((2 ** 8))
+echo $((0x1F)) $((017)) $((1 << 3)) $((0xff >> 4)) $((6 & 3)) $((6 | 3)) $((6 ^ 3)) $((~5))
+echo $((3 > 2)) $((3 < 2)) $((2 >= 2)) $((2 == 2)) $((2 != 2)) $((!0)) $((1 && 0)) $((1 || 0))
+
+i=0
+while [ $i -lt 3 ]; do
+    echo "loop $i"
+    i=$((i+1))
+done
+echo "i is $i"
+
+n=5
+echo $((n++)) $n $((++n)) $((n += 10)) $((n -= 1)) $((n *= 2)) $((n /= 4)) $((n %= 3))
+echo "result: $((n > 1 ? 100 : 200))"
+echo $(( $(echo 3) + 1 )) $((`echo 2` * n))
+
+function sum() {
+    local total=0
+    for x in $@; do
+        total=$((total + x))
+    done
+    echo $total
+}
+sum 1 2 3 4
@@ -0,0 +1,5 @@
+# the script exits at the error, the trap exits 0 for the test runner
+trap 'echo "exit status: $?"; exit 0' EXIT
+echo $((7 / 2))
+echo "result: $((1 / 0))"
+echo "not reached"
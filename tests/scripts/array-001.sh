@@ -0,0 +1,48 @@
+files=(a.txt "b c.txt" d.rs)
+files+=(e.md)
+echo ${#files[@]} ${files[1]} ${files[-1]}
+echo ${files[@]}
+
+for f in "${files[@]}"; do
+    echo "[$f]"
+done
+
+for f in ${files[@]}; do
+    echo "<$f>"
+done
+
+i=2
+echo ${files[i]} ${files[$i+1]} ${files}
+echo ${!files[@]}
+echo ${files[@]%.*}
+echo ${files[@]:1:2}
+
+files[6]=g.sh
+echo ${!files[@]}
+unset 'files[0]'
+echo ${files[*]}
+
+declare -A roles=([web1]=nginx [db1]="postgres 16")
+roles[cache1]=redis
+echo ${#roles[@]} ${roles[db1]}
+host=web1
+echo ${roles[$host]} ${roles[none]:-unknown}
+for h in ${!roles[@]}; do
+    echo "$h => ${roles[$h]}"
+done
+declare -p roles
+
+empty=()
+echo "empty: ${#empty[@]}"
+for x in "${empty[@]}"; do
+    echo "never"
+done
+
+arr[3]="hello world"
+roles[host2]='db server'
+msg=hello
+msg+=" there"
+echo "${arr[3]}, ${roles[host2]}, $msg"
+# only shell variables, not for the environment of commands
+arr[4]=x env | grep arr
+echo "prefix status: $?"
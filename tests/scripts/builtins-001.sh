@@ -1,2 +1,8 @@
 check cp | grep -qE "^.*/cp: .*executable$" && echo "check: test check passed"
 ulimit -X >/dev/null 2>&1 || echo 'ulimit: unsupported option does not crash'
+complete -W "start stop" myservice
+complete -s install -o -r -G "*.txt" pip
+complete -p
+complete -r myservice
+complete -p myservice
+complete -W x 2>/dev/null || echo 'complete: no command given'
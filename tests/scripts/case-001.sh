@@ -0,0 +1,44 @@
+for action in start stop 'a b' 42 restart x1
+    case "$action" in
+        start|begin)
+            echo "starting"
+            ;;
+        stop) echo "stopping";;
+
+        'a b'|[0-9]*) echo "quoted or digits: $action" ;;
+        x*)
+            if echo $action | grep -q 1
+                echo "x with 1"
+            fi
+            ;;
+        *)
+            echo "default: $action"
+    esac
+done
+
+name=cicada
+case $name in
+    "*") echo literal star;;
+    cic*) echo "glob matched";;
+esac
+
+case $(echo foo) in
+    bar) echo bar;;
+esac
+echo "no branch matched"
+
+for x in a b c
+    case $x in
+        b) continue;;
+    esac
+    echo "x = $x"
+done
+
+case bin in
+    $(echo b)*) echo "substitution in patterns";;
+esac
+
+case abc in a*) echo "one-line case";; *) echo other;; esac
+for x in a b
+    case $x in a) echo inline a;; b) echo inline b; echo and more;; esac
+done
@@ -0,0 +1,39 @@
+x=global
+function foo() {
+    local x=inner
+    local y
+    echo "in foo: $x [$y] $# $@ / $*"
+    return 3
+    echo "not reached"
+}
+foo a b c
+echo "rc: $? x: $x y: [$y]"
+function bar() {
+    for i in 1 2 3; do
+        if [ $i = 2 ]; then
+            return $i
+        fi
+        echo "loop $i"
+    done
+    echo "not reached"
+}
+bar
+echo "rc: $?"
+function baz() {
+    echo "n=$#"
+    false
+}
+baz
+echo "rc: $?"
+function qux() {
+    x=changed
+}
+qux
+echo "x: $x"
+local z=1
+echo "rc: $?"
+function rec() {
+    rec
+}
+rec
+echo "rc: $?"
@@ -0,0 +1,20 @@
+greet() { echo "hello $1"; }
+greet world
+
+show-args() {
+    echo "$# args: $@"
+}
+show-args a b c
+
+function nested {
+    inner() { echo "inner $1"; }
+    inner from-nested
+}
+nested
+inner again
+
+functions greet
+declare -f show-args
+declare -F
+
+./target/debug/cicada -c 'hi() { echo hi $1; }; hi there && echo done'
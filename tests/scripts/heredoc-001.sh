@@ -0,0 +1,50 @@
+name=cicada
+cat <<EOF
+hello $name
+sub: $(echo foo | tr a-z A-Z)
+EOF
+
+cat <<'EOF'
+no $name expansion
+EOF
+
+if true
+	cat <<-END | wc -l
+		line one
+		line two
+	END
+fi
+
+cat <<EOF; echo after
+body; with && separators
+EOF
+
+read a b <<EOF
+first second
+EOF
+echo "$a-$b"
+
+cat <<EOF
+$1 fi
+done
+EOF
+
+echo ignored | cat <<EOF | tr a-z A-Z
+in a pipeline
+EOF
+
+cat <<A; wc -l <<B
+for cat
+B
+A
+for wc
+A
+B
+
+if true
+	cat <<-EOF <<EOF
+		stripped
+	EOF
+	kept
+EOF
+fi
@@ -0,0 +1,21 @@
+sh -c 'sleep 0.2; exit 3' &
+pid=$!
+sh -c 'sleep 0.1; exit 5' &
+wait %2
+echo "job 2: $?"
+wait $pid
+echo "pid: $?"
+sleep 5 &
+kill %1
+wait %1
+echo "killed: $?"
+sleep 0.1 &
+sleep 0.2 &
+wait
+echo "all: $? [$(jobs)]"
+wait 1
+echo "not child: $?"
+kill -l 15
+trap 'echo got USR1' USR1
+kill -USR1 $$
+echo end
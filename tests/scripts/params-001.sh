@@ -0,0 +1,21 @@
+f=archive.tar.gz
+p=/usr/local/lib/libfoo.so.1
+echo ${f%.tar.gz} ${f%.*} ${f%%.*} ${f#*.} ${f##*.}
+echo ${p##*/} ${p%/*} ${#p} ${#f}
+echo ${unset_v:-default} "${unset_v:-a b}" ${unset_v-dash} [${unset_v:+alt}] [${f:+alt}]
+empty=
+echo [${empty:-d1}] [${empty-d2}] [${empty:+x}] [${empty+y}]
+echo ${newv:=assigned} $newv
+echo ${f/tar/TAR} ${f//a/_} ${f/#arch/ARCH} ${f/%gz/xz} ${f/a} ${f//[a-c]/-}
+echo ${f:2} ${f:2:3} ${f: -2} ${f:(-6):3} ${f:0:-3}
+echo ${unset_v:-${f%%.*}} ${unset_v:-$p/x}
+echo ${f:-${lazy:=side}} [$lazy] ${f:-${lazy:?boom}} [${unset_v:+$((1/0))}]
+echo ${p//\//:} ${p/#\/usr/X} ${unset_v:-"q  r"} ${f/tar/"T R"}
+star='a*b'
+echo ${star/\*/S} ${star#"a*"} ${star%\*b}
+
+function show() {
+    echo "${1:-no-first} ${2:-no-second} ${#1} ${1%.txt} ${HOME:+home-set}"
+}
+show notes.txt
+show
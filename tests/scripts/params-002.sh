@@ -0,0 +1,6 @@
+# the script exits at the error, the trap exits 0 for the test runner
+trap 'echo "exit status: $?"; exit 0' EXIT
+echo ${NOPE:-default}
+echo "before"
+echo rm -rf "${NOPE_DIR:?must be set}/"
+echo "not reached"
@@ -0,0 +1,17 @@
+cat <(echo foo)
+echo <(echo foo) | grep -c '^/dev/fd/[0-9]*$'
+diff <(seq 3) <(seq 4)
+echo "diff status: $?"
+diff <(echo a b) <(echo a b) && echo same
+wc -l < <(seq 3)
+paste -d, <(seq 2) <(seq 3 4)
+echo hello | tee >(tr a-z A-Z) > /dev/null
+sleep 0.1
+cat <(echo "$(echo nested)")
+
+# substitutions are killed when the main command is interrupted
+start=$(date +%s)
+sh -c 'kill -INT $$' <(sleep 5 | cat)
+echo "interrupted: $?"
+end=$(date +%s)
+test $((end - start)) -lt 3 && echo "substitution killed"
@@ -0,0 +1,21 @@
+trap 'echo "cleanup on exit"' EXIT
+trap 'echo "got TERM"' TERM
+trap "echo 'got HUP'" SIGHUP
+trap -p
+kill -TERM $$
+echo "after TERM"
+kill -HUP $$
+trap - TERM
+trap -p TERM
+trap 'echo "error: $?"' ERR
+false
+if false; then
+    echo no
+fi
+false && echo no
+false || true
+echo "status: $?"
+trap -p ERR
+trap - ERR
+trap 'echo x' FOO
+echo done